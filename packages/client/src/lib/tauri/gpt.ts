@@ -1,6 +1,8 @@
 import { invoke } from "@tauri-apps/api/core";
+import { emit, listen } from "@tauri-apps/api/event";
 export { create } from "./gpt/create";
 export { infer } from "./gpt/infer";
+export { inferMany } from "./gpt/inferMany";
 
 export type LoadModelResult = {
   modelId: string;
@@ -21,14 +23,175 @@ export type LoadModelResult = {
   nCtxTrain: number;
 };
 
+export type LoadModelOptions = {
+  /**
+   * Map the model file into memory instead of reading it (default `true`).
+   */
+  useMmap?: boolean;
+
+  /**
+   * Lock the model in RAM, so that it is never swapped out.
+   */
+  useMlock?: boolean;
+
+  /**
+   * Only load the vocabulary, e.g. for tokenization.
+   * Such a model can not be used to create sessions.
+   */
+  vocabOnly?: boolean;
+
+  /**
+   * Validate the tensor data while loading. Slows the loading down.
+   */
+  checkTensors?: boolean;
+
+  /**
+   * The default number of threads for the sessions created with the model.
+   */
+  nThreads?: number;
+};
+
+const LOAD_MODEL_PROGRESS_EVENT_NAME = "app://gpt/load-model-progress";
+const ABORT_LOAD_MODEL_EVENT_NAME = "app://gpt/abort-load-model";
+
 /**
  * Load a GPT model from a file path.
- * Can be called multiple times with the same model path.
+ * Can be called multiple times with the same model path,
+ * in which case the options are ignored.
  */
-export async function loadModel(modelPath: string) {
-  return invoke("gpt_load_model", {
-    modelPath,
-  }) as Promise<LoadModelResult>;
+export async function loadModel(
+  modelPath: string,
+  progressCallback?: (event: { progress: number }) => void,
+  abortSignal?: AbortSignal,
+  options?: LoadModelOptions,
+) {
+  const unlisten = progressCallback
+    ? await listen(LOAD_MODEL_PROGRESS_EVENT_NAME, (event) => {
+        progressCallback(event.payload as { progress: number });
+      })
+    : undefined;
+
+  abortSignal?.addEventListener("abort", () => {
+    console.log("Aborting model loading");
+    emit(ABORT_LOAD_MODEL_EVENT_NAME, modelPath);
+  });
+
+  try {
+    return (await invoke("gpt_load_model", {
+      modelPath,
+      options,
+      progressEventName: progressCallback
+        ? LOAD_MODEL_PROGRESS_EVENT_NAME
+        : undefined,
+    })) as LoadModelResult;
+  } finally {
+    unlisten?.();
+  }
+}
+
+/**
+ * Load a LoRA adapter from a file path, returning its ID.
+ * Can be called multiple times with the same adapter path.
+ */
+export async function loadLora(loraPath: string) {
+  return (
+    (await invoke("gpt_load_lora", { loraPath })) as {
+      loraId: string;
+    }
+  ).loraId;
+}
+
+/**
+ * Unload a LoRA adapter.
+ * The sessions using the adapter keep it until {@link setLoras} is called.
+ */
+export async function unloadLora(loraId: string): Promise<void> {
+  return await invoke("gpt_unload_lora", { loraId });
+}
+
+export type GgufSpecialToken = {
+  id: number;
+  text: string | null;
+};
+
+export type GgufSummary = {
+  version: number;
+  tensorCount: number;
+  nParams: number;
+  architecture: string | null;
+  name: string | null;
+  contextLength: number | null;
+  embeddingLength: number | null;
+  blockCount: number | null;
+  fileType: number | null;
+
+  /**
+   * Quantization type name, e.g. `Q4_K_M`.
+   */
+  fileTypeName: string | null;
+
+  tokenizerModel: string | null;
+  vocabSize: number | null;
+  specialTokens: Record<
+    "bos" | "eos" | "eot" | "unk" | "sep" | "pad",
+    GgufSpecialToken | null
+  >;
+  chatTemplate: string | null;
+
+  /**
+   * All the metadata, except for long arrays (e.g. the vocabulary).
+   */
+  metadata: Record<string, unknown>;
+};
+
+/**
+ * Inspect a GGUF model file metadata without loading the model.
+ */
+export async function inspectGguf(modelPath: string) {
+  return invoke("gguf_inspect", { modelPath }) as Promise<GgufSummary>;
+}
+
+export type ChatMessage = {
+  role: "system" | "user" | "assistant" | (string & {});
+  content: string;
+};
+
+export type BuiltinChatTemplate =
+  | "chatml"
+  | "llama2"
+  | "llama3"
+  | "mistral"
+  | "gemma"
+  | "phi3"
+  | "alpaca";
+
+export type RenderChatResult = {
+  prompt: string;
+
+  /**
+   * Strings the template uses to end a turn.
+   */
+  stopSequences: string[];
+};
+
+/**
+ * Render chat messages using the chat template embedded in the model file,
+ * falling back to a built-in template if the model has none.
+ */
+export async function renderChat(
+  messages: ChatMessage[],
+  options: {
+    modelPath?: string;
+    fallback?: BuiltinChatTemplate;
+    addGenerationPrompt?: boolean;
+  },
+) {
+  return invoke("chat_template_render", {
+    messages,
+    modelPath: options.modelPath,
+    fallback: options.fallback,
+    addGenerationPrompt: options.addGenerationPrompt,
+  }) as Promise<RenderChatResult>;
 }
 
 export type ModelHashResult = {
@@ -53,6 +216,74 @@ export async function getModelHashByPath(modelPath: string) {
   }) as Promise<ModelHashResult>;
 }
 
+/**
+ * Tokenize a text using a GPT model by its ID.
+ */
+export async function tokenize(
+  modelId: string,
+  text: string,
+  options?: { addSpecial?: boolean; parseSpecial?: boolean },
+) {
+  return (
+    (await invoke("gpt_tokenize", {
+      modelId,
+      text,
+      addSpecial: options?.addSpecial,
+      parseSpecial: options?.parseSpecial,
+    })) as { tokens: number[] }
+  ).tokens;
+}
+
+/**
+ * Convert tokens back to a text using a GPT model by its ID.
+ */
+export async function detokenize(modelId: string, tokens: number[]) {
+  return (
+    (await invoke("gpt_detokenize", { modelId, tokens })) as { text: string }
+  ).text;
+}
+
+/**
+ * Convert a single token to its text piece using a GPT model by its ID.
+ */
+export async function tokenToPiece(modelId: string, token: number) {
+  return (
+    (await invoke("gpt_token_to_piece", { modelId, token })) as {
+      text: string;
+    }
+  ).text;
+}
+
+/**
+ * Compute the text embeddings using a GPT model by its ID,
+ * one embedding per text.
+ */
+export async function embed(
+  modelId: string,
+  texts: string[],
+  options?: {
+    /**
+     * `default` is the model's pooling, or `mean` if it has none.
+     */
+    pooling?: "default" | "mean" | "cls" | "last";
+
+    /**
+     * `euclidean` by default, so that the dot product
+     * equals the cosine similarity.
+     */
+    normalization?: "none" | "taxicab" | "euclidean";
+  },
+) {
+  return (
+    (await invoke("gpt_embed", {
+      modelId,
+      texts,
+      pooling: options?.pooling,
+      normalization: options?.normalization,
+    })) as { embeddings: number[][] }
+  ).embeddings;
+}
+
 /**
  * Find a GPT session, and return its model ID if found.
  */
@@ -62,9 +293,54 @@ export async function find(sessionId: string) {
   } | null;
 }
 
+/**
+ * Fork a GPT session, copying its KV cache and committed prompt
+ * into a new session. Returns the new session ID.
+ */
+export async function fork(sessionId: string) {
+  return (
+    (await invoke("gpt_fork", { sessionId })) as {
+      sessionId: string;
+    }
+  ).sessionId;
+}
+
+/**
+ * Set the LoRA adapters of a GPT session as `[loraId, scale]` pairs,
+ * replacing the current ones. Changing the adapters clears the KV cache.
+ * Returns the number of tokens cleared.
+ */
+export async function setLoras(
+  sessionId: string,
+  loras: [loraId: string, scale: number][],
+) {
+  return (
+    (await invoke("gpt_set_loras", { sessionId, loras })) as {
+      clearedTokens: number;
+    }
+  ).clearedTokens;
+}
+
 /**
  * Destroy a GPT session.
  */
 export async function destroy(sessionId: string): Promise<void> {
   return await invoke("gpt_destroy", { sessionId });
 }
+
+/**
+ * Save a GPT session state (KV cache and committed prompt) to a file.
+ */
+export async function saveState(sessionId: string, path: string) {
+  return invoke("gpt_save_state", { sessionId, path }) as Promise<void>;
+}
+
+/**
+ * Restore a GPT session state from a file saved with {@link saveState}.
+ * The session must have been created with the same model and context size.
+ */
+export async function loadState(sessionId: string, path: string) {
+  return invoke("gpt_load_state", { sessionId, path }) as Promise<{
+    contextLength: number;
+  }>;
+}
@@ -1,5 +1,5 @@
 import { invoke } from "@tauri-apps/api/core";
-import { listen } from "@tauri-apps/api/event";
+import { emit, listen } from "@tauri-apps/api/event";
 
 type Response = {
   sessionId: string;
@@ -12,8 +12,63 @@ type ProgressEventPayload = {
   progress: number;
 };
 
+export type KvCacheType = "f16" | "q8_0" | "q4_0";
+
+/**
+ * Session context parameters, the defaults if not set.
+ */
+export type SessionOptions = {
+  /**
+   * Physical batch size, i.e. the maximum tokens computed at once.
+   */
+  ubatchSize?: number;
+
+  /**
+   * Threads for the generation.
+   */
+  threads?: number;
+
+  /**
+   * Threads for the prompt decoding, same as `threads` by default.
+   */
+  batchThreads?: number;
+
+  /**
+   * A quantized KV cache takes less memory at a slight quality cost.
+   * A quantized V cache requires flash attention.
+   */
+  typeK?: KvCacheType;
+  typeV?: KvCacheType;
+
+  /**
+   * Enabled by default.
+   */
+  flashAttn?: boolean;
+
+  /**
+   * RoPE scaling, to run a model past its training context size.
+   * Implied to be `yarn` if `yarn` is set.
+   */
+  ropeScaling?: "none" | "linear" | "yarn";
+  ropeFreqBase?: number;
+  ropeFreqScale?: number;
+  yarn?: {
+    extFactor?: number;
+    attnFactor?: number;
+    betaFast?: number;
+    betaSlow?: number;
+    origCtx?: number;
+  };
+
+  /**
+   * The maximum number of sequences for `inferMany`, 1 by default.
+   */
+  maxSequences?: number;
+};
+
 const COMMAND_NAME = "gpt_create";
 const PROGRESS_EVENT_NAME = "app://gpt/progress";
+const ABORT_CREATE_EVENT_NAME = "app://gpt/abort-create";
 
 /**
  * Create a new GPT instance.
@@ -26,6 +81,20 @@ export async function create(args: {
   initialPrompt?: string;
   progressCallback?: (event: ProgressEventPayload) => void;
   cacheDir?: string;
+
+  /**
+   * If set, the context is shifted upon an overflow: the first `nKeep`
+   * tokens are kept, and the oldest `nDiscard` tokens after them are evicted
+   * (half of the rest by default).
+   */
+  contextShift?: { nKeep: number; nDiscard?: number };
+
+  /**
+   * Other context parameters.
+   */
+  options?: SessionOptions;
+
+  abortSignal?: AbortSignal;
 }): Promise<Response> {
   const unlisten = args.progressCallback
     ? await listen(PROGRESS_EVENT_NAME, (event) => {
@@ -33,15 +102,27 @@ export async function create(args: {
       })
     : undefined;
 
-  const result = (await invoke(COMMAND_NAME, {
-    modelId: args.modelId,
-    contextSize: args.contextSize,
-    batchSize: args.batchSize,
-    initialPrompt: args.initialPrompt,
-    progressEventName: args.progressCallback ? PROGRESS_EVENT_NAME : undefined,
-    cacheDir: args.cacheDir,
-  })) as Response;
-
-  unlisten?.();
-  return result;
+  args.abortSignal?.addEventListener("abort", () => {
+    console.log("Aborting session creation");
+    emit(ABORT_CREATE_EVENT_NAME, args.modelId);
+  });
+
+  try {
+    return (await invoke(COMMAND_NAME, {
+      modelId: args.modelId,
+      options: {
+        ...args.options,
+        contextSize: args.contextSize,
+        batchSize: args.batchSize,
+        contextShift: args.contextShift,
+      },
+      initialPrompt: args.initialPrompt,
+      progressEventName: args.progressCallback
+        ? PROGRESS_EVENT_NAME
+        : undefined,
+      cacheDir: args.cacheDir,
+    })) as Response;
+  } finally {
+    unlisten?.();
+  }
 }
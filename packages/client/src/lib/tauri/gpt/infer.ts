@@ -2,22 +2,91 @@ import { TauriInvokeError } from "@/lib/tauri";
 import { v } from "@/lib/valibot";
 import { invoke } from "@tauri-apps/api/core";
 import { emit, listen } from "@tauri-apps/api/event";
+import type { StopReason } from "./inferMany";
 
 type Response = {
+  /**
+   * The inferred text, excluding a matched stop sequence.
+   */
   result: string;
+
   inputContextLength: number;
   outputContextLength: number;
+
+  /**
+   * The number of tokens evicted by the context shift policy.
+   */
+  evictedTokens: number;
+
+  stopReason: StopReason;
+
+  /**
+   * The decoded prompt length in tokens.
+   */
+  promptTokens: number;
+
+  /**
+   * The number of inferred tokens, excluding a matched stop sequence.
+   */
+  generatedTokens: number;
+
+  decodeDurationMs: number;
+  generationDurationMs: number;
+  tokensPerSecond: number;
+
+  /**
+   * The number of tokens proposed by the draft model, if any.
+   */
+  draftedTokens: number;
+
+  /**
+   * The number of drafted tokens accepted.
+   */
+  acceptedTokens: number;
+
+  /**
+   * The share of drafted tokens accepted, `null` without drafting.
+   */
+  acceptanceRate: number | null;
 };
 
 type DecodeProgressEventPayload = {
   progress: number;
 };
 
+type TokenLogprob = {
+  token: number;
+  piece: string;
+
+  /**
+   * Natural logarithm of the token probability.
+   */
+  logprob: number;
+};
+
 type InferenceEventPayload = {
+  /**
+   * The token text.
+   */
   content: string;
+
+  /**
+   * The token ID.
+   */
+  token: number;
+
+  /**
+   * Natural logarithm of the token probability, `null` if unknown.
+   */
+  logprob: number | null;
+
+  /**
+   * Top `nProbs` candidates, if requested in the options.
+   */
+  topLogprobs: TokenLogprob[];
 };
 
-const CompletionOptionsSchema = v.strictObject({
+export const CompletionOptionsSchema = v.strictObject({
   nPrev: v.optional(v.number()),
   nProbs: v.optional(v.number()),
   minKeep: v.optional(v.number()),
@@ -52,6 +121,47 @@ const CompletionOptionsSchema = v.strictObject({
   seed: v.optional(v.number()),
   grammar: v.optional(v.string()),
   luaGrammar: v.optional(v.string()),
+
+  /**
+   * Limits for the Lua grammar script, per script call.
+   * Zero disables a limit.
+   */
+  luaLimits: v.optional(
+    v.object({
+      maxInstructions: v.optional(v.number()),
+      timeoutMs: v.optional(v.number()),
+      maxMemory: v.optional(v.number()),
+      libraries: v.optional(
+        v.array(v.picklist(["base", "string", "table", "math", "cjson"])),
+      ),
+    }),
+  ),
+
+  /**
+   * Added to the logits of the tokens, keyed by either a token ID,
+   * or a text to bias each of its tokens.
+   */
+  logitBias: v.optional(
+    v.array(v.tuple([v.union([v.number(), v.string()]), v.number()])),
+  ),
+
+  /**
+   * Strings not to infer, even if spanning several tokens.
+   * Tokens which may start a banned string are yielded with a delay.
+   */
+  bannedStrings: v.optional(v.array(v.string())),
+
+  /**
+   * Let a loaded draft model with the same vocabulary propose
+   * `nDraft` tokens per step (speculative decoding).
+   * The output is the same as without it.
+   */
+  speculative: v.optional(
+    v.object({
+      draftModelId: v.string(),
+      nDraft: v.optional(v.number()),
+    }),
+  ),
 });
 
 const COMMAND_NAME = "gpt_infer";
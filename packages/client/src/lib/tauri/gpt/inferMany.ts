@@ -0,0 +1,82 @@
+import { TauriInvokeError } from "@/lib/tauri";
+import { v } from "@/lib/valibot";
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { CompletionOptionsSchema } from "./infer";
+
+export type StopReason =
+  | { type: "nEval" }
+  | { type: "eos" }
+  | { type: "stopSequence"; sequence: string }
+  | { type: "callback" }
+  | { type: "lua" };
+
+export type InferredSequence = {
+  text: string;
+  tokens: number[];
+  stopReason: StopReason;
+};
+
+type InferenceEventPayload = {
+  /**
+   * The sequence index.
+   */
+  seq: number;
+
+  /**
+   * The token text.
+   */
+  content: string;
+
+  /**
+   * The token ID.
+   */
+  token: number;
+};
+
+const COMMAND_NAME = "gpt_infer_many";
+const INFERENCE_EVENT_NAME = "app://gpt/inference-many";
+
+/**
+ * Infer `n` independent sequences from the same prompt in a single batch,
+ * up to the session's `SessionOptions.maxSequences`.
+ * The inferred tokens are not committed to the session.
+ */
+export async function inferMany(
+  sessionId: string,
+  prompt: string | null,
+  numEval: number,
+  n: number,
+  inferOptions: v.InferInput<typeof CompletionOptionsSchema> = {},
+  inferenceCallback?: (event: InferenceEventPayload) => void,
+): Promise<InferredSequence[]> {
+  const inferenceCallbackEventName = inferenceCallback
+    ? `${INFERENCE_EVENT_NAME}/${sessionId}`
+    : undefined;
+
+  const unlistenInference = inferenceCallback
+    ? await listen(inferenceCallbackEventName!, (event) => {
+        inferenceCallback(event.payload as InferenceEventPayload);
+      })
+    : undefined;
+
+  try {
+    return (
+      (await invoke(COMMAND_NAME, {
+        sessionId,
+
+        // NOTE: Only send the prompt if it is truthy.
+        prompt: prompt ? prompt : undefined,
+
+        nEval: numEval,
+        n,
+        options: inferOptions,
+        inferenceCallbackEventName,
+      })) as { sequences: InferredSequence[] }
+    ).sequences;
+  } catch (e: any) {
+    throw new TauriInvokeError(e);
+  } finally {
+    unlistenInference?.();
+  }
+}
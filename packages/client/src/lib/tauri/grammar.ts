@@ -0,0 +1,43 @@
+import { invoke } from "@tauri-apps/api/core";
+
+export type GrammarInfo = {
+  /**
+   * The defined rules, in the order of definition.
+   * The line is 1-based.
+   */
+  rules: { name: string; line: number }[];
+
+  /**
+   * The rule the grammar starts with.
+   */
+  root: string;
+};
+
+export type GrammarError = {
+  message: string;
+
+  /**
+   * 1-based, null if the error concerns the whole grammar
+   * (e.g. a missing root rule).
+   */
+  line: number | null;
+
+  /**
+   * 1-based, in characters.
+   */
+  column: number | null;
+
+  /**
+   * The rule the error occurred in, if any.
+   */
+  rule: string | null;
+};
+
+/**
+ * Validate a GBNF grammar without loading a model.
+ */
+export async function validateGrammar(gbnf: string) {
+  return invoke("grammar_validate", { gbnf }) as Promise<
+    { info: GrammarInfo; error: null } | { info: null; error: GrammarError }
+  >;
+}
@@ -1,6 +1,7 @@
 #ifndef SIMULARITY_H
 #define SIMULARITY_H
 
+#include <cstddef>
 #include <cstdint>
 
 #ifdef __cplusplus
@@ -8,7 +9,7 @@ extern "C" {
 #endif // __cplusplus
 
 /**
-  Initialize the Simularity backend.
+  Initialize the Simularity backend with the default options.
   Must be called once before any other function.
 
   @param gpt_sessions_ttl The time-to-live for GPT sessions in seconds.
@@ -16,6 +17,75 @@ extern "C" {
  */
 void simularity_init(unsigned gpt_sessions_ttl, unsigned gpt_sessions_max);
 
+/**
+  A log level, matching `spdlog::level::level_enum`.
+ */
+enum simularity_log_level {
+  SIMULARITY_LOG_LEVEL_TRACE = 0,
+  SIMULARITY_LOG_LEVEL_DEBUG = 1,
+  SIMULARITY_LOG_LEVEL_INFO  = 2,
+  SIMULARITY_LOG_LEVEL_WARN  = 3,
+  SIMULARITY_LOG_LEVEL_ERROR = 4,
+  SIMULARITY_LOG_LEVEL_OFF   = 6, // Only as a filter.
+};
+
+/**
+  A log callback, called with a single message line at a time, without
+  the trailing newline. The strings are valid during the call only.
+  May be called from any thread, but not concurrently.
+
+  @param level `enum simularity_log_level`, except `OFF`.
+  @param target "simularity" for the library messages,
+    "llama.cpp" for the llama.cpp ones.
+ */
+typedef void (*simularity_log_callback)(
+    int level, const char *target, const char *message, void *user_data
+);
+
+struct simularity_init_options {
+  unsigned gpt_sessions_ttl; // in seconds, 0 = never expire
+  unsigned gpt_sessions_max; // 0 = unlimited
+
+  // A directory to resolve relative `simularity_gpt_create` state file paths
+  // against, created if missing. NULL to resolve against the working one.
+  const char *gpt_sessions_cache_dir;
+
+  int log_level; // `enum simularity_log_level`, messages below are ignored
+
+  // If set, all the messages are passed to the callback instead of
+  // being printed to stdout (the library) and stderr (llama.cpp).
+  simularity_log_callback log_callback;
+  void *log_callback_user_data;
+
+  // The number of threads for the models loaded without an explicit
+  // `simularity_model_load_options.n_threads`, 0 = the library default.
+  unsigned n_threads;
+};
+
+/**
+  Get the default init options.
+ */
+struct simularity_init_options simularity_init_options_default();
+
+/**
+  Initialize the Simularity backend. Must be called once before any other
+  function, otherwise once again to change the options.
+
+  @return 0 on success.
+  @return -1 if the cache directory could not be created.
+ */
+int simularity_init_with_options(const struct simularity_init_options *options
+);
+
+/**
+  Get the last error message set by a failed function call on the current
+  thread, e.g. a grammar parse error or a Lua traceback. The string is valid
+  until the next API call on the same thread.
+
+  @return The last error message, or an empty string if none.
+ */
+const char *simularity_last_error();
+
 /**
   Information about a model.
  */
@@ -25,11 +95,36 @@ struct simularity_model_info {
   long n_ctx_train;
 };
 
+/**
+  Model loading options.
+ */
+struct simularity_model_load_options {
+  /// Map the model file into memory, instead of reading it (default true).
+  bool use_mmap;
+
+  /// Lock the model in RAM, so that it is not swapped out.
+  bool use_mlock;
+
+  /// Only load the vocabulary, e.g. for tokenization. A vocabulary-only
+  /// model can not be used to create sessions or compute embeddings.
+  bool vocab_only;
+
+  /// Validate the tensor data while loading, failing on invalid values.
+  /// Makes the loading slower.
+  bool check_tensors;
+
+  /// The default number of threads for the sessions created with the model,
+  /// zero for `simularity_init_options.n_threads`.
+  unsigned n_threads;
+};
+
 /**
   Load a model from the given path into the model map.
 
   @param model_path Path to the model file.
   @param model_id Unique identifier for the model.
+  @param options The loading options, may be NULL for the defaults.
+    Ignored if a model with the same ID already exists.
   @param progress_callback Callback function to report progress from 0 to 1.
     If the provided progress_callback returns true, model loading continues.
     If it returns false, model loading is immediately aborted.
@@ -40,6 +135,7 @@ struct simularity_model_info {
   @return -1 if a model with the same ID already exists (sets `model_info`).
   @return -2 if there was an error loading the model (does not set
   `model_info`).
+  @return -3 if the progress callback aborted the loading.
 
   SAFETY: `simularity_model_*` functions are NOT thread-safe.
   TODO: Find an existing model by ID.
@@ -47,6 +143,7 @@ struct simularity_model_info {
 int simularity_model_load(
     const char *model_path,
     const char *model_id,
+    const struct simularity_model_load_options *options,
     bool(progress_callback)(float, void *),
     void *progress_callback_user_data,
     struct simularity_model_info *model_info
@@ -59,7 +156,9 @@ int simularity_model_load(
   @param model_id Unique identifier for the model.
 
   @return The xx64 hash of the model.
-  @return -1 if there was an error hashing the model.
+  @return -1 if the model was not found.
+  @return 0 if there was an error hashing the model
+    (see `simularity_last_error`).
  */
 uint64_t simularity_model_get_hash_by_id(const char *model_id);
 
@@ -82,6 +181,69 @@ uint64_t simularity_model_get_hash_by_path(const char *model_path);
  */
 int simularity_model_unload(const char *model_id);
 
+/**
+  Get information about a loaded model.
+
+  @param model_id The model ID.
+  @param model_info Model info struct to fill.
+
+  @return 0 on success.
+  @return -1 if the model not found.
+ */
+int simularity_model_get_info(
+    const char *model_id, struct simularity_model_info *model_info
+);
+
+/**
+  List the IDs of the loaded models.
+
+  @param model_ids The buffer to write the NUL-terminated IDs to,
+    one after another.
+  @param size The buffer size in bytes.
+
+  @return The total size of the IDs in bytes. If it is greater than `size`,
+    nothing is written.
+ */
+int simularity_model_list(char *model_ids, size_t size);
+
+/**
+  Register a LoRA adapter file. The adapter is initialized for a base model
+  upon its first use by a session of that model, see `simularity_gpt_set_loras`.
+
+  @param lora_path Path to the adapter GGUF file.
+  @param lora_id Unique identifier for the adapter.
+
+  @return 0 on success.
+  @return -1 if an adapter with the same ID is already loaded.
+  @return -2 if the file is not a valid GGUF adapter file.
+ */
+int simularity_lora_load(const char *lora_path, const char *lora_id);
+
+/**
+  Unload a LoRA adapter. The sessions using it keep it until changed.
+
+  @param lora_id The adapter ID.
+
+  @return 0 on success.
+  @return -1 if the adapter was not found.
+ */
+int simularity_lora_unload(const char *lora_id);
+
+/**
+  Validate a GBNF grammar without a model, the same way as the `grammar`
+  inference option is, see `simularity_gpt_inference_options`.
+
+  @param grammar The GBNF grammar.
+  @param error_offset Set to the byte offset of the error in the grammar,
+  or -1 if the error concerns the whole grammar. May be NULL.
+
+  @return 0 if the grammar is valid.
+  @return -1 if invalid, see `simularity_last_error`.
+
+  SAFETY: This function is thread-safe.
+ */
+int simularity_grammar_validate(const char *grammar, int64_t *error_offset);
+
 /**
   Return token length of the prompt using the given model ID.
 
@@ -96,24 +258,218 @@ int simularity_model_unload(const char *model_id);
  */
 int simularity_gpt_token_length(const char *model_id, const char *prompt);
 
+/**
+  Tokenize the text using the given model ID.
+
+  @param model_id The model ID.
+  @param text The text to tokenize.
+  @param add_special Whether to add special tokens (e.g. BOS) if the model
+    is configured to do so.
+  @param parse_special Whether to parse special tokens in the text
+    (e.g. `<|im_start|>`), otherwise they are tokenized as plain text.
+  @param tokens The buffer to write the tokens to.
+  @param n_tokens_max The buffer capacity.
+
+  @return The number of tokens. If it exceeds `n_tokens_max`, nothing is
+    written; call again with a buffer of sufficient size.
+  @return -1 if the model was not found.
+  @return -2 on tokenization error.
+
+  SAFETY: This function is thread-safe.
+ */
+int simularity_gpt_tokenize(
+    const char *model_id,
+    const char *text,
+    bool add_special,
+    bool parse_special,
+    int32_t *tokens,
+    unsigned n_tokens_max
+);
+
+/**
+  Convert tokens back to text using the given model ID.
+  Special tokens are rendered as text.
+
+  @param model_id The model ID.
+  @param tokens The tokens to detokenize.
+  @param n_tokens The number of tokens.
+  @param text The buffer to write the text to (NOT null-terminated).
+  @param text_len_max The buffer capacity in bytes.
+
+  @return The text length in bytes. If it exceeds `text_len_max`, nothing is
+    written; call again with a buffer of sufficient size.
+  @return -1 if the model was not found.
+  @return -2 on detokenization error.
+  @return -3 if a token is out of the vocabulary range.
+
+  SAFETY: This function is thread-safe.
+ */
+int simularity_gpt_detokenize(
+    const char *model_id,
+    const int32_t *tokens,
+    unsigned n_tokens,
+    char *text,
+    unsigned text_len_max
+);
+
+/**
+  Convert a single token to its text piece using the given model ID.
+  Special tokens are rendered as text.
+
+  @param model_id The model ID.
+  @param token The token.
+  @param piece The buffer to write the piece to (NOT null-terminated).
+  @param piece_len_max The buffer capacity in bytes.
+
+  @return The piece length in bytes. If it exceeds `piece_len_max`, nothing is
+    written; call again with a buffer of sufficient size.
+  @return -1 if the model was not found.
+  @return -2 on conversion error.
+  @return -3 if the token is out of the vocabulary range.
+
+  SAFETY: This function is thread-safe.
+ */
+int simularity_gpt_token_to_piece(
+    const char *model_id, int32_t token, char *piece, unsigned piece_len_max
+);
+
+/**
+  Pooling of token embeddings into a single sequence embedding,
+  matching `enum llama_pooling_type`.
+ */
+enum simularity_pooling_type {
+  SIMULARITY_POOLING_TYPE_DEFAULT = -1, // Model's default, or mean if none.
+  SIMULARITY_POOLING_TYPE_MEAN    = 1,
+  SIMULARITY_POOLING_TYPE_CLS     = 2,
+  SIMULARITY_POOLING_TYPE_LAST    = 3,
+};
+
+/**
+  Compute the embeddings of the texts using the given model ID,
+  in an embedding-mode context created for the call. The embeddings
+  are not normalized.
+
+  @param model_id The model ID.
+  @param texts The texts to embed.
+  @param n_texts The number of texts.
+  @param pooling `enum simularity_pooling_type`.
+  @param embeddings The buffer to write `n_texts * n_embd` floats to,
+    one embedding after another.
+  @param n_floats_max The buffer size in floats.
+
+  @return The embedding size (`n_embd`). If the buffer is too small,
+    nothing is computed; call again with a buffer of sufficient size.
+  @return -1 if the model was not found.
+  @return -2 if a text exceeds the model's training context size.
+  @return -3 if there was an error creating the context.
+  @return -4 on decoding error.
+  @return -5 if a text is empty or could not be tokenized.
+
+  SAFETY: This function is thread-safe.
+ */
+int simularity_gpt_embed(
+    const char *model_id,
+    const char *const *texts,
+    unsigned n_texts,
+    int pooling,
+    float *embeddings,
+    size_t n_floats_max
+);
+
+/**
+  A context shift policy. Once the context overflows, the first `n_keep`
+  tokens (e.g. a system prompt) are kept, the oldest `n_discard` tokens after
+  them are evicted, and the rest of the KV cache is shifted to fill the gap.
+ */
+struct simularity_gpt_context_shift {
+  /// The number of tokens to always keep at the beginning of the context.
+  unsigned n_keep;
+
+  /// The number of tokens to evict at once, zero for half of the tokens
+  /// after `n_keep`. Prompts are shortened by multiples of this number.
+  unsigned n_discard;
+};
+
+/**
+  A KV cache data type, matching `enum ggml_type`. A quantized cache takes
+  less memory at a slight quality cost.
+ */
+enum simularity_gpt_kv_cache_type {
+  SIMULARITY_GPT_KV_CACHE_TYPE_F16  = 1,
+  SIMULARITY_GPT_KV_CACHE_TYPE_Q4_0 = 2,
+  SIMULARITY_GPT_KV_CACHE_TYPE_Q8_0 = 8,
+};
+
+/**
+  A RoPE scaling type, matching `enum llama_rope_scaling_type`.
+ */
+enum simularity_gpt_rope_scaling_type {
+  SIMULARITY_GPT_ROPE_SCALING_TYPE_UNSPECIFIED = -1, // Model's default.
+  SIMULARITY_GPT_ROPE_SCALING_TYPE_NONE        = 0,
+  SIMULARITY_GPT_ROPE_SCALING_TYPE_LINEAR      = 1,
+  SIMULARITY_GPT_ROPE_SCALING_TYPE_YARN        = 2,
+};
+
+/**
+  Session context parameters. Those affecting the KV cache layout
+  (`n_batch`, the cache types and `flash_attn`) shall be the same
+  for a session state to be loaded.
+ */
+struct simularity_gpt_session_options {
+  unsigned n_ubatch;        // physical batch size, 0 = default
+  unsigned n_threads;       // 0 = the model default
+  unsigned n_threads_batch; // for prompt decoding, 0 = same as `n_threads`
+  int type_k;               // `enum simularity_gpt_kv_cache_type`
+  int type_v;               // ditto, quantized requires `flash_attn`
+  bool flash_attn;          // true by default
+
+  int rope_scaling_type; // `enum simularity_gpt_rope_scaling_type`
+  float rope_freq_base;  // 0 = from the model
+  float rope_freq_scale; // 0 = from the model, e.g. 0.5 for 2x context
+
+  float yarn_ext_factor;  // negative = from the model
+  float yarn_attn_factor; // magnitude scaling
+  float yarn_beta_fast;   // low correction dimension
+  float yarn_beta_slow;   // high correction dimension
+  unsigned yarn_orig_ctx; // original context size, 0 = `n_ctx_train`
+
+  // The maximum number of sequences for `simularity_gpt_infer_many`, 0 = 1.
+  unsigned n_seq_max;
+};
+
+/**
+  Get the default session options.
+ */
+simularity_gpt_session_options simularity_gpt_session_options_default();
+
 /**
   Create a new GPT session with the given model ID and initial prompt.
 
   @param model_id The model ID.
   @param context_size The context size, zero for default.
   @param batch_size The batch size, zero for default.
+  @param options The context parameters, may be NULL for the defaults.
   @param initial_prompt The initial prompt, may be NULL.
   @param state_file_path The path to a file to load the session state from
-    or save it to. May be NULL. Ignored if `initial_prompt` is NULL.
+    or save it to, relative to `simularity_init_options.gpt_sessions_cache_dir`
+    if set. May be NULL. Ignored if `initial_prompt` is NULL.
+  @param context_shift The context shift policy, may be NULL. Without it,
+    a prompt or an inference overflowing the context fails.
   @param progress_callback Callback function to report progress from 0 to 1.
+    Return false to abort the session creation.
     Ignored if `initial_prompt` is NULL.
 
   @return The session ID on success.
   @return -1 if the model was not found.
   @return -2 if the maximum number of sessions has been reached.
   @return -3 if there was an error creating the session.
-  @return -4 upon decoding error.
+  @return -4 on context overflow.
+  @return -5 if the progress callback aborted the creation.
+  @return -6 upon other decoding error.
+  @return -7 if the context shift policy does not fit into the context.
+  @return -8 if the options are invalid.
   @return <0 on other errors.
+  The session is not created on error.
 
   SAFETY: This function is threadsafe: it locks the sessions map mutex,
   and then the new session mutex.
@@ -124,8 +480,10 @@ int simularity_gpt_create(
     const char *model_id,
     unsigned context_size,
     unsigned batch_size,
+    const struct simularity_gpt_session_options *options,
     const char *initial_prompt,
     const char *state_file_path,
+    const struct simularity_gpt_context_shift *context_shift,
     bool(progress_callback)(float, void *),
     void *progress_callback_user_data
 );
@@ -143,14 +501,18 @@ bool simularity_gpt_touch(unsigned session_id);
   @param session_id The session ID.
   @param prompt The *whole* prompt to decode. The function will take care of
     reusing and/or updating the KV cache. The more the prompt mismatches
-    existing KV cache, the longer it takes to decode.
+    existing KV cache, the longer it takes to decode. If the session has
+    a context shift policy, an overflowing prompt is shortened (see
+    `simularity_gpt_session_info.n_evicted`).
   @param progress_callback Callback function to report decoding progress from 0
-    to 1.
+    to 1. Return false to abort the decoding.
 
   @returns New context length on success.
   @returns -1 when session not found.
   @returns -2 on context overflow.
-  @returns <0 on other decode error.
+  @returns -3 if the progress callback aborted the decoding.
+  @returns -4 on other decode error.
+  On error, the session keeps the successfully decoded part of the prompt.
 
   SAFETY: `simularity_gpt_*` functions are thread-safe.
  */
@@ -161,6 +523,38 @@ int simularity_gpt_decode(
     void *progress_callback_user_data
 );
 
+/**
+  Lua libraries a grammar script may use, see `simularity_gpt_lua_limits`.
+ */
+enum simularity_lua_library {
+  SIMULARITY_LUA_LIB_BASE   = 1 << 0, // w/o `[do|load]file`, `load[string]`
+  SIMULARITY_LUA_LIB_STRING = 1 << 1, // w/o `string.dump`
+  SIMULARITY_LUA_LIB_TABLE  = 1 << 2,
+  SIMULARITY_LUA_LIB_MATH   = 1 << 3,
+  SIMULARITY_LUA_LIB_CJSON  = 1 << 4, // the `cjson` global
+};
+
+/**
+  Limits for a Lua grammar script. The instruction and time limits apply
+  to each script call (the initial run, `start()` and every `on_eos()`).
+  The `debug`, `io`, `os` and `package` libraries are never available.
+ */
+struct simularity_gpt_lua_limits {
+  unsigned max_instructions; // 0 = unlimited, approximate
+  unsigned timeout_ms;       // 0 = unlimited
+  size_t max_memory;         // in bytes, 0 = unlimited
+  unsigned libraries;        // `enum simularity_lua_library` flags
+};
+
+/**
+  A logit bias, see `simularity_gpt_inference_options.logit_bias`.
+ */
+struct simularity_gpt_logit_bias {
+  int32_t token;    // the token ID, ignored if `text` is set
+  const char *text; // if not NULL, the bias applies to each of its tokens
+  float bias;       // added to the logit, -INFINITY bans the token
+};
+
 struct simularity_gpt_inference_options {
   int n_prev;      // number of previous tokens to remember
   int n_probs;     // if greater than 0, output the probabilities of top n_probs
@@ -192,6 +586,42 @@ struct simularity_gpt_inference_options {
   const char **stop_sequences;
 
   const char *lua_grammar;
+  struct simularity_gpt_lua_limits lua_limits;
+
+  const unsigned logit_bias_len;
+  const struct simularity_gpt_logit_bias *logit_bias;
+
+  // Strings not to infer, even if spanning several tokens.
+  // See `simularity_gpt_infer` for details.
+  const unsigned banned_strings_len;
+  const char **banned_strings;
+
+  // A loaded model proposing `n_draft` tokens per step for the session model
+  // to verify at once (speculative decoding). The output is the same as
+  // without a draft model. The models' vocabularies shall match.
+  const char *draft_model_id;
+  unsigned n_draft;
+};
+
+/**
+  A candidate token with its log-probability.
+ */
+struct simularity_gpt_token_logprob {
+  int32_t token;
+  const char *piece;
+  float logprob;
+};
+
+/**
+  An inferred token, passed to the inference callback.
+  The pointers are valid during the callback call only.
+ */
+struct simularity_gpt_inference_token {
+  int32_t token;       // the token ID
+  const char *piece;   // the token text
+  float logprob;       // log-probability of the token, -INFINITY if unknown
+  unsigned n_top;      // number of top candidates, up to `options.n_probs`
+  const struct simularity_gpt_token_logprob *top; // sorted by probability
 };
 
 /**
@@ -199,30 +629,76 @@ struct simularity_gpt_inference_options {
  */
 simularity_gpt_inference_options simularity_gpt_inference_options_default();
 
+/**
+  Why an inferred sequence has stopped.
+ */
+enum simularity_gpt_stop_reason {
+  SIMULARITY_GPT_STOP_REASON_N_EVAL        = 0, // `n_eval` tokens inferred
+  SIMULARITY_GPT_STOP_REASON_EOS           = 1, // end-of-generation token
+  SIMULARITY_GPT_STOP_REASON_STOP_SEQUENCE = 2, // a stop sequence matched
+  SIMULARITY_GPT_STOP_REASON_CALLBACK      = 3, // the callback returned false
+  SIMULARITY_GPT_STOP_REASON_LUA           = 4, // Lua `on_eos()` returned nil
+};
+
+/**
+  The outcome of `simularity_gpt_infer`.
+ */
+struct simularity_gpt_infer_result {
+  unsigned n_prompt;  // the decoded prompt length, in tokens
+  unsigned n_tokens;  // inferred tokens, excluding a matched stop sequence
+  unsigned n_evicted; // tokens evicted by the context shift policy
+  enum simularity_gpt_stop_reason stop_reason;
+  int stop_sequence;   // the matched `options.stop_sequences` index, or -1
+  int64_t decode_us;   // prompt decoding duration, in microseconds
+  int64_t infer_us;    // inference duration, in microseconds
+  unsigned n_drafted;  // tokens proposed by the draft model
+  unsigned n_accepted; // drafted tokens accepted by the session model
+};
+
 /**
   Inference from the given prompt.
 
   @param session_id The session ID.
   @param prompt The *whole* prompt to inference from. The function will take
     care of reusing and/or updating the KV cache. The more the prompt mismatches
-    existing KV cache, the longer it takes to decode.
+    existing KV cache, the longer it takes to decode. With a context shift
+    policy, both the prompt and the inference may evict tokens.
   @param n_eval The number of evaluations to perform.
   @param options Inference options.
   @param decode_progress_callback Callback function to report decode progress
-    from 0 to 1.
-  @param inference_callback Callback function to report inference output. Return
-    true to continue inference, false to stop.
+    from 0 to 1. Return false to abort the decoding.
+  @param inference_callback Callback function to report each inferred token,
+    along with its log-probability and top `options.n_probs` candidates.
+    Return true to continue inference, false to stop.
+  @param result If not NULL, filled with the outcome on success.
 
   @returns New context length on success.
   @returns -1 when session not found.
   @returns -2 on context overflow.
   @returns -3 on failure to initialize sampling (likely a grammar error).
-  @returns -8 on Lua script error.
+  @returns -5 if the decode progress callback aborted the decoding.
+  @returns -8 on Lua script error (including an exceeded limit),
+    the last error contains the Lua traceback.
+  @returns -9 if both the prompt and the session are empty.
+  @returns -10 if the draft model was not found.
+  @returns -11 if the draft model vocabulary does not match.
+  @returns -12 on failure to create the draft model context.
   @returns <0 on other error.
+  On error, see `simularity_last_error` for details.
 
   SAFETY: `simularity_gpt_*` functions are thread-safe.
-  NOTE: Stop sequences are NOT added to the KV cache, yet yielded in the output.
-  It's a client's responsibility to trim the output's end.
+  NOTE: The session keeps the draft model context for the next inferences,
+  until a different draft model is used.
+  NOTE: Stop sequences are matched on the inferred text, however tokenized.
+  They are NOT added to the KV cache, nor yielded to the inference callback:
+  tokens which may start a stop sequence are held back until decided,
+  and the piece of the token a stop sequence starts within is cut.
+  Use `result->n_tokens` to trim the output's end.
+  NOTE: With `options.banned_strings`, tokens which may start a banned string
+  are held back from the inference callback. Once a banned string is inferred,
+  its tokens are rolled back and its first token is banned at that position.
+  After 64 rollbacks at the same position, the banned string is let through.
+  A rollback does not cross a Lua `on_eos()` call.
  */
 int simularity_gpt_infer(
     unsigned session_id,
@@ -231,8 +707,93 @@ int simularity_gpt_infer(
     const struct simularity_gpt_inference_options options,
     bool(decode_progress_callback)(float, void *),
     void *decode_progress_callback_user_data,
-    bool(inference_callback)(const char *output, void *),
-    void *inference_callback_user_data
+    bool(inference_callback)(
+        const struct simularity_gpt_inference_token *token, void *
+    ),
+    void *inference_callback_user_data,
+    struct simularity_gpt_infer_result *result
+);
+
+/**
+  An inferred sequence result, see `simularity_gpt_infer_many`.
+ */
+struct simularity_gpt_sequence_result {
+  unsigned n_tokens; // inferred tokens, excluding a matched stop sequence
+  enum simularity_gpt_stop_reason stop_reason;
+  int stop_sequence; // the matched `options.stop_sequences` index, or -1
+};
+
+/**
+  Infer `n_seqs` independent sequences from the same prompt at once,
+  decoding the prompt only once and sampling all the sequences in a single
+  batch per step. Sequences are seeded with `options.seed + i`, unless
+  the seed is random. The inferred tokens are NOT committed to the session,
+  which keeps the decoded prompt.
+
+  @param prompt The *whole* prompt to inference from, see
+    `simularity_gpt_infer`. NULL to use the session's committed prompt.
+  @param n_eval The maximum number of tokens to infer per sequence.
+  @param n_seqs The number of sequences to infer, up to the session's
+    `simularity_gpt_session_options.n_seq_max`.
+  @param options Inference options, `lua_grammar`, `banned_strings`
+    and `draft_model_id` are not supported.
+  @param inference_callback Callback function to report each inferred token
+    of the sequence with the given index, see `simularity_gpt_infer`.
+    Return false to stop that sequence. Like with `simularity_gpt_infer`,
+    stop sequences are never yielded.
+  @param results The array of `n_seqs` results to fill.
+
+  @returns New context length on success.
+  @returns -1 when session not found.
+  @returns -2 on context overflow (the KV cache must fit all the sequences).
+  @returns -3 on failure to initialize sampling (likely a grammar error).
+  @returns -5 if the decode progress callback aborted the decoding.
+  @returns -9 if the prompt is empty, or `n_seqs` is zero or exceeds
+    the session's `n_seq_max`.
+  @returns <0 on other error.
+  On error, see `simularity_last_error` for details.
+
+  SAFETY: `simularity_gpt_*` functions are thread-safe.
+ */
+int simularity_gpt_infer_many(
+    unsigned session_id,
+    const char *prompt,
+    unsigned n_eval,
+    unsigned n_seqs,
+    const struct simularity_gpt_inference_options options,
+    bool(decode_progress_callback)(float, void *),
+    void *decode_progress_callback_user_data,
+    bool(inference_callback)(
+        unsigned seq, const struct simularity_gpt_inference_token *token, void *
+    ),
+    void *inference_callback_user_data,
+    struct simularity_gpt_sequence_result *results
+);
+
+/**
+  Set the LoRA adapters applied to the session model, replacing the current
+  ones. The base model is not reloaded. As the adapters affect the whole
+  KV cache, changing them clears it (the prompt is decoded anew then).
+
+  @param session_id The session ID.
+  @param n_loras The number of adapters, zero to remove all.
+  @param lora_ids The adapter IDs, see `simularity_lora_load`.
+  @param scales The adapter scales, 1.0 for the full effect.
+
+  @return The number of tokens cleared from the KV cache on success
+    (zero if the adapters are the same).
+  @return -1 if the session was not found.
+  @return -2 if an adapter was not found.
+  @return -3 if an adapter does not fit the session model.
+  @return -4 if the session model has been unloaded.
+
+  SAFETY: `simularity_gpt_*` functions are thread-safe.
+ */
+int simularity_gpt_set_loras(
+    unsigned session_id,
+    unsigned n_loras,
+    const char *const *lora_ids,
+    const float *scales
 );
 
 /**
@@ -245,6 +806,141 @@ int simularity_gpt_infer(
  */
 int simularity_gpt_destroy(unsigned session_id);
 
+/**
+  Information about a GPT session.
+ */
+struct simularity_gpt_session_info {
+  /// The context size.
+  unsigned n_ctx;
+
+  /// The batch size.
+  unsigned n_batch;
+
+  /// The number of committed (i.e. KV-cached) prompt tokens.
+  unsigned n_prompt;
+
+  /// When the session expires, in Unix milliseconds (0 if no TTL).
+  int64_t expired_at;
+
+  /// When the session was last used, in Unix milliseconds.
+  int64_t last_used_at;
+
+  /// The number of tokens evicted by the context shift policy during
+  /// the last decode or inference call.
+  unsigned n_evicted;
+};
+
+/**
+  Get information about a GPT session. Blocks while the session is in use.
+  Does not prolong the session.
+
+  @param session_id The session ID.
+  @param info Session info struct to fill.
+  @param model_id The buffer to write the NUL-terminated model ID to.
+  @param model_id_size The buffer size in bytes.
+
+  @return The size of the NUL-terminated model ID on success. If it is greater
+    than `model_id_size`, the model ID is not written (`info` is still filled).
+  @return -1 when session not found.
+
+  SAFETY: `simularity_gpt_*` functions are thread-safe.
+ */
+int simularity_gpt_info(
+    unsigned session_id,
+    struct simularity_gpt_session_info *info,
+    char *model_id,
+    size_t model_id_size
+);
+
+/**
+  List the IDs of the live GPT sessions, including the expired ones
+  which have not been removed yet.
+
+  @param session_ids The buffer to write the session IDs to.
+  @param size The buffer size in elements.
+
+  @return The total number of sessions, only the first `size` are written.
+
+  SAFETY: `simularity_gpt_*` functions are thread-safe.
+ */
+int simularity_gpt_list(unsigned *session_ids, size_t size);
+
+/**
+  Fork a GPT session, creating a new session with the same model and
+  context parameters, which copies the parent's KV cache and committed
+  prompt. The sessions then diverge independently.
+
+  @param session_id The parent session ID.
+
+  @return The new session ID on success.
+  @return -1 when session not found.
+  @return -2 if the maximum number of sessions has been reached.
+  @return -3 if there was an error creating the session.
+  @return -4 if failed to copy the session state.
+  The session is not created on error.
+
+  SAFETY: `simularity_gpt_*` functions are thread-safe.
+ */
+int simularity_gpt_fork(unsigned session_id);
+
+/**
+  Save the session state (KV cache and the committed prompt tokens)
+  to a file, in the llama.cpp session file format.
+
+  @return 0 on success.
+  @return -1 when session not found.
+  @return -2 if failed to save the state.
+
+  SAFETY: `simularity_gpt_*` functions are thread-safe.
+ */
+int simularity_gpt_state_save_file(unsigned session_id, const char *path);
+
+/**
+  Load the session state from a file, see `simularity_gpt_state_save_file`.
+  The state must have been saved with the same model and context parameters.
+
+  @return The number of committed prompt tokens on success.
+  @return -1 when session not found.
+  @return -2 if failed to load the state, in which case the session
+    is cleared.
+
+  SAFETY: `simularity_gpt_*` functions are thread-safe.
+ */
+int simularity_gpt_state_load_file(unsigned session_id, const char *path);
+
+/**
+  Save the session state (KV cache and the committed prompt tokens)
+  into a buffer.
+
+  @param dst The buffer, may be NULL if `size` is 0.
+  @param size The buffer size in bytes.
+
+  @return The number of bytes written on success.
+    If greater than `size`, nothing is written, and the function shall be
+    called again with a buffer of (at least) the returned size.
+  @return -1 when session not found.
+  @return -2 if failed to save the state.
+
+  SAFETY: `simularity_gpt_*` functions are thread-safe.
+ */
+int64_t
+simularity_gpt_state_save(unsigned session_id, uint8_t *dst, size_t size);
+
+/**
+  Load the session state from a buffer, see `simularity_gpt_state_save`.
+  The state must have been saved with the same model and context parameters.
+
+  @return The number of committed prompt tokens on success.
+  @return -1 when session not found.
+  @return -2 if the state is invalid, in which case the session may be
+    cleared.
+
+  SAFETY: `simularity_gpt_*` functions are thread-safe.
+ */
+int simularity_gpt_state_load(
+    unsigned session_id, const uint8_t *src, size_t size
+);
+
 #ifdef __cplusplus
 }
 #endif // __cplusplus
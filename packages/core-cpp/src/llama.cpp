@@ -1,6 +1,9 @@
 #pragma once
 
+#include <algorithm>
 #include <cstdint>
+#include <memory>
+#include <mutex>
 #include <random>
 #include <string>
 #include <unordered_map>
@@ -17,6 +20,12 @@ public:
   /// The hash of the model file, memoized.
   uint64_t xx64_hash = 0;
 
+  /// Whether only the vocabulary is loaded.
+  bool vocab_only = false;
+
+  /// The default number of threads for the model sessions, zero for default.
+  unsigned n_threads = 0;
+
   LlamaModel(const char *path, llama_model *model) : path(path), model(model) {}
 
   ~LlamaModel() { llama_free_model(model); }
@@ -25,6 +34,59 @@ public:
 std::unordered_map<std::string, std::shared_ptr<LlamaModel>> LLAMA_MODELS = {};
 std::mutex LLAMA_MODELS_MUTEX;
 
+/// The number of threads for the models loaded without one, zero for default.
+unsigned LLAMA_N_THREADS_DEFAULT = 0;
+
+/// A LoRA adapter initialized for a base model.
+class LlamaLoraAdapter {
+public:
+  /// The base model. Once it is freed, so is the adapter.
+  std::weak_ptr<LlamaModel> model;
+  struct llama_lora_adapter *adapter;
+
+  LlamaLoraAdapter(
+      std::weak_ptr<LlamaModel> model, struct llama_lora_adapter *adapter
+  )
+      : model(std::move(model)), adapter(adapter) {}
+
+  ~LlamaLoraAdapter() {
+    if (!model.expired()) llama_lora_adapter_free(adapter);
+  }
+};
+
+/// A LoRA adapter file, initialized per base model upon first use.
+class LlamaLora {
+public:
+  std::string path;
+
+  /// The adapters initialized for the base models.
+  std::vector<std::shared_ptr<LlamaLoraAdapter>> adapters;
+
+  LlamaLora(const char *path) : path(path) {}
+
+  /// Get the adapter for the `model`, initializing it if needed.
+  /// @return NULL if the adapter does not fit the model.
+  std::shared_ptr<LlamaLoraAdapter>
+  adapter_for(const std::shared_ptr<LlamaModel> &model) {
+    std::erase_if(adapters, [](auto &adapter) {
+      return adapter->model.expired();
+    });
+
+    for (auto &adapter : adapters) {
+      if (adapter->model.lock() == model) return adapter;
+    }
+
+    auto adapter = llama_lora_adapter_init(model->model, path.c_str());
+    if (adapter == NULL) return nullptr;
+
+    adapters.push_back(std::make_shared<LlamaLoraAdapter>(model, adapter));
+    return adapters.back();
+  }
+};
+
+/// Loaded LoRA adapters, guarded by `LLAMA_MODELS_MUTEX`.
+std::unordered_map<std::string, std::shared_ptr<LlamaLora>> LLAMA_LORAS = {};
+
 std::vector<llama_token> llama_tokenize(
     const llama_model *model,
     const char *text,
@@ -53,6 +115,24 @@ std::vector<llama_token> llama_tokenize(
   );
   spdlog::debug("llama_tokenize -> {}", n_tokens);
 
+  // The buffer may be too small when special tokens are added,
+  // in which case the required size is returned negated.
+  if (n_tokens < 0 && size_t(-n_tokens) > n_tokens_max) {
+    n_tokens_max = -n_tokens;
+    tokens.resize(n_tokens_max);
+
+    n_tokens = llama_tokenize(
+        model,
+        text,
+        text_len,
+        tokens.data(),
+        n_tokens_max,
+        add_special,
+        parse_special
+    );
+    spdlog::debug("llama_tokenize (retry) -> {}", n_tokens);
+  }
+
   if (n_tokens >= 0) {
     tokens.resize(n_tokens);
   } else {
@@ -72,6 +152,58 @@ std::string llama_token_to_piece(
   char buf[LLAMA_MAX_PIECE_SIZE];
   auto len =
       llama_token_to_piece(model, token, buf, LLAMA_MAX_PIECE_SIZE, 0, special);
-  if (len < 0) throw std::runtime_error("Failed to convert token to piece.");
+
+  // Some (special) pieces may be longer than the buffer,
+  // in which case the required size is returned negated.
+  if (len < 0) {
+    std::string piece(-len, '\0');
+    len = llama_token_to_piece(
+        model, token, &piece[0], piece.size(), 0, special
+    );
+    if (len < 0) throw std::runtime_error("Failed to convert token to piece.");
+    piece.resize(len);
+    return piece;
+  }
+
   return std::string(buf, len);
 }
+
+std::string llama_detokenize(
+    const struct llama_model *model,
+    const std::vector<llama_token> &tokens,
+    bool remove_special,
+    bool unparse_special
+) {
+  std::string text;
+  text.resize(std::max(text.capacity(), tokens.size()));
+
+  auto n_chars = llama_detokenize(
+      model,
+      tokens.data(),
+      tokens.size(),
+      &text[0],
+      text.size(),
+      remove_special,
+      unparse_special
+  );
+
+  // The required size is returned negated if the buffer is too small.
+  if (n_chars < 0) {
+    text.resize(-n_chars);
+
+    n_chars = llama_detokenize(
+        model,
+        tokens.data(),
+        tokens.size(),
+        &text[0],
+        text.size(),
+        remove_special,
+        unparse_special
+    );
+
+    if (n_chars < 0) throw std::runtime_error("Failed to detokenize tokens.");
+  }
+
+  text.resize(n_chars);
+  return text;
+}
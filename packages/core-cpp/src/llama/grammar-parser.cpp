@@ -384,7 +384,7 @@ static const char *parse_rule(parse_state &state, const char *src) {
   return parse_space(pos, true);
 }
 
-parse_state parse(const char *src) {
+parse_state parse(const char *src, std::string *error) {
   try {
     parse_state state;
     const char *pos = parse_space(src, true);
@@ -413,6 +413,7 @@ parse_state parse(const char *src) {
     return state;
   } catch (const std::exception &err) {
     fprintf(stderr, "%s: error parsing grammar: %s\n", __func__, err.what());
+    if (error != nullptr) *error = err.what();
     return parse_state();
   }
 }
@@ -26,6 +26,7 @@ struct parse_state {
   std::vector<const llama_grammar_element *> c_rules();
 };
 
-parse_state parse(const char *src);
+// On failure, returns an empty state and sets `error`, if provided.
+parse_state parse(const char *src, std::string *error = nullptr);
 void print_grammar(FILE *file, const parse_state &state);
 } // namespace grammar_parser
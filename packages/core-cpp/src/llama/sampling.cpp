@@ -1,3 +1,6 @@
+#include <algorithm>
+#include <cmath>
+#include <memory>
 #include <random>
 
 #include <llama.h>
@@ -22,28 +25,101 @@ public:
   int set_grammar(const char *new_grammar) {
     return llama_sampling_reset(context, new_grammar);
   }
+
+  // The state changed by `accept()`, to be restored with `restore()`.
+  struct Snapshot {
+    llama_grammar *grammar;
+    std::vector<llama_token> prev;
+    float mirostat_mu;
+
+    Snapshot(const llama_sampling_context *ctx)
+        : grammar(ctx->grammar ? llama_grammar_copy(ctx->grammar) : nullptr),
+          prev(ctx->prev), mirostat_mu(ctx->mirostat_mu) {}
+    Snapshot(const Snapshot &)            = delete;
+    Snapshot &operator=(const Snapshot &) = delete;
+    ~Snapshot() {
+      if (grammar) llama_grammar_free(grammar);
+    }
+  };
+
+  std::unique_ptr<Snapshot> snapshot() const {
+    return std::make_unique<Snapshot>(context);
+  }
+
+  void restore(const Snapshot &snapshot) {
+    if (context->grammar) llama_grammar_free(context->grammar);
+    context->grammar =
+        snapshot.grammar ? llama_grammar_copy(snapshot.grammar) : nullptr;
+    context->prev        = snapshot.prev;
+    context->mirostat_mu = snapshot.mirostat_mu;
+  }
+
+  // Get the log-probability of the sampled `token`, and fill `top` with
+  // up to `n_probs` most probable candidates (sorted by probability).
+  // Must be called right after `sample()`.
+  float logprobs(
+      llama_token token, size_t n_probs, std::vector<llama_token_data> &top
+  ) {
+    auto &cur = context->cur;
+    size_t n  = context->n_valid;
+    top.clear();
+
+    if (n == 0) {
+      // Greedy sampling does not compute the probabilities,
+      // so apply softmax to the whole vocabulary.
+      n = cur.size();
+
+      float max_logit = -INFINITY;
+      for (auto &data : cur) max_logit = std::max(max_logit, data.logit);
+
+      float sum = 0.0f;
+      for (auto &data : cur) {
+        data.p = expf(data.logit - max_logit);
+        sum += data.p;
+      }
+      for (auto &data : cur) data.p /= sum;
+
+      std::partial_sort(
+          cur.begin(),
+          cur.begin() + std::min(n_probs, n),
+          cur.end(),
+          [](const llama_token_data &a, const llama_token_data &b) {
+            return a.p > b.p;
+          }
+      );
+    }
+
+    // Otherwise, the first `n_valid` candidates are already sorted.
+    top.assign(cur.begin(), cur.begin() + std::min(n_probs, n));
+
+    for (size_t i = 0; i < n; i++) {
+      if (cur[i].id == token) return logf(cur[i].p);
+    }
+
+    return -INFINITY;
+  }
 };
 
 std::string llama_token_to_piece(
     const struct llama_model *model, const llama_token token, bool special
 );
 
+// Throws `std::runtime_error` with a descriptive message on parse errors.
 std::pair<grammar_parser::parse_state, llama_grammar *> *
 parse_grammar(const char *grammar_str) {
   spdlog::debug("Parsing grammar: {}", grammar_str);
-  auto parsed_grammar = grammar_parser::parse(grammar_str);
+  std::string error;
+  auto parsed_grammar = grammar_parser::parse(grammar_str, &error);
 
   // will be empty (default) if there are parse errors
   if (parsed_grammar.rules.empty()) {
-    spdlog::warn("Failed to parse grammar");
-    return nullptr;
+    throw std::runtime_error("Failed to parse grammar: " + error);
   }
 
   // Ensure that there is a "root" node.
   if (parsed_grammar.symbol_ids.find("root") ==
       parsed_grammar.symbol_ids.end()) {
-    spdlog::warn("Grammar does not contain a 'root' symbol");
-    return nullptr;
+    throw std::runtime_error("Grammar does not contain a 'root' symbol");
   }
 
   std::vector<const llama_grammar_element *> grammar_rules(
@@ -74,11 +150,13 @@ llama_sampling_init(const struct llama_sampling_params &params) {
 
   // if there is a grammar, parse it
   if (!params.grammar.empty()) {
-    auto parse_result = parse_grammar(params.grammar.c_str());
+    std::pair<grammar_parser::parse_state, llama_grammar *> *parse_result;
 
-    if (parse_result == nullptr) {
+    try {
+      parse_result = parse_grammar(params.grammar.c_str());
+    } catch (...) {
       delete result;
-      return nullptr;
+      throw;
     }
 
     result->parsed_grammar = parse_result->first;
@@ -113,10 +191,6 @@ int llama_sampling_reset(
   if (new_grammar_str) {
     auto parse_result = parse_grammar(new_grammar_str);
 
-    if (parse_result == nullptr) {
-      return -1;
-    }
-
     ctx->parsed_grammar = parse_result->first;
     ctx->grammar        = parse_result->second;
   } else if (!ctx->parsed_grammar.rules.empty()) {
@@ -93,6 +93,7 @@ struct llama_sampling_context {
 };
 
 // Create a new sampling context instance.
+// Throws `std::runtime_error` if the grammar fails to parse.
 struct llama_sampling_context *
 llama_sampling_init(const struct llama_sampling_params &params);
 
@@ -102,6 +103,7 @@ void llama_sampling_free(struct llama_sampling_context *ctx);
 // - clear prev tokens
 // - reset grammar
 // - set grammar to new_grammar_str, if provided
+//   (throws `std::runtime_error` if it fails to parse)
 int llama_sampling_reset(
     llama_sampling_context *ctx, const char *new_grammar_str
 );
@@ -1,3 +1,4 @@
+#include <cstring>
 #include <filesystem>
 
 #include <llama.h>
@@ -6,32 +7,95 @@
 #include <spdlog/spdlog.h>
 
 #include "./gguf-hash.cpp"
+#include "./simularity/error.cpp"
 #include "./simularity/gpt.cpp"
+#include "./simularity/grammar.cpp"
+#include "./simularity/log.cpp"
+
+extern "C" struct simularity_init_options simularity_init_options_default() {
+  return simularity_init_options{
+      .gpt_sessions_ttl       = 0,
+      .gpt_sessions_max       = 0,
+      .gpt_sessions_cache_dir = nullptr,
+      .log_level              = SIMULARITY_LOG_LEVEL_DEBUG,
+      .log_callback           = nullptr,
+      .log_callback_user_data = nullptr,
+      .n_threads              = 0,
+  };
+}
 
 extern "C" void
 simularity_init(unsigned gpt_sessions_ttl, unsigned gpt_sessions_max) {
-  simularity_gpt_init(gpt_sessions_ttl, gpt_sessions_max);
+  auto options             = simularity_init_options_default();
+  options.gpt_sessions_ttl = gpt_sessions_ttl;
+  options.gpt_sessions_max = gpt_sessions_max;
+  simularity_init_with_options(&options);
+}
+
+extern "C" int
+simularity_init_with_options(const struct simularity_init_options *options) {
+  clear_last_error();
+
+  init_logging(
+      options->log_level,
+      options->log_callback,
+      options->log_callback_user_data
+  );
+
+  if (options->gpt_sessions_cache_dir != NULL) {
+    std::error_code err;
+    std::filesystem::create_directories(options->gpt_sessions_cache_dir, err);
+
+    if (err) {
+      set_last_error(
+          "Failed to create the cache directory {}: {}",
+          options->gpt_sessions_cache_dir,
+          err.message()
+      );
+      return -1;
+    }
+
+    GPT_SESSIONS_CACHE_DIR = options->gpt_sessions_cache_dir;
+  } else {
+    GPT_SESSIONS_CACHE_DIR.clear();
+  }
+
+  simularity_gpt_init(options->gpt_sessions_ttl, options->gpt_sessions_max);
+  LLAMA_N_THREADS_DEFAULT = options->n_threads;
   llama_backend_init();
-  spdlog::set_level(spdlog::level::debug);
-  spdlog::set_pattern("[%H:%M:%S.%e] [libsimularity] [%^%l%$] %v");
-  spdlog::info("Initialized");
+
+  spdlog::info(
+      "Initialized (gpt_sessions_ttl: {}, gpt_sessions_max: {}, "
+      "gpt_sessions_cache_dir: {}, n_threads: {})",
+      options->gpt_sessions_ttl,
+      options->gpt_sessions_max,
+      options->gpt_sessions_cache_dir ? options->gpt_sessions_cache_dir
+                                      : "<None>",
+      options->n_threads
+  );
+
+  return 0;
 }
 
 extern "C" int simularity_model_load(
     const char *model_path,
     const char *model_id,
+    const struct simularity_model_load_options *options,
     llama_progress_callback progress_callback,
     void *progress_callback_user_data,
     struct simularity_model_info *model_info
 ) {
   spdlog::debug(
-      "simularity_model_load(model_path: {}, model_id: {}, "
+      "simularity_model_load(model_path: {}, model_id: {}, options: {}, "
       "progress_callback: {})",
       model_path,
       model_id,
+      options ? "<Some>" : "<None>",
       progress_callback ? "<Some>" : "<None>"
   );
 
+  clear_last_error();
+
   // Acquire the models mutex lock.
   spdlog::debug("Acquiring models lock");
   std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
@@ -47,22 +111,52 @@ extern "C" int simularity_model_load(
     return -1; // Model with the same ID already exists.
   }
 
+  // Wrap the progress callback to know whether the loading was aborted.
+  struct progress_callback_wrapper_data {
+    llama_progress_callback callback;
+    void *user_data;
+    bool aborted;
+  } wrapper_data = {progress_callback, progress_callback_user_data, false};
+
   // Params for the model.
-  llama_model_params params          = llama_model_default_params();
-  params.progress_callback           = progress_callback;
-  params.progress_callback_user_data = progress_callback_user_data;
-  params.n_gpu_layers                = 9999; // Always offload to GPU.
+  llama_model_params params = llama_model_default_params();
+  params.n_gpu_layers       = 9999; // Always offload to GPU.
+
+  if (options != NULL) {
+    params.use_mmap      = options->use_mmap;
+    params.use_mlock     = options->use_mlock;
+    params.vocab_only    = options->vocab_only;
+    params.check_tensors = options->check_tensors;
+  }
+
+  if (progress_callback != NULL) {
+    params.progress_callback = [](float progress, void *user_data) -> bool {
+      auto data     = static_cast<progress_callback_wrapper_data *>(user_data);
+      data->aborted = !data->callback(progress, data->user_data);
+      return !data->aborted;
+    };
+    params.progress_callback_user_data = &wrapper_data;
+  }
 
   // Load the model.
   struct llama_model *model = llama_load_model_from_file(model_path, params);
   if (model == NULL) {
+    if (wrapper_data.aborted) {
+      set_last_error("Model loading aborted by the progress callback");
+      return -3; // Loading aborted.
+    }
+
+    set_last_error("Failed to load model from file: {}", model_path);
     return -2; // Error loading the model.
   }
 
   // Add the model to the list.
-  LLAMA_MODELS.insert(
-      {model_id, std::make_shared<LlamaModel>(model_path, model)}
-  );
+  auto loaded        = std::make_shared<LlamaModel>(model_path, model);
+  loaded->vocab_only = params.vocab_only;
+  loaded->n_threads  = options != NULL && options->n_threads > 0
+                           ? options->n_threads
+                           : LLAMA_N_THREADS_DEFAULT;
+  LLAMA_MODELS.insert({model_id, loaded});
 
   model_info->n_params    = llama_model_n_params(model);
   model_info->size        = llama_model_size(model);
@@ -82,13 +176,15 @@ extern "C" int simularity_model_load(
 extern "C" uint64_t simularity_model_get_hash_by_id(const char *model_id) {
   spdlog::debug("simularity_model_hash(model_id: {})", model_id);
 
+  clear_last_error();
+
   // Acquire the models mutex lock.
   spdlog::debug("Acquiring models lock");
   std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
 
   // Check if the model exists.
   if (LLAMA_MODELS.find(model_id) == LLAMA_MODELS.end()) {
-    spdlog::warn("Model does not exist: {}", model_id);
+    set_last_error("Model does not exist: {}", model_id);
     return -1; // Model does not exist.
   }
 
@@ -101,25 +197,41 @@ extern "C" uint64_t simularity_model_get_hash_by_id(const char *model_id) {
     models_lock.unlock(); // Release the lock before heavy computation.
     auto hash = gguf_hash_xx64(model->path.c_str());
     spdlog::debug("Hashed model: {} -> {}", model->path, hash);
-    if (hash > 0) model->xx64_hash = hash;
+
+    if (hash == (uint64_t)-1) {
+      set_last_error("Failed to hash model file: {}", model->path);
+      return 0; // Error hashing the model.
+    }
+
+    model->xx64_hash = hash;
     return hash;
   }
 }
 
 extern "C" uint64_t simularity_model_get_hash_by_path(const char *model_path) {
   spdlog::debug("simularity_model_hash(model_path: {})", model_path);
-  return gguf_hash_xx64(model_path);
+  clear_last_error();
+
+  auto hash = gguf_hash_xx64(model_path);
+  if (hash == (uint64_t)-1) {
+    set_last_error("Failed to hash model file: {}", model_path);
+  }
+
+  return hash;
 }
 
 extern "C" int simularity_model_unload(const char *model_id) {
   spdlog::debug("simularity_model_unload(model_id: {})", model_id);
 
+  clear_last_error();
+
   // Acquire the models mutex lock.
   spdlog::debug("Acquiring models lock");
   std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
 
   // Check if the model exists.
   if (LLAMA_MODELS.find(model_id) == LLAMA_MODELS.end()) {
+    set_last_error("Model does not exist: {}", model_id);
     return -1; // Model does not exist.
   }
 
@@ -128,3 +240,101 @@ extern "C" int simularity_model_unload(const char *model_id) {
 
   return 0;
 }
+
+extern "C" int simularity_model_get_info(
+    const char *model_id, struct simularity_model_info *model_info
+) {
+  spdlog::debug("simularity_model_get_info(model_id: {})", model_id);
+  clear_last_error();
+
+  std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+
+  auto it = LLAMA_MODELS.find(model_id);
+  if (it == LLAMA_MODELS.end()) {
+    set_last_error("Model does not exist: {}", model_id);
+    return -1; // Model does not exist.
+  }
+
+  model_info->n_params    = llama_model_n_params(it->second->model);
+  model_info->size        = llama_model_size(it->second->model);
+  model_info->n_ctx_train = llama_n_ctx_train(it->second->model);
+
+  return 0;
+}
+
+extern "C" int simularity_model_list(char *model_ids, size_t size) {
+  clear_last_error();
+
+  std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+
+  size_t needed = 0;
+  for (auto &[id, _] : LLAMA_MODELS) {
+    needed += id.size() + 1;
+  }
+
+  if (size >= needed) {
+    size_t offset = 0;
+    for (auto &[id, _] : LLAMA_MODELS) {
+      std::memcpy(model_ids + offset, id.c_str(), id.size() + 1);
+      offset += id.size() + 1;
+    }
+  }
+
+  return needed;
+}
+
+extern "C" int
+simularity_lora_load(const char *lora_path, const char *lora_id) {
+  spdlog::debug(
+      "simularity_lora_load(lora_path: {}, lora_id: {})", lora_path, lora_id
+  );
+  clear_last_error();
+
+  std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+
+  if (LLAMA_LORAS.find(lora_id) != LLAMA_LORAS.end()) {
+    return -1; // Adapter with the same ID already exists.
+  }
+
+  // Only read the metadata, the tensors are loaded per base model.
+  struct gguf_init_params params = {
+      /*.no_alloc = */ true,
+      /*.ctx      = */ NULL,
+  };
+
+  struct gguf_context *ctx = gguf_init_from_file(lora_path, params);
+  if (ctx == NULL) {
+    set_last_error("Failed to read GGUF file: {}", lora_path);
+    return -2;
+  }
+
+  auto key_id = gguf_find_key(ctx, "general.type");
+  bool is_adapter =
+      key_id < 0 ||
+      std::strcmp(gguf_get_val_str(ctx, key_id), "adapter") == 0;
+  gguf_free(ctx);
+
+  if (!is_adapter) {
+    set_last_error("Not a GGUF adapter file: {}", lora_path);
+    return -2;
+  }
+
+  LLAMA_LORAS.insert({lora_id, std::make_shared<LlamaLora>(lora_path)});
+  spdlog::info("LoRA adapter loaded: {} ({})", lora_id, lora_path);
+
+  return 0;
+}
+
+extern "C" int simularity_lora_unload(const char *lora_id) {
+  spdlog::debug("simularity_lora_unload(lora_id: {})", lora_id);
+  clear_last_error();
+
+  std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+
+  if (LLAMA_LORAS.erase(lora_id) == 0) {
+    set_last_error("LoRA adapter does not exist: {}", lora_id);
+    return -1;
+  }
+
+  return 0;
+}
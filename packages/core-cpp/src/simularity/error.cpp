@@ -0,0 +1,25 @@
+#pragma once
+
+#include <string>
+#include <utility>
+
+#include <spdlog/spdlog.h>
+
+/// The last error message, per thread. See `simularity_last_error`.
+static thread_local std::string SIMULARITY_LAST_ERROR;
+
+/// Log an error message and remember it as the thread's last error.
+template <typename... Args>
+static void
+set_last_error(spdlog::format_string_t<Args...> fmt, Args &&...args) {
+  SIMULARITY_LAST_ERROR = fmt::format(fmt, std::forward<Args>(args)...);
+  spdlog::error(SIMULARITY_LAST_ERROR);
+}
+
+/// Clear the thread's last error message.
+/// Called at the beginning of every fallible API function.
+static void clear_last_error() { SIMULARITY_LAST_ERROR.clear(); }
+
+extern "C" const char *simularity_last_error() {
+  return SIMULARITY_LAST_ERROR.c_str();
+}
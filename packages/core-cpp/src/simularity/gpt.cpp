@@ -1,8 +1,15 @@
 #include "./gpt/create.cpp"
 #include "./gpt/decode.cpp"
 #include "./gpt/destroy.cpp"
+#include "./gpt/embed.cpp"
+#include "./gpt/fork.cpp"
+#include "./gpt/info.cpp"
 #include "./gpt/infer.cpp"
+#include "./gpt/infer_many.cpp"
+#include "./gpt/loras.cpp"
+#include "./gpt/state.cpp"
 #include "./gpt/token_length.cpp"
+#include "./gpt/tokenize.cpp"
 
 void simularity_gpt_init(unsigned gpt_sessions_ttl, unsigned gpt_sessions_max) {
   GPT_SESSIONS_TTL = gpt_sessions_ttl;
@@ -1,10 +1,14 @@
 #pragma once
 
+#include <algorithm>
+#include <atomic>
 #include <cstddef>
+#include <filesystem>
 #include <functional>
 #include <memory>
 #include <mutex>
 #include <optional>
+#include <string>
 #include <utility>
 #include <vector>
 
@@ -12,29 +16,85 @@
 #include <simularity.h>
 
 #include "../../llama.cpp"
+#include "../error.cpp"
+#include "draft.cpp"
 #include "spdlog/spdlog.h"
 
 unsigned GPT_SESSIONS_TTL;
 unsigned GPT_SESSIONS_MAX;
 
+/// The directory to resolve relative state file paths against, if not empty.
+std::string GPT_SESSIONS_CACHE_DIR;
+
+/// Resolve a relative state file path against `GPT_SESSIONS_CACHE_DIR`.
+static std::string resolve_state_file_path(const char *path) {
+  if (GPT_SESSIONS_CACHE_DIR.empty() ||
+      !std::filesystem::path(path).is_relative()) {
+    return path;
+  }
+
+  return (std::filesystem::path(GPT_SESSIONS_CACHE_DIR) / path).string();
+}
+
 class Session {
 public:
-  Session(struct llama_context *ctx) : context(ctx) { this->touch(); }
+  Session(struct llama_context *ctx, std::string model_id)
+      : context(ctx), model_id(std::move(model_id)) {
+    this->touch();
+    this->mark_used();
+
+    // Abort the graph computation once requested (CPU backend only,
+    // otherwise the abortion takes effect after the current batch).
+    llama_set_abort_callback(
+        ctx,
+        [](void *data) -> bool {
+          return static_cast<Session *>(data)->decode_aborted;
+        },
+        this
+    );
+  }
+
   ~Session() { llama_free(this->context); }
 
   struct llama_context *context;
   std::mutex mutex;
 
+  /// The ID of the model the session was created with.
+  const std::string model_id;
+
   /// The committed (i.e. KV-cached) prompt tokens.
   std::vector<llama_token> prompt = {};
 
   /// When the session expires.
   std::chrono::time_point<std::chrono::system_clock> expired_at;
 
+  /// When the session was last locked for an operation.
+  std::chrono::time_point<std::chrono::system_clock> last_used_at;
+
+  /// The context shift policy, if any.
+  std::optional<simularity_gpt_context_shift> context_shift;
+
+  /// The context parameters, with the model default thread count applied.
+  simularity_gpt_session_options options =
+      simularity_gpt_session_options_default();
+
+  /// The number of tokens evicted during the current (or the last) call.
+  unsigned n_evicted = 0;
+
+  /// The draft context of the last speculative inference, if any.
+  std::unique_ptr<DraftContext> draft;
+
+  /// The LoRA adapters applied to the context, with their scales.
+  std::vector<std::pair<std::shared_ptr<LlamaLoraAdapter>, float>> loras;
+
   // Decode progress callback (used internally to connect llama's
   // `cb_eval` with user-defined callbacks). See
   // `llama_universal_cb_eval` in `./create.cpp`.
-  std::function<void()> decode_progress_callback;
+  // Returns false to abort the decoding.
+  std::function<bool()> decode_progress_callback;
+
+  /// Set when the decode progress callback returns false.
+  std::atomic<bool> decode_aborted = false;
 
   // Prolong the session expiration time by `GPT_SESSIONS_TTL` seconds.
   void touch() {
@@ -44,15 +104,55 @@ public:
     }
   }
 
+  void mark_used() { last_used_at = std::chrono::system_clock::now(); }
+
   // Clear KV cache. Does not affect the `prompt`.
   bool clear_cache(int p0 = -1, int p1 = -1) {
     return llama_kv_cache_seq_rm(this->context, 0, p0, p1);
   }
 
   const llama_model *model() { return llama_get_model(this->context); }
+
+  /// The number of tokens to evict at once upon a context shift.
+  unsigned shift_chunk() {
+    if (context_shift->n_discard) return context_shift->n_discard;
+    return (llama_n_ctx(this->context) - context_shift->n_keep) / 2;
+  }
+
+  /**
+    Evict up to `n_discard` tokens after the kept ones, both from the KV cache
+    and the `prompt`, shifting the following tokens' positions.
+    Does not affect `n_evicted`.
+
+    @return The number of tokens evicted.
+   */
+  unsigned shift_context(unsigned n_discard) {
+    const unsigned n_keep = context_shift->n_keep;
+    const unsigned n_past = prompt.size();
+    n_discard             = std::min(n_discard, n_past - n_keep);
+
+    spdlog::info(
+        "Shifting context: n_keep = {}, n_discard = {}, n_past = {}",
+        n_keep,
+        n_discard,
+        n_past
+    );
+
+    llama_kv_cache_seq_rm(this->context, 0, n_keep, n_keep + n_discard);
+    llama_kv_cache_seq_add(
+        this->context, 0, n_keep + n_discard, -1, -(int)n_discard
+    );
+
+    prompt.erase(
+        prompt.begin() + n_keep, prompt.begin() + n_keep + n_discard
+    );
+
+    return n_discard;
+  }
 };
 
-/// A single-sequence `llama_batch` wrapper with a destructor.
+/// A `llama_batch` wrapper with a destructor,
+/// where each token belongs to a single sequence.
 class Batch {
 public:
   struct llama_batch batch;
@@ -61,11 +161,11 @@ public:
 
   /// Add a token to the batch.
   /// @returns The new number of tokens in the batch.
-  int add(llama_token id, llama_pos pos, bool logits) {
+  int add(llama_token id, llama_pos pos, bool logits, llama_seq_id seq = 0) {
     batch.token[batch.n_tokens]     = id;
     batch.pos[batch.n_tokens]       = pos;
     batch.n_seq_id[batch.n_tokens]  = 1;
-    batch.seq_id[batch.n_tokens][0] = 0;
+    batch.seq_id[batch.n_tokens][0] = seq;
     batch.logits[batch.n_tokens]    = logits;
     return ++batch.n_tokens;
   }
@@ -80,21 +180,23 @@ static std::mutex GPT_SESSIONS_MUTEX;
 /**
   Lock a session by ID.
 
+  @param mark_used Whether to update the session's last-used time.
+
   @return A pair of session mutex lock and session pointer, or `std::nullopt`
     if the session does not exist.
 
   SAFETY: The sessions mutex is implicitly acquired.
  */
 static std::optional<std::pair<std::unique_lock<std::mutex>, Session *>>
-try_locking_session(unsigned session_id) {
+try_locking_session(unsigned session_id, bool mark_used = true) {
   std::unique_lock sessions_lock(GPT_SESSIONS_MUTEX);
   auto it = GPT_SESSIONS.find(session_id);
   if (it == GPT_SESSIONS.end()) {
     return std::nullopt;
   } else {
-    return std::make_pair(
-        std::unique_lock(it->second->mutex), it->second.get()
-    );
+    std::unique_lock session_lock(it->second->mutex);
+    if (mark_used) it->second->mark_used();
+    return std::make_pair(std::move(session_lock), it->second.get());
   }
 }
 
@@ -108,7 +210,8 @@ try_locking_session(unsigned session_id) {
   batches.
   @param progress_callback The progress callback.
 
-  @return The result of the `llama_decode` call.
+  @return The result of the `llama_decode` call. Check
+    `session->decode_aborted` to see if the decoding has been aborted.
  */
 static int decode_with_progress(
     Session *session,
@@ -139,7 +242,7 @@ static int decode_with_progress(
                                          batch_index,
                                          n_batches,
                                          progress_callback_user_data]() {
-      progress_callback(
+      return progress_callback(
           (float)batch_index / n_batches +
               ((float)++current_call / max_calls) / n_batches,
           progress_callback_user_data
@@ -150,6 +253,7 @@ static int decode_with_progress(
   }
 
   // Decode the batch.
+  session->decode_aborted = false;
   auto start = std::chrono::high_resolution_clock::now();
   int result = llama_decode(session->context, batch);
   auto end   = std::chrono::high_resolution_clock::now();
@@ -4,6 +4,8 @@
 #include <filesystem>
 #include <locale>
 #include <memory>
+#include <optional>
+#include <string>
 #include <utility>
 #include <vector>
 
@@ -17,34 +19,216 @@ static bool llama_universal_cb_eval(ggml_tensor *, bool, void *user_data) {
   // Cast the user data to unsigned, this is the session ID.
   unsigned session_id = *static_cast<unsigned *>(user_data);
 
-  if (GPT_SESSIONS[session_id]->decode_progress_callback) {
-    GPT_SESSIONS[session_id]->decode_progress_callback();
+  auto &session = GPT_SESSIONS[session_id];
+
+  // Stop reporting the progress once aborted.
+  if (session->decode_progress_callback && !session->decode_aborted &&
+      !session->decode_progress_callback()) {
+    session->decode_aborted = true;
   }
 
   return false; // See https://github.com/ggerganov/llama.cpp/discussions/8051.
 }
 
+/**
+  Make room for a new session. If the maximum number of sessions has been
+  reached, remove the first expired session.
+
+  @return false if there is no room (sets the last error).
+
+  SAFETY: `GPT_SESSIONS_MUTEX` must be held.
+ */
+static bool reserve_session_slot() {
+  // Check if the maximum number of sessions has been reached.
+  if (GPT_SESSIONS_MAX > 0 && GPT_SESSIONS.size() >= GPT_SESSIONS_MAX) {
+    // If there is no TTL, it means that no new sessions
+    // can be created until one is removed.
+    if (!GPT_SESSIONS_TTL) {
+      set_last_error(
+          "Maximum number of sessions reached: {}", GPT_SESSIONS_MAX
+      );
+      return false;
+    }
+
+    // Find the first expired session and remove it.
+    // OPTIMIZE: Use a priority queue to store the sessions ordered by
+    // expiration time.
+    bool found = false;
+    for (auto it = GPT_SESSIONS.begin(); it != GPT_SESSIONS.end(); ++it) {
+      if (it->second->expired_at < std::chrono::system_clock::now()) {
+        GPT_SESSIONS.erase(it);
+        found = true;
+        break;
+      }
+    }
+
+    if (!found) {
+      set_last_error(
+          "Maximum number of sessions reached, none expired: {}",
+          GPT_SESSIONS_MAX
+      );
+      return false;
+    }
+  }
+
+  return true;
+}
+
+simularity_gpt_session_options simularity_gpt_session_options_default() {
+  return simularity_gpt_session_options{
+      .n_ubatch          = 0,
+      .n_threads         = 0,
+      .n_threads_batch   = 0,
+      .type_k            = SIMULARITY_GPT_KV_CACHE_TYPE_F16,
+      .type_v            = SIMULARITY_GPT_KV_CACHE_TYPE_F16,
+      .flash_attn        = true,
+      .rope_scaling_type = SIMULARITY_GPT_ROPE_SCALING_TYPE_UNSPECIFIED,
+      .rope_freq_base    = 0.0f,
+      .rope_freq_scale   = 0.0f,
+      .yarn_ext_factor   = -1.0f,
+      .yarn_attn_factor  = 1.0f,
+      .yarn_beta_fast    = 32.0f,
+      .yarn_beta_slow    = 1.0f,
+      .yarn_orig_ctx     = 0,
+      .n_seq_max         = 1,
+  };
+}
+
+static bool is_kv_cache_type(int type) {
+  return type == SIMULARITY_GPT_KV_CACHE_TYPE_F16 ||
+         type == SIMULARITY_GPT_KV_CACHE_TYPE_Q4_0 ||
+         type == SIMULARITY_GPT_KV_CACHE_TYPE_Q8_0;
+}
+
+/// Validate the session options.
+/// @return A description of the first invalid option, if any.
+static std::optional<std::string>
+validate_session_options(const simularity_gpt_session_options &options) {
+  if (!is_kv_cache_type(options.type_k)) {
+    return fmt::format("Invalid K cache type: {}", options.type_k);
+  }
+
+  if (!is_kv_cache_type(options.type_v)) {
+    return fmt::format("Invalid V cache type: {}", options.type_v);
+  }
+
+  // NOTE: llama.cpp only supports a quantized V cache with flash attention.
+  if (options.type_v != SIMULARITY_GPT_KV_CACHE_TYPE_F16 &&
+      !options.flash_attn) {
+    return "A quantized V cache requires flash attention";
+  }
+
+  if (options.rope_scaling_type <
+          SIMULARITY_GPT_ROPE_SCALING_TYPE_UNSPECIFIED ||
+      options.rope_scaling_type > SIMULARITY_GPT_ROPE_SCALING_TYPE_YARN) {
+    return fmt::format(
+        "Invalid RoPE scaling type: {}", options.rope_scaling_type
+    );
+  }
+
+  if (options.rope_freq_base < 0 || options.rope_freq_scale < 0) {
+    return "RoPE frequency base and scale must not be negative";
+  }
+
+  return std::nullopt;
+}
+
+/**
+  Create a llama context for a new session.
+
+  @param n_ctx The context size, zero for default.
+  @param n_batch The batch size, zero for default.
+  @param options The validated context parameters.
+
+  @return The context, or NULL on error.
+ */
+static struct llama_context *new_session_context(
+    struct llama_model *model,
+    unsigned session_id,
+    unsigned n_ctx,
+    unsigned n_batch,
+    const simularity_gpt_session_options &options
+) {
+  llama_context_params params = llama_context_default_params();
+  params.n_ctx                = n_ctx;
+  if (n_batch > 0) params.n_batch = n_batch; // NOTE: Affects state loading.
+  if (options.n_ubatch > 0) params.n_ubatch = options.n_ubatch;
+
+  if (options.n_threads > 0) {
+    params.n_threads       = options.n_threads;
+    params.n_threads_batch = options.n_threads;
+  }
+
+  if (options.n_threads_batch > 0) {
+    params.n_threads_batch = options.n_threads_batch;
+  }
+
+  params.cb_eval           = llama_universal_cb_eval;
+  // Cast the session ID to void * and pass it as user data.
+  params.cb_eval_user_data = static_cast<void *>(new unsigned(session_id));
+
+  // NOTE: These affect state loading.
+  params.type_k     = static_cast<enum ggml_type>(options.type_k);
+  params.type_v     = static_cast<enum ggml_type>(options.type_v);
+  params.flash_attn = options.flash_attn;
+
+  params.rope_scaling_type =
+      static_cast<enum llama_rope_scaling_type>(options.rope_scaling_type);
+  params.rope_freq_base   = options.rope_freq_base;
+  params.rope_freq_scale  = options.rope_freq_scale;
+  params.yarn_ext_factor  = options.yarn_ext_factor;
+  params.yarn_attn_factor = options.yarn_attn_factor;
+  params.yarn_beta_fast   = options.yarn_beta_fast;
+  params.yarn_beta_slow   = options.yarn_beta_slow;
+  params.yarn_orig_ctx    = options.yarn_orig_ctx;
+
+  // NOTE: `simularity_gpt_infer_many` uses sequences 0..n_seq_max.
+  if (options.n_seq_max > 0) params.n_seq_max = options.n_seq_max;
+
+  return llama_new_context_with_model(model, params);
+}
+
 int simularity_gpt_create(
     const char *model_id,
     unsigned n_ctx,
     unsigned n_batch,
+    const struct simularity_gpt_session_options *options,
     const char *initial_prompt,
     const char *state_file_path,
+    const struct simularity_gpt_context_shift *context_shift,
     llama_progress_callback progress_callback,
     void *progress_callback_user_data
 ) {
   spdlog::debug(
       "simularity_gpt_create(model_id: {}, n_ctx: {}, n_batch: {}, "
-      "initial_prompt: {}, "
-      "state_file_path: {}, progress_callback: {})",
+      "options: {}, initial_prompt: {}, "
+      "state_file_path: {}, context_shift: {}, progress_callback: {})",
       model_id,
       n_ctx,
       n_batch,
+      options ? "<Some>" : "<None>",
       initial_prompt ? "<Some>" : "<None>",
       state_file_path ? state_file_path : "<None>",
+      context_shift ? "<Some>" : "<None>",
       progress_callback ? "<Some>" : "<None>"
   );
 
+  clear_last_error();
+
+  std::string resolved_state_file_path;
+  if (state_file_path != NULL) {
+    resolved_state_file_path = resolve_state_file_path(state_file_path);
+    state_file_path          = resolved_state_file_path.c_str();
+  }
+
+  auto session_options =
+      options ? *options : simularity_gpt_session_options_default();
+
+  if (auto err = validate_session_options(session_options)) {
+    set_last_error("{}", *err);
+    return -8; // Invalid options.
+  }
+
   // Acquire the models mutex.
   spdlog::debug("Acquiring models lock");
   std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
@@ -52,70 +236,48 @@ int simularity_gpt_create(
   // Check if the model exists.
   spdlog::debug("Checking model: {}", model_id);
   if (LLAMA_MODELS.find(model_id) == LLAMA_MODELS.end()) {
-    spdlog::error("Model does not exist: {}", model_id);
+    set_last_error("Model does not exist: {}", model_id);
     return -1; // Model does not exist.
   }
   spdlog::info("Model exists: {}", model_id);
 
-  // Acquire the GPT session mutex.
-  std::unique_lock sessions_lock(GPT_SESSIONS_MUTEX);
+  auto model = LLAMA_MODELS[model_id];
+  if (model->vocab_only) {
+    set_last_error("Model is loaded vocabulary-only: {}", model_id);
+    return -3; // Error creating the session.
+  }
 
-  // Check if the maximum number of sessions has been reached.
-  if (GPT_SESSIONS_MAX > 0 && GPT_SESSIONS.size() >= GPT_SESSIONS_MAX) {
-    // If there is no TTL, it means that no new sessions
-    // can be created until one is removed.
-    if (!GPT_SESSIONS_TTL) {
-      return -2; // Maximum number of sessions reached.
-    }
+  if (session_options.n_threads == 0) {
+    session_options.n_threads = model->n_threads;
+  }
 
-    // Find the first expired session and remove it.
-    // OPTIMIZE: Use a priority queue to store the sessions ordered by
-    // expiration time.
-    bool found = false;
-    for (auto it = GPT_SESSIONS.begin(); it != GPT_SESSIONS.end(); ++it) {
-      if (it->second->expired_at < std::chrono::system_clock::now()) {
-        GPT_SESSIONS.erase(it);
-        found = true;
-        break;
-      }
-    }
+  // Acquire the GPT session mutex.
+  std::unique_lock sessions_lock(GPT_SESSIONS_MUTEX);
 
-    if (!found) {
-      return -2; // Maximum number of sessions reached.
-    }
+  if (!reserve_session_slot()) {
+    return -2; // Maximum number of sessions reached.
   }
 
   // NOTE: The atomic is not incremented yet.
   const unsigned session_id = GPT_SESSIONS_COUNTER + 1;
 
-  // Create new llama context.
-  //
-
-  llama_context_params params = llama_context_default_params();
-  params.n_ctx                = n_ctx;
-  if (n_batch > 0) params.n_batch = n_batch; // NOTE: Affects state loading.
-  params.cb_eval           = llama_universal_cb_eval;
-  // Cast the session ID to void * and pass it as user data.
-  params.cb_eval_user_data = static_cast<void *>(new unsigned(session_id));
-  params.flash_attn        = true; // NOTE: Affects state loading.
-  // params.rope_freq_base       = 100000;
-  // params.rope_freq_scale      = 1;
-
   spdlog::debug("Creating GPT session...", session_id);
-
-  struct llama_context *ctx =
-      llama_new_context_with_model(LLAMA_MODELS[model_id]->model, params);
+  struct llama_context *ctx = new_session_context(
+      model->model, session_id, n_ctx, n_batch, session_options
+  );
 
   spdlog::info("Created GPT session with ID: {}", session_id);
   models_lock.unlock(); // Release the llama models mutex.
 
   if (ctx == NULL) {
+    set_last_error("Failed to create llama context (n_ctx: {})", n_ctx);
     return -3; // Error creating the context.
   }
 
   // Create a new session.
   GPT_SESSIONS_COUNTER++; // Actually increment the atomic counter.
-  auto session = std::make_shared<Session>(ctx);
+  auto session     = std::make_shared<Session>(ctx, model_id);
+  session->options = session_options;
   GPT_SESSIONS.insert({session_id, session});
   sessions_lock.unlock(); // Release the GPT sessions mutex.
   spdlog::debug("Inserted session");
@@ -124,6 +286,33 @@ int simularity_gpt_create(
   std::unique_lock session_lock(session->mutex);
   spdlog::debug("Acquired session lock");
 
+  // Remove the session upon an error, so that it does not leak.
+  auto discard_session = [&]() {
+    session_lock.unlock();
+    std::unique_lock sessions_lock(GPT_SESSIONS_MUTEX);
+    GPT_SESSIONS.erase(session_id);
+  };
+
+  if (context_shift != NULL) {
+    session->context_shift = *context_shift;
+
+    // NOTE: The actual context size may differ from the requested one.
+    const unsigned actual_n_ctx = llama_n_ctx(ctx);
+    if (context_shift->n_keep >= actual_n_ctx ||
+        context_shift->n_keep + session->shift_chunk() > actual_n_ctx ||
+        session->shift_chunk() == 0) {
+      set_last_error(
+          "Context shift (n_keep: {}, n_discard: {}) does not fit into "
+          "the context (n_ctx: {})",
+          context_shift->n_keep,
+          context_shift->n_discard,
+          actual_n_ctx
+      );
+      discard_session();
+      return -7;
+    }
+  }
+
   // If there is an initial prompt, calculate its hash.
   // Check if there is a file with the same hash.
   //
@@ -165,7 +354,12 @@ int simularity_gpt_create(
         // TODO: Better progress reporting.
         if (progress_callback != NULL) {
           spdlog::debug("Calling progress callback with value 0");
-          progress_callback(0, progress_callback_user_data);
+
+          if (!progress_callback(0, progress_callback_user_data)) {
+            set_last_error("Session creation aborted by the progress callback");
+            discard_session();
+            return -5;
+          }
         }
 
         state_loaded = llama_state_load_file(
@@ -209,11 +403,17 @@ int simularity_gpt_create(
         );
         spdlog::info("Decoded initial prompt");
       } catch (ContextOverflowError &e) {
-        spdlog::error(e.what());
+        set_last_error("{}", e.what());
+        discard_session();
         return -4;
+      } catch (DecodeAbortedError &e) {
+        set_last_error("{}", e.what());
+        discard_session();
+        return -5;
       } catch (UnknownDecodeError &e) {
-        spdlog::error("Unknown decode error: {}", e.code);
-        return e.code;
+        set_last_error("{}", e.what());
+        discard_session();
+        return -6;
       }
 
       if (state_file_path && !state_loaded) {
@@ -1,5 +1,6 @@
 #pragma once
 
+#include <algorithm>
 #include <cstdint>
 #include <llama.h>
 #include <simularity.h>
@@ -18,6 +19,11 @@ struct ContextOverflowError : public std::runtime_error {
       ) {}
 };
 
+struct DecodeAbortedError : public std::runtime_error {
+  DecodeAbortedError() :
+      std::runtime_error("Decoding aborted by the progress callback") {}
+};
+
 struct UnknownDecodeError : public std::runtime_error {
   int code;
 
@@ -28,8 +34,10 @@ struct UnknownDecodeError : public std::runtime_error {
 
 /**
  * Decode a prompt in batches of default context size, reusing and updating the
- * session's KV cache.
+ * session's KV cache. The session's prompt is committed after each batch,
+ * so it stays consistent with the KV cache upon an error.
  * @param prompt The *full* prompt to decode.
+ * @throws ContextOverflowError, DecodeAbortedError, UnknownDecodeError.
  */
 static void simularity_gpt_decode_internal(
     Session *session,
@@ -44,11 +52,17 @@ int simularity_gpt_decode(
     llama_progress_callback progress_callback,
     void *progress_callback_user_data
 ) {
+  clear_last_error();
+
   // Acquire the session.
   auto locking_result = try_locking_session(session_id);
-  if (!locking_result.has_value()) return -1; // Session not found.
+  if (!locking_result.has_value()) {
+    set_last_error("Session not found: {}", session_id);
+    return -1;
+  }
   auto [_, session] = std::move(locking_result.value());
   spdlog::info("Decoding prompt for session {}", session_id);
+  session->n_evicted = 0;
 
   // Tokenize the prompt.
   auto prompt_tokens = llama_tokenize(session->model(), prompt, false, true);
@@ -62,12 +76,61 @@ int simularity_gpt_decode(
     // Return the new context size.
     return session->prompt.size();
   } catch (ContextOverflowError &e) {
-    spdlog::error(e.what());
+    set_last_error("{}", e.what());
     return -2;
+  } catch (DecodeAbortedError &e) {
+    set_last_error("{}", e.what());
+    return -3;
   } catch (UnknownDecodeError &e) {
-    spdlog::error("Unknown decode error: {}", e.code);
-    return e.code;
+    set_last_error("{}", e.what());
+    return -4;
+  }
+}
+
+/// @return The number of leading tokens the session prompt has in common
+/// with the `prompt`.
+static size_t
+match_session_prompt(Session *session, const std::vector<llama_token> &prompt) {
+  size_t n_match;
+
+  for (n_match = 0; n_match < prompt.size(); n_match++) {
+    if (n_match == session->prompt.size() ||
+        session->prompt[n_match] != prompt[n_match]) {
+      break;
+    }
   }
+
+  return n_match;
+}
+
+/**
+  Find the least number of tokens to evict after the kept ones, so that
+  the rest of the session prompt becomes a prefix of the prompt's rest,
+  and shift the session context accordingly.
+
+  @return Whether the context has been shifted.
+ */
+static bool
+shift_to_match(Session *session, const std::vector<llama_token> &prompt) {
+  const size_t n_keep    = session->context_shift->n_keep;
+  const size_t n_session = session->prompt.size();
+
+  if (n_session <= n_keep || prompt.size() <= n_keep) return false;
+
+  // NOTE: Evicting all the tokens after the kept ones is not worth it.
+  for (size_t n_discard = 1; n_keep + n_discard < n_session; n_discard++) {
+    const auto from = session->prompt.begin() + n_keep + n_discard;
+    const auto len  = std::min<size_t>(
+        session->prompt.end() - from, prompt.size() - n_keep
+    );
+
+    if (std::equal(from, from + len, prompt.begin() + n_keep)) {
+      session->shift_context(n_discard);
+      return true;
+    }
+  }
+
+  return false;
 }
 
 static void simularity_gpt_decode_internal(
@@ -76,28 +139,40 @@ static void simularity_gpt_decode_internal(
     llama_progress_callback progress_callback,
     void *progress_callback_user_data
 ) {
-  auto batch_size = llama_n_batch(session->context);
+  auto batch_size      = llama_n_batch(session->context);
+  const unsigned n_ctx = llama_n_ctx(session->context);
 
-  auto n_prompt = prompt.size();
-  if (n_prompt > llama_n_ctx(session->context)) {
-    spdlog::error(
-        "Prompt is too long ({} tokens, max {})",
-        n_prompt,
-        llama_n_ctx(session->context)
-    );
+  if (prompt.size() > n_ctx && session->context_shift) {
+    // Evict whole chunks after the kept tokens, the same way an inference
+    // would, so that the KV cache shifted by it may be reused.
+    const unsigned n_keep    = session->context_shift->n_keep;
+    const unsigned chunk     = session->shift_chunk();
+    const unsigned n_over    = prompt.size() - n_ctx;
+    const unsigned n_discard = (n_over + chunk - 1) / chunk * chunk;
 
-    throw ContextOverflowError(llama_n_ctx(session->context), n_prompt);
+    spdlog::info("Prompt overflows the context, evicting {} tokens", n_discard);
+    prompt.erase(prompt.begin() + n_keep, prompt.begin() + n_keep + n_discard);
+    session->n_evicted += n_discard;
   }
 
-  auto n_session = session->prompt.size();
-  size_t n_match;
+  auto n_prompt = prompt.size();
+  if (n_prompt > n_ctx) {
+    spdlog::error("Prompt is too long ({} tokens, max {})", n_prompt, n_ctx);
+    throw ContextOverflowError(n_ctx, n_prompt);
+  }
 
-  for (n_match = 0; n_match < n_prompt; n_match++) {
-    if (n_match == n_session || session->prompt[n_match] != prompt[n_match]) {
-      break;
+  auto n_match = match_session_prompt(session, prompt);
+
+  // The KV cache may have been shifted by a previous call, so that
+  // the prompt matches it past the evicted tokens.
+  if (session->context_shift && n_match == session->context_shift->n_keep) {
+    if (shift_to_match(session, prompt)) {
+      n_match = match_session_prompt(session, prompt);
     }
   }
 
+  auto n_session = session->prompt.size();
+
   spdlog::debug(
       "n_prompt = {}, n_session = {}, n_match = {}",
       n_prompt,
@@ -107,6 +182,7 @@ static void simularity_gpt_decode_internal(
 
   // Clear the KV cache starting from the first non-matching token.
   session->clear_cache(n_match);
+  session->prompt.resize(n_match);
 
   auto batch = Batch(batch_size);
 
@@ -169,9 +245,24 @@ static void simularity_gpt_decode_internal(
         progress_callback,
         progress_callback_user_data
     );
-    if (err == 1)
-      throw ContextOverflowError(llama_n_batch(session->context), n_prompt);
-    else if (err) throw UnknownDecodeError(err);
+
+    if (session->decode_aborted) {
+      // Discard the partially decoded batch.
+      spdlog::info("Decoding aborted at batch #{}¹", i + 1);
+      session->clear_cache(from);
+      throw DecodeAbortedError();
+    } else if (err) {
+      session->clear_cache(from);
+
+      if (err == 1)
+        throw ContextOverflowError(llama_n_batch(session->context), n_prompt);
+      else throw UnknownDecodeError(err);
+    }
+
+    // Commit the decoded batch.
+    session->prompt.insert(
+        session->prompt.end(), prompt.begin() + from, prompt.begin() + to
+    );
 
     // Clear the batch.
     batch.batch.n_tokens = 0;
@@ -3,9 +3,12 @@
 #include "common.cpp"
 
 int simularity_gpt_destroy(unsigned session_id) {
+  clear_last_error();
+
   // Acquire the GPT session mutex lock.
   std::unique_lock sessions_lock(GPT_SESSIONS_MUTEX);
   if (GPT_SESSIONS.find(session_id) == GPT_SESSIONS.end()) {
+    set_last_error("Session not found: {}", session_id);
     return -1; // Session not found.
   }
 
@@ -0,0 +1,171 @@
+#pragma once
+
+#include <algorithm>
+#include <cstdlib>
+#include <cstring>
+#include <memory>
+#include <optional>
+#include <stdexcept>
+#include <string>
+#include <utility>
+#include <vector>
+
+#include <llama.h>
+#include <spdlog/spdlog.h>
+
+#include "../../llama.cpp"
+
+/// The maximum vocabulary size difference between a target and a draft model.
+static const int DRAFT_VOCAB_MAX_SIZE_DIFFERENCE = 100;
+
+/**
+  A draft model context proposing tokens for a session to verify
+  (speculative decoding). Its KV cache is synced with the session prompt
+  upon drafting, reusing the common prefix.
+ */
+class DraftContext {
+public:
+  /// The draft model ID.
+  const std::string model_id;
+
+  /// Holding a pointer copy, so that the model outlives its unloading.
+  const std::shared_ptr<LlamaModel> model;
+
+  struct llama_context *context;
+
+  /// The KV-cached tokens.
+  std::vector<llama_token> prompt = {};
+
+  DraftContext(
+      std::string model_id,
+      std::shared_ptr<LlamaModel> model,
+      struct llama_context *ctx
+  )
+      : model_id(std::move(model_id)), model(std::move(model)), context(ctx) {
+  }
+
+  ~DraftContext() { llama_free(context); }
+
+  /// Create a draft context of the same size as the `target` one.
+  /// @return NULL on error, e.g. if the model is vocabulary-only.
+  static std::unique_ptr<DraftContext> create(
+      std::string model_id,
+      std::shared_ptr<LlamaModel> model,
+      const struct llama_context *target
+  ) {
+    if (model->vocab_only) return nullptr;
+
+    llama_context_params params = llama_context_default_params();
+    params.n_ctx                = llama_n_ctx(target);
+    params.n_batch              = llama_n_batch(target);
+    if (model->n_threads > 0) {
+      params.n_threads       = model->n_threads;
+      params.n_threads_batch = model->n_threads;
+    }
+
+    auto ctx = llama_new_context_with_model(model->model, params);
+    if (ctx == NULL) return nullptr;
+
+    return std::make_unique<DraftContext>(
+        std::move(model_id), std::move(model), ctx
+    );
+  }
+
+  /// Check that the draft model's vocabulary matches the target's,
+  /// so that the token IDs are interchangeable.
+  /// @return A description of the mismatch, if any.
+  static std::optional<std::string>
+  check_vocab(const llama_model *target, const llama_model *draft) {
+    if (llama_vocab_type(target) != llama_vocab_type(draft)) {
+      return "the vocabulary types differ";
+    }
+
+    if (llama_token_bos(target) != llama_token_bos(draft) ||
+        llama_token_eos(target) != llama_token_eos(draft)) {
+      return "the special tokens differ";
+    }
+
+    const int n_vocab_target = llama_n_vocab(target);
+    const int n_vocab_draft  = llama_n_vocab(draft);
+
+    if (std::abs(n_vocab_target - n_vocab_draft) >
+        DRAFT_VOCAB_MAX_SIZE_DIFFERENCE) {
+      return fmt::format(
+          "the vocabulary sizes differ too much ({} vs {})",
+          n_vocab_target,
+          n_vocab_draft
+      );
+    }
+
+    for (int i = 0; i < std::min(n_vocab_target, n_vocab_draft); i++) {
+      if (strcmp(
+              llama_token_get_text(target, i), llama_token_get_text(draft, i)
+          ) != 0) {
+        return fmt::format("token #{} differs", i);
+      }
+    }
+
+    return std::nullopt;
+  }
+
+  /**
+    Greedily propose up to `n_draft` tokens following the `tokens`.
+    Drafting stops early at an end-of-generation token, or a token
+    out of the target vocabulary (`n_vocab`).
+
+    @throws std::runtime_error on decoding error.
+   */
+  std::vector<llama_token> draft(
+      const std::vector<llama_token> &tokens, unsigned n_draft, int n_vocab
+  ) {
+    // Reuse the common prefix, yet decode the last token for its logits.
+    size_t n_past = 0;
+    while (n_past < prompt.size() && n_past + 1 < tokens.size() &&
+           prompt[n_past] == tokens[n_past]) {
+      n_past++;
+    }
+
+    llama_kv_cache_seq_rm(context, 0, n_past, -1);
+    prompt.resize(n_past);
+
+    const size_t n_batch = llama_n_batch(context);
+    while (prompt.size() < tokens.size()) {
+      auto n = std::min(n_batch, tokens.size() - prompt.size());
+      decode(tokens.data() + prompt.size(), n);
+    }
+
+    std::vector<llama_token> drafted;
+    const int n_vocab_draft = llama_n_vocab(model->model);
+
+    while (drafted.size() < n_draft) {
+      const float *logits = llama_get_logits_ith(context, -1);
+      auto best = std::max_element(logits, logits + n_vocab_draft) - logits;
+      if (best >= n_vocab) break;
+
+      drafted.push_back(best);
+      if (drafted.size() == n_draft || llama_token_is_eog(model->model, best)) {
+        break;
+      }
+
+      decode(&drafted.back(), 1);
+    }
+
+    return drafted;
+  }
+
+private:
+  /// Decode the `n` tokens following the `prompt`, and commit them.
+  void decode(const llama_token *tokens, size_t n) {
+    auto batch = llama_batch_get_one(
+        const_cast<llama_token *>(tokens), n, prompt.size(), 0
+    );
+
+    auto err = llama_decode(context, batch);
+    if (err) {
+      llama_kv_cache_seq_rm(context, 0, prompt.size(), -1);
+      throw std::runtime_error(fmt::format("Draft decoding failed: {}", err));
+    }
+
+    prompt.insert(prompt.end(), tokens, tokens + n);
+  }
+};
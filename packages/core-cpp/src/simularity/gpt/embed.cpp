@@ -0,0 +1,154 @@
+#include <algorithm>
+#include <memory>
+#include <vector>
+
+#include <llama.h>
+#include <simularity.h>
+#include <spdlog/spdlog.h>
+
+#include "common.cpp"
+
+/// A `llama_context` wrapper with a destructor.
+struct EmbeddingContext {
+  struct llama_context *context;
+
+  EmbeddingContext(struct llama_context *ctx) : context(ctx) {}
+  ~EmbeddingContext() { llama_free(context); }
+};
+
+/// Create an embedding-mode context fitting `n_tokens` per sequence.
+/// @return NULL on error.
+static std::unique_ptr<EmbeddingContext> new_embedding_context(
+    const LlamaModel &model, unsigned n_tokens, int pooling
+) {
+  llama_context_params params = llama_context_default_params();
+  params.embeddings           = true;
+  params.pooling_type         = static_cast<enum llama_pooling_type>(pooling);
+  if (model.n_threads > 0) {
+    params.n_threads       = model.n_threads;
+    params.n_threads_batch = model.n_threads;
+  }
+
+  // NOTE: Non-causal models require a whole sequence in a single ubatch.
+  params.n_ctx    = n_tokens;
+  params.n_batch  = n_tokens;
+  params.n_ubatch = n_tokens;
+
+  auto ctx = llama_new_context_with_model(model.model, params);
+  if (ctx == NULL) return nullptr;
+  return std::make_unique<EmbeddingContext>(ctx);
+}
+
+int simularity_gpt_embed(
+    const char *model_id,
+    const char *const *texts,
+    unsigned n_texts,
+    int pooling,
+    float *embeddings,
+    size_t n_floats_max
+) {
+  spdlog::debug(
+      "simularity_gpt_embed(model_id: {}, n_texts: {}, pooling: {}, "
+      "n_floats_max: {})",
+      model_id,
+      n_texts,
+      pooling,
+      n_floats_max
+  );
+  clear_last_error();
+
+  // Acquire the models mutex.
+  spdlog::debug("Acquiring models lock");
+  std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+
+  // Check if the model exists.
+  if (LLAMA_MODELS.find(model_id) == LLAMA_MODELS.end()) {
+    set_last_error("Model does not exist: {}", model_id);
+    return -1; // Model does not exist.
+  }
+
+  // NOTE: Holding a pointer copy, so that the model outlives its unloading.
+  auto model = LLAMA_MODELS[model_id];
+  models_lock.unlock();
+
+  if (model->vocab_only) {
+    set_last_error("Model is loaded vocabulary-only: {}", model_id);
+    return -3;
+  }
+
+  const int n_embd = llama_n_embd(model->model);
+  if (n_floats_max < (size_t)n_texts * n_embd) {
+    return n_embd;
+  }
+
+  // Tokenize the texts.
+  std::vector<std::vector<llama_token>> tokens(n_texts);
+  unsigned n_tokens_max = 1;
+  for (unsigned i = 0; i < n_texts; i++) {
+    try {
+      tokens[i] = llama_tokenize(model->model, texts[i], true, false);
+    } catch (const std::runtime_error &e) {
+      set_last_error("Failed to tokenize text #{}: {}", i, e.what());
+      return -5;
+    }
+
+    if (tokens[i].empty()) {
+      set_last_error("Text #{} is empty", i);
+      return -5;
+    }
+
+    n_tokens_max = std::max(n_tokens_max, (unsigned)tokens[i].size());
+  }
+
+  const unsigned n_ctx_train = llama_n_ctx_train(model->model);
+  if (n_tokens_max > n_ctx_train) {
+    set_last_error(
+        "Text is too long ({} tokens, max {})", n_tokens_max, n_ctx_train
+    );
+    return -2;
+  }
+
+  auto ctx = new_embedding_context(*model, n_tokens_max, pooling);
+
+  // A generative model usually has no pooling type set.
+  if (ctx != nullptr &&
+      llama_pooling_type(ctx->context) == LLAMA_POOLING_TYPE_NONE) {
+    spdlog::debug("The model has no pooling type, falling back to mean");
+    ctx = new_embedding_context(*model, n_tokens_max, LLAMA_POOLING_TYPE_MEAN);
+  }
+
+  if (ctx == nullptr) {
+    set_last_error(
+        "Failed to create llama embedding context (n_ctx: {})", n_tokens_max
+    );
+    return -3;
+  }
+
+  auto batch = Batch(n_tokens_max);
+  for (unsigned i = 0; i < n_texts; i++) {
+    llama_kv_cache_clear(ctx->context);
+
+    batch.batch.n_tokens = 0;
+    for (size_t j = 0; j < tokens[i].size(); j++) {
+      batch.add(tokens[i][j], j, true);
+    }
+
+    auto err = llama_decode(ctx->context, batch.batch);
+    if (err) {
+      set_last_error("Failed to decode text #{} -> {}", i, err);
+      return -4;
+    }
+
+    const float *embd = llama_get_embeddings_seq(ctx->context, 0);
+    if (embd == NULL) {
+      set_last_error("Failed to get embeddings for text #{}", i);
+      return -4;
+    }
+
+    std::copy(embd, embd + n_embd, embeddings + (size_t)i * n_embd);
+  }
+
+  spdlog::info("Embedded {} texts with model {}", n_texts, model_id);
+
+  return n_embd;
+}
@@ -0,0 +1,102 @@
+#include <memory>
+#include <vector>
+
+#include <llama.h>
+#include <simularity.h>
+#include <spdlog/spdlog.h>
+
+#include "common.cpp"
+#include "create.cpp"
+
+int simularity_gpt_fork(unsigned session_id) {
+  spdlog::debug("simularity_gpt_fork(session_id: {})", session_id);
+  clear_last_error();
+
+  // NOTE: The locking order is the same as in `simularity_gpt_create`.
+  std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+  std::unique_lock sessions_lock(GPT_SESSIONS_MUTEX);
+
+  if (!reserve_session_slot()) {
+    return -2; // Maximum number of sessions reached.
+  }
+
+  auto it = GPT_SESSIONS.find(session_id);
+  if (it == GPT_SESSIONS.end()) {
+    set_last_error("Session not found: {}", session_id);
+    return -1;
+  }
+
+  // NOTE: Holding a pointer copy, so that the parent outlives its lock.
+  auto parent = it->second;
+  std::unique_lock parent_lock(parent->mutex);
+  parent->mark_used();
+
+  // NOTE: The atomic is not incremented yet.
+  const unsigned child_id = GPT_SESSIONS_COUNTER + 1;
+
+  spdlog::debug("Forking GPT session {} into {}", session_id, child_id);
+  struct llama_context *ctx = new_session_context(
+      const_cast<llama_model *>(parent->model()),
+      child_id,
+      llama_n_ctx(parent->context),
+      llama_n_batch(parent->context),
+      parent->options
+  );
+
+  models_lock.unlock(); // Release the llama models mutex.
+
+  if (ctx == NULL) {
+    set_last_error(
+        "Failed to create llama context (n_ctx: {})",
+        llama_n_ctx(parent->context)
+    );
+    return -3; // Error creating the context.
+  }
+
+  // Create a new session.
+  GPT_SESSIONS_COUNTER++; // Actually increment the atomic counter.
+  auto child     = std::make_shared<Session>(ctx, parent->model_id);
+  child->options = parent->options;
+  GPT_SESSIONS.insert({child_id, child});
+  std::unique_lock child_lock(child->mutex);
+  sessions_lock.unlock(); // Release the GPT sessions mutex.
+
+  // Copy the KV cache (and the last logits) over.
+  std::vector<uint8_t> state(llama_state_get_size(parent->context));
+  bool copied =
+      llama_state_get_data(parent->context, state.data(), state.size()) > 0;
+  copied =
+      copied &&
+      llama_state_set_data(child->context, state.data(), state.size()) > 0;
+
+  if (!copied) {
+    set_last_error("Failed to copy the session {} state", session_id);
+
+    // NOTE: Releasing the session locks before re-acquiring the sessions mutex.
+    parent_lock.unlock();
+    child_lock.unlock();
+    sessions_lock.lock();
+    GPT_SESSIONS.erase(child_id);
+
+    return -4;
+  }
+
+  // The copied KV cache has been computed with the parent's adapters.
+  for (auto &[lora, scale] : parent->loras) {
+    llama_lora_adapter_set(child->context, lora->adapter, scale);
+  }
+
+  child->prompt        = parent->prompt;
+  child->context_shift = parent->context_shift;
+  child->loras         = parent->loras;
+
+  spdlog::info(
+      "Forked GPT session {} into {} ({} tokens, {} bytes)",
+      session_id,
+      child_id,
+      child->prompt.size(),
+      state.size()
+  );
+
+  return child_id;
+}
@@ -1,23 +1,26 @@
+#pragma once
+
+#include <algorithm>
 #include <chrono>
 #include <iostream>
+#include <map>
+#include <memory>
 #include <optional>
-#include <spdlog/fmt/bin_to_hex.h>
 #include <spdlog/fmt/ranges.h>
 #include <spdlog/spdlog.h>
 #include <sstream>
 #include <string>
+#include <string_view>
 
 #include "../../llama/grammar-parser.cpp"
 #include "../../llama/sampling.cpp"
 #include "common.cpp"
 #include "decode.cpp"
 #include "llama.h"
+#include "lua.cpp"
 #include "sol/sol.hpp"
 #include "sol/types.hpp"
 
-// Defined in lua-cjson.c
-extern "C" int luaopen_cjson(lua_State *l);
-
 simularity_gpt_inference_options simularity_gpt_inference_options_default() {
   return simularity_gpt_inference_options{
       .n_prev             = 64,
@@ -44,27 +47,20 @@ simularity_gpt_inference_options simularity_gpt_inference_options_default() {
       .stop_sequences_len = 0,
       .stop_sequences     = nullptr,
       .lua_grammar        = nullptr,
+      .lua_limits         = LUA_LIMITS_DEFAULT,
+      .logit_bias_len     = 0,
+      .logit_bias         = nullptr,
+      .banned_strings_len = 0,
+      .banned_strings     = nullptr,
+      .draft_model_id     = nullptr,
+      .n_draft            = 5,
   };
 }
 
-int simularity_gpt_infer(
-    unsigned session_id,
-    const char *prompt,
-    unsigned n_eval,
-    const struct simularity_gpt_inference_options options,
-    llama_progress_callback decode_progress_callback,
-    void *decode_progress_callback_user_data,
-    bool(inference_callback)(const char *output, void *),
-    void *inference_callback_user_data
-) {
-  // Acquire the session.
-  auto locking_result = try_locking_session(session_id);
-  if (!locking_result.has_value()) return -1; // Session not found.
-  auto [_, session] = std::move(locking_result.value());
-  spdlog::info("Inferencing for session {}", session_id);
-
-  // Prepare sampling params.
-  struct llama_sampling_params sampling_params = {
+/// Convert the inference options to sampling params, except for the grammar.
+static struct llama_sampling_params
+to_sampling_params(const struct simularity_gpt_inference_options &options) {
+  return {
       .n_prev            = options.n_prev,
       .n_probs           = options.n_probs,
       .min_keep          = options.min_keep,
@@ -85,54 +81,291 @@ int simularity_gpt_infer(
       .mirostat_eta      = options.mirostat_eta,
       .penalize_nl       = options.penalize_nl,
       .seed              = options.seed};
+}
 
-  std::optional<sol::state> lua_state;
-  std::optional<sol::function> lua_on_eos_function;
+/// Set the sampling params logit bias, tokenizing the text keys.
+/// @throws std::runtime_error on an invalid token.
+static void set_logit_bias(
+    const llama_model *model,
+    const struct simularity_gpt_inference_options &options,
+    struct llama_sampling_params &params
+) {
+  const int n_vocab = llama_n_vocab(model);
+
+  for (unsigned i = 0; i < options.logit_bias_len; i++) {
+    auto &logit_bias = options.logit_bias[i];
+
+    if (logit_bias.text == nullptr) {
+      if (logit_bias.token < 0 || logit_bias.token >= n_vocab) {
+        throw std::runtime_error(fmt::format(
+            "Logit bias token out of vocabulary: {}", logit_bias.token
+        ));
+      }
+
+      params.logit_bias[logit_bias.token] = logit_bias.bias;
+      continue;
+    }
+
+    auto tokens = llama_tokenize(model, logit_bias.text, false, false);
+    spdlog::debug(
+        "Logit bias: `{}` ({}) -> {}", logit_bias.text, tokens, logit_bias.bias
+    );
+
+    for (auto token : tokens) params.logit_bias[token] = logit_bias.bias;
+  }
+}
+
+/// Rollbacks at the same position before a banned string is let through.
+static const unsigned BANNED_STRING_MAX_RETRIES = 64;
+
+/// A string found in a text, see `StringSet::find`.
+struct StringMatch {
+  /// The offset in the text.
+  size_t offset;
+
+  /// The index of the string in the set.
+  size_t index;
+};
+
+/// Strings to find in the inferred text, i.e. the banned strings
+/// or the stop sequences. They are matched on the text rather than
+/// on the tokens, as the same text may be tokenized differently.
+struct StringSet {
+  /// Empty strings are never found.
+  std::vector<std::string> strings;
+
+  StringSet(const char **strings, unsigned len) {
+    for (unsigned i = 0; i < len; i++) this->strings.push_back(strings[i]);
+  }
+
+  bool empty() const {
+    return std::all_of(strings.begin(), strings.end(), [](auto &string) {
+      return string.empty();
+    });
+  }
+
+  /// @return The first string found in `text`, if any.
+  std::optional<StringMatch> find(const std::string &text) const {
+    std::optional<StringMatch> first;
+
+    for (size_t i = 0; i < strings.size(); i++) {
+      if (strings[i].empty()) continue;
+
+      auto offset = text.find(strings[i]);
+      if (offset != std::string::npos && (!first || offset < first->offset)) {
+        first = StringMatch{.offset = offset, .index = i};
+      }
+    }
+
+    return first;
+  }
+
+  /// @return The offset of the first `text` suffix which may be continued
+  /// to a string, or `text.size()` if there is none.
+  size_t undecided(const std::string &text) const {
+    for (size_t offset = 0; offset < text.size(); offset++) {
+      auto suffix = std::string_view(text).substr(offset);
+
+      for (auto &string : strings) {
+        if (string.size() > suffix.size() && string.starts_with(suffix)) {
+          return offset;
+        }
+      }
+    }
+
+    return text.size();
+  }
+};
+
+/// @return Microseconds elapsed since `start`.
+static int64_t elapsed_us(std::chrono::steady_clock::time_point start) {
+  return std::chrono::duration_cast<std::chrono::microseconds>(
+             std::chrono::steady_clock::now() - start
+  )
+      .count();
+}
+
+/// Buffers for the inference callback argument,
+/// which shall outlive the callback call.
+struct InferenceTokenBuffers {
+  std::vector<std::string> top_pieces;
+  std::vector<simularity_gpt_token_logprob> top_logprobs;
+
+  /// Build the inference callback argument for a token sampled earlier.
+  simularity_gpt_inference_token build(
+      const llama_model *model,
+      llama_token next,
+      const std::string &piece,
+      float logprob,
+      const std::vector<llama_token_data> &top_candidates
+  ) {
+    top_pieces.clear();
+    for (auto &candidate : top_candidates) {
+      try {
+        top_pieces.push_back(llama_token_to_piece(model, candidate.id, true));
+      } catch (std::exception &e) {
+        top_pieces.push_back("�");
+      }
+    }
+
+    top_logprobs.clear();
+    for (size_t i = 0; i < top_candidates.size(); i++) {
+      top_logprobs.push_back(simularity_gpt_token_logprob{
+          .token   = top_candidates[i].id,
+          .piece   = top_pieces[i].c_str(),
+          .logprob = logf(top_candidates[i].p),
+      });
+    }
+
+    return simularity_gpt_inference_token{
+        .token   = next,
+        .piece   = piece.c_str(),
+        .logprob = logprob,
+        .n_top   = (unsigned)top_logprobs.size(),
+        .top     = top_logprobs.data(),
+    };
+  }
+};
+
+/// An inferred token held back from the inference callback,
+/// as it may start a banned string or a stop sequence.
+struct HeldToken {
+  llama_token token;
+  std::string piece;
+  float logprob;
+  std::vector<llama_token_data> top_candidates;
+
+  /// The sampling state before the token has been accepted,
+  /// set with banned strings only.
+  std::unique_ptr<LlamaSamplingContext::Snapshot> snapshot;
+};
+
+int simularity_gpt_infer(
+    unsigned session_id,
+    const char *prompt,
+    unsigned n_eval,
+    const struct simularity_gpt_inference_options options,
+    llama_progress_callback decode_progress_callback,
+    void *decode_progress_callback_user_data,
+    bool(inference_callback)(const simularity_gpt_inference_token *, void *),
+    void *inference_callback_user_data,
+    struct simularity_gpt_infer_result *result
+) {
+  clear_last_error();
+
+  // Acquire the draft model, if any (before the session, to keep lock order).
+  std::shared_ptr<LlamaModel> draft_model;
+  if (options.draft_model_id != nullptr) {
+    std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+
+    auto it = LLAMA_MODELS.find(options.draft_model_id);
+    if (it == LLAMA_MODELS.end()) {
+      set_last_error("Draft model does not exist: {}", options.draft_model_id);
+      return -10;
+    }
+
+    draft_model = it->second;
+  }
+
+  // Acquire the session.
+  auto locking_result = try_locking_session(session_id);
+  if (!locking_result.has_value()) {
+    set_last_error("Session not found: {}", session_id);
+    return -1;
+  }
+  auto [_, session] = std::move(locking_result.value());
+  spdlog::info("Inferencing for session {}", session_id);
+  session->n_evicted = 0;
+
+  // Reuse the session draft context, if it is of the same model.
+  DraftContext *draft = nullptr;
+  if (draft_model) {
+    if (!session->draft || session->draft->model_id != options.draft_model_id) {
+      session->draft.reset();
+
+      if (auto err = DraftContext::check_vocab(
+              session->model(), draft_model->model
+          )) {
+        set_last_error("Draft model vocabulary mismatch: {}", *err);
+        return -11;
+      }
+
+      session->draft = DraftContext::create(
+          options.draft_model_id, draft_model, session->context
+      );
+
+      if (!session->draft) {
+        set_last_error("Failed to create llama draft context");
+        return -12;
+      }
+    }
+
+    draft = session->draft.get();
+  }
+
+  // Not to exceed the batch size.
+  const unsigned n_draft =
+      draft ? std::min(options.n_draft, llama_n_batch(session->context) - 1)
+            : 0;
+
+  // Prepare sampling params.
+  struct llama_sampling_params sampling_params = to_sampling_params(options);
+  try {
+    set_logit_bias(session->model(), options, sampling_params);
+  } catch (std::exception &e) {
+    set_last_error("{}", e.what());
+    return -3;
+  }
+
+  std::unique_ptr<LuaSandbox> lua;
+  std::optional<sol::protected_function> lua_on_eos_function;
 
   // Set grammar, if provided.
   if (options.grammar != nullptr) {
     if (options.lua_grammar != nullptr) {
-      spdlog::error("Both grammar and lua_grammar are provided, that's a error"
-      );
-
+      set_last_error("Both grammar and lua_grammar are provided");
       return -3;
     }
 
     sampling_params.grammar = std::string(options.grammar);
   } else if (options.lua_grammar != nullptr) {
     spdlog::debug("Initializing Lua state");
-    sol::state lua;
-
-    // Make `cjson` table available in the Lua state.
-    luaopen_cjson(lua.lua_state());
-
-    spdlog::info("Running Lua grammar script");
-    // spdlog::debug(options.lua_grammar);
-
-    // Add the cjson library.
-    lua.open_libraries(sol::lib::base, sol::lib::string, sol::lib::table);
 
     try {
-      lua.safe_script(options.lua_grammar);
+      lua = LuaSandbox::create(options.lua_limits);
     } catch (const sol::error &e) {
-      spdlog::error("Error during initial Lua script loading: {}", e.what());
+      set_last_error("Failed to initialize Lua state: {}", e.what());
+      return -8;
+    }
+
+    spdlog::info("Running Lua grammar script");
+    if (auto err = lua->load(options.lua_grammar)) {
+      set_last_error("Error during initial Lua script loading: {}", *err);
       return -8;
     }
 
-    sol::function lua_start_function = lua["start"];
+    auto lua_start_function = lua->function("start");
+    if (!lua_start_function.has_value()) {
+      set_last_error("The Lua script does not define .start()");
+      return -8;
+    }
 
-    try {
-      sampling_params.grammar = lua_start_function();
-    } catch (const sol::error &e) {
-      spdlog::error("Error during Lua .start() call: {}", e.what());
+    auto start_result = lua->call(*lua_start_function);
+    if (!start_result.valid()) {
+      sol::error e = start_result;
+      set_last_error(
+          "Error during Lua .start() call: {}", lua->describe_error(e.what())
+      );
       return -8;
     }
 
-    // ADHOC: Need to extract to a variable in MSVC.
-    sol::function f     = lua["on_eos"];
-    lua_on_eos_function = f;
+    if (start_result.get_type() != sol::type::string) {
+      set_last_error("Lua .start() must return a string");
+      return -8;
+    }
 
-    lua_state = std::move(lua);
+    sampling_params.grammar = start_result.get<std::string>();
+    lua_on_eos_function     = lua->function("on_eos");
   }
 
   // Create the sampling context.
@@ -140,43 +373,41 @@ int simularity_gpt_infer(
   try {
     raw_sampling_ctx = llama_sampling_init(sampling_params);
     if (raw_sampling_ctx == nullptr) {
-      return -3; // Failed to initialize the sampling context.
+      set_last_error("Failed to initialize the sampling context");
+      return -3;
     }
   } catch (std::exception &e) {
-    spdlog::error("Error at llama_sampling_init: {}", e.what());
-    return -5;
+    // Likely a grammar parse error.
+    set_last_error("{}", e.what());
+    return -3;
   }
   auto sampling_ctx = new LlamaSamplingContext(raw_sampling_ctx);
   spdlog::debug("Sampling context initialized");
 
-  // Add stop sequences.
-  std::vector<std::vector<llama_token>> stop_sequences;
-  spdlog::debug("Adding stop sequences");
-  for (unsigned i = 0; i < options.stop_sequences_len; i++) {
-    auto string = std::string(options.stop_sequences[i]);
-    auto tokens = llama_tokenize(
-        session->model(), options.stop_sequences[i], false, true
-    );
-
-    spdlog::debug(
-        "Stop sequence: `{:np}` ({}) ({})",
-        spdlog::to_hex(string),
-        options.stop_sequences[i],
-        tokens
-    );
-
-    stop_sequences.push_back(tokens);
-  }
+  auto stop_sequences =
+      StringSet(options.stop_sequences, options.stop_sequences_len);
+  auto banned_strings =
+      StringSet(options.banned_strings, options.banned_strings_len);
 
   // Tokenize the prompt.
   spdlog::debug("Tokenizing the prompt");
   auto prompt_tokens =
-      prompt == NULL ? std::vector<llama_token>()
+      prompt == NULL ? session->prompt
                      : llama_tokenize(session->model(), prompt, false, true);
 
-  auto n_prompt = prompt_tokens.size();
-  auto n_target = n_prompt + n_eval;
+  simularity_gpt_infer_result outcome = {
+      .n_prompt      = 0,
+      .n_tokens      = 0,
+      .n_evicted     = 0,
+      .stop_reason   = SIMULARITY_GPT_STOP_REASON_N_EVAL,
+      .stop_sequence = -1,
+      .decode_us     = 0,
+      .infer_us      = 0,
+      .n_drafted     = 0,
+      .n_accepted    = 0,
+  };
 
+  auto decode_start = std::chrono::steady_clock::now();
   try {
     simularity_gpt_decode_internal(
         session,
@@ -185,137 +416,401 @@ int simularity_gpt_infer(
         decode_progress_callback_user_data
     );
   } catch (ContextOverflowError &e) {
-    spdlog::error(e.what());
+    set_last_error("{}", e.what());
     return -2;
+  } catch (DecodeAbortedError &e) {
+    set_last_error("{}", e.what());
+    return -5;
   } catch (UnknownDecodeError &e) {
-    spdlog::error("Unknown decode error: {}", e.code);
+    set_last_error("{}", e.what());
     return -4;
   }
+  outcome.decode_us = elapsed_us(decode_start);
 
-  auto batch = Batch(1);
-  batch.add(prompt_tokens.back(), n_prompt - 1, true);
+  if (session->prompt.empty()) {
+    set_last_error("The prompt is empty");
+    return -9;
+  }
+  outcome.n_prompt = session->prompt.size();
+
+  // NOTE: The prompt may have been shortened by the context shift.
+  auto batch = Batch(1 + n_draft);
+  batch.add(session->prompt.back(), session->prompt.size() - 1, true);
+
+  // The drafted tokens following the last one, decoded in the KV cache
+  // yet not verified, and the batch index of the logits to sample from.
+  std::vector<llama_token> drafted;
+  int logits_index = -1;
 
   std::vector<llama_token> eval_tokens;
   std::string eval_string;
-  auto start = std::chrono::high_resolution_clock::now();
+
+  // Buffers for the inference callback argument.
+  InferenceTokenBuffers buffers;
+
+  // Tokens not yet yielded to the inference callback,
+  // with their text starting at `eval_string[held_offset]`.
+  std::vector<HeldToken> held;
+  size_t held_offset = 0;
+
+  // The trailing tokens of a matched stop sequence, not to be counted.
+  size_t n_stop_tokens = 0;
+
+  // Tokens banned at an `eval_tokens` index after a rollback.
+  std::map<size_t, std::vector<llama_token>> banned_tokens;
+
+  // Remove the held tokens since `index` from the session.
+  auto rollback = [&](size_t index) {
+    const size_t n_tokens = held.size() - index;
+    for (size_t i = index; i < held.size(); i++) {
+      eval_string.resize(eval_string.size() - held[i].piece.size());
+    }
+
+    held.resize(index);
+    eval_tokens.resize(eval_tokens.size() - n_tokens);
+    session->prompt.resize(session->prompt.size() - n_tokens);
+    session->clear_cache(session->prompt.size());
+  };
+
+  // Yield the first `n_tokens` held tokens to the inference callback.
+  // If the callback stops the inference, the following tokens are removed.
+  // @return False if the callback has stopped the inference.
+  auto release = [&](size_t n_tokens) -> bool {
+    size_t n_released = 0;
+    bool proceed      = true;
+
+    while (n_released < n_tokens && proceed) {
+      auto &token = held[n_released++];
+
+      if (inference_callback != NULL) {
+        auto argument = buffers.build(
+            session->model(),
+            token.token,
+            token.piece,
+            token.logprob,
+            token.top_candidates
+        );
+
+        proceed = inference_callback(&argument, inference_callback_user_data);
+      }
+
+      held_offset += token.piece.size();
+    }
+
+    if (!proceed) rollback(n_released);
+    held.erase(held.begin(), held.begin() + n_released);
+
+    return proceed;
+  };
+
+  auto infer_start = std::chrono::steady_clock::now();
 
   while (eval_tokens.size() < n_eval) {
     try {
       llama_token next;
 
+      // Ban the tokens which have led to a banned string at this position.
+      auto &logit_bias = sampling_ctx->context->params.logit_bias;
+      std::optional<std::unordered_map<llama_token, float>> logit_bias_backup;
+
+      if (auto bans = banned_tokens.find(eval_tokens.size());
+          bans != banned_tokens.end()) {
+        logit_bias_backup = logit_bias;
+        for (auto token : bans->second) logit_bias[token] = -INFINITY;
+      }
+
       try {
-        next = sampling_ctx->sample(session->context);
+        next = sampling_ctx->sample(session->context, logits_index);
       } catch (std::exception &e) {
-        spdlog::error("Error at sample: {}", e.what());
+        set_last_error("Error at sample: {}", e.what());
         return -7;
       }
 
-      if (next == llama_token_eos(session->model())) {
-        // If we have a Lua instance, call on_eos(eval_string)
+      if (logit_bias_backup) logit_bias = std::move(*logit_bias_backup);
+
+      if (llama_token_is_eog(session->model(), next)) {
+        // If the Lua script defines on_eos(eval_string), call it
         // to get the new grammar. on_eos(eval_string) may return string or nil.
         if (lua_on_eos_function.has_value()) {
+          // The grammar changes, so there is no rolling back further.
+          if (!release(held.size())) {
+            spdlog::info("Stop: inference callback returned false");
+            outcome.stop_reason = SIMULARITY_GPT_STOP_REASON_CALLBACK;
+            break;
+          }
+
           spdlog::info("Calling Lua .on_eos({})", eval_string);
 
-          try {
-            auto new_grammar = lua_on_eos_function.value()(eval_string);
-
-            if (new_grammar.get_type() != sol::type::lua_nil) {
-              sampling_params.grammar = new_grammar;
-
-              if (sampling_ctx->set_grammar(sampling_params.grammar.c_str()) !=
-                  0) {
-                spdlog::error("Failed to set new grammar");
-                return -3;
-              } else {
-                spdlog::debug("Set new grammar");
-              }
-
-              eval_string.clear();
-              continue;
-            } else {
-              spdlog::info("Stop: Lua .on_eos() returned nil");
-              break;
-            }
-          } catch (const sol::error &e) {
-            spdlog::error("Error during Lua .on_eos() call: {}", e.what());
+          auto new_grammar = lua->call(*lua_on_eos_function, eval_string);
+          if (!new_grammar.valid()) {
+            sol::error e = new_grammar;
+            set_last_error(
+                "Error during Lua .on_eos() call: {}",
+                lua->describe_error(e.what())
+            );
+            return -8;
+          }
+
+          if (new_grammar.get_type() == sol::type::lua_nil) {
+            spdlog::info("Stop: Lua .on_eos() returned nil");
+            outcome.stop_reason = SIMULARITY_GPT_STOP_REASON_LUA;
+            break;
+          } else if (new_grammar.get_type() != sol::type::string) {
+            set_last_error("Lua .on_eos() must return a string or nil");
             return -8;
           }
+
+          sampling_params.grammar = new_grammar.get<std::string>();
+
+          try {
+            sampling_ctx->set_grammar(sampling_params.grammar.c_str());
+            spdlog::debug("Set new grammar");
+          } catch (std::exception &e) {
+            set_last_error("{}", e.what());
+            return -3;
+          }
+
+          eval_string.clear();
+          held_offset = 0;
+          continue;
         } else {
           spdlog::info("Stop: EOS token");
+          outcome.stop_reason = SIMULARITY_GPT_STOP_REASON_EOS;
           break;
         }
       }
 
+      HeldToken token = {.token = next, .logprob = -INFINITY};
+      if (inference_callback != NULL) {
+        token.logprob = sampling_ctx->logprobs(
+            next, std::max(options.n_probs, 0), token.top_candidates
+        );
+      }
+
+      if (!banned_strings.empty()) {
+        token.snapshot = sampling_ctx->snapshot();
+      }
+
       // Accept the token.
       sampling_ctx->accept(session->context, next);
       eval_tokens.push_back(next);
       session->prompt.push_back(next);
 
+      // Verify the drafted token at this position, if any.
+      bool is_decoded = false;
+      if (!drafted.empty()) {
+        if (next == drafted.front()) {
+          drafted.erase(drafted.begin());
+          outcome.n_accepted++;
+          is_decoded = true;
+        } else {
+          drafted.clear();
+          session->clear_cache(session->prompt.size() - 1);
+        }
+      }
+
       // Convert the token to a piece.
-      std::string piece;
       try {
-        piece = llama_token_to_piece(session->model(), next, true);
+        token.piece = llama_token_to_piece(session->model(), next, true);
       } catch (std::exception &e) {
         spdlog::warn("Failed to convert token to piece: ⌘{}", next);
-        piece = "�";
+        token.piece = "�";
       }
 
-      // Call the inference callback.
-      if (inference_callback != NULL) {
-        if (!inference_callback(piece.c_str(), inference_callback_user_data)) {
+      // Append the piece to the output string.
+      eval_string += token.piece;
+      held.push_back(std::move(token));
+
+      auto held_text = eval_string.substr(held_offset);
+      auto found     = banned_strings.find(held_text);
+
+      if (found.has_value()) {
+        // Find the held token the banned string starts within.
+        size_t index = 0, offset = 0;
+        while (offset + held[index].piece.size() <= found->offset) {
+          offset += held[index++].piece.size();
+        }
+
+        auto position = eval_tokens.size() - held.size() + index;
+        auto &banned  = banned_tokens[position];
+
+        if (banned.size() < BANNED_STRING_MAX_RETRIES) {
+          spdlog::debug(
+              "Banned string found at token #{}, rolling back", position
+          );
+
+          banned.push_back(held[index].token);
+          banned_tokens.erase(
+              banned_tokens.upper_bound(position), banned_tokens.end()
+          );
+
+          sampling_ctx->restore(*held[index].snapshot);
+          rollback(index);
+          drafted.clear();
+          logits_index = -1;
+
+          // Decode the last token again to get its logits.
+          session->clear_cache(session->prompt.size() - 1);
+          batch.batch.n_tokens = 0;
+          batch.add(session->prompt.back(), session->prompt.size() - 1, true);
+
+          auto err = llama_decode(session->context, batch.batch);
+          if (err == 1) {
+            set_last_error("Could not find a KV slot (context overflow)");
+            return -2;
+          } else if (err) {
+            set_last_error("Failed to decode -> {}", err);
+            return -6; // Decoding error.
+          }
+
+          continue;
+        }
+
+        spdlog::warn(
+            "Letting a banned string through at token #{} after {} retries",
+            position,
+            banned.size()
+        );
+      }
+
+      // Stop at the first stop sequence, wherever it starts within a token.
+      if (auto stop = stop_sequences.find(held_text)) {
+        spdlog::info(
+            "Stop: sequence found ({})", stop_sequences.strings[stop->index]
+        );
+
+        // Yield the text preceding the stop sequence, never the latter,
+        // cutting the piece of the token it starts within.
+        size_t n_preceding = 0, offset = 0;
+        while (offset < stop->offset) {
+          auto &piece = held[n_preceding++].piece;
+          piece.resize(std::min(piece.size(), stop->offset - offset));
+          offset += piece.size();
+        }
+
+        n_stop_tokens = held.size() - n_preceding;
+        if (!release(n_preceding)) {
           spdlog::info("Stop: inference callback returned false");
+          outcome.stop_reason = SIMULARITY_GPT_STOP_REASON_CALLBACK;
           break;
         }
+
+        held.clear();
+        outcome.stop_reason   = SIMULARITY_GPT_STOP_REASON_STOP_SEQUENCE;
+        outcome.stop_sequence = stop->index;
+        break;
       }
 
-      bool found = false;
-      for (auto &stop_sequence : stop_sequences) {
-        if (eval_tokens.size() < stop_sequence.size() ||
-            !std::equal(
-                eval_tokens.end() - stop_sequence.size(),
-                eval_tokens.end(),
-                stop_sequence.begin()
-            )) {
-          continue;
-        }
+      // Yield the tokens which may start neither a banned string
+      // nor a stop sequence.
+      auto undecided = std::min(
+          found.has_value() ? held_text.size()
+                            : banned_strings.undecided(held_text),
+          stop_sequences.undecided(held_text)
+      );
 
-        spdlog::info("Stop: sequence found ({})", stop_sequence);
-        found = true;
+      size_t n_release = 0, n_bytes = 0;
+      while (n_release < held.size() &&
+             n_bytes + held[n_release].piece.size() <= undecided) {
+        n_bytes += held[n_release++].piece.size();
+      }
 
+      if (!release(n_release)) {
+        spdlog::info("Stop: inference callback returned false");
+        outcome.stop_reason = SIMULARITY_GPT_STOP_REASON_CALLBACK;
         break;
       }
-      if (found) break;
 
-      // Append the piece to the output string.
-      eval_string += piece;
+      // The accepted drafted token's logits have been computed already.
+      if (is_decoded) {
+        logits_index++;
+        continue;
+      }
+
+      // Make room for the next token, if the policy allows.
+      // NOTE: The last prompt token occupies an extra KV cell.
+      if (session->context_shift &&
+          session->prompt.size() >= llama_n_ctx(session->context)) {
+        session->n_evicted += session->shift_context(session->shift_chunk());
+      }
 
-      // Clear the batch and add the single next token to it.
+      // Draft the following tokens, as many as fit into the context
+      // and are left to infer.
+      if (draft) {
+        const size_t n_room = std::min<size_t>(
+            llama_n_ctx(session->context) - session->prompt.size(),
+            n_eval - eval_tokens.size()
+        );
+
+        try {
+          drafted = draft->draft(
+              session->prompt,
+              std::min<size_t>(n_draft, n_room),
+              llama_n_vocab(session->model())
+          );
+        } catch (std::exception &e) {
+          set_last_error("{}", e.what());
+          return -6;
+        }
+
+        outcome.n_drafted += drafted.size();
+      }
+
+      // Clear the batch and add the next token, followed by the drafted ones.
       batch.batch.n_tokens = 0;
-      batch.add(next, n_prompt + eval_tokens.size(), true);
+      batch.add(next, session->prompt.size() - 1, true);
+      for (size_t i = 0; i < drafted.size(); i++) {
+        batch.add(drafted[i], session->prompt.size() + i, true);
+      }
+      logits_index = 0;
 
       // Decode the next token.
       auto err = llama_decode(session->context, batch.batch);
-      if (err == 1) return -2; // Could not find a KV slot (context overflow).
-      else if (err) {
-        spdlog::warn("Failed to decode -> {}", err);
+      if (err == 1) {
+        set_last_error("Could not find a KV slot (context overflow)");
+        return -2;
+      } else if (err) {
+        set_last_error("Failed to decode -> {}", err);
         return -6; // Decoding error.
       }
     } catch (std::exception &e) {
-      spdlog::error("Unhandled error during inference loop: {}", e.what());
+      set_last_error("Unhandled error during inference loop: {}", e.what());
       return -100;
     }
   }
 
-  auto end = std::chrono::high_resolution_clock::now();
+  // Discard the drafted tokens left unverified.
+  if (!drafted.empty()) session->clear_cache(session->prompt.size());
+
+  // Yield the tokens held back till the end.
+  if (!held.empty() && !release(held.size())) {
+    spdlog::info("Stop: inference callback returned false");
+    outcome.stop_reason   = SIMULARITY_GPT_STOP_REASON_CALLBACK;
+    outcome.stop_sequence = -1;
+  }
+
+  outcome.infer_us  = elapsed_us(infer_start);
+  outcome.n_tokens  = eval_tokens.size();
+  outcome.n_evicted = session->n_evicted;
+  if (outcome.stop_reason == SIMULARITY_GPT_STOP_REASON_STOP_SEQUENCE) {
+    outcome.n_tokens -= n_stop_tokens;
+  }
+
   spdlog::info(
       "Inferenced {} tokens in {:.3f}s ({:.2f} tok/s)",
       eval_tokens.size(),
-      (float)std::chrono::duration_cast<std::chrono::milliseconds>(end - start)
-              .count() /
-          1000,
-      (float)eval_tokens.size() /
-          std::chrono::duration_cast<std::chrono::seconds>(end - start).count()
+      outcome.infer_us / 1e6,
+      outcome.infer_us ? eval_tokens.size() * 1e6 / outcome.infer_us : 0
   );
 
-  return n_prompt + eval_tokens.size();
+  if (draft) {
+    spdlog::info(
+        "Accepted {} of {} drafted tokens",
+        outcome.n_accepted,
+        outcome.n_drafted
+    );
+  }
+
+  if (result != NULL) *result = outcome;
+  return session->prompt.size();
 }
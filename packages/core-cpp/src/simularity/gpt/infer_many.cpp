@@ -0,0 +1,369 @@
+#include <chrono>
+#include <memory>
+#include <string>
+#include <vector>
+
+#include <llama.h>
+#include <simularity.h>
+#include <spdlog/fmt/ranges.h>
+#include <spdlog/spdlog.h>
+
+#include "common.cpp"
+#include "decode.cpp"
+#include "infer.cpp"
+
+/// A sequence being inferred by `simularity_gpt_infer_many`.
+struct InferredSequence {
+  std::unique_ptr<LlamaSamplingContext> sampling_ctx;
+  std::vector<llama_token> tokens;
+
+  /// Tokens not yet yielded to the inference callback,
+  /// as they may start a stop sequence.
+  std::vector<HeldToken> held;
+
+  /// The sequence's logits index in the current batch.
+  int batch_index = -1;
+
+  bool done = false;
+  simularity_gpt_sequence_result result = {
+      0, SIMULARITY_GPT_STOP_REASON_N_EVAL, -1
+  };
+
+  void stop(simularity_gpt_stop_reason reason, unsigned n_trim = 0) {
+    done               = true;
+    result.stop_reason = reason;
+    result.n_tokens    = tokens.size() - n_trim;
+  }
+
+  /// The text of the held tokens.
+  std::string held_text() const {
+    std::string text;
+    for (auto &token : held) text += token.piece;
+    return text;
+  }
+};
+
+/// Removes the inferred sequences from the KV cache upon leaving the scope,
+/// so that the session keeps its committed prompt only.
+struct InferredSequencesGuard {
+  Session *session;
+  unsigned n_seqs;
+
+  ~InferredSequencesGuard() {
+    for (unsigned i = 1; i < n_seqs; i++) {
+      llama_kv_cache_seq_rm(session->context, i, -1, -1);
+    }
+
+    session->clear_cache(session->prompt.size());
+  }
+};
+
+int simularity_gpt_infer_many(
+    unsigned session_id,
+    const char *prompt,
+    unsigned n_eval,
+    unsigned n_seqs,
+    const struct simularity_gpt_inference_options options,
+    llama_progress_callback decode_progress_callback,
+    void *decode_progress_callback_user_data,
+    bool(inference_callback)(
+        unsigned, const simularity_gpt_inference_token *, void *
+    ),
+    void *inference_callback_user_data,
+    struct simularity_gpt_sequence_result *results
+) {
+  clear_last_error();
+
+  if (n_seqs == 0) {
+    set_last_error("The number of sequences must be positive");
+    return -9;
+  }
+
+  if (options.lua_grammar != nullptr) {
+    set_last_error("Lua grammar is not supported for multiple sequences");
+    return -3;
+  }
+
+  if (options.banned_strings_len > 0) {
+    set_last_error("Banned strings are not supported for multiple sequences");
+    return -3;
+  }
+
+  if (options.draft_model_id != nullptr) {
+    set_last_error("A draft model is not supported for multiple sequences");
+    return -3;
+  }
+
+  // Acquire the session.
+  auto locking_result = try_locking_session(session_id);
+  if (!locking_result.has_value()) {
+    set_last_error("Session not found: {}", session_id);
+    return -1;
+  }
+  auto [_, session] = std::move(locking_result.value());
+
+  if (n_seqs > llama_n_seq_max(session->context)) {
+    set_last_error(
+        "The number of sequences ({}) exceeds the session's n_seq_max ({})",
+        n_seqs,
+        llama_n_seq_max(session->context)
+    );
+    return -9;
+  }
+
+  spdlog::info("Inferencing {} sequences for session {}", n_seqs, session_id);
+  session->n_evicted = 0;
+
+  // Create a sampling context per sequence.
+  std::vector<InferredSequence> seqs(n_seqs);
+  for (unsigned i = 0; i < n_seqs; i++) {
+    auto sampling_params = to_sampling_params(options);
+
+    if (options.grammar != nullptr) {
+      sampling_params.grammar = std::string(options.grammar);
+    }
+
+    // Otherwise, the sequences would be identical.
+    if (sampling_params.seed != LLAMA_DEFAULT_SEED) {
+      sampling_params.seed += i;
+    }
+
+    try {
+      set_logit_bias(session->model(), options, sampling_params);
+
+      auto raw_sampling_ctx = llama_sampling_init(sampling_params);
+      if (raw_sampling_ctx == nullptr) {
+        set_last_error("Failed to initialize the sampling context");
+        return -3;
+      }
+
+      seqs[i].sampling_ctx =
+          std::make_unique<LlamaSamplingContext>(raw_sampling_ctx);
+    } catch (std::exception &e) {
+      // Likely a grammar parse or logit bias error.
+      set_last_error("{}", e.what());
+      return -3;
+    }
+
+    seqs[i].done = n_eval == 0;
+  }
+
+  auto stop_sequences =
+      StringSet(options.stop_sequences, options.stop_sequences_len);
+
+  // Tokenize the prompt, defaulting to the committed one.
+  spdlog::debug("Tokenizing the prompt");
+  auto prompt_tokens =
+      prompt == NULL ? session->prompt
+                     : llama_tokenize(session->model(), prompt, false, true);
+
+  if (prompt_tokens.empty()) {
+    set_last_error("The prompt is empty");
+    return -9;
+  }
+
+  const auto n_prompt = prompt_tokens.size();
+
+  try {
+    simularity_gpt_decode_internal(
+        session,
+        prompt_tokens,
+        decode_progress_callback,
+        decode_progress_callback_user_data
+    );
+  } catch (ContextOverflowError &e) {
+    set_last_error("{}", e.what());
+    return -2;
+  } catch (DecodeAbortedError &e) {
+    set_last_error("{}", e.what());
+    return -5;
+  } catch (UnknownDecodeError &e) {
+    set_last_error("{}", e.what());
+    return -4;
+  }
+
+  InferredSequencesGuard guard{session, n_seqs};
+
+  // Share the prompt (but its last token) with all the sequences,
+  // and re-decode the last token per sequence to get the logits.
+  session->clear_cache(n_prompt - 1);
+  session->prompt.pop_back();
+  for (unsigned i = 1; i < n_seqs; i++) {
+    llama_kv_cache_seq_cp(session->context, 0, i, -1, -1);
+  }
+
+  auto batch = Batch(n_seqs);
+  for (unsigned i = 0; i < n_seqs; i++) {
+    seqs[i].batch_index =
+        batch.add(prompt_tokens.back(), n_prompt - 1, true, i) - 1;
+  }
+
+  // Buffers for the inference callback argument.
+  InferenceTokenBuffers buffers;
+  unsigned n_inferred = 0;
+  auto start          = std::chrono::high_resolution_clock::now();
+
+  // Yield the first `n_tokens` held tokens of the sequence `i`
+  // to the inference callback. If the callback stops the sequence,
+  // the following tokens are trimmed.
+  // @return False if the callback has stopped the sequence.
+  auto release = [&](unsigned i, size_t n_tokens) -> bool {
+    auto &seq         = seqs[i];
+    size_t n_released = 0;
+    bool proceed      = true;
+
+    while (n_released < n_tokens && proceed) {
+      auto &token = seq.held[n_released++];
+
+      if (inference_callback != NULL) {
+        auto argument = buffers.build(
+            session->model(),
+            token.token,
+            token.piece,
+            token.logprob,
+            token.top_candidates
+        );
+
+        proceed =
+            inference_callback(i, &argument, inference_callback_user_data);
+      }
+    }
+
+    if (!proceed) {
+      spdlog::info("Sequence #{}: stop by the inference callback", i);
+      seq.stop(
+          SIMULARITY_GPT_STOP_REASON_CALLBACK, seq.held.size() - n_released
+      );
+      seq.held.clear();
+    } else {
+      seq.held.erase(seq.held.begin(), seq.held.begin() + n_released);
+    }
+
+    return proceed;
+  };
+
+  while (batch.batch.n_tokens > 0) {
+    auto err = llama_decode(session->context, batch.batch);
+    if (err == 1) {
+      set_last_error("Could not find a KV slot (context overflow)");
+      return -2;
+    } else if (err) {
+      set_last_error("Failed to decode -> {}", err);
+      return -6; // Decoding error.
+    }
+
+    // The last prompt token is decoded again.
+    if (session->prompt.size() < n_prompt) {
+      session->prompt.push_back(prompt_tokens.back());
+    }
+
+    batch.batch.n_tokens = 0;
+
+    for (unsigned i = 0; i < n_seqs; i++) {
+      auto &seq = seqs[i];
+      if (seq.done) continue;
+
+      llama_token next;
+      try {
+        next = seq.sampling_ctx->sample(session->context, seq.batch_index);
+      } catch (std::exception &e) {
+        set_last_error("Error at sample: {}", e.what());
+        return -7;
+      }
+
+      if (llama_token_is_eog(session->model(), next)) {
+        if (!release(i, seq.held.size())) continue;
+        spdlog::info("Sequence #{}: stop at EOS token", i);
+        seq.stop(SIMULARITY_GPT_STOP_REASON_EOS);
+        continue;
+      }
+
+      // Accept the token.
+      seq.sampling_ctx->accept(session->context, next);
+      seq.tokens.push_back(next);
+      n_inferred++;
+
+      HeldToken token = {.token = next, .logprob = -INFINITY};
+      if (inference_callback != NULL) {
+        token.logprob = seq.sampling_ctx->logprobs(
+            next, std::max(options.n_probs, 0), token.top_candidates
+        );
+      }
+
+      // Convert the token to a piece.
+      try {
+        token.piece = llama_token_to_piece(session->model(), next, true);
+      } catch (std::exception &e) {
+        spdlog::warn("Failed to convert token to piece: ⌘{}", next);
+        token.piece = "�";
+      }
+
+      seq.held.push_back(std::move(token));
+
+      // Stop at the first stop sequence, see `simularity_gpt_infer`.
+      auto held_text = seq.held_text();
+      if (auto stop = stop_sequences.find(held_text)) {
+        spdlog::info(
+            "Sequence #{}: stop sequence found ({})",
+            i,
+            stop_sequences.strings[stop->index]
+        );
+
+        // Yield the text preceding the stop sequence, never the latter,
+        // cutting the piece of the token it starts within.
+        size_t n_preceding = 0, offset = 0;
+        while (offset < stop->offset) {
+          auto &piece = seq.held[n_preceding++].piece;
+          piece.resize(std::min(piece.size(), stop->offset - offset));
+          offset += piece.size();
+        }
+
+        auto n_stop_tokens = seq.held.size() - n_preceding;
+        if (!release(i, n_preceding)) continue;
+
+        seq.held.clear();
+        seq.stop(SIMULARITY_GPT_STOP_REASON_STOP_SEQUENCE, n_stop_tokens);
+        seq.result.stop_sequence = stop->index;
+        continue;
+      }
+
+      if (seq.tokens.size() >= n_eval) {
+        if (release(i, seq.held.size())) {
+          seq.stop(SIMULARITY_GPT_STOP_REASON_N_EVAL);
+        }
+
+        continue;
+      }
+
+      // Yield the tokens which may not start a stop sequence.
+      auto undecided   = stop_sequences.undecided(held_text);
+      size_t n_release = 0, n_bytes = 0;
+      while (n_release < seq.held.size() &&
+             n_bytes + seq.held[n_release].piece.size() <= undecided) {
+        n_bytes += seq.held[n_release++].piece.size();
+      }
+
+      if (!release(i, n_release)) continue;
+
+      // Decode the next token of the sequence.
+      seq.batch_index =
+          batch.add(next, n_prompt + seq.tokens.size() - 1, true, i) - 1;
+    }
+  }
+
+  auto end = std::chrono::high_resolution_clock::now();
+  spdlog::info(
+      "Inferenced {} tokens in {} sequences in {:.3f}s",
+      n_inferred,
+      n_seqs,
+      (float)std::chrono::duration_cast<std::chrono::milliseconds>(end - start)
+              .count() /
+          1000
+  );
+
+  for (unsigned i = 0; i < n_seqs; i++) {
+    results[i] = seqs[i].result;
+  }
+
+  return session->prompt.size();
+}
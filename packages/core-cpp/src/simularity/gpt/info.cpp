@@ -0,0 +1,61 @@
+#include <chrono>
+#include <cstring>
+
+#include <llama.h>
+#include <simularity.h>
+#include <spdlog/spdlog.h>
+
+#include "common.cpp"
+
+using std::chrono::milliseconds;
+
+/// Convert a time point to Unix time in milliseconds.
+static int64_t to_unix_ms(std::chrono::system_clock::time_point t) {
+  return std::chrono::duration_cast<milliseconds>(t.time_since_epoch()).count();
+}
+
+int simularity_gpt_info(
+    unsigned session_id,
+    struct simularity_gpt_session_info *info,
+    char *model_id,
+    size_t model_id_size
+) {
+  spdlog::debug("simularity_gpt_info(session_id: {})", session_id);
+  clear_last_error();
+
+  // NOTE: Inspecting a session does not count as using it.
+  auto lock = try_locking_session(session_id, false);
+  if (!lock.has_value()) {
+    set_last_error("Session not found: {}", session_id);
+    return -1;
+  }
+  auto session = lock->second;
+
+  info->n_ctx        = llama_n_ctx(session->context);
+  info->n_batch      = llama_n_batch(session->context);
+  info->n_prompt     = session->prompt.size();
+  info->expired_at   = GPT_SESSIONS_TTL ? to_unix_ms(session->expired_at) : 0;
+  info->last_used_at = to_unix_ms(session->last_used_at);
+  info->n_evicted    = session->n_evicted;
+
+  const size_t needed = session->model_id.size() + 1;
+  if (model_id_size >= needed) {
+    std::memcpy(model_id, session->model_id.c_str(), needed);
+  }
+
+  return needed;
+}
+
+int simularity_gpt_list(unsigned *session_ids, size_t size) {
+  clear_last_error();
+
+  std::unique_lock sessions_lock(GPT_SESSIONS_MUTEX);
+
+  size_t i = 0;
+  for (auto &[id, _] : GPT_SESSIONS) {
+    if (i < size) session_ids[i] = id;
+    i++;
+  }
+
+  return GPT_SESSIONS.size();
+}
@@ -0,0 +1,93 @@
+#include <memory>
+#include <utility>
+#include <vector>
+
+#include <llama.h>
+#include <simularity.h>
+#include <spdlog/spdlog.h>
+
+#include "common.cpp"
+
+int simularity_gpt_set_loras(
+    unsigned session_id,
+    unsigned n_loras,
+    const char *const *lora_ids,
+    const float *scales
+) {
+  spdlog::debug(
+      "simularity_gpt_set_loras(session_id: {}, n_loras: {})",
+      session_id,
+      n_loras
+  );
+  clear_last_error();
+
+  // Acquire the models mutex, which also guards the adapters.
+  spdlog::debug("Acquiring models lock");
+  std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+
+  auto locking_result = try_locking_session(session_id);
+  if (!locking_result.has_value()) {
+    set_last_error("Session not found: {}", session_id);
+    return -1;
+  }
+  auto [_, session] = std::move(locking_result.value());
+
+  // NOTE: The model may have been reloaded with the same ID.
+  auto model = LLAMA_MODELS.find(session->model_id);
+  if (model == LLAMA_MODELS.end() ||
+      model->second->model != session->model()) {
+    set_last_error("Session model has been unloaded: {}", session->model_id);
+    return -4;
+  }
+
+  std::vector<std::pair<std::shared_ptr<LlamaLoraAdapter>, float>> loras;
+  for (unsigned i = 0; i < n_loras; i++) {
+    auto lora = LLAMA_LORAS.find(lora_ids[i]);
+    if (lora == LLAMA_LORAS.end()) {
+      set_last_error("LoRA adapter does not exist: {}", lora_ids[i]);
+      return -2;
+    }
+
+    auto adapter = lora->second->adapter_for(model->second);
+    if (adapter == nullptr) {
+      set_last_error(
+          "Failed to apply LoRA adapter {} to model {}",
+          lora_ids[i],
+          session->model_id
+      );
+      return -3;
+    }
+
+    loras.push_back({adapter, scales[i]});
+  }
+
+  models_lock.unlock();
+
+  if (loras == session->loras) {
+    spdlog::debug("The LoRA adapters are the same");
+    return 0;
+  }
+
+  for (auto &lora : session->loras) {
+    llama_lora_adapter_remove(session->context, lora.first->adapter);
+  }
+
+  for (auto &[lora, scale] : loras) {
+    llama_lora_adapter_set(session->context, lora->adapter, scale);
+  }
+
+  // Every layer output depends on the adapters, hence the whole cache.
+  const unsigned n_cleared = session->prompt.size();
+  session->clear_cache();
+  session->prompt.clear();
+  session->loras = std::move(loras);
+
+  spdlog::info(
+      "Set {} LoRA adapter(s) for session {}, cleared {} tokens",
+      session->loras.size(),
+      session_id,
+      n_cleared
+  );
+
+  return n_cleared;
+}
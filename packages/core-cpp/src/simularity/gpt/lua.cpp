@@ -0,0 +1,215 @@
+#pragma once
+
+#include <chrono>
+#include <cstdlib>
+#include <memory>
+#include <optional>
+#include <string>
+#include <utility>
+
+#include <simularity.h>
+#include <spdlog/spdlog.h>
+
+#include "sol/sol.hpp"
+
+// Defined in lua-cjson.c
+extern "C" int luaopen_cjson(lua_State *l);
+
+/// The instruction count hook is called every that many VM instructions.
+static const int LUA_HOOK_INSTRUCTIONS = 1000;
+
+/// Generous for a grammar script, yet stopping a runaway loop.
+/// The libraries are the ones available before the limits were introduced.
+static const simularity_gpt_lua_limits LUA_LIMITS_DEFAULT = {
+    .max_instructions = 10000000,
+    .timeout_ms       = 1000,
+    .max_memory       = 64 * 1024 * 1024,
+    .libraries        = SIMULARITY_LUA_LIB_BASE | SIMULARITY_LUA_LIB_STRING |
+                 SIMULARITY_LUA_LIB_TABLE | SIMULARITY_LUA_LIB_CJSON,
+};
+
+/// A Lua state running an untrusted grammar script,
+/// with the script calls limited by `simularity_gpt_lua_limits`.
+class LuaSandbox {
+public:
+  /// Create a sandbox with the allowed libraries opened.
+  /// @throws sol::error if the state can not be initialized.
+  static std::unique_ptr<LuaSandbox>
+  create(const simularity_gpt_lua_limits &limits) {
+    // NOTE: The allocator refers to the sandbox, hence the stable address.
+    auto sandbox = std::unique_ptr<LuaSandbox>(new LuaSandbox(limits));
+    sandbox->lua.emplace(sol::default_at_panic, allocate, sandbox.get());
+    sandbox->open_libraries();
+    lua_sethook(
+        sandbox->lua->lua_state(), hook, LUA_MASKCOUNT, LUA_HOOK_INSTRUCTIONS
+    );
+
+    return sandbox;
+  }
+
+  /// Load the script and run its top-level chunk.
+  /// @return An error message with a traceback, if any.
+  std::optional<std::string> load(const char *script) {
+    sol::load_result chunk = lua->load(script, "=grammar");
+    if (!chunk.valid()) {
+      sol::error err = chunk;
+      return describe_error(err.what());
+    }
+
+    sol::protected_function function = chunk;
+    auto result                      = call(function);
+    if (!result.valid()) {
+      sol::error err = result;
+      return describe_error(err.what());
+    }
+
+    return std::nullopt;
+  }
+
+  /// Get a global function, callable with `call`.
+  /// @return `std::nullopt` if the global is not a function.
+  std::optional<sol::protected_function> function(const char *name) {
+    sol::object object = (*lua)[name];
+    if (object.get_type() != sol::type::function) return std::nullopt;
+    return object.as<sol::protected_function>();
+  }
+
+  /// Call a function with the limits reset.
+  template <typename... Args>
+  sol::protected_function_result
+  call(sol::protected_function &function, Args &&...args) {
+    instructions    = 0;
+    memory_exceeded = false;
+
+    auto timeout = std::chrono::milliseconds(limits.timeout_ms);
+    deadline     = std::chrono::steady_clock::now() + timeout;
+
+    function.set_error_handler(traceback);
+
+    return function(std::forward<Args>(args)...);
+  }
+
+  /// Describe an error of the last call.
+  /// A memory error has no traceback, as the handler is not called then.
+  std::string describe_error(const char *what) {
+    if (memory_exceeded && std::string(what) == "not enough memory") {
+      return fmt::format("Memory limit exceeded ({} bytes)", limits.max_memory);
+    }
+
+    return what;
+  }
+
+  ~LuaSandbox() {
+    // NOTE: References shall be released before the state is closed.
+    traceback = sol::reference();
+    lua.reset();
+  }
+
+private:
+  LuaSandbox(const simularity_gpt_lua_limits &limits) : limits(limits) {}
+
+  const simularity_gpt_lua_limits limits;
+
+  /// The bytes currently allocated by the state.
+  size_t memory = 0;
+
+  /// Set when an allocation is refused.
+  bool memory_exceeded = false;
+
+  /// The VM instructions run during the current call (approximately).
+  unsigned long long instructions = 0;
+
+  /// When the current call times out.
+  std::chrono::steady_clock::time_point deadline;
+
+  /// `debug.traceback`, kept after the `debug` library is removed.
+  sol::reference traceback;
+
+  std::optional<sol::state> lua;
+
+  void open_libraries() {
+    auto libraries = limits.libraries;
+
+    // The traceback handler is the only function kept from `debug`,
+    // which would allow to escape the sandbox (e.g. `debug.sethook`).
+    lua->open_libraries(sol::lib::debug);
+    traceback       = (*lua)["debug"]["traceback"];
+    (*lua)["debug"] = sol::lua_nil;
+
+    if (libraries & SIMULARITY_LUA_LIB_BASE) {
+      lua->open_libraries(sol::lib::base);
+
+      // Loading files or bytecode is not allowed.
+      for (auto name : {"dofile", "loadfile", "load", "loadstring"}) {
+        (*lua)[name] = sol::lua_nil;
+      }
+    }
+
+    if (libraries & SIMULARITY_LUA_LIB_STRING) {
+      lua->open_libraries(sol::lib::string);
+      (*lua)["string"]["dump"] = sol::lua_nil;
+    }
+
+    if (libraries & SIMULARITY_LUA_LIB_TABLE) {
+      lua->open_libraries(sol::lib::table);
+    }
+
+    if (libraries & SIMULARITY_LUA_LIB_MATH) {
+      lua->open_libraries(sol::lib::math);
+    }
+
+    if (libraries & SIMULARITY_LUA_LIB_CJSON) {
+      // Sets the `cjson` global and returns the table.
+      luaopen_cjson(lua->lua_state());
+      lua_pop(lua->lua_state(), 1);
+    }
+  }
+
+  /// A `lua_Alloc` accounting for the memory limit.
+  static void *allocate(void *ud, void *ptr, size_t osize, size_t nsize) {
+    auto sandbox = static_cast<LuaSandbox *>(ud);
+
+    if (nsize == 0) {
+      free(ptr);
+      sandbox->memory -= osize;
+      return NULL;
+    }
+
+    // NOTE: Lua assumes that shrinking never fails.
+    if (sandbox->limits.max_memory && nsize > osize &&
+        sandbox->memory - osize + nsize > sandbox->limits.max_memory) {
+      sandbox->memory_exceeded = true;
+      return NULL;
+    }
+
+    auto result = realloc(ptr, nsize);
+    if (result != NULL) sandbox->memory = sandbox->memory - osize + nsize;
+    return result;
+  }
+
+  /// A `lua_Hook` enforcing the instruction and time limits.
+  /// NOTE: `luaL_error` does not return, hence no destructors here.
+  /// NOTE: `lua_pushfstring` does not support `%u`.
+  static void hook(lua_State *L, lua_Debug *) {
+    void *ud;
+    lua_getallocf(L, &ud);
+    auto sandbox = static_cast<LuaSandbox *>(ud);
+
+    sandbox->instructions += LUA_HOOK_INSTRUCTIONS;
+    if (sandbox->limits.max_instructions &&
+        sandbox->instructions > sandbox->limits.max_instructions) {
+      luaL_error(
+          L,
+          "Instruction limit exceeded (%d)",
+          (int)sandbox->limits.max_instructions
+      );
+    }
+
+    if (sandbox->limits.timeout_ms &&
+        std::chrono::steady_clock::now() > sandbox->deadline) {
+      luaL_error(
+          L, "Timeout exceeded (%d ms)", (int)sandbox->limits.timeout_ms
+      );
+    }
+  }
+};
@@ -0,0 +1,221 @@
+#include <cstring>
+
+#include <llama.h>
+#include <simularity.h>
+#include <spdlog/spdlog.h>
+
+#include "common.cpp"
+
+/// Magic bytes of a session state buffer ("SIMS").
+static const uint32_t SESSION_STATE_MAGIC   = 0x534d4953;
+static const uint32_t SESSION_STATE_VERSION = 1;
+
+/// A session state buffer header, followed by `n_tokens` prompt tokens
+/// and then by the llama context state.
+struct SessionStateHeader {
+  uint32_t magic;
+  uint32_t version;
+  uint32_t n_tokens;
+};
+
+int simularity_gpt_state_save_file(unsigned session_id, const char *path) {
+  spdlog::debug(
+      "simularity_gpt_state_save_file(session_id: {}, path: {})",
+      session_id,
+      path
+  );
+  clear_last_error();
+
+  auto lock = try_locking_session(session_id);
+  if (!lock.has_value()) {
+    set_last_error("Session not found: {}", session_id);
+    return -1;
+  }
+  auto session = lock->second;
+
+  if (!llama_state_save_file(
+          session->context,
+          path,
+          session->prompt.data(),
+          session->prompt.size()
+      )) {
+    set_last_error("Failed to save session state to file: {}", path);
+    return -2;
+  }
+
+  spdlog::info(
+      "Saved session {} state ({} tokens) to file: {}",
+      session_id,
+      session->prompt.size(),
+      path
+  );
+
+  return 0;
+}
+
+int simularity_gpt_state_load_file(unsigned session_id, const char *path) {
+  spdlog::debug(
+      "simularity_gpt_state_load_file(session_id: {}, path: {})",
+      session_id,
+      path
+  );
+  clear_last_error();
+
+  auto lock = try_locking_session(session_id);
+  if (!lock.has_value()) {
+    set_last_error("Session not found: {}", session_id);
+    return -1;
+  }
+  auto session = lock->second;
+
+  std::vector<llama_token> tokens(llama_n_ctx(session->context));
+  size_t n_tokens = 0;
+
+  if (!llama_state_load_file(
+          session->context, path, tokens.data(), tokens.size(), &n_tokens
+      )) {
+    set_last_error("Failed to load session state from file: {}", path);
+
+    // The KV cache may be partially overwritten.
+    session->clear_cache();
+    session->prompt.clear();
+
+    return -2;
+  }
+
+  tokens.resize(n_tokens);
+  session->prompt = tokens;
+
+  spdlog::info(
+      "Loaded session {} state ({} tokens) from file: {}",
+      session_id,
+      n_tokens,
+      path
+  );
+
+  return n_tokens;
+}
+
+int64_t
+simularity_gpt_state_save(unsigned session_id, uint8_t *dst, size_t size) {
+  spdlog::debug(
+      "simularity_gpt_state_save(session_id: {}, size: {})", session_id, size
+  );
+  clear_last_error();
+
+  auto lock = try_locking_session(session_id);
+  if (!lock.has_value()) {
+    set_last_error("Session not found: {}", session_id);
+    return -1;
+  }
+  auto session = lock->second;
+
+  const size_t tokens_size = session->prompt.size() * sizeof(llama_token);
+  const size_t header_size = sizeof(SessionStateHeader) + tokens_size;
+  const size_t total_size =
+      header_size + llama_state_get_size(session->context);
+
+  if (size < total_size) {
+    return total_size;
+  }
+
+  SessionStateHeader header = {
+      SESSION_STATE_MAGIC,
+      SESSION_STATE_VERSION,
+      static_cast<uint32_t>(session->prompt.size())
+  };
+  std::memcpy(dst, &header, sizeof(header));
+  std::memcpy(dst + sizeof(header), session->prompt.data(), tokens_size);
+
+  const size_t written = llama_state_get_data(
+      session->context, dst + header_size, size - header_size
+  );
+  if (written == 0) {
+    set_last_error("Failed to copy the llama context state");
+    return -2;
+  }
+
+  spdlog::info(
+      "Saved session {} state ({} tokens, {} bytes)",
+      session_id,
+      session->prompt.size(),
+      header_size + written
+  );
+
+  return header_size + written;
+}
+
+int simularity_gpt_state_load(
+    unsigned session_id, const uint8_t *src, size_t size
+) {
+  spdlog::debug(
+      "simularity_gpt_state_load(session_id: {}, size: {})", session_id, size
+  );
+  clear_last_error();
+
+  auto lock = try_locking_session(session_id);
+  if (!lock.has_value()) {
+    set_last_error("Session not found: {}", session_id);
+    return -1;
+  }
+  auto session = lock->second;
+
+  SessionStateHeader header;
+  if (size < sizeof(header)) {
+    set_last_error("Session state is too short: {} bytes", size);
+    return -2;
+  }
+  std::memcpy(&header, src, sizeof(header));
+
+  if (header.magic != SESSION_STATE_MAGIC) {
+    set_last_error("Invalid session state magic: {:#x}", header.magic);
+    return -2;
+  }
+
+  if (header.version != SESSION_STATE_VERSION) {
+    set_last_error("Unsupported session state version: {}", header.version);
+    return -2;
+  }
+
+  if (header.n_tokens > llama_n_ctx(session->context)) {
+    set_last_error(
+        "Session state has more tokens than the context size: {} > {}",
+        header.n_tokens,
+        llama_n_ctx(session->context)
+    );
+    return -2;
+  }
+
+  const size_t tokens_size = header.n_tokens * sizeof(llama_token);
+  const size_t header_size = sizeof(header) + tokens_size;
+  if (size < header_size) {
+    set_last_error("Session state is truncated: {} bytes", size);
+    return -2;
+  }
+
+  std::vector<llama_token> tokens(header.n_tokens);
+  std::memcpy(tokens.data(), src + sizeof(header), tokens_size);
+
+  if (llama_state_set_data(
+          session->context, src + header_size, size - header_size
+      ) == 0) {
+    set_last_error("Failed to restore the llama context state");
+
+    // The KV cache may be partially overwritten.
+    session->clear_cache();
+    session->prompt.clear();
+
+    return -2;
+  }
+
+  session->prompt = tokens;
+
+  spdlog::info(
+      "Loaded session {} state ({} tokens, {} bytes)",
+      session_id,
+      header.n_tokens,
+      size
+  );
+
+  return header.n_tokens;
+}
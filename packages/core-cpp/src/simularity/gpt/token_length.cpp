@@ -6,6 +6,7 @@
 
 int simularity_gpt_token_length(const char *model_id, const char *prompt) {
   spdlog::debug("simularity_gpt_token_length(model_id: {})", model_id);
+  clear_last_error();
 
   // Acquire the models mutex.
   spdlog::debug("Acquiring models lock");
@@ -14,7 +15,7 @@ int simularity_gpt_token_length(const char *model_id, const char *prompt) {
   // Check if the model exists.
   spdlog::debug("Checking model: {}", model_id);
   if (LLAMA_MODELS.find(model_id) == LLAMA_MODELS.end()) {
-    spdlog::error("Model does not exist: {}", model_id);
+    set_last_error("Model does not exist: {}", model_id);
     return -1; // Model does not exist.
   }
   spdlog::info("Model exists: {}", model_id);
@@ -25,7 +26,7 @@ int simularity_gpt_token_length(const char *model_id, const char *prompt) {
         llama_tokenize(LLAMA_MODELS[model_id]->model, prompt, false, false);
     return prompt_tokens.size();
   } catch (const std::runtime_error &e) {
-    spdlog::error("Failed to tokenize the prompt: {}", e.what());
+    set_last_error("Failed to tokenize the prompt: {}", e.what());
     return -2;
   }
 }
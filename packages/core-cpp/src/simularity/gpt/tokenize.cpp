@@ -0,0 +1,150 @@
+#include <cstring>
+
+#include <llama.h>
+#include <simularity.h>
+#include <spdlog/spdlog.h>
+
+#include "common.cpp"
+
+int simularity_gpt_tokenize(
+    const char *model_id,
+    const char *text,
+    bool add_special,
+    bool parse_special,
+    int32_t *tokens,
+    unsigned n_tokens_max
+) {
+  spdlog::debug(
+      "simularity_gpt_tokenize(model_id: {}, add_special: {}, "
+      "parse_special: {}, n_tokens_max: {})",
+      model_id,
+      add_special,
+      parse_special,
+      n_tokens_max
+  );
+  clear_last_error();
+
+  // Acquire the models mutex.
+  spdlog::debug("Acquiring models lock");
+  std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+
+  // Check if the model exists.
+  if (LLAMA_MODELS.find(model_id) == LLAMA_MODELS.end()) {
+    set_last_error("Model does not exist: {}", model_id);
+    return -1; // Model does not exist.
+  }
+
+  try {
+    auto result = llama_tokenize(
+        LLAMA_MODELS[model_id]->model, text, add_special, parse_special
+    );
+
+    if (result.size() <= n_tokens_max) {
+      std::copy(result.begin(), result.end(), tokens);
+    }
+
+    return result.size();
+  } catch (const std::runtime_error &e) {
+    set_last_error("Failed to tokenize the text: {}", e.what());
+    return -2;
+  }
+}
+
+int simularity_gpt_detokenize(
+    const char *model_id,
+    const int32_t *tokens,
+    unsigned n_tokens,
+    char *text,
+    unsigned text_len_max
+) {
+  spdlog::debug(
+      "simularity_gpt_detokenize(model_id: {}, n_tokens: {}, "
+      "text_len_max: {})",
+      model_id,
+      n_tokens,
+      text_len_max
+  );
+  clear_last_error();
+
+  // Acquire the models mutex.
+  spdlog::debug("Acquiring models lock");
+  std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+
+  // Check if the model exists.
+  if (LLAMA_MODELS.find(model_id) == LLAMA_MODELS.end()) {
+    set_last_error("Model does not exist: {}", model_id);
+    return -1; // Model does not exist.
+  }
+
+  auto model   = LLAMA_MODELS[model_id]->model;
+  auto n_vocab = llama_n_vocab(model);
+
+  for (unsigned i = 0; i < n_tokens; i++) {
+    if (tokens[i] < 0 || tokens[i] >= n_vocab) {
+      set_last_error(
+          "Token {} at position {} is out of vocabulary range (0..{})",
+          tokens[i],
+          i,
+          n_vocab
+      );
+      return -3;
+    }
+  }
+
+  try {
+    auto result = llama_detokenize(
+        model, std::vector<llama_token>(tokens, tokens + n_tokens), false, true
+    );
+
+    if (result.size() <= text_len_max) {
+      std::memcpy(text, result.data(), result.size());
+    }
+
+    return result.size();
+  } catch (const std::runtime_error &e) {
+    set_last_error("Failed to detokenize the tokens: {}", e.what());
+    return -2;
+  }
+}
+
+int simularity_gpt_token_to_piece(
+    const char *model_id, int32_t token, char *piece, unsigned piece_len_max
+) {
+  spdlog::debug(
+      "simularity_gpt_token_to_piece(model_id: {}, token: {})", model_id, token
+  );
+  clear_last_error();
+
+  // Acquire the models mutex.
+  spdlog::debug("Acquiring models lock");
+  std::unique_lock models_lock(LLAMA_MODELS_MUTEX);
+
+  // Check if the model exists.
+  if (LLAMA_MODELS.find(model_id) == LLAMA_MODELS.end()) {
+    set_last_error("Model does not exist: {}", model_id);
+    return -1; // Model does not exist.
+  }
+
+  auto model   = LLAMA_MODELS[model_id]->model;
+  auto n_vocab = llama_n_vocab(model);
+
+  if (token < 0 || token >= n_vocab) {
+    set_last_error(
+        "Token {} is out of vocabulary range (0..{})", token, n_vocab
+    );
+    return -3;
+  }
+
+  try {
+    auto result = llama_token_to_piece(model, token, true);
+
+    if (result.size() <= piece_len_max) {
+      std::memcpy(piece, result.data(), result.size());
+    }
+
+    return result.size();
+  } catch (const std::runtime_error &e) {
+    set_last_error("Failed to convert the token to piece: {}", e.what());
+    return -2;
+  }
+}
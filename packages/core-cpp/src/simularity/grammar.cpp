@@ -0,0 +1,57 @@
+#pragma once
+
+#include <cstring>
+#include <llama.h>
+#include <simularity.h>
+#include <spdlog/spdlog.h>
+
+#include "./error.cpp"
+
+// NOTE: `parse_grammar` is included with `./gpt/infer.cpp`.
+
+/**
+  Find the offset of the source suffix a parse error message ends with,
+  i.e. "expecting name at <the rest of the source>", and cut it off.
+ */
+static int64_t cut_error_offset(std::string &message, const char *src) {
+  const auto src_len = strlen(src);
+
+  auto at = message.find(" at ");
+
+  while (at != std::string::npos) {
+    const auto rest     = message.c_str() + at + 4;
+    const auto rest_len = message.size() - at - 4;
+
+    if (rest_len <= src_len &&
+        memcmp(src + src_len - rest_len, rest, rest_len) == 0) {
+      message.resize(at);
+      return src_len - rest_len;
+    }
+
+    at = message.find(" at ", at + 1);
+  }
+
+  return -1;
+}
+
+int simularity_grammar_validate(const char *grammar, int64_t *error_offset) {
+  spdlog::debug("simularity_grammar_validate()");
+  clear_last_error();
+
+  if (error_offset) *error_offset = -1;
+
+  try {
+    auto parse_result = parse_grammar(grammar);
+    llama_grammar_free(parse_result->second);
+    delete parse_result;
+
+    return 0;
+  } catch (const std::runtime_error &e) {
+    std::string message = e.what();
+    auto offset         = cut_error_offset(message, grammar);
+    if (error_offset) *error_offset = offset;
+
+    set_last_error("{}", message);
+    return -1;
+  }
+}
@@ -0,0 +1,119 @@
+#pragma once
+
+#include <algorithm>
+#include <atomic>
+#include <memory>
+#include <mutex>
+#include <string>
+
+#include <llama.h>
+#include <simularity.h>
+#include <spdlog/sinks/base_sink.h>
+#include <spdlog/sinks/stdout_color_sinks.h>
+#include <spdlog/spdlog.h>
+
+/// The log callback, if any. See `simularity_init_options.log_callback`.
+static simularity_log_callback LOG_CALLBACK = NULL;
+static void *LOG_CALLBACK_USER_DATA         = NULL;
+
+/// Guards the log callback, so that it is not called concurrently.
+static std::mutex LOG_CALLBACK_MUTEX;
+
+/// The minimum level of the llama.cpp messages to log.
+static std::atomic<int> LOG_LEVEL = SIMULARITY_LOG_LEVEL_DEBUG;
+
+/// Pass a message to the log callback.
+/// @return false if there is no log callback.
+static bool
+log_to_callback(int level, const char *target, const char *message) {
+  std::lock_guard lock(LOG_CALLBACK_MUTEX);
+  if (LOG_CALLBACK == NULL) return false;
+
+  LOG_CALLBACK(level, target, message, LOG_CALLBACK_USER_DATA);
+  return true;
+}
+
+/// A spdlog sink passing the messages to the log callback.
+class LogCallbackSink
+    : public spdlog::sinks::base_sink<spdlog::details::null_mutex> {
+protected:
+  void sink_it_(const spdlog::details::log_msg &msg) override {
+    // NOTE: The callback has no level above error.
+    int level = std::min<int>(msg.level, SIMULARITY_LOG_LEVEL_ERROR);
+    std::string message(msg.payload.data(), msg.payload.size());
+    log_to_callback(level, "simularity", message.c_str());
+  }
+
+  void flush_() override {}
+};
+
+static int from_ggml_log_level(enum ggml_log_level level) {
+  switch (level) {
+  case GGML_LOG_LEVEL_ERROR:
+    return SIMULARITY_LOG_LEVEL_ERROR;
+  case GGML_LOG_LEVEL_WARN:
+    return SIMULARITY_LOG_LEVEL_WARN;
+  case GGML_LOG_LEVEL_INFO:
+    return SIMULARITY_LOG_LEVEL_INFO;
+  default:
+    return SIMULARITY_LOG_LEVEL_DEBUG;
+  }
+}
+
+/**
+  A `ggml_log_callback` passing the llama.cpp messages either to the log
+  callback, or to spdlog. A message may come in parts (e.g. the progress
+  dots), hence the buffering until a newline.
+ */
+static void
+llama_log_callback(enum ggml_log_level level, const char *text, void *) {
+  static std::mutex mutex;
+  static std::string buffer;
+
+  std::lock_guard lock(mutex);
+  buffer += text;
+
+  size_t newline;
+  while ((newline = buffer.find('\n')) != std::string::npos) {
+    auto line = buffer.substr(0, newline);
+    buffer.erase(0, newline + 1);
+
+    const int simularity_level = from_ggml_log_level(level);
+    if (simularity_level < LOG_LEVEL || line.empty()) continue;
+
+    if (!log_to_callback(simularity_level, "llama.cpp", line.c_str())) {
+      spdlog::log(
+          static_cast<spdlog::level::level_enum>(simularity_level),
+          "[llama.cpp] {}",
+          line
+      );
+    }
+  }
+}
+
+/// Set up the logging of both the library and llama.cpp.
+static void init_logging(
+    int level, simularity_log_callback callback, void *callback_user_data
+) {
+  {
+    std::lock_guard lock(LOG_CALLBACK_MUTEX);
+    LOG_CALLBACK           = callback;
+    LOG_CALLBACK_USER_DATA = callback_user_data;
+  }
+
+  LOG_LEVEL = level;
+
+  if (callback != NULL) {
+    spdlog::set_default_logger(std::make_shared<spdlog::logger>(
+        "simularity", std::make_shared<LogCallbackSink>()
+    ));
+  } else {
+    spdlog::set_default_logger(std::make_shared<spdlog::logger>(
+        "simularity", std::make_shared<spdlog::sinks::stdout_color_sink_mt>()
+    ));
+    spdlog::set_pattern("[%H:%M:%S.%e] [libsimularity] [%^%l%$] %v");
+  }
+
+  spdlog::set_level(static_cast<spdlog::level::level_enum>(level));
+  llama_log_set(llama_log_callback, NULL);
+}
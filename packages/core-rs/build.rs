@@ -8,6 +8,11 @@ fn main() {
     println!("cargo:rerun-if-changed=../core-cpp/src");
     println!("cargo:rerun-if-changed=../core-cpp/vendor");
 
+    // The mock backend needs no native library.
+    if env::var("CARGO_FEATURE_MOCK").is_ok() {
+        return;
+    }
+
     #[cfg(feature = "cuda")]
     {
         #[cfg(target_os = "windows")]
@@ -0,0 +1,253 @@
+use crate::{
+    ffi,
+    gpt::{
+        self,
+        embed::{Normalization, Pooling},
+        infer::InferenceToken,
+        InferOutcome, SessionOptions,
+    },
+    model, Error, LoadedModel, ModelLoadOptions,
+};
+
+#[cfg(feature = "mock")]
+mod mock;
+#[cfg(feature = "mock")]
+pub use mock::{MockBackend, MockOp};
+
+/// The backend of [`crate::ModelRegistry::global`], used by the free functions
+/// and [`gpt::Session`]: [`FfiBackend`], or `MockBackend` with the `mock`
+/// feature (the other native functions panic then).
+#[cfg(not(feature = "mock"))]
+pub type DefaultBackend = FfiBackend;
+#[cfg(feature = "mock")]
+pub type DefaultBackend = MockBackend;
+
+/// A progress callback, return `true` to continue.
+pub type ProgressCallback<'a> = &'a mut dyn FnMut(f32) -> bool;
+
+/// An inference callback, return `true` to continue.
+pub type InferenceCallback<'a> = &'a mut dyn FnMut(&InferenceToken) -> bool;
+
+/// The operations on models and sessions, so that the code depending on them
+/// can be tested without the native library with the `MockBackend` of the
+/// `mock` feature. [`FfiBackend`] is the native implementation.
+///
+/// The methods mirror the free functions of the same names,
+/// see them for the arguments and the errors. Unlike the free functions,
+/// the model operations are not serialized, see [`crate::ModelRegistry`].
+pub trait Backend: Send + Sync {
+    /// See [`crate::model_load`].
+    fn model_load(
+        &self,
+        model_path: &str,
+        model_id: &str,
+        options: Option<ModelLoadOptions>,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<ffi::SimularityModelInfo, Error>;
+
+    /// See [`crate::model_unload`]. Unloads a model even if used by sessions.
+    fn model_unload(&self, model_id: &str) -> Result<(), Error>;
+
+    /// See [`crate::model_get_hash_by_id`].
+    fn model_hash(&self, model_id: &str) -> Result<u64, Error>;
+
+    /// See [`crate::model_get_info`].
+    fn model_info(&self, model_id: &str) -> Result<ffi::SimularityModelInfo, Error>;
+
+    /// See [`crate::models_list`].
+    fn model_list(&self) -> Result<Vec<LoadedModel>, Error>;
+
+    /// IDs of the live sessions using the model, including the expired ones
+    /// which have not been removed yet, see [`crate::models_list`].
+    fn model_sessions(&self, model_id: &str) -> Result<Vec<u32>, Error>;
+
+    /// See [`gpt::create`].
+    fn create(
+        &self,
+        model_id: &str,
+        options: Option<SessionOptions>,
+        initial_prompt: Option<&str>,
+        state_file_path: Option<&str>,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<u32, Error>;
+
+    /// See [`gpt::decode`].
+    fn decode(
+        &self,
+        session_id: u32,
+        prompt: &str,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<u32, Error>;
+
+    /// See [`gpt::infer`].
+    fn infer(
+        &self,
+        session_id: u32,
+        prompt: Option<&str>,
+        n_eval: u32,
+        options: Option<gpt::infer::Options>,
+        decode_progress_callback: Option<ProgressCallback>,
+        inference_callback: Option<InferenceCallback>,
+    ) -> Result<InferOutcome, Error>;
+
+    /// See [`gpt::fork`].
+    fn fork(&self, session_id: u32) -> Result<u32, Error>;
+
+    /// See [`gpt::tokenize`].
+    fn tokenize(
+        &self,
+        model_id: &str,
+        text: &str,
+        add_special: bool,
+        parse_special: bool,
+    ) -> Result<Vec<i32>, Error>;
+
+    /// See [`gpt::detokenize`].
+    fn detokenize(&self, model_id: &str, tokens: &[i32]) -> Result<String, Error>;
+
+    /// See [`gpt::token_to_piece`].
+    fn token_to_piece(&self, model_id: &str, token: i32) -> Result<String, Error>;
+
+    /// See [`gpt::token_length`].
+    fn token_length(&self, model_id: &str, prompt: &str) -> Result<u32, Error>;
+
+    /// See [`gpt::embed`].
+    fn embed(
+        &self,
+        model_id: &str,
+        texts: &[&str],
+        pooling: Pooling,
+        normalization: Normalization,
+    ) -> Result<Vec<Vec<f32>>, Error>;
+
+    /// See [`gpt::touch`].
+    fn touch(&self, session_id: u32) -> bool;
+
+    /// See [`gpt::destroy`].
+    fn destroy(&self, session_id: u32) -> Result<(), Error>;
+}
+
+/// The native library backend.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FfiBackend;
+
+impl Backend for FfiBackend {
+    fn model_load(
+        &self,
+        model_path: &str,
+        model_id: &str,
+        options: Option<ModelLoadOptions>,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<ffi::SimularityModelInfo, Error> {
+        model::load(model_path, model_id, options, progress_callback)
+    }
+
+    fn model_unload(&self, model_id: &str) -> Result<(), Error> {
+        model::unload(model_id)
+    }
+
+    fn model_hash(&self, model_id: &str) -> Result<u64, Error> {
+        model::hash_by_id(model_id)
+    }
+
+    fn model_info(&self, model_id: &str) -> Result<ffi::SimularityModelInfo, Error> {
+        model::get_info(model_id)
+    }
+
+    fn model_list(&self) -> Result<Vec<LoadedModel>, Error> {
+        model::list()
+    }
+
+    fn model_sessions(&self, model_id: &str) -> Result<Vec<u32>, Error> {
+        model::sessions(model_id)
+    }
+
+    fn create(
+        &self,
+        model_id: &str,
+        options: Option<SessionOptions>,
+        initial_prompt: Option<&str>,
+        state_file_path: Option<&str>,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<u32, Error> {
+        gpt::create::create_native(
+            model_id,
+            options,
+            initial_prompt,
+            state_file_path,
+            progress_callback,
+        )
+    }
+
+    fn decode(
+        &self,
+        session_id: u32,
+        prompt: &str,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<u32, Error> {
+        gpt::decode::decode_native(session_id, prompt, progress_callback)
+    }
+
+    fn infer(
+        &self,
+        session_id: u32,
+        prompt: Option<&str>,
+        n_eval: u32,
+        options: Option<gpt::infer::Options>,
+        decode_progress_callback: Option<ProgressCallback>,
+        inference_callback: Option<InferenceCallback>,
+    ) -> Result<InferOutcome, Error> {
+        gpt::infer::infer_native(
+            session_id,
+            prompt,
+            n_eval,
+            options,
+            decode_progress_callback,
+            inference_callback,
+        )
+    }
+
+    fn fork(&self, session_id: u32) -> Result<u32, Error> {
+        gpt::fork::fork_native(session_id)
+    }
+
+    fn tokenize(
+        &self,
+        model_id: &str,
+        text: &str,
+        add_special: bool,
+        parse_special: bool,
+    ) -> Result<Vec<i32>, Error> {
+        gpt::tokenize::tokenize_native(model_id, text, add_special, parse_special)
+    }
+
+    fn detokenize(&self, model_id: &str, tokens: &[i32]) -> Result<String, Error> {
+        gpt::tokenize::detokenize_native(model_id, tokens)
+    }
+
+    fn token_to_piece(&self, model_id: &str, token: i32) -> Result<String, Error> {
+        gpt::tokenize::token_to_piece_native(model_id, token)
+    }
+
+    fn token_length(&self, model_id: &str, prompt: &str) -> Result<u32, Error> {
+        gpt::token_length::token_length_native(model_id, prompt)
+    }
+
+    fn embed(
+        &self,
+        model_id: &str,
+        texts: &[&str],
+        pooling: Pooling,
+        normalization: Normalization,
+    ) -> Result<Vec<Vec<f32>>, Error> {
+        gpt::embed::embed_native(model_id, texts, pooling, normalization)
+    }
+
+    fn touch(&self, session_id: u32) -> bool {
+        gpt::touch_native(session_id)
+    }
+
+    fn destroy(&self, session_id: u32) -> Result<(), Error> {
+        gpt::destroy::destroy_native(session_id)
+    }
+}
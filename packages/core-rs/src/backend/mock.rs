@@ -0,0 +1,967 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Instant,
+};
+
+use super::{Backend, InferenceCallback, ProgressCallback};
+use crate::{
+    ffi,
+    gpt::{
+        self,
+        embed::{Normalization, Pooling},
+        infer::{InferenceToken, StopReason},
+        ContextShift, InferOutcome, SessionOptions,
+    },
+    Error, LoadedModel, ModelLoadOptions,
+};
+
+/// A [`MockBackend`] operation, to script an error for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockOp {
+    ModelLoad,
+    ModelUnload,
+    Create,
+    Fork,
+    Decode,
+    Infer,
+    /// Any of the tokenizer operations, e.g. [`Backend::detokenize`].
+    Tokenize,
+    Embed,
+    Destroy,
+}
+
+struct MockModel {
+    info: ffi::SimularityModelInfo,
+    vocab_only: bool,
+}
+
+#[derive(Clone)]
+struct MockSession {
+    model_id: String,
+    context_size: u32,
+    context_shift: Option<ContextShift>,
+    tokens: Vec<i32>,
+}
+
+#[derive(Default)]
+struct State {
+    models: HashMap<String, MockModel>,
+    sessions: HashMap<u32, MockSession>,
+    sessions_counter: u32,
+    /// The responses as the pieces of their tokens.
+    responses: VecDeque<Vec<String>>,
+    errors: VecDeque<(MockOp, Error)>,
+}
+
+impl State {
+    /// Take the scripted error for the operation, if any.
+    fn check(&mut self, op: MockOp) -> Result<(), Error> {
+        match self.errors.iter().position(|(o, _)| *o == op) {
+            Some(index) => Err(self.errors.remove(index).unwrap().1),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A deterministic scripted backend, which needs no native library
+/// nor model files.
+///
+/// A character is a token, with its code point as the ID, unless pushed
+/// with [`MockBackend::push_response_tokens`]. Special tokens are never
+/// added. Each inference streams the next response pushed with
+/// [`MockBackend::push_response`] (or an empty one), and stops once it ends
+/// (with [`StopReason::Eos`]), `n_eval` is reached, a stop sequence
+/// is matched, or the callback returns `false`. Like natively, the tokens
+/// which may start a stop sequence are held back from the callback.
+///
+/// A session context is as large as its [`SessionOptions::context_size`],
+/// or the model training context size by default, and overflows
+/// with [`Error::ContextOverflow`] unless it has a context shift policy.
+/// The session state files are ignored, and the sampling options
+/// other than the stop sequences have no effect (a draft model must be
+/// loaded though). An embedding counts
+/// the letters, digits, whitespace and other characters of the text.
+pub struct MockBackend {
+    n_ctx_train: u32,
+    state: Mutex<State>,
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self {
+            n_ctx_train: 4096,
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The training context size of the loaded models, 4096 by default.
+    pub fn with_n_ctx_train(mut self, n_ctx_train: u32) -> Self {
+        self.n_ctx_train = n_ctx_train;
+        self
+    }
+
+    /// Queue a response for an upcoming inference, in order.
+    pub fn push_response(&self, response: impl Into<String>) {
+        let pieces = response.into().chars().map(String::from).collect();
+        self.state().responses.push_back(pieces);
+    }
+
+    /// Queue a response inferred as the given tokens, e.g. to tokenize
+    /// a stop sequence differently. A token's ID is the code point of
+    /// its first character, and it takes a context cell per character.
+    pub fn push_response_tokens(&self, pieces: impl IntoIterator<Item = impl Into<String>>) {
+        let pieces = pieces.into_iter().map(Into::into).collect();
+        self.state().responses.push_back(pieces);
+    }
+
+    /// Fail the next call of the operation with the error.
+    pub fn fail_next(&self, op: MockOp, error: Error) {
+        self.state().errors.push_back((op, error));
+    }
+
+    /// The session context text, `None` if the session does not exist.
+    pub fn context(&self, session_id: u32) -> Option<String> {
+        self.state()
+            .sessions
+            .get(&session_id)
+            .map(|session| detokenize(&session.tokens))
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Get a copy of the session, to work on without holding the lock.
+    fn session(&self, op: MockOp, session_id: u32) -> Result<MockSession, Error> {
+        let mut state = self.state();
+        state.check(op)?;
+
+        let session = state
+            .sessions
+            .get(&session_id)
+            .cloned()
+            .ok_or(Error::SessionNotFound)?;
+
+        if !state.models.contains_key(&session.model_id) {
+            return Err(Error::ModelNotFound);
+        }
+
+        Ok(session)
+    }
+
+    /// Check if the model is loaded for a tokenizer operation.
+    fn tokenizer(&self, model_id: &str) -> Result<(), Error> {
+        let mut state = self.state();
+        state.check(MockOp::Tokenize)?;
+
+        if !state.models.contains_key(model_id) {
+            return Err(Error::ModelNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Update the session, unless destroyed in the meantime.
+    fn commit(&self, session_id: u32, tokens: Vec<i32>) {
+        if let Some(session) = self.state().sessions.get_mut(&session_id) {
+            session.tokens = tokens;
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Vec<i32> {
+    text.chars().map(|c| c as i32).collect()
+}
+
+fn detokenize(tokens: &[i32]) -> String {
+    tokens
+        .iter()
+        .map(|&token| char::from_u32(token as u32).unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Fit the tokens into the session context, returning the number
+/// of the evicted tokens.
+fn fit(session: &MockSession, tokens: &mut Vec<i32>) -> Result<u32, Error> {
+    let context_size = session.context_size as usize;
+    let mut evicted = 0;
+
+    while tokens.len() > context_size {
+        let shift = match session.context_shift {
+            Some(shift) if (shift.n_keep as usize) < context_size => shift,
+            _ => {
+                return Err(Error::ContextOverflow(Some(format!(
+                    "{} tokens exceed the context size of {}",
+                    tokens.len(),
+                    context_size
+                ))))
+            }
+        };
+
+        let n_keep = shift.n_keep as usize;
+        let n_discard = shift
+            .n_discard
+            .map_or((tokens.len() - n_keep) / 2, |n| n as usize)
+            .clamp(1, tokens.len() - n_keep);
+
+        tokens.drain(n_keep..n_keep + n_discard);
+        evicted += n_discard as u32;
+    }
+
+    Ok(evicted)
+}
+
+/// Decode the prompt, i.e. replace the session tokens with it.
+fn decode(
+    session: &MockSession,
+    prompt: &str,
+    progress_callback: Option<ProgressCallback>,
+) -> Result<(Vec<i32>, u32), Error> {
+    let mut tokens = tokenize(prompt);
+    let evicted = fit(session, &mut tokens)?;
+
+    if let Some(cb) = progress_callback {
+        if !cb(1.0) {
+            return Err(Error::Cancelled);
+        }
+    }
+
+    Ok((tokens, evicted))
+}
+
+/// The offset of the first `text` suffix which may be continued
+/// to the stop sequence, or `text.len()` if there is none.
+fn undecided(text: &str, stop_sequence: &str) -> usize {
+    text.char_indices()
+        .map(|(offset, _)| offset)
+        .find(|&offset| {
+            let suffix = &text[offset..];
+            stop_sequence.len() > suffix.len() && stop_sequence.starts_with(suffix)
+        })
+        .unwrap_or(text.len())
+}
+
+/// Yield the first `n` held tokens to the inference callback, appending
+/// their pieces to the output. Returns `false` if the callback has stopped
+/// the inference, with the tokens following the stopping one left held.
+fn release(
+    output: &mut String,
+    held: &mut Vec<HeldToken>,
+    n: usize,
+    mut callback: Option<&mut InferenceCallback>,
+) -> bool {
+    for token in held.drain(..n).collect::<Vec<_>>() {
+        output.push_str(&token.piece);
+
+        if let Some(callback) = callback.as_mut() {
+            let proceed = callback(&InferenceToken {
+                token: token.piece.chars().next().map_or(0, |c| c as i32),
+                piece: token.piece,
+                logprob: 0.0,
+                top_logprobs: Vec::new(),
+            });
+
+            if !proceed {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// An inferred token not yielded yet, as it may start a stop sequence.
+struct HeldToken {
+    piece: String,
+
+    /// The number of the context cells, kept if the piece is cut.
+    n_cells: usize,
+}
+
+impl Backend for MockBackend {
+    fn model_load(
+        &self,
+        _model_path: &str,
+        model_id: &str,
+        options: Option<ModelLoadOptions>,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<ffi::SimularityModelInfo, Error> {
+        {
+            let mut state = self.state();
+            state.check(MockOp::ModelLoad)?;
+
+            if let Some(model) = state.models.get(model_id) {
+                return Ok(model.info.clone());
+            }
+        }
+
+        if let Some(cb) = progress_callback {
+            if !cb(1.0) {
+                return Err(Error::Cancelled);
+            }
+        }
+
+        let model = MockModel {
+            info: ffi::SimularityModelInfo {
+                n_params: 0,
+                size: 0,
+                n_ctx_train: self.n_ctx_train as i64,
+            },
+            vocab_only: options.unwrap_or_default().vocab_only,
+        };
+
+        Ok(self
+            .state()
+            .models
+            .entry(model_id.to_string())
+            .or_insert(model)
+            .info
+            .clone())
+    }
+
+    fn model_unload(&self, model_id: &str) -> Result<(), Error> {
+        let mut state = self.state();
+        state.check(MockOp::ModelUnload)?;
+
+        match state.models.remove(model_id) {
+            Some(_) => Ok(()),
+            None => Err(Error::ModelNotFound),
+        }
+    }
+
+    fn model_hash(&self, model_id: &str) -> Result<u64, Error> {
+        if !self.state().models.contains_key(model_id) {
+            return Err(Error::ModelNotFound);
+        }
+
+        // FNV-1a of the model ID.
+        Ok(model_id.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        }))
+    }
+
+    fn model_info(&self, model_id: &str) -> Result<ffi::SimularityModelInfo, Error> {
+        self.state()
+            .models
+            .get(model_id)
+            .map(|model| model.info.clone())
+            .ok_or(Error::ModelNotFound)
+    }
+
+    fn model_list(&self) -> Result<Vec<LoadedModel>, Error> {
+        let model_ids: Vec<String> = self.state().models.keys().cloned().collect();
+        let mut models = Vec::new();
+
+        for id in model_ids {
+            models.push(LoadedModel {
+                info: self.model_info(&id)?,
+                session_ids: self.model_sessions(&id)?,
+                id,
+            });
+        }
+
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(models)
+    }
+
+    fn model_sessions(&self, model_id: &str) -> Result<Vec<u32>, Error> {
+        let mut session_ids: Vec<u32> = self
+            .state()
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.model_id == model_id)
+            .map(|(&id, _)| id)
+            .collect();
+
+        session_ids.sort();
+        Ok(session_ids)
+    }
+
+    fn create(
+        &self,
+        model_id: &str,
+        options: Option<SessionOptions>,
+        initial_prompt: Option<&str>,
+        _state_file_path: Option<&str>,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<u32, Error> {
+        let options = options.unwrap_or_default();
+
+        {
+            let mut state = self.state();
+            state.check(MockOp::Create)?;
+
+            match state.models.get(model_id) {
+                Some(model) if model.vocab_only => {
+                    return Err(Error::ContextCreationFailed(Some(
+                        "Model is loaded vocabulary-only".to_string(),
+                    )))
+                }
+                Some(_) => {}
+                None => return Err(Error::ModelNotFound),
+            }
+        }
+
+        let mut session = MockSession {
+            model_id: model_id.to_string(),
+            context_size: options.context_size.unwrap_or(self.n_ctx_train),
+            context_shift: options.context_shift,
+            tokens: Vec::new(),
+        };
+
+        if let Some(prompt) = initial_prompt {
+            session.tokens = decode(&session, prompt, progress_callback)?.0;
+        }
+
+        let mut state = self.state();
+        state.sessions_counter += 1;
+        let session_id = state.sessions_counter;
+        state.sessions.insert(session_id, session);
+
+        Ok(session_id)
+    }
+
+    fn fork(&self, session_id: u32) -> Result<u32, Error> {
+        let session = self.session(MockOp::Fork, session_id)?;
+
+        let mut state = self.state();
+        state.sessions_counter += 1;
+        let fork_id = state.sessions_counter;
+        state.sessions.insert(fork_id, session);
+
+        Ok(fork_id)
+    }
+
+    fn decode(
+        &self,
+        session_id: u32,
+        prompt: &str,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<u32, Error> {
+        let session = self.session(MockOp::Decode, session_id)?;
+        let (tokens, _) = decode(&session, prompt, progress_callback)?;
+
+        let context_length = tokens.len() as u32;
+        self.commit(session_id, tokens);
+
+        Ok(context_length)
+    }
+
+    fn infer(
+        &self,
+        session_id: u32,
+        prompt: Option<&str>,
+        n_eval: u32,
+        options: Option<gpt::infer::Options>,
+        decode_progress_callback: Option<ProgressCallback>,
+        mut inference_callback: Option<InferenceCallback>,
+    ) -> Result<InferOutcome, Error> {
+        let session = self.session(MockOp::Infer, session_id)?;
+
+        if let Some(speculative) = options.as_ref().and_then(|o| o.speculative.as_ref()) {
+            if !self
+                .state()
+                .models
+                .contains_key(&speculative.draft_model_id)
+            {
+                return Err(Error::ModelNotFound);
+            }
+        }
+
+        let response = self.state().responses.pop_front().unwrap_or_default();
+        let stop_sequences = options.and_then(|o| o.stop_sequences).unwrap_or_default();
+
+        let decode_start = Instant::now();
+        let (mut tokens, mut evicted_tokens) = match prompt {
+            Some(prompt) => decode(&session, prompt, decode_progress_callback)?,
+            None => (session.tokens.clone(), 0),
+        };
+        let prompt_tokens = tokens.len() as u32;
+        let decode_duration = decode_start.elapsed();
+
+        let generation_start = Instant::now();
+        let mut output = String::new();
+        let mut generated_tokens = 0;
+        let mut stop_reason = None;
+        let mut held: Vec<HeldToken> = Vec::new();
+
+        for piece in response {
+            if generated_tokens == n_eval {
+                break;
+            }
+
+            let n_cells = piece.chars().count();
+            tokens.extend(tokenize(&piece));
+            evicted_tokens += fit(&session, &mut tokens)?;
+            generated_tokens += 1;
+            held.push(HeldToken { piece, n_cells });
+
+            let held_text: String = held.iter().map(|token| token.piece.as_str()).collect();
+
+            // Stop at the first stop sequence, wherever it starts within a token.
+            let stop = stop_sequences
+                .iter()
+                .filter(|s| !s.is_empty())
+                .filter_map(|s| held_text.find(s.as_str()).map(|offset| (offset, s)))
+                .min_by_key(|(offset, _)| *offset);
+
+            if let Some((offset, sequence)) = stop {
+                // Cut the piece of the token the stop sequence starts within.
+                let (mut n_preceding, mut start) = (0, 0);
+                while start < offset {
+                    let piece = &mut held[n_preceding].piece;
+                    piece.truncate(piece.len().min(offset - start));
+                    start += piece.len();
+                    n_preceding += 1;
+                }
+
+                if !release(
+                    &mut output,
+                    &mut held,
+                    n_preceding,
+                    inference_callback.as_mut(),
+                ) {
+                    stop_reason = Some(StopReason::Callback);
+                    break;
+                }
+
+                // The stop sequence is never yielded, yet kept in the context.
+                generated_tokens -= held.len() as u32;
+                held.clear();
+                stop_reason = Some(StopReason::StopSequence {
+                    sequence: sequence.clone(),
+                });
+                break;
+            }
+
+            // Yield the tokens which may not start a stop sequence.
+            let undecided = stop_sequences
+                .iter()
+                .map(|s| undecided(&held_text, s))
+                .min()
+                .unwrap_or(held_text.len());
+
+            let (mut n_release, mut n_bytes) = (0, 0);
+            while n_release < held.len() && n_bytes + held[n_release].piece.len() <= undecided {
+                n_bytes += held[n_release].piece.len();
+                n_release += 1;
+            }
+
+            if !release(
+                &mut output,
+                &mut held,
+                n_release,
+                inference_callback.as_mut(),
+            ) {
+                stop_reason = Some(StopReason::Callback);
+                break;
+            }
+        }
+
+        // Yield the tokens held back till the end.
+        let n_held = held.len();
+        if stop_reason.is_none()
+            && !release(&mut output, &mut held, n_held, inference_callback.as_mut())
+        {
+            stop_reason = Some(StopReason::Callback);
+        }
+
+        // Remove the tokens following the one the callback has stopped at.
+        for token in held {
+            tokens.truncate(tokens.len() - token.n_cells);
+            generated_tokens -= 1;
+        }
+
+        let stop_reason = stop_reason.unwrap_or(if generated_tokens == n_eval {
+            StopReason::NEval
+        } else {
+            StopReason::Eos
+        });
+        let generation_duration = generation_start.elapsed();
+
+        let context_length = tokens.len() as u32;
+        self.commit(session_id, tokens);
+
+        Ok(InferOutcome {
+            output,
+            stop_reason,
+            context_length,
+            prompt_tokens,
+            generated_tokens,
+            evicted_tokens,
+            decode_duration,
+            generation_duration,
+            drafted_tokens: 0,
+            accepted_tokens: 0,
+        })
+    }
+
+    fn tokenize(
+        &self,
+        model_id: &str,
+        text: &str,
+        _add_special: bool,
+        _parse_special: bool,
+    ) -> Result<Vec<i32>, Error> {
+        self.tokenizer(model_id)?;
+        Ok(tokenize(text))
+    }
+
+    fn detokenize(&self, model_id: &str, tokens: &[i32]) -> Result<String, Error> {
+        self.tokenizer(model_id)?;
+        Ok(detokenize(tokens))
+    }
+
+    fn token_to_piece(&self, model_id: &str, token: i32) -> Result<String, Error> {
+        self.tokenizer(model_id)?;
+
+        match char::from_u32(token as u32) {
+            Some(c) => Ok(c.to_string()),
+            None => Err(Error::InvalidInput {
+                message: format!("Invalid token {}", token),
+                source: None,
+            }),
+        }
+    }
+
+    fn token_length(&self, model_id: &str, prompt: &str) -> Result<u32, Error> {
+        self.tokenizer(model_id)?;
+        Ok(prompt.chars().count() as u32)
+    }
+
+    fn embed(
+        &self,
+        model_id: &str,
+        texts: &[&str],
+        _pooling: Pooling,
+        normalization: Normalization,
+    ) -> Result<Vec<Vec<f32>>, Error> {
+        {
+            let mut state = self.state();
+            state.check(MockOp::Embed)?;
+
+            if !state.models.contains_key(model_id) {
+                return Err(Error::ModelNotFound);
+            }
+        }
+
+        // The counts of the character classes.
+        let classes: [fn(&char) -> bool; 4] = [
+            |c| c.is_alphabetic(),
+            |c| c.is_numeric(),
+            |c| c.is_whitespace(),
+            |c| !c.is_alphanumeric() && !c.is_whitespace(),
+        ];
+
+        Ok(texts
+            .iter()
+            .map(|text| {
+                let mut embedding: Vec<f32> = classes
+                    .iter()
+                    .map(|class| text.chars().filter(class).count() as f32)
+                    .collect();
+
+                normalization.apply(&mut embedding);
+                embedding
+            })
+            .collect())
+    }
+
+    fn touch(&self, session_id: u32) -> bool {
+        // NOTE: The sessions never expire.
+        self.state().sessions.contains_key(&session_id)
+    }
+
+    fn destroy(&self, session_id: u32) -> Result<(), Error> {
+        let mut state = self.state();
+        state.check(MockOp::Destroy)?;
+
+        match state.sessions.remove(&session_id) {
+            Some(_) => Ok(()),
+            None => Err(Error::SessionNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{gpt::Session, ModelRegistry};
+
+    /// Serializes the tests scripting the global backend responses.
+    static GLOBAL_RESPONSES: Mutex<()> = Mutex::new(());
+
+    fn backend_with_session(model_id: &str, options: Option<SessionOptions>) -> (MockBackend, u32) {
+        let backend = MockBackend::new();
+        backend.model_load("", model_id, None, None).unwrap();
+        let session_id = backend.create(model_id, options, None, None, None).unwrap();
+        (backend, session_id)
+    }
+
+    fn stop_sequences(sequences: &[&str]) -> Option<gpt::infer::Options> {
+        Some(gpt::infer::Options {
+            stop_sequences: Some(sequences.iter().map(|s| s.to_string()).collect()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn infer_streams_the_response() {
+        let (backend, session_id) = backend_with_session("model", None);
+        backend.push_response("Hello!");
+
+        let mut pieces = Vec::new();
+        let outcome = backend
+            .infer(
+                session_id,
+                Some("Hi. "),
+                16,
+                None,
+                None,
+                Some(&mut |token: &InferenceToken| {
+                    pieces.push(token.piece.clone());
+                    true
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(pieces, ["H", "e", "l", "l", "o", "!"]);
+        assert_eq!(outcome.output, "Hello!");
+        assert_eq!(outcome.stop_reason, StopReason::Eos);
+        assert_eq!(outcome.prompt_tokens, 4);
+        assert_eq!(outcome.generated_tokens, 6);
+        assert_eq!(outcome.context_length, 10);
+        assert_eq!(backend.context(session_id).unwrap(), "Hi. Hello!");
+    }
+
+    #[test]
+    fn infer_stops() {
+        let (backend, session_id) = backend_with_session("model", None);
+
+        backend.push_response("Hello!");
+        let outcome = backend
+            .infer(session_id, None, 3, None, None, None)
+            .unwrap();
+        assert_eq!(outcome.output, "Hel");
+        assert_eq!(outcome.stop_reason, StopReason::NEval);
+
+        backend.push_response("Hello!");
+        let mut pieces = Vec::new();
+        let outcome = backend
+            .infer(
+                session_id,
+                None,
+                16,
+                stop_sequences(&["", "lo", "Help"]),
+                None,
+                Some(&mut |token: &InferenceToken| {
+                    pieces.push(token.piece.clone());
+                    true
+                }),
+            )
+            .unwrap();
+        assert_eq!(pieces, ["H", "e", "l"]);
+        assert_eq!(outcome.output, "Hel");
+        assert_eq!(outcome.generated_tokens, 3);
+        assert_eq!(
+            outcome.stop_reason,
+            StopReason::StopSequence {
+                sequence: "lo".to_string()
+            }
+        );
+
+        backend.push_response("Hello!");
+        let outcome = backend
+            .infer(
+                session_id,
+                None,
+                16,
+                None,
+                None,
+                Some(&mut |token: &InferenceToken| token.piece != "e"),
+            )
+            .unwrap();
+        assert_eq!(outcome.output, "He");
+        assert_eq!(outcome.stop_reason, StopReason::Callback);
+
+        // The characters held back are trimmed after the callback stop.
+        backend.push_response("Hello!");
+        let outcome = backend
+            .infer(
+                session_id,
+                None,
+                16,
+                stop_sequences(&["lo?"]),
+                None,
+                Some(&mut |token: &InferenceToken| token.piece != "l"),
+            )
+            .unwrap();
+        assert_eq!(outcome.output, "Hel");
+        assert_eq!(outcome.stop_reason, StopReason::Callback);
+
+        backend.push_response("Hello!");
+        let mut pieces = Vec::new();
+        let outcome = backend
+            .infer(
+                session_id,
+                None,
+                16,
+                stop_sequences(&["!?"]),
+                None,
+                Some(&mut |token: &InferenceToken| {
+                    pieces.push(token.piece.clone());
+                    true
+                }),
+            )
+            .unwrap();
+        assert_eq!(pieces, ["H", "e", "l", "l", "o", "!"]);
+        assert_eq!(outcome.output, "Hello!");
+        assert_eq!(outcome.stop_reason, StopReason::Eos);
+    }
+
+    #[test]
+    fn infer_stops_across_tokens() {
+        let (backend, session_id) = backend_with_session("model", None);
+        let infer = |stop_sequence: &str| {
+            let mut pieces = Vec::new();
+            let outcome = backend
+                .infer(
+                    session_id,
+                    Some("Hi. "),
+                    16,
+                    stop_sequences(&[stop_sequence]),
+                    None,
+                    Some(&mut |token: &InferenceToken| {
+                        pieces.push(token.piece.clone());
+                        true
+                    }),
+                )
+                .unwrap();
+
+            assert_eq!(
+                outcome.stop_reason,
+                StopReason::StopSequence {
+                    sequence: stop_sequence.to_string()
+                }
+            );
+
+            (pieces, outcome)
+        };
+
+        // Tokenized differently from the stop sequence on its own.
+        backend.push_response_tokens(["Hello", "\n", "\nUser", ":", " Hi"]);
+        let (pieces, outcome) = infer("\n\nUser:");
+        assert_eq!(pieces, ["Hello"]);
+        assert_eq!(outcome.output, "Hello");
+        assert_eq!(outcome.generated_tokens, 1);
+
+        // Starting within a token.
+        backend.push_response_tokens(["Hello\n", "Us", "er: Hi"]);
+        let (pieces, outcome) = infer("\nUser:");
+        assert_eq!(pieces, ["Hello"]);
+        assert_eq!(outcome.output, "Hello");
+        assert_eq!(outcome.generated_tokens, 1);
+        assert_eq!(backend.context(session_id).unwrap(), "Hi. Hello\nUser: Hi");
+    }
+
+    #[test]
+    fn decode_overflows_the_context() {
+        let options = SessionOptions {
+            context_size: Some(8),
+            ..Default::default()
+        };
+
+        let (backend, session_id) = backend_with_session("model", Some(options));
+        assert_eq!(backend.decode(session_id, "12345678", None).unwrap(), 8);
+        assert!(matches!(
+            backend.decode(session_id, "123456789", None),
+            Err(Error::ContextOverflow(_))
+        ));
+        assert_eq!(backend.context(session_id).unwrap(), "12345678");
+
+        let options = SessionOptions {
+            context_shift: Some(ContextShift {
+                n_keep: 2,
+                n_discard: Some(3),
+            }),
+            ..options
+        };
+
+        let (backend, session_id) = backend_with_session("model", Some(options));
+        backend.push_response("abc");
+        let outcome = backend
+            .infer(session_id, Some("1234567"), 16, None, None, None)
+            .unwrap();
+        assert_eq!(outcome.evicted_tokens, 3);
+        assert_eq!(backend.context(session_id).unwrap(), "1267abc");
+    }
+
+    #[test]
+    fn fail_next_fails_once() {
+        let (backend, session_id) = backend_with_session("model", None);
+        backend.fail_next(MockOp::Decode, Error::DecodeFailed(None));
+
+        assert!(matches!(
+            backend.decode(session_id, "Hi", None),
+            Err(Error::DecodeFailed(None))
+        ));
+        assert_eq!(backend.decode(session_id, "Hi", None).unwrap(), 2);
+    }
+
+    #[test]
+    fn session_uses_the_global_backend() {
+        crate::model_load("", "global-session", None, None::<fn(_) -> bool>).unwrap();
+
+        let session = Session::create(
+            "global-session",
+            None,
+            Some("Hi. "),
+            None,
+            None::<fn(_) -> bool>,
+        )
+        .unwrap();
+        assert!(session.touch());
+        assert_eq!(
+            crate::gpt::tokenize("global-session", "Hey", false, false).unwrap(),
+            [72, 101, 121]
+        );
+
+        let outcome = {
+            let _lock = GLOBAL_RESPONSES.lock().unwrap_or_else(|e| e.into_inner());
+            ModelRegistry::global().backend().push_response("Hello!");
+
+            session
+                .infer(
+                    None,
+                    16,
+                    None,
+                    None::<fn(_) -> bool>,
+                    None::<fn(&InferenceToken) -> bool>,
+                )
+                .unwrap()
+        };
+        assert_eq!(outcome.output, "Hello!");
+
+        let session_id = session.id();
+        assert_eq!(
+            crate::gpt::decode(session_id, "Bye.", None::<fn(_) -> bool>).unwrap(),
+            4
+        );
+
+        drop(session);
+        assert!(!crate::gpt::touch(session_id));
+        assert!(matches!(
+            crate::gpt::destroy(session_id),
+            Err(Error::SessionNotFound)
+        ));
+    }
+}
@@ -0,0 +1,463 @@
+//! Chat template rendering. Renders a list of role/content messages
+//! into a prompt using either the Jinja template embedded in the GGUF
+//! metadata (`tokenizer.chat_template`), or one of the built-in templates.
+//!
+//! Templates are rendered with [minijinja](https://docs.rs/minijinja),
+//! configured to be compatible with Hugging Face's `apply_chat_template`.
+
+use minijinja::{context, Environment, ErrorKind};
+
+use crate::{gguf::Gguf, Error};
+
+/// Names of the built-in templates, see [`ChatTemplate::builtin`].
+pub const BUILTIN_TEMPLATES: &[&str] = &[
+    "chatml", "llama2", "llama3", "mistral", "gemma", "phi3", "alpaca",
+];
+
+/// Turn end markers which a template may use, in addition to EOS.
+const KNOWN_STOP_SEQUENCES: &[&str] = &[
+    "<|im_end|>",
+    "<|eot_id|>",
+    "<|end|>",
+    "<end_of_turn>",
+    "<|endoftext|>",
+];
+
+/// A chat message.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Message {
+    /// E.g. `system`, `user` or `assistant`.
+    pub role: String,
+    pub content: String,
+}
+
+/// A rendered chat prompt.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rendered {
+    pub prompt: String,
+
+    /// Strings the template uses to end a turn,
+    /// upon which the inference shall be stopped.
+    pub stop_sequences: Vec<String>,
+}
+
+/// A Jinja chat template.
+#[derive(Debug, Clone)]
+pub struct ChatTemplate {
+    pub source: String,
+    pub bos_token: String,
+    pub eos_token: String,
+    pub stop_sequences: Vec<String>,
+}
+
+impl ChatTemplate {
+    /// Create a template from a Jinja source.
+    /// Stop sequences are inferred from the source and the EOS token.
+    pub fn new(source: &str, bos_token: &str, eos_token: &str) -> Self {
+        let mut stop_sequences: Vec<String> = KNOWN_STOP_SEQUENCES
+            .iter()
+            .filter(|stop| source.contains(*stop))
+            .map(|stop| stop.to_string())
+            .collect();
+
+        if !eos_token.is_empty() && !stop_sequences.iter().any(|stop| stop == eos_token) {
+            stop_sequences.push(eos_token.to_string());
+        }
+
+        Self {
+            source: source.to_string(),
+            bos_token: bos_token.to_string(),
+            eos_token: eos_token.to_string(),
+            stop_sequences,
+        }
+    }
+
+    /// Get a built-in template by its name, see [`BUILTIN_TEMPLATES`].
+    pub fn builtin(name: &str) -> Result<Self, Error> {
+        let (source, bos_token, eos_token, stop_sequences): (_, _, _, &[&str]) = match name {
+            "chatml" => (CHATML, "", "", &["<|im_end|>"]),
+            "llama2" => (LLAMA2, "<s>", "</s>", &["</s>", "[INST]"]),
+            "llama3" => (LLAMA3, "<|begin_of_text|>", "<|eot_id|>", &["<|eot_id|>"]),
+            "mistral" => (MISTRAL, "<s>", "</s>", &["</s>", "[INST]"]),
+            "gemma" => (GEMMA, "<bos>", "<eos>", &["<end_of_turn>"]),
+            "phi3" => (PHI3, "", "<|endoftext|>", &["<|end|>", "<|endoftext|>"]),
+            "alpaca" => (ALPACA, "", "", &["### Instruction:"]),
+            _ => {
+                return Err(Error::InvalidInput {
+                    message: format!(
+                        "Unknown chat template `{}`, expected one of: {}",
+                        name,
+                        BUILTIN_TEMPLATES.join(", ")
+                    ),
+                    source: None,
+                })
+            }
+        };
+
+        Ok(Self {
+            source: source.to_string(),
+            bos_token: bos_token.to_string(),
+            eos_token: eos_token.to_string(),
+            stop_sequences: stop_sequences.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    /// Get the template embedded in the GGUF metadata, if any.
+    pub fn from_gguf(gguf: &Gguf) -> Option<Self> {
+        let source = gguf.chat_template()?;
+        let special_tokens = gguf.special_tokens();
+        let text = |token: Option<crate::gguf::SpecialToken>| {
+            token.and_then(|token| token.text).unwrap_or_default()
+        };
+
+        let eot_token = text(special_tokens.eot);
+        let mut template = Self::new(source, &text(special_tokens.bos), &text(special_tokens.eos));
+
+        if !eot_token.is_empty() && !template.stop_sequences.contains(&eot_token) {
+            template.stop_sequences.push(eot_token);
+        }
+
+        Some(template)
+    }
+
+    /// Render the messages into a prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The chat messages.
+    /// * `add_generation_prompt` - Whether to append the assistant
+    ///   turn start, so that the model continues as the assistant.
+    ///
+    pub fn render(
+        &self,
+        messages: &[Message],
+        add_generation_prompt: bool,
+    ) -> Result<Rendered, Error> {
+        let prompt = environment()
+            .render_str(
+                &self.source,
+                context! {
+                    messages => messages,
+                    add_generation_prompt => add_generation_prompt,
+                    bos_token => self.bos_token,
+                    eos_token => self.eos_token,
+                },
+            )
+            .map_err(|err| Error::TemplateError(Some(err.to_string())))?;
+
+        Ok(Rendered {
+            prompt,
+            stop_sequences: self.stop_sequences.clone(),
+        })
+    }
+}
+
+/// Render the messages using the chat template of a model file,
+/// falling back to a built-in template if the model has no template
+/// or it fails to render (the error is logged then).
+///
+/// # Arguments
+///
+/// * `model_path` - Path to a GGUF model file.
+/// * `fallback` - The built-in template name, see [`BUILTIN_TEMPLATES`].
+/// * `messages` - The chat messages.
+/// * `add_generation_prompt` - See [`ChatTemplate::render`].
+///
+pub fn render(
+    model_path: Option<&str>,
+    fallback: Option<&str>,
+    messages: &[Message],
+    add_generation_prompt: bool,
+) -> Result<Rendered, Error> {
+    let fallback = fallback.map(ChatTemplate::builtin).transpose()?;
+    let embedded = match model_path {
+        Some(model_path) => ChatTemplate::from_gguf(&Gguf::open(model_path)?),
+        None => None,
+    };
+
+    match (embedded, fallback) {
+        (Some(embedded), Some(fallback)) => {
+            embedded
+                .render(messages, add_generation_prompt)
+                .or_else(|err| {
+                    log::warn!("Falling back from the model chat template: {}", err);
+
+                    // Report the model template error if the fallback fails too.
+                    fallback
+                        .render(messages, add_generation_prompt)
+                        .map_err(|_| err)
+                })
+        }
+        (Some(template), None) | (None, Some(template)) => {
+            template.render(messages, add_generation_prompt)
+        }
+        (None, None) => Err(Error::TemplateError(Some(
+            "The model has no chat template and no fallback is given".to_string(),
+        ))),
+    }
+}
+
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+
+    // Same as in Hugging Face's `apply_chat_template`.
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+
+    // Python string and dict methods, e.g. `message.content.strip()`.
+    env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+
+    env.add_function("raise_exception", |message: String| -> Result<(), _> {
+        Err(minijinja::Error::new(ErrorKind::InvalidOperation, message))
+    });
+
+    env
+}
+
+const CHATML: &str = r#"
+{%- for message in messages -%}
+  {{ '<|im_start|>' ~ message.role ~ '\n' ~ message.content ~ '<|im_end|>\n' }}
+{%- endfor -%}
+{%- if add_generation_prompt -%}
+  {{ '<|im_start|>assistant\n' }}
+{%- endif -%}
+"#;
+
+const LLAMA2: &str = r#"
+{%- set ns = namespace(system='') -%}
+{%- for message in messages -%}
+  {%- if message.role == 'system' -%}
+    {%- set ns.system = '<<SYS>>\n' ~ message.content ~ '\n<</SYS>>\n\n' -%}
+  {%- elif message.role == 'user' -%}
+    {{ bos_token ~ '[INST] ' ~ ns.system ~ message.content ~ ' [/INST]' }}
+    {%- set ns.system = '' -%}
+  {%- elif message.role == 'assistant' -%}
+    {{ ' ' ~ message.content ~ ' ' ~ eos_token }}
+  {%- endif -%}
+{%- endfor -%}
+"#;
+
+const LLAMA3: &str = r#"
+{{- bos_token -}}
+{%- for message in messages -%}
+  {{ '<|start_header_id|>' ~ message.role ~ '<|end_header_id|>\n\n' ~ message.content ~ '<|eot_id|>' }}
+{%- endfor -%}
+{%- if add_generation_prompt -%}
+  {{ '<|start_header_id|>assistant<|end_header_id|>\n\n' }}
+{%- endif -%}
+"#;
+
+const MISTRAL: &str = r#"
+{{- bos_token -}}
+{%- set ns = namespace(system='') -%}
+{%- for message in messages -%}
+  {%- if message.role == 'system' -%}
+    {%- set ns.system = message.content ~ '\n\n' -%}
+  {%- elif message.role == 'user' -%}
+    {{ '[INST] ' ~ ns.system ~ message.content ~ ' [/INST]' }}
+    {%- set ns.system = '' -%}
+  {%- elif message.role == 'assistant' -%}
+    {{ message.content ~ eos_token }}
+  {%- endif -%}
+{%- endfor -%}
+"#;
+
+const GEMMA: &str = r#"
+{{- bos_token -}}
+{%- set ns = namespace(system='') -%}
+{%- for message in messages -%}
+  {%- if message.role == 'system' -%}
+    {%- set ns.system = message.content ~ '\n\n' -%}
+  {%- elif message.role == 'user' -%}
+    {{ '<start_of_turn>user\n' ~ ns.system ~ message.content ~ '<end_of_turn>\n' }}
+    {%- set ns.system = '' -%}
+  {%- elif message.role == 'assistant' -%}
+    {{ '<start_of_turn>model\n' ~ message.content ~ '<end_of_turn>\n' }}
+  {%- endif -%}
+{%- endfor -%}
+{%- if add_generation_prompt -%}
+  {{ '<start_of_turn>model\n' }}
+{%- endif -%}
+"#;
+
+const PHI3: &str = r#"
+{%- for message in messages -%}
+  {{ '<|' ~ message.role ~ '|>\n' ~ message.content ~ '<|end|>\n' }}
+{%- endfor -%}
+{%- if add_generation_prompt -%}
+  {{ '<|assistant|>\n' }}
+{%- endif -%}
+"#;
+
+const ALPACA: &str = r#"
+{%- for message in messages -%}
+  {%- if message.role == 'system' -%}
+    {{ message.content ~ '\n\n' }}
+  {%- elif message.role == 'user' -%}
+    {{ '### Instruction:\n' ~ message.content ~ '\n\n' }}
+  {%- elif message.role == 'assistant' -%}
+    {{ '### Response:\n' ~ message.content ~ '\n\n' }}
+  {%- endif -%}
+{%- endfor -%}
+{%- if add_generation_prompt -%}
+  {{ '### Response:\n' }}
+{%- endif -%}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<Message> {
+        [
+            ("system", "Be nice."),
+            ("user", "Hi"),
+            ("assistant", "Hello"),
+            ("user", "Bye"),
+        ]
+        .into_iter()
+        .map(|(role, content)| Message {
+            role: role.to_string(),
+            content: content.to_string(),
+        })
+        .collect()
+    }
+
+    fn template_error(result: Result<Rendered, Error>) -> String {
+        match result {
+            Err(Error::TemplateError(Some(message))) => message,
+            result => panic!("Expected a template error, got {:?}", result),
+        }
+    }
+
+    /// Write a GGUF file with the chat template to a temporary path.
+    fn gguf_with_template(name: &str, template: &str) -> String {
+        let key = "tokenizer.chat_template";
+        let mut bytes = b"GGUF".to_vec();
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&1u64.to_le_bytes());
+        bytes.extend_from_slice(&(key.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(key.as_bytes());
+        bytes.extend_from_slice(&8u32.to_le_bytes());
+        bytes.extend_from_slice(&(template.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(template.as_bytes());
+
+        let path = std::env::temp_dir().join(format!(
+            "simularity-chat-template-{}-{}.gguf",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn renders_builtin_templates() {
+        let expected = [
+            (
+                "chatml",
+                "<|im_start|>system\nBe nice.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n\
+                 <|im_start|>assistant\nHello<|im_end|>\n<|im_start|>user\nBye<|im_end|>\n\
+                 <|im_start|>assistant\n",
+            ),
+            (
+                "llama2",
+                "<s>[INST] <<SYS>>\nBe nice.\n<</SYS>>\n\nHi [/INST] Hello </s>\
+                 <s>[INST] Bye [/INST]",
+            ),
+            (
+                "llama3",
+                "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\nBe nice.<|eot_id|>\
+                 <|start_header_id|>user<|end_header_id|>\n\nHi<|eot_id|>\
+                 <|start_header_id|>assistant<|end_header_id|>\n\nHello<|eot_id|>\
+                 <|start_header_id|>user<|end_header_id|>\n\nBye<|eot_id|>\
+                 <|start_header_id|>assistant<|end_header_id|>\n\n",
+            ),
+            (
+                "mistral",
+                "<s>[INST] Be nice.\n\nHi [/INST]Hello</s>[INST] Bye [/INST]",
+            ),
+            (
+                "gemma",
+                "<bos><start_of_turn>user\nBe nice.\n\nHi<end_of_turn>\n\
+                 <start_of_turn>model\nHello<end_of_turn>\n\
+                 <start_of_turn>user\nBye<end_of_turn>\n<start_of_turn>model\n",
+            ),
+            (
+                "phi3",
+                "<|system|>\nBe nice.<|end|>\n<|user|>\nHi<|end|>\n\
+                 <|assistant|>\nHello<|end|>\n<|user|>\nBye<|end|>\n<|assistant|>\n",
+            ),
+            (
+                "alpaca",
+                "Be nice.\n\n### Instruction:\nHi\n\n### Response:\nHello\n\n\
+                 ### Instruction:\nBye\n\n### Response:\n",
+            ),
+        ];
+
+        assert_eq!(expected.map(|(name, _)| name).as_slice(), BUILTIN_TEMPLATES);
+
+        for (name, prompt) in expected {
+            let template = ChatTemplate::builtin(name).unwrap();
+            let rendered = template.render(&messages(), true).unwrap();
+
+            assert_eq!(rendered.prompt, prompt, "template `{}`", name);
+            assert_eq!(rendered.stop_sequences, template.stop_sequences);
+        }
+
+        assert!(matches!(
+            ChatTemplate::builtin("unknown"),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+
+    #[test]
+    fn infers_stop_sequences() {
+        let template = ChatTemplate::new("{{ '<|eot_id|>' }}<|im_end|>", "<s>", "</s>");
+        assert_eq!(
+            template.stop_sequences,
+            ["<|im_end|>", "<|eot_id|>", "</s>"]
+        );
+
+        let template = ChatTemplate::new("{{ eos_token }}<|eot_id|>", "", "<|eot_id|>");
+        assert_eq!(template.stop_sequences, ["<|eot_id|>"]);
+
+        let template = ChatTemplate::new("{{ messages }}", "", "");
+        assert!(template.stop_sequences.is_empty());
+    }
+
+    #[test]
+    fn raises_exceptions() {
+        let template = ChatTemplate::new(
+            "{% if messages[0].role == 'system' %}\
+             {{ raise_exception('System role not supported') }}{% endif %}",
+            "",
+            "",
+        );
+
+        let message = template_error(template.render(&messages(), false));
+        assert!(message.contains("System role not supported"), "{}", message);
+        assert!(template.render(&messages()[1..], false).is_ok());
+    }
+
+    #[test]
+    fn falls_back_from_the_model_template() {
+        let path = gguf_with_template("fallback", "{{ raise_exception('Unsupported') }}");
+
+        let rendered = render(Some(&path), Some("chatml"), &messages()[1..2], false).unwrap();
+        assert_eq!(rendered.prompt, "<|im_start|>user\nHi<|im_end|>\n");
+
+        let message = template_error(render(Some(&path), None, &messages(), false));
+        assert!(message.contains("Unsupported"), "{}", message);
+
+        std::fs::remove_file(path).unwrap();
+
+        let message = template_error(render(None, None, &messages(), false));
+        assert_eq!(
+            message,
+            "The model has no chat template and no fallback is given"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use std::ffi::{CStr, CString};
+
+use crate::ffi;
+
+/// An error returned by any of the `simularity_core` functions.
+#[derive(Debug)]
+pub enum Error {
+    /// An argument could not be passed to the native library,
+    /// e.g. a string containing a NUL byte, or a missing file.
+    InvalidInput {
+        message: String,
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+
+    /// A model with the given ID is not loaded.
+    ModelNotFound,
+
+    /// The native library failed to load a model.
+    ModelLoadFailed(Option<String>),
+
+    /// The native library failed to hash a model.
+    ModelHashFailed(Option<String>),
+
+    /// A model can not be unloaded while used by live sessions,
+    /// or by calls in progress, e.g. creating a session with it.
+    ModelInUse { session_ids: Vec<u32> },
+
+    /// A LoRA adapter with the given ID is not loaded.
+    LoraNotFound,
+
+    /// A LoRA adapter could not be loaded, or applied to a model.
+    LoraLoadFailed(Option<String>),
+
+    /// A session with the given ID does not exist (or has expired).
+    SessionNotFound,
+
+    /// The maximum number of sessions has been reached.
+    SessionLimitReached,
+
+    /// The native library failed to create a llama context.
+    ContextCreationFailed(Option<String>),
+
+    /// The prompt does not fit into the session context.
+    ContextOverflow(Option<String>),
+
+    /// The native library failed to decode a prompt.
+    DecodeFailed(Option<String>),
+
+    /// The native library failed to tokenize or detokenize.
+    TokenizationFailed(Option<String>),
+
+    /// A file is not a valid GGUF file.
+    GgufParseFailed(Option<String>),
+
+    /// The native library failed to save or load a session state.
+    StateError(Option<String>),
+
+    /// A chat template failed to render.
+    TemplateError(Option<String>),
+
+    /// Failed to initialize sampling, likely due to an invalid grammar.
+    SamplingError(Option<String>),
+
+    /// The operation was cancelled by a callback returning `false`.
+    Cancelled,
+
+    /// A Lua grammar script failed, or exceeded its limits.
+    LuaError {
+        message: Option<String>,
+
+        /// The Lua stack traceback, if available.
+        traceback: Option<String>,
+    },
+
+    /// An unexpected native error code.
+    Unknown { code: i32, message: Option<String> },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (description, message) = match self {
+            Error::InvalidInput { message, .. } => ("Invalid input", Some(message)),
+            Error::ModelNotFound => ("Model not found", None),
+            Error::ModelLoadFailed(m) => ("Model load failed", m.as_ref()),
+            Error::ModelHashFailed(m) => ("Model hashing failed", m.as_ref()),
+            Error::ModelInUse { session_ids } => {
+                write!(f, "Model in use")?;
+                return if session_ids.is_empty() {
+                    Ok(())
+                } else {
+                    write!(f, " by sessions {:?}", session_ids)
+                };
+            }
+            Error::LoraNotFound => ("LoRA adapter not found", None),
+            Error::LoraLoadFailed(m) => ("LoRA adapter load failed", m.as_ref()),
+            Error::SessionNotFound => ("Session not found", None),
+            Error::SessionLimitReached => ("Session limit reached", None),
+            Error::ContextCreationFailed(m) => ("Context creation failed", m.as_ref()),
+            Error::ContextOverflow(m) => ("Context overflow", m.as_ref()),
+            Error::DecodeFailed(m) => ("Decode failed", m.as_ref()),
+            Error::TokenizationFailed(m) => ("Tokenization failed", m.as_ref()),
+            Error::GgufParseFailed(m) => ("GGUF parsing failed", m.as_ref()),
+            Error::StateError(m) => ("Session state error", m.as_ref()),
+            Error::TemplateError(m) => ("Chat template error", m.as_ref()),
+            Error::SamplingError(m) => ("Sampling error", m.as_ref()),
+            Error::Cancelled => ("Cancelled", None),
+            Error::LuaError { message, traceback } => {
+                write!(f, "Lua error")?;
+                if let Some(message) = message {
+                    write!(f, ": {}", message)?;
+                }
+                return match traceback {
+                    Some(traceback) => write!(f, "\nstack traceback:\n{}", traceback),
+                    None => Ok(()),
+                };
+            }
+            Error::Unknown { code, message } => {
+                write!(f, "Unknown error code {}", code)?;
+                return match message {
+                    Some(message) => write!(f, ": {}", message),
+                    None => Ok(()),
+                };
+            }
+        };
+
+        match message {
+            Some(message) => write!(f, "{}: {}", description, message),
+            None => write!(f, "{}", description),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidInput {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// Create an [`Error::Unknown`] from a native error code,
+    /// reading the native last error message.
+    pub(crate) fn unknown(code: i32) -> Self {
+        Error::Unknown {
+            code,
+            message: last_error(),
+        }
+    }
+
+    /// Create an [`Error::LuaError`] from a native error message,
+    /// separating the traceback appended by `debug.traceback`.
+    pub(crate) fn lua(message: Option<String>) -> Self {
+        match message
+            .as_deref()
+            .and_then(|message| message.split_once("\nstack traceback:\n"))
+        {
+            Some((message, traceback)) => Error::LuaError {
+                message: Some(message.to_string()),
+                traceback: Some(traceback.to_string()),
+            },
+            None => Error::LuaError {
+                message,
+                traceback: None,
+            },
+        }
+    }
+}
+
+/// Read the last native error message on the current thread, if any.
+pub(crate) fn last_error() -> Option<String> {
+    let message = unsafe { CStr::from_ptr(ffi::simularity_last_error()) };
+    let message = message.to_string_lossy();
+
+    if message.is_empty() {
+        None
+    } else {
+        Some(message.into_owned())
+    }
+}
+
+/// Convert a string argument to a C string,
+/// returning [`Error::InvalidInput`] if it contains a NUL byte.
+pub(crate) fn to_cstring(value: &str, argument: &str) -> Result<CString, Error> {
+    CString::new(value).map_err(|err| Error::InvalidInput {
+        message: format!(
+            "`{}` contains a NUL byte at position {}",
+            argument,
+            err.nul_position()
+        ),
+        source: Some(Box::new(err)),
+    })
+}
+
+/// Convert a file path argument to a C string, ensuring the file exists.
+pub(crate) fn to_existing_path(path: &str, argument: &str) -> Result<CString, Error> {
+    if let Err(err) = std::fs::metadata(path) {
+        return Err(Error::InvalidInput {
+            message: format!("`{}` is not accessible: {}", argument, path),
+            source: Some(Box::new(err)),
+        });
+    }
+
+    to_cstring(path, argument)
+}
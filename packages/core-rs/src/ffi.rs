@@ -1,4 +1,6 @@
-use std::ffi::{c_char, c_float, c_int, c_uint, c_void};
+use std::ffi::{c_char, c_float, c_int, c_uint, c_void, CStr};
+
+use crate::gpt::infer::{InferenceToken, TokenLogprob};
 
 #[derive(Debug)]
 #[repr(C)]
@@ -27,27 +29,203 @@ pub struct SimularityGptInferenceOptions {
     pub stop_sequences_len: c_uint,
     pub stop_sequences: *const *const c_char,
     pub lua_grammar: *const c_char,
+    pub lua_limits: SimularityGptLuaLimits,
+    pub logit_bias_len: c_uint,
+    pub logit_bias: *const SimularityGptLogitBias,
+    pub banned_strings_len: c_uint,
+    pub banned_strings: *const *const c_char,
+    pub draft_model_id: *const c_char,
+    pub n_draft: c_uint,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SimularityGptLogitBias {
+    pub token: c_int,
+
+    /// If not null, the bias applies to each of the text tokens.
+    pub text: *const c_char,
+
+    pub bias: c_float,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SimularityGptLuaLimits {
+    pub max_instructions: c_uint,
+    pub timeout_ms: c_uint,
+    pub max_memory: usize,
+
+    /// `enum simularity_lua_library` flags.
+    pub libraries: c_uint,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct SimularityGptTokenLogprob {
+    pub token: i32,
+    pub piece: *const c_char,
+    pub logprob: c_float,
 }
 
 #[derive(Debug)]
 #[repr(C)]
+pub struct SimularityGptInferenceToken {
+    pub token: i32,
+    pub piece: *const c_char,
+    pub logprob: c_float,
+    pub n_top: c_uint,
+    pub top: *const SimularityGptTokenLogprob,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SimularityGptSequenceResult {
+    pub n_tokens: c_uint,
+
+    /// `enum simularity_gpt_stop_reason`.
+    pub stop_reason: c_int,
+
+    pub stop_sequence: c_int,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SimularityGptInferResult {
+    pub n_prompt: c_uint,
+    pub n_tokens: c_uint,
+    pub n_evicted: c_uint,
+
+    /// `enum simularity_gpt_stop_reason`.
+    pub stop_reason: c_int,
+
+    pub stop_sequence: c_int,
+    pub decode_us: i64,
+    pub infer_us: i64,
+    pub n_drafted: c_uint,
+    pub n_accepted: c_uint,
+}
+
+#[derive(Debug, Clone)]
+#[repr(C)]
 pub struct SimularityModelInfo {
     pub n_params: u64,
     pub size: u64,
     pub n_ctx_train: i64,
 }
 
-#[link(name = "simularity")]
-extern "C" {
-    // void simularity_init(
-    //     unsigned gpt_sessions_ttl, unsigned gpt_sessions_max,
-    //     const char *gpt_sessions_cache_dir
-    // );
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SimularityModelLoadOptions {
+    pub use_mmap: bool,
+    pub use_mlock: bool,
+    pub vocab_only: bool,
+    pub check_tensors: bool,
+    pub n_threads: c_uint,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SimularityGptSessionInfo {
+    pub n_ctx: c_uint,
+    pub n_batch: c_uint,
+    pub n_prompt: c_uint,
+    pub expired_at: i64,
+    pub last_used_at: i64,
+    pub n_evicted: c_uint,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SimularityGptContextShift {
+    pub n_keep: c_uint,
+    pub n_discard: c_uint,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SimularityGptSessionOptions {
+    pub n_ubatch: c_uint,
+    pub n_threads: c_uint,
+    pub n_threads_batch: c_uint,
+    pub type_k: c_int,
+    pub type_v: c_int,
+    pub flash_attn: bool,
+
+    pub rope_scaling_type: c_int,
+    pub rope_freq_base: c_float,
+    pub rope_freq_scale: c_float,
+
+    pub yarn_ext_factor: c_float,
+    pub yarn_attn_factor: c_float,
+    pub yarn_beta_fast: c_float,
+    pub yarn_beta_slow: c_float,
+    pub yarn_orig_ctx: c_uint,
+    pub n_seq_max: c_uint,
+}
+
+pub type SimularityLogCallback = extern "C" fn(
+    level: c_int,
+    target: *const c_char,
+    message: *const c_char,
+    user_data: *mut c_void,
+);
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SimularityInitOptions {
+    pub gpt_sessions_ttl: c_uint,
+    pub gpt_sessions_max: c_uint,
+    pub gpt_sessions_cache_dir: *const c_char,
+    pub log_level: c_int,
+    pub log_callback: Option<SimularityLogCallback>,
+    pub log_callback_user_data: *mut c_void,
+    pub n_threads: c_uint,
+}
+
+/// Declare the native functions. With the `mock` feature, the native library
+/// is not linked, and the functions panic instead.
+macro_rules! native {
+    ($(pub fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*) => {
+        #[cfg(not(feature = "mock"))]
+        #[link(name = "simularity")]
+        extern "C" {
+            $(pub fn $name($($arg: $ty),*) $(-> $ret)?;)*
+        }
+
+        $(
+            #[cfg(feature = "mock")]
+            #[allow(clippy::too_many_arguments)]
+            pub unsafe fn $name($(_: $ty),*) $(-> $ret)? {
+                panic!(concat!(
+                    "`",
+                    stringify!($name),
+                    "` requires the native library, not linked with the `mock` feature"
+                ))
+            }
+        )*
+    };
+}
+
+native! {
+    // void simularity_init(unsigned gpt_sessions_ttl, unsigned gpt_sessions_max);
     pub fn simularity_init(gpt_sessions_ttl: c_uint, gpt_sessions_max: c_uint) -> c_void;
 
+    // struct simularity_init_options simularity_init_options_default();
+    pub fn simularity_init_options_default() -> SimularityInitOptions;
+
+    // int simularity_init_with_options(
+    //     const struct simularity_init_options *options
+    // );
+    pub fn simularity_init_with_options(options: *const SimularityInitOptions) -> c_int;
+
+    // const char *simularity_last_error();
+    pub fn simularity_last_error() -> *const c_char;
+
     // int simularity_model_load(
     //     const char *model_path,
     //     const char *model_id,
+    //     const struct simularity_model_load_options *options,
     //     bool(progress_callback)(float, void *),
     //     void *progress_callback_user_data,
     //     struct simularity_model_info *model_info
@@ -55,6 +233,7 @@ extern "C" {
     pub fn simularity_model_load(
         model_path: *const c_char,
         model_id: *const c_char,
+        options: *const SimularityModelLoadOptions,
         progress_callback: Option<extern "C" fn(c_float, *mut c_void) -> bool>,
         progress_callback_user_data: *mut c_void,
         model_info: *mut SimularityModelInfo,
@@ -66,18 +245,89 @@ extern "C" {
     // int simularity_model_unload(const char *model_id);
     pub fn simularity_model_unload(model_id: *const c_char) -> c_int;
 
+    // int simularity_model_get_info(
+    //     const char *model_id, struct simularity_model_info *model_info
+    // );
+    pub fn simularity_model_get_info(
+        model_id: *const c_char,
+        model_info: *mut SimularityModelInfo,
+    ) -> c_int;
+
+    // int simularity_model_list(char *model_ids, size_t size);
+    pub fn simularity_model_list(model_ids: *mut c_char, size: usize) -> c_int;
+
+    // int simularity_lora_load(const char *lora_path, const char *lora_id);
+    pub fn simularity_lora_load(lora_path: *const c_char, lora_id: *const c_char) -> c_int;
+
+    // int simularity_lora_unload(const char *lora_id);
+    pub fn simularity_lora_unload(lora_id: *const c_char) -> c_int;
+
     // uint64_t simularity_model_get_hash_by_path(const char *model_path);
     pub fn simularity_model_get_hash_by_path(model_path: *const c_char) -> u64;
 
+    // int simularity_grammar_validate(const char *grammar, int64_t *error_offset);
+    pub fn simularity_grammar_validate(grammar: *const c_char, error_offset: *mut i64) -> c_int;
+
     // int simularity_gpt_token_length(const char *model_id, const char *prompt);
     pub fn simularity_gpt_token_length(model_id: *const c_char, prompt: *const c_char) -> c_int;
 
+    // int simularity_gpt_tokenize(
+    //     const char *model_id,
+    //     const char *text,
+    //     bool add_special,
+    //     bool parse_special,
+    //     int32_t *tokens,
+    //     unsigned n_tokens_max
+    // );
+    pub fn simularity_gpt_tokenize(
+        model_id: *const c_char,
+        text: *const c_char,
+        add_special: bool,
+        parse_special: bool,
+        tokens: *mut i32,
+        n_tokens_max: c_uint,
+    ) -> c_int;
+
+    // int simularity_gpt_detokenize(
+    //     const char *model_id,
+    //     const int32_t *tokens,
+    //     unsigned n_tokens,
+    //     char *text,
+    //     unsigned text_len_max
+    // );
+    pub fn simularity_gpt_detokenize(
+        model_id: *const c_char,
+        tokens: *const i32,
+        n_tokens: c_uint,
+        text: *mut c_char,
+        text_len_max: c_uint,
+    ) -> c_int;
+
+    // int simularity_gpt_token_to_piece(
+    //     const char *model_id,
+    //     int32_t token,
+    //     char *piece,
+    //     unsigned piece_len_max
+    // );
+    pub fn simularity_gpt_token_to_piece(
+        model_id: *const c_char,
+        token: i32,
+        piece: *mut c_char,
+        piece_len_max: c_uint,
+    ) -> c_int;
+
+    // struct simularity_gpt_session_options
+    // simularity_gpt_session_options_default();
+    pub fn simularity_gpt_session_options_default() -> SimularityGptSessionOptions;
+
     // int simularity_gpt_create(
     //     const char *model_id,
     //     unsigned context_size,
     //     unsigned unsigned batch_size,,
+    //     const struct simularity_gpt_session_options *options,
     //     const char *initial_prompt,
     //     const char *state_file_path,
+    //     const struct simularity_gpt_context_shift *context_shift,
     //     void(progress_callback)(float, void *),
     //     void *progress_callback_user_data
     // );
@@ -85,8 +335,10 @@ extern "C" {
         model_id: *const c_char,
         context_size: c_uint,
         batch_size: c_uint,
+        options: *const SimularityGptSessionOptions,
         initial_prompt: *const c_char,
         state_file_path: *const c_char,
+        context_shift: *const SimularityGptContextShift,
         progress_callback: Option<extern "C" fn(c_float, *mut c_void) -> bool>,
         progress_callback_user_data: *mut c_void,
     ) -> c_int;
@@ -118,8 +370,11 @@ extern "C" {
     //     const struct simularity_gpt_inference_options options,
     //     bool(decode_progress_callback)(float, void *),
     //     void *decode_progress_callback_user_data,
-    //     bool(inference_callback)(const char *output, void *),
-    //     void *inference_callback_user_data
+    //     bool(inference_callback)(
+    //         const struct simularity_gpt_inference_token *token, void *
+    //     ),
+    //     void *inference_callback_user_data,
+    //     struct simularity_gpt_infer_result *result
     // );
     pub fn simularity_gpt_infer(
         session_id: c_uint,
@@ -128,12 +383,108 @@ extern "C" {
         options: SimularityGptInferenceOptions,
         decode_progress_callback: Option<extern "C" fn(c_float, *mut c_void) -> bool>,
         decode_progress_callback_user_data: *mut c_void,
-        inference_callback: Option<extern "C" fn(*const c_char, *mut c_void) -> bool>,
+        inference_callback: Option<
+            extern "C" fn(*const SimularityGptInferenceToken, *mut c_void) -> bool,
+        >,
+        inference_callback_user_data: *mut c_void,
+        result: *mut SimularityGptInferResult,
+    ) -> c_int;
+
+    // int simularity_gpt_infer_many(
+    //     unsigned session_id,
+    //     const char *prompt,
+    //     unsigned n_eval,
+    //     unsigned n_seqs,
+    //     const struct simularity_gpt_inference_options options,
+    //     bool(decode_progress_callback)(float, void *),
+    //     void *decode_progress_callback_user_data,
+    //     bool(inference_callback)(
+    //         unsigned seq, const struct simularity_gpt_inference_token *token, void *
+    //     ),
+    //     void *inference_callback_user_data,
+    //     struct simularity_gpt_sequence_result *results
+    // );
+    pub fn simularity_gpt_infer_many(
+        session_id: c_uint,
+        prompt: *const c_char,
+        n_eval: c_uint,
+        n_seqs: c_uint,
+        options: SimularityGptInferenceOptions,
+        decode_progress_callback: Option<extern "C" fn(c_float, *mut c_void) -> bool>,
+        decode_progress_callback_user_data: *mut c_void,
+        inference_callback: Option<
+            extern "C" fn(c_uint, *const SimularityGptInferenceToken, *mut c_void) -> bool,
+        >,
         inference_callback_user_data: *mut c_void,
+        results: *mut SimularityGptSequenceResult,
+    ) -> c_int;
+
+    // int simularity_gpt_set_loras(
+    //     unsigned session_id,
+    //     unsigned n_loras,
+    //     const char *const *lora_ids,
+    //     const float *scales
+    // );
+    pub fn simularity_gpt_set_loras(
+        session_id: c_uint,
+        n_loras: c_uint,
+        lora_ids: *const *const c_char,
+        scales: *const c_float,
     ) -> c_int;
 
     // int simularity_gpt_destroy(unsigned session_id);
     pub fn simularity_gpt_destroy(session_id: c_uint) -> c_int;
+
+    // int simularity_gpt_embed(
+    //     const char *model_id,
+    //     const char *const *texts,
+    //     unsigned n_texts,
+    //     int pooling,
+    //     float *embeddings,
+    //     size_t n_floats_max
+    // );
+    pub fn simularity_gpt_embed(
+        model_id: *const c_char,
+        texts: *const *const c_char,
+        n_texts: c_uint,
+        pooling: c_int,
+        embeddings: *mut c_float,
+        n_floats_max: usize,
+    ) -> c_int;
+
+    // int simularity_gpt_info(
+    //     unsigned session_id,
+    //     struct simularity_gpt_session_info *info,
+    //     char *model_id,
+    //     size_t model_id_size
+    // );
+    pub fn simularity_gpt_info(
+        session_id: c_uint,
+        info: *mut SimularityGptSessionInfo,
+        model_id: *mut c_char,
+        model_id_size: usize,
+    ) -> c_int;
+
+    // int simularity_gpt_list(unsigned *session_ids, size_t size);
+    pub fn simularity_gpt_list(session_ids: *mut c_uint, size: usize) -> c_int;
+
+    // int simularity_gpt_fork(unsigned session_id);
+    pub fn simularity_gpt_fork(session_id: c_uint) -> c_int;
+
+    // int simularity_gpt_state_save_file(unsigned session_id, const char *path);
+    pub fn simularity_gpt_state_save_file(session_id: c_uint, path: *const c_char) -> c_int;
+
+    // int simularity_gpt_state_load_file(unsigned session_id, const char *path);
+    pub fn simularity_gpt_state_load_file(session_id: c_uint, path: *const c_char) -> c_int;
+
+    // int64_t
+    // simularity_gpt_state_save(unsigned session_id, uint8_t *dst, size_t size);
+    pub fn simularity_gpt_state_save(session_id: c_uint, dst: *mut u8, size: usize) -> i64;
+
+    // int simularity_gpt_state_load(
+    //     unsigned session_id, const uint8_t *src, size_t size
+    // );
+    pub fn simularity_gpt_state_load(session_id: c_uint, src: *const u8, size: usize) -> c_int;
 }
 
 // See https://stackoverflow.com/a/32270215/3645337.
@@ -144,13 +495,58 @@ pub extern "C" fn progress_callback_wrapper(progress: c_float, user_data: *mut c
 }
 
 // See https://stackoverflow.com/a/32270215/3645337.
+/// Called with the token and its raw piece bytes.
+pub type InferCallback<'a> = dyn FnMut(&InferenceToken, &[u8]) -> bool + 'a;
+
 pub extern "C" fn inference_callback_wrapper(
-    output: *const c_char,
+    token: *const SimularityGptInferenceToken,
     user_data: *mut c_void,
 ) -> bool {
     #[allow(clippy::transmute_ptr_to_ref)]
-    let closure: &mut &mut dyn FnMut(&str) -> bool = unsafe { std::mem::transmute(user_data) };
-    let output = unsafe { std::ffi::CStr::from_ptr(output) };
-    // FIXME: Utf8Error (invalid utf-8) handling.
-    closure(output.to_str().unwrap())
+    let closure: &mut &mut InferCallback = unsafe { std::mem::transmute(user_data) };
+    let token = unsafe { &*token };
+    let piece = unsafe { CStr::from_ptr(token.piece) }.to_bytes();
+    closure(&to_inference_token(token), piece)
+}
+
+/// Called with the sequence index, the token and its raw piece bytes.
+pub type InferManyCallback<'a> = dyn FnMut(u32, &InferenceToken, &[u8]) -> bool + 'a;
+
+pub extern "C" fn inference_many_callback_wrapper(
+    seq: c_uint,
+    token: *const SimularityGptInferenceToken,
+    user_data: *mut c_void,
+) -> bool {
+    #[allow(clippy::transmute_ptr_to_ref)]
+    let closure: &mut &mut InferManyCallback = unsafe { std::mem::transmute(user_data) };
+    let token = unsafe { &*token };
+    let piece = unsafe { CStr::from_ptr(token.piece) }.to_bytes();
+    closure(seq, &to_inference_token(token), piece)
+}
+
+fn to_inference_token(token: &SimularityGptInferenceToken) -> InferenceToken {
+    let top = if token.n_top > 0 {
+        unsafe { std::slice::from_raw_parts(token.top, token.n_top as usize) }
+    } else {
+        &[]
+    };
+
+    // NOTE: A token may contain an incomplete UTF-8 sequence.
+    InferenceToken {
+        token: token.token,
+        piece: unsafe { CStr::from_ptr(token.piece) }
+            .to_string_lossy()
+            .into_owned(),
+        logprob: token.logprob,
+        top_logprobs: top
+            .iter()
+            .map(|candidate| TokenLogprob {
+                token: candidate.token,
+                piece: unsafe { CStr::from_ptr(candidate.piece) }
+                    .to_string_lossy()
+                    .into_owned(),
+                logprob: candidate.logprob,
+            })
+            .collect(),
+    }
 }
@@ -0,0 +1,851 @@
+//! A pure-Rust GGUF metadata reader. Parses the file header,
+//! the key/value metadata and the tensor infos without reading
+//! the tensor data, so that a model may be inspected before loading.
+//!
+//! See https://github.com/ggerganov/ggml/blob/master/docs/gguf.md.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, Read},
+};
+
+use crate::Error;
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+/// Arrays longer than that are omitted from [`Summary::metadata`].
+const SUMMARY_MAX_ARRAY_LEN: usize = 16;
+
+/// Protects from a stack overflow on malformed files.
+const MAX_ARRAY_DEPTH: usize = 8;
+
+/// A GGUF metadata value.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    String(String),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// Get the value as an unsigned integer, if it is a non-negative integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::U8(v) => Some(v as u64),
+            Value::U16(v) => Some(v as u64),
+            Value::U32(v) => Some(v as u64),
+            Value::U64(v) => Some(v),
+            Value::I8(v) => u64::try_from(v).ok(),
+            Value::I16(v) => u64::try_from(v).ok(),
+            Value::I32(v) => u64::try_from(v).ok(),
+            Value::I64(v) => u64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a string slice, if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Get the value as an array slice, if it is an array.
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// Information about a tensor, without its data.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TensorInfo {
+    pub name: String,
+    pub dimensions: Vec<u64>,
+
+    /// The `ggml_type` of the tensor, see [`TensorInfo::type_name`].
+    pub ggml_type: u32,
+
+    /// Offset of the tensor data, relative to the data section.
+    pub offset: u64,
+}
+
+impl TensorInfo {
+    /// The number of elements in the tensor. Saturates, though it never
+    /// overflows for a parsed file.
+    pub fn n_elements(&self) -> u64 {
+        self.dimensions.iter().fold(1, |n, &d| n.saturating_mul(d))
+    }
+
+    /// The name of the tensor `ggml_type`, e.g. `Q4_K`.
+    pub fn type_name(&self) -> Option<&'static str> {
+        ggml_type_name(self.ggml_type)
+    }
+}
+
+/// A special token, with its text if the vocabulary is present.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpecialToken {
+    pub id: u32,
+    pub text: Option<String>,
+}
+
+/// Special tokens of the model vocabulary.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpecialTokens {
+    pub bos: Option<SpecialToken>,
+    pub eos: Option<SpecialToken>,
+    pub eot: Option<SpecialToken>,
+    pub unk: Option<SpecialToken>,
+    pub sep: Option<SpecialToken>,
+    pub pad: Option<SpecialToken>,
+}
+
+/// A serializable summary of a GGUF file, see [`Gguf::summary`].
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    pub version: u32,
+    pub tensor_count: u64,
+    pub n_params: u64,
+    pub architecture: Option<String>,
+    pub name: Option<String>,
+    pub context_length: Option<u64>,
+    pub embedding_length: Option<u64>,
+    pub block_count: Option<u64>,
+    pub file_type: Option<u32>,
+    pub file_type_name: Option<String>,
+    pub tokenizer_model: Option<String>,
+    pub vocab_size: Option<u64>,
+    pub special_tokens: SpecialTokens,
+    pub chat_template: Option<String>,
+
+    /// All the metadata, except for long arrays (e.g. the vocabulary).
+    pub metadata: BTreeMap<String, Value>,
+}
+
+/// Parsed GGUF file header.
+#[derive(Debug, Clone)]
+pub struct Gguf {
+    /// GGUF format version.
+    pub version: u32,
+
+    /// Key/value metadata, in the file order.
+    pub metadata: Vec<(String, Value)>,
+
+    /// Tensor infos, in the file order.
+    pub tensors: Vec<TensorInfo>,
+}
+
+impl Gguf {
+    /// Read a GGUF file header from the path, without reading the tensor data.
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|err| Error::InvalidInput {
+            message: format!("`path` is not accessible: {}", path),
+            source: Some(Box::new(err)),
+        })?;
+
+        Self::from_reader(BufReader::new(file))
+    }
+
+    /// Read a GGUF header from the reader, which shall be at the file start.
+    pub fn from_reader(reader: impl Read) -> Result<Self, Error> {
+        Parser { reader }.parse()
+    }
+
+    /// Get a metadata value by its key.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Get a metadata string by its key.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(Value::as_str)
+    }
+
+    /// Get a metadata unsigned integer by its key.
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        self.get(key).and_then(Value::as_u64)
+    }
+
+    /// Model architecture, e.g. `llama`.
+    pub fn architecture(&self) -> Option<&str> {
+        self.get_str("general.architecture")
+    }
+
+    /// Human-readable model name.
+    pub fn name(&self) -> Option<&str> {
+        self.get_str("general.name")
+    }
+
+    /// Get an architecture-specific metadata value, e.g. `llama.context_length`.
+    pub fn get_arch(&self, key: &str) -> Option<&Value> {
+        self.get(&format!("{}.{}", self.architecture()?, key))
+    }
+
+    /// The context length the model was trained on.
+    pub fn context_length(&self) -> Option<u64> {
+        self.get_arch("context_length").and_then(Value::as_u64)
+    }
+
+    /// The embedding size.
+    pub fn embedding_length(&self) -> Option<u64> {
+        self.get_arch("embedding_length").and_then(Value::as_u64)
+    }
+
+    /// The number of layers.
+    pub fn block_count(&self) -> Option<u64> {
+        self.get_arch("block_count").and_then(Value::as_u64)
+    }
+
+    /// The `llama_ftype` of the model, see [`Gguf::file_type_name`].
+    pub fn file_type(&self) -> Option<u32> {
+        self.get_u64("general.file_type").map(|v| v as u32)
+    }
+
+    /// The quantization type name, e.g. `Q4_K_M`.
+    pub fn file_type_name(&self) -> Option<&'static str> {
+        self.file_type().and_then(llama_ftype_name)
+    }
+
+    /// The tokenizer model, e.g. `llama` or `gpt2`.
+    pub fn tokenizer_model(&self) -> Option<&str> {
+        self.get_str("tokenizer.ggml.model")
+    }
+
+    /// The vocabulary tokens.
+    pub fn tokens(&self) -> Option<&[Value]> {
+        self.get("tokenizer.ggml.tokens").and_then(Value::as_array)
+    }
+
+    /// The vocabulary size.
+    pub fn vocab_size(&self) -> Option<u64> {
+        self.tokens()
+            .map(|tokens| tokens.len() as u64)
+            .or_else(|| self.get_arch("vocab_size").and_then(Value::as_u64))
+    }
+
+    /// Special tokens of the vocabulary.
+    pub fn special_tokens(&self) -> SpecialTokens {
+        let token = |name: &str| {
+            let id = self.get_u64(&format!("tokenizer.ggml.{}_token_id", name))? as u32;
+            let text = self
+                .tokens()
+                .and_then(|tokens| tokens.get(id as usize))
+                .and_then(Value::as_str)
+                .map(str::to_string);
+
+            Some(SpecialToken { id, text })
+        };
+
+        SpecialTokens {
+            bos: token("bos"),
+            eos: token("eos"),
+            eot: token("eot"),
+            unk: token("unknown"),
+            sep: token("seperator"),
+            pad: token("padding"),
+        }
+    }
+
+    /// The embedded Jinja chat template.
+    pub fn chat_template(&self) -> Option<&str> {
+        self.get_str("tokenizer.chat_template")
+    }
+
+    /// The number of model parameters, computed from the tensor shapes.
+    /// Saturates, though it never overflows for a parsed file.
+    pub fn n_params(&self) -> u64 {
+        self.tensors
+            .iter()
+            .fold(0, |n, tensor| n.saturating_add(tensor.n_elements()))
+    }
+
+    /// A serializable summary, omitting long arrays such as the vocabulary.
+    pub fn summary(&self) -> Summary {
+        Summary {
+            version: self.version,
+            tensor_count: self.tensors.len() as u64,
+            n_params: self.n_params(),
+            architecture: self.architecture().map(str::to_string),
+            name: self.name().map(str::to_string),
+            context_length: self.context_length(),
+            embedding_length: self.embedding_length(),
+            block_count: self.block_count(),
+            file_type: self.file_type(),
+            file_type_name: self.file_type_name().map(str::to_string),
+            tokenizer_model: self.tokenizer_model().map(str::to_string),
+            vocab_size: self.vocab_size(),
+            special_tokens: self.special_tokens(),
+            chat_template: self.chat_template().map(str::to_string),
+            metadata: self
+                .metadata
+                .iter()
+                .filter(|(_, value)| {
+                    !matches!(value, Value::Array(array) if array.len() > SUMMARY_MAX_ARRAY_LEN)
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Read a GGUF file header, see [`Gguf::open`].
+pub fn inspect(path: &str) -> Result<Gguf, Error> {
+    Gguf::open(path)
+}
+
+struct Parser<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> Parser<R> {
+    fn parse(mut self) -> Result<Gguf, Error> {
+        let magic: [u8; 4] = self.bytes()?;
+        if &magic != GGUF_MAGIC {
+            return Err(parse_error("Not a GGUF file (invalid magic)".to_string()));
+        }
+
+        let version = self.u32()?;
+        if !(1..=3).contains(&version) {
+            return Err(parse_error(format!(
+                "Unsupported GGUF version: {}",
+                version
+            )));
+        }
+
+        let tensor_count = self.length(version)?;
+        let metadata_count = self.length(version)?;
+
+        let mut metadata = Vec::with_capacity(metadata_count.min(1024) as usize);
+        for _ in 0..metadata_count {
+            let key = self.string(version)?;
+            let value_type = self.u32()?;
+            let value = self
+                .value(version, value_type, 0)
+                .map_err(|err| with_context(err, &format!("key `{}`", key)))?;
+            metadata.push((key, value));
+        }
+
+        let mut tensors = Vec::with_capacity(tensor_count.min(4096) as usize);
+        let mut n_params: u64 = 0;
+        for _ in 0..tensor_count {
+            let name = self.string(version)?;
+            let n_dimensions = self.u32()?;
+            let dimensions = (0..n_dimensions)
+                .map(|_| self.length(version))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // NOTE: The dimensions are not trusted not to overflow.
+            n_params = dimensions
+                .iter()
+                .try_fold(1u64, |n, &d| n.checked_mul(d))
+                .and_then(|n_elements| n_params.checked_add(n_elements))
+                .ok_or_else(|| parse_error(format!("Too many elements in tensor `{}`", name)))?;
+            let ggml_type = self.u32()?;
+            let offset = self.u64()?;
+
+            tensors.push(TensorInfo {
+                name,
+                dimensions,
+                ggml_type,
+                offset,
+            });
+        }
+
+        Ok(Gguf {
+            version,
+            metadata,
+            tensors,
+        })
+    }
+
+    fn value(&mut self, version: u32, value_type: u32, depth: usize) -> Result<Value, Error> {
+        Ok(match value_type {
+            0 => Value::U8(u8::from_le_bytes(self.bytes()?)),
+            1 => Value::I8(i8::from_le_bytes(self.bytes()?)),
+            2 => Value::U16(u16::from_le_bytes(self.bytes()?)),
+            3 => Value::I16(i16::from_le_bytes(self.bytes()?)),
+            4 => Value::U32(self.u32()?),
+            5 => Value::I32(i32::from_le_bytes(self.bytes()?)),
+            6 => Value::F32(f32::from_le_bytes(self.bytes()?)),
+            7 => Value::Bool(u8::from_le_bytes(self.bytes()?) != 0),
+            8 => Value::String(self.string(version)?),
+            9 => {
+                if depth >= MAX_ARRAY_DEPTH {
+                    return Err(parse_error("Arrays are nested too deep".to_string()));
+                }
+
+                let element_type = self.u32()?;
+                let length = self.length(version)?;
+
+                // NOTE: The length is not trusted for the allocation.
+                let mut array = Vec::with_capacity(length.min(1024) as usize);
+                for _ in 0..length {
+                    array.push(self.value(version, element_type, depth + 1)?);
+                }
+
+                Value::Array(array)
+            }
+            10 => Value::U64(self.u64()?),
+            11 => Value::I64(i64::from_le_bytes(self.bytes()?)),
+            12 => Value::F64(f64::from_le_bytes(self.bytes()?)),
+            _ => {
+                return Err(parse_error(format!(
+                    "Unknown metadata value type: {}",
+                    value_type
+                )))
+            }
+        })
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut buf = [0u8; N];
+        self.reader.read_exact(&mut buf).map_err(io_error)?;
+        Ok(buf)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    /// Counts and lengths are 32-bit in GGUF v1, and 64-bit since v2.
+    fn length(&mut self, version: u32) -> Result<u64, Error> {
+        if version == 1 {
+            self.u32().map(u64::from)
+        } else {
+            self.u64()
+        }
+    }
+
+    fn string(&mut self, version: u32) -> Result<String, Error> {
+        let length = self.length(version)?;
+
+        let mut buf = Vec::new();
+        (&mut self.reader)
+            .take(length)
+            .read_to_end(&mut buf)
+            .map_err(io_error)?;
+
+        if buf.len() as u64 != length {
+            return Err(parse_error("Unexpected end of file".to_string()));
+        }
+
+        String::from_utf8(buf).map_err(|err| parse_error(format!("Invalid UTF-8 string: {}", err)))
+    }
+}
+
+fn parse_error(message: String) -> Error {
+    Error::GgufParseFailed(Some(message))
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    if err.kind() == std::io::ErrorKind::UnexpectedEof {
+        parse_error("Unexpected end of file".to_string())
+    } else {
+        parse_error(err.to_string())
+    }
+}
+
+fn with_context(err: Error, context: &str) -> Error {
+    match err {
+        Error::GgufParseFailed(Some(message)) => {
+            Error::GgufParseFailed(Some(format!("{} (at {})", message, context)))
+        }
+        err => err,
+    }
+}
+
+/// The name of a `ggml_type`.
+fn ggml_type_name(ggml_type: u32) -> Option<&'static str> {
+    Some(match ggml_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        6 => "Q5_0",
+        7 => "Q5_1",
+        8 => "Q8_0",
+        9 => "Q8_1",
+        10 => "Q2_K",
+        11 => "Q3_K",
+        12 => "Q4_K",
+        13 => "Q5_K",
+        14 => "Q6_K",
+        15 => "Q8_K",
+        16 => "IQ2_XXS",
+        17 => "IQ2_XS",
+        18 => "IQ3_XXS",
+        19 => "IQ1_S",
+        20 => "IQ4_NL",
+        21 => "IQ3_S",
+        22 => "IQ2_S",
+        23 => "IQ4_XS",
+        24 => "I8",
+        25 => "I16",
+        26 => "I32",
+        27 => "I64",
+        28 => "F64",
+        29 => "IQ1_M",
+        30 => "BF16",
+        31 => "Q4_0_4_4",
+        32 => "Q4_0_4_8",
+        33 => "Q4_0_8_8",
+        34 => "TQ1_0",
+        35 => "TQ2_0",
+        _ => return None,
+    })
+}
+
+/// The name of a `llama_ftype`, i.e. the model quantization type.
+fn llama_ftype_name(file_type: u32) -> Option<&'static str> {
+    Some(match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        19 => "IQ2_XXS",
+        20 => "IQ2_XS",
+        21 => "Q2_K_S",
+        22 => "IQ3_XS",
+        23 => "IQ3_XXS",
+        24 => "IQ1_S",
+        25 => "IQ4_NL",
+        26 => "IQ3_S",
+        27 => "IQ3_M",
+        28 => "IQ2_S",
+        29 => "IQ2_M",
+        30 => "IQ4_XS",
+        31 => "IQ1_M",
+        32 => "BF16",
+        33 => "Q4_0_4_4",
+        34 => "Q4_0_4_8",
+        35 => "Q4_0_8_8",
+        36 => "TQ1_0",
+        37 => "TQ2_0",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A synthetic GGUF file builder.
+    struct Builder {
+        version: u32,
+        buf: Vec<u8>,
+    }
+
+    impl Builder {
+        fn new(version: u32, tensor_count: u64, metadata_count: u64) -> Self {
+            let mut builder = Self {
+                version,
+                buf: GGUF_MAGIC.to_vec(),
+            };
+
+            builder.u32(version);
+            builder.length(tensor_count);
+            builder.length(metadata_count);
+            builder
+        }
+
+        fn u32(&mut self, value: u32) -> &mut Self {
+            self.bytes(&value.to_le_bytes())
+        }
+
+        fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+            self.buf.extend_from_slice(bytes);
+            self
+        }
+
+        fn length(&mut self, length: u64) -> &mut Self {
+            if self.version == 1 {
+                self.u32(length as u32)
+            } else {
+                self.bytes(&length.to_le_bytes())
+            }
+        }
+
+        fn string(&mut self, string: &str) -> &mut Self {
+            self.length(string.len() as u64).bytes(string.as_bytes())
+        }
+
+        /// A key with a value of the type.
+        fn key(&mut self, key: &str, value_type: u32) -> &mut Self {
+            self.string(key).u32(value_type)
+        }
+
+        fn parse(&self) -> Result<Gguf, Error> {
+            Gguf::from_reader(self.buf.as_slice())
+        }
+    }
+
+    fn parse_error_message(result: Result<Gguf, Error>) -> String {
+        match result {
+            Err(Error::GgufParseFailed(Some(message))) => message,
+            result => panic!("Expected a parse error, got {:?}", result),
+        }
+    }
+
+    /// Nest arrays `depth` times, the innermost one being empty.
+    fn nested_arrays(depth: usize) -> Builder {
+        let mut builder = Builder::new(3, 0, 1);
+        builder.key("nested", 9);
+
+        for _ in 1..depth {
+            builder.u32(9).length(1);
+        }
+
+        builder.u32(4).length(0);
+        builder
+    }
+
+    #[test]
+    fn parses_every_value_type() {
+        let mut builder = Builder::new(3, 1, 14);
+        builder.key("u8", 0).bytes(&[200]);
+        builder.key("i8", 1).bytes(&(-100i8).to_le_bytes());
+        builder.key("u16", 2).bytes(&60000u16.to_le_bytes());
+        builder.key("i16", 3).bytes(&(-30000i16).to_le_bytes());
+        builder.key("u32", 4).u32(4_000_000_000);
+        builder
+            .key("i32", 5)
+            .bytes(&(-2_000_000_000i32).to_le_bytes());
+        builder.key("f32", 6).bytes(&1.5f32.to_le_bytes());
+        builder.key("bool", 7).bytes(&[1]);
+        builder.key("string", 8).string("Привет");
+        builder
+            .key("array", 9)
+            .u32(8)
+            .length(2)
+            .string("a")
+            .string("b");
+        builder.key("u64", 10).bytes(&u64::MAX.to_le_bytes());
+        builder.key("i64", 11).bytes(&i64::MIN.to_le_bytes());
+        builder.key("f64", 12).bytes(&(-0.25f64).to_le_bytes());
+        builder.key("general.architecture", 8).string("llama");
+
+        builder
+            .string("token_embd.weight")
+            .u32(2)
+            .length(32)
+            .length(100);
+        builder.u32(12).bytes(&64u64.to_le_bytes());
+
+        let gguf = builder.parse().unwrap();
+        assert_eq!(gguf.version, 3);
+
+        let values: Vec<&Value> = gguf.metadata.iter().map(|(_, value)| value).collect();
+        assert_eq!(
+            values,
+            [
+                &Value::U8(200),
+                &Value::I8(-100),
+                &Value::U16(60000),
+                &Value::I16(-30000),
+                &Value::U32(4_000_000_000),
+                &Value::I32(-2_000_000_000),
+                &Value::F32(1.5),
+                &Value::Bool(true),
+                &Value::String("Привет".to_string()),
+                &Value::Array(vec![
+                    Value::String("a".to_string()),
+                    Value::String("b".to_string())
+                ]),
+                &Value::U64(u64::MAX),
+                &Value::I64(i64::MIN),
+                &Value::F64(-0.25),
+                &Value::String("llama".to_string()),
+            ]
+        );
+
+        assert_eq!(gguf.architecture(), Some("llama"));
+        assert_eq!(gguf.tensors.len(), 1);
+        assert_eq!(gguf.tensors[0].name, "token_embd.weight");
+        assert_eq!(gguf.tensors[0].dimensions, [32, 100]);
+        assert_eq!(gguf.tensors[0].type_name(), Some("Q4_K"));
+        assert_eq!(gguf.tensors[0].offset, 64);
+        assert_eq!(gguf.n_params(), 3200);
+    }
+
+    #[test]
+    fn parses_v1_lengths() {
+        let mut builder = Builder::new(1, 0, 1);
+        builder.key("general.name", 8).string("Model");
+
+        let gguf = builder.parse().unwrap();
+        assert_eq!(gguf.version, 1);
+        assert_eq!(gguf.name(), Some("Model"));
+    }
+
+    #[test]
+    fn limits_array_depth() {
+        let mut value = nested_arrays(MAX_ARRAY_DEPTH).parse().unwrap().metadata[0]
+            .1
+            .clone();
+
+        for _ in 1..MAX_ARRAY_DEPTH {
+            value = value.as_array().unwrap()[0].clone();
+        }
+
+        assert_eq!(value, Value::Array(Vec::new()));
+
+        let message = parse_error_message(nested_arrays(MAX_ARRAY_DEPTH + 1).parse());
+        assert!(message.starts_with("Arrays are nested too deep"));
+        assert!(message.ends_with("(at key `nested`)"));
+    }
+
+    #[test]
+    fn fails_on_truncated_input() {
+        let mut builder = Builder::new(3, 1, 2);
+        builder.key("general.name", 8).string("Model");
+        builder.key("array", 9).u32(4).length(2).u32(1).u32(2);
+        builder
+            .string("output.weight")
+            .u32(1)
+            .length(8)
+            .u32(0)
+            .bytes(&[0; 8]);
+
+        assert!(builder.parse().is_ok());
+
+        for len in 0..builder.buf.len() {
+            let result = Gguf::from_reader(&builder.buf[..len]);
+            assert!(
+                parse_error_message(result).starts_with("Unexpected end of file"),
+                "at {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn fails_on_bad_header() {
+        let mut builder = Builder::new(3, 0, 0);
+        builder.buf[0] = b'F';
+        assert_eq!(
+            parse_error_message(builder.parse()),
+            "Not a GGUF file (invalid magic)"
+        );
+
+        for version in [0, 4] {
+            assert_eq!(
+                parse_error_message(Builder::new(version, 0, 0).parse()),
+                format!("Unsupported GGUF version: {}", version)
+            );
+        }
+
+        let mut builder = Builder::new(3, 0, 1);
+        builder.key("unknown", 13);
+        assert_eq!(
+            parse_error_message(builder.parse()),
+            "Unknown metadata value type: 13 (at key `unknown`)"
+        );
+
+        let mut builder = Builder::new(3, 0, 1);
+        builder.key("invalid", 8).length(1).bytes(&[0xff]);
+        assert!(parse_error_message(builder.parse()).starts_with("Invalid UTF-8 string"));
+    }
+
+    #[test]
+    fn does_not_trust_huge_lengths() {
+        // Would take exabytes if allocated upfront.
+        assert!(
+            parse_error_message(Builder::new(3, u64::MAX, u64::MAX).parse())
+                .starts_with("Unexpected end of file")
+        );
+
+        let mut builder = Builder::new(3, 0, 1);
+        builder.key("string", 8).length(u64::MAX).bytes(b"short");
+        assert!(parse_error_message(builder.parse()).starts_with("Unexpected end of file"));
+
+        let mut builder = Builder::new(3, 0, 1);
+        builder
+            .key("array", 9)
+            .u32(0)
+            .length(u64::MAX)
+            .bytes(&[1, 2, 3]);
+        assert!(parse_error_message(builder.parse()).starts_with("Unexpected end of file"));
+
+        let mut builder = Builder::new(3, 1, 0);
+        builder.string("tensor").u32(u32::MAX).length(1);
+        assert!(parse_error_message(builder.parse()).starts_with("Unexpected end of file"));
+    }
+
+    #[test]
+    fn rejects_overflowing_dimensions() {
+        let mut builder = Builder::new(3, 1, 0);
+        builder
+            .string("huge")
+            .u32(2)
+            .length(u64::MAX)
+            .length(2)
+            .u32(0)
+            .length(0);
+        assert_eq!(
+            parse_error_message(builder.parse()),
+            "Too many elements in tensor `huge`"
+        );
+
+        // Each tensor fits, but not their sum.
+        let mut builder = Builder::new(3, 2, 0);
+        for name in ["a", "b"] {
+            builder
+                .string(name)
+                .u32(1)
+                .length(u64::MAX)
+                .u32(0)
+                .length(0);
+        }
+        assert_eq!(
+            parse_error_message(builder.parse()),
+            "Too many elements in tensor `b`"
+        );
+
+        let tensor = TensorInfo {
+            name: "huge".to_string(),
+            dimensions: vec![u64::MAX, 2],
+            ggml_type: 0,
+            offset: 0,
+        };
+        assert_eq!(tensor.n_elements(), u64::MAX);
+    }
+}
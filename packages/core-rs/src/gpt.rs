@@ -1,5 +1,8 @@
+mod session;
+pub use session::Session;
+
 pub mod create;
-pub use create::create;
+pub use create::{create, ContextShift, KvCacheType, RopeScalingType, SessionOptions, Yarn};
 
 pub mod decode;
 pub use decode::decode;
@@ -7,16 +10,45 @@ pub use decode::decode;
 pub mod destroy;
 pub use destroy::destroy;
 
+pub mod embed;
+pub use embed::embed;
+
+pub mod fork;
+pub use fork::fork;
+
+pub mod info;
+pub use info::{info, list, SessionInfo};
+
 pub mod infer;
-pub use infer::infer;
+pub use infer::{infer, InferOutcome};
+
+pub mod infer_many;
+pub use infer_many::infer_many;
+
+pub mod loras;
+pub use loras::set_loras;
+
+#[cfg(feature = "tokio")]
+pub mod infer_stream;
+#[cfg(feature = "tokio")]
+pub use infer_stream::{infer_stream, InferStream, InferenceEvent};
+
+pub mod state;
+pub use state::{load_state, load_state_bytes, save_state, save_state_bytes};
 
 pub mod token_length;
 pub use token_length::token_length;
 
-use crate::ffi;
+pub mod tokenize;
+pub use tokenize::{detokenize, token_to_piece, tokenize};
 
 /// Check if a session exists and is not expired.
 /// If the session exists, prolong its expiration time.
 pub fn touch(session_id: u32) -> bool {
-    unsafe { ffi::simularity_gpt_touch(session_id) }
+    Session::borrow(session_id).touch()
+}
+
+/// Touch the session with the native library.
+pub(crate) fn touch_native(session_id: u32) -> bool {
+    unsafe { crate::ffi::simularity_gpt_touch(session_id) }
 }
@@ -1,43 +1,295 @@
-use std::ffi::CString;
+use super::Session;
+use crate::{backend::ProgressCallback, error, ffi, Error, ModelRegistry};
 
-use crate::ffi;
+/// A context shift policy, applied once the context overflows instead of
+/// failing with [`Error::ContextOverflow`]: the first `n_keep` tokens are kept,
+/// the oldest `n_discard` tokens after them are evicted, and the rest
+/// is shifted to fill the gap. See [`super::SessionInfo::evicted_tokens`].
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContextShift {
+    /// The number of tokens to always keep, e.g. a system prompt.
+    pub n_keep: u32,
 
-#[derive(Debug, Clone)]
-pub enum Error {
-    ModelNotFound,
-    SessionLimitReached,
-    ContextCreationFailed,
-    DecodeFailed,
-    Unknown(i32),
+    /// The number of tokens to evict at once,
+    /// `None` for half of the tokens after `n_keep`.
+    pub n_discard: Option<u32>,
 }
 
-/// Create a new GPT session.
-///
-/// # Arguments
-///
-/// * `model_id` - The model ID, after calling `model_load`.
-/// * `context_size` - Context size, or `None` for default by the model.
-/// * `batch_size` - Batch size, or `None` for default.
-/// * `initial_prompt` - Initial prompt to start the session.
-/// * `state_file_path` - Path to the session state file to load from or save to.
-/// * `progress_callback` - Progress callback on either session loading or decoding.
-///   Return `true` to continue, or `false` to cancel (not implemented yet).
+impl From<ContextShift> for ffi::SimularityGptContextShift {
+    fn from(context_shift: ContextShift) -> Self {
+        Self {
+            n_keep: context_shift.n_keep,
+            n_discard: context_shift.n_discard.unwrap_or(0),
+        }
+    }
+}
+
+/// A KV cache data type. A quantized cache takes less memory
+/// (a half for `Q8_0`, about a quarter for `Q4_0`) at a slight quality cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub enum KvCacheType {
+    #[serde(rename = "f16")]
+    F16,
+    #[serde(rename = "q8_0")]
+    Q8_0,
+    #[serde(rename = "q4_0")]
+    Q4_0,
+}
+
+impl From<KvCacheType> for i32 {
+    fn from(kv_cache_type: KvCacheType) -> Self {
+        // See `enum simularity_gpt_kv_cache_type`.
+        match kv_cache_type {
+            KvCacheType::F16 => 1,
+            KvCacheType::Q4_0 => 2,
+            KvCacheType::Q8_0 => 8,
+        }
+    }
+}
+
+/// A RoPE scaling type, to run a model past its training context size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RopeScalingType {
+    None,
+    Linear,
+    Yarn,
+}
+
+impl From<RopeScalingType> for i32 {
+    fn from(rope_scaling_type: RopeScalingType) -> Self {
+        // See `enum simularity_gpt_rope_scaling_type`.
+        match rope_scaling_type {
+            RopeScalingType::None => 0,
+            RopeScalingType::Linear => 1,
+            RopeScalingType::Yarn => 2,
+        }
+    }
+}
+
+/// YaRN RoPE scaling parameters, `None` for the model defaults.
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Yarn {
+    /// Extrapolation mix factor.
+    pub ext_factor: Option<f32>,
+
+    /// Magnitude scaling factor.
+    pub attn_factor: Option<f32>,
+
+    /// Low correction dimension.
+    pub beta_fast: Option<f32>,
+
+    /// High correction dimension.
+    pub beta_slow: Option<f32>,
+
+    /// The original context size, `None` for the model training one.
+    pub orig_ctx: Option<u32>,
+}
+
+/// Session context parameters, `None` for the defaults.
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SessionOptions {
+    /// Context size, or `None` for default by the model.
+    pub context_size: Option<u32>,
+
+    /// Logical batch size, i.e. the maximum tokens per decode call.
+    pub batch_size: Option<u32>,
+
+    /// Physical batch size, i.e. the maximum tokens computed at once.
+    pub ubatch_size: Option<u32>,
+
+    /// Threads for the generation, `None` for the model default
+    /// (see [`crate::ModelLoadOptions::n_threads`]).
+    pub threads: Option<u32>,
+
+    /// Threads for the prompt decoding, `None` for the same as `threads`.
+    pub batch_threads: Option<u32>,
+
+    /// The KV cache K type, `F16` by default.
+    pub type_k: Option<KvCacheType>,
+
+    /// The KV cache V type, `F16` by default.
+    /// A quantized V cache requires flash attention.
+    pub type_v: Option<KvCacheType>,
+
+    /// Whether to use flash attention, `true` by default.
+    pub flash_attn: Option<bool>,
+
+    /// RoPE scaling type, `None` for the model default.
+    /// Implied to be `Yarn` if `yarn` is set.
+    pub rope_scaling: Option<RopeScalingType>,
+
+    /// RoPE base frequency, `None` for the model default.
+    pub rope_freq_base: Option<f32>,
+
+    /// RoPE frequency scaling factor, e.g. `0.5` to double the context.
+    pub rope_freq_scale: Option<f32>,
+
+    /// YaRN parameters.
+    pub yarn: Option<Yarn>,
+
+    /// The maximum number of sequences for [`super::infer_many`], 1 by default.
+    pub max_sequences: Option<u32>,
+
+    /// Context shift policy, or `None` to fail on overflow.
+    pub context_shift: Option<ContextShift>,
+}
+
+impl From<SessionOptions> for ffi::SimularityGptSessionOptions {
+    fn from(options: SessionOptions) -> Self {
+        let mut result = unsafe { ffi::simularity_gpt_session_options_default() };
+
+        if let Some(ubatch_size) = options.ubatch_size {
+            result.n_ubatch = ubatch_size;
+        }
+
+        if let Some(threads) = options.threads {
+            result.n_threads = threads;
+        }
+
+        if let Some(batch_threads) = options.batch_threads {
+            result.n_threads_batch = batch_threads;
+        }
+
+        if let Some(type_k) = options.type_k {
+            result.type_k = type_k.into();
+        }
+
+        if let Some(type_v) = options.type_v {
+            result.type_v = type_v.into();
+        }
+
+        if let Some(flash_attn) = options.flash_attn {
+            result.flash_attn = flash_attn;
+        }
+
+        if let Some(rope_scaling) = options.rope_scaling {
+            result.rope_scaling_type = rope_scaling.into();
+        } else if options.yarn.is_some() {
+            result.rope_scaling_type = RopeScalingType::Yarn.into();
+        }
+
+        if let Some(rope_freq_base) = options.rope_freq_base {
+            result.rope_freq_base = rope_freq_base;
+        }
+
+        if let Some(rope_freq_scale) = options.rope_freq_scale {
+            result.rope_freq_scale = rope_freq_scale;
+        }
+
+        if let Some(yarn) = options.yarn {
+            if let Some(ext_factor) = yarn.ext_factor {
+                result.yarn_ext_factor = ext_factor;
+            }
+
+            if let Some(attn_factor) = yarn.attn_factor {
+                result.yarn_attn_factor = attn_factor;
+            }
+
+            if let Some(beta_fast) = yarn.beta_fast {
+                result.yarn_beta_fast = beta_fast;
+            }
+
+            if let Some(beta_slow) = yarn.beta_slow {
+                result.yarn_beta_slow = beta_slow;
+            }
+
+            if let Some(orig_ctx) = yarn.orig_ctx {
+                result.yarn_orig_ctx = orig_ctx;
+            }
+        }
+
+        if let Some(max_sequences) = options.max_sequences {
+            result.n_seq_max = max_sequences;
+        }
+
+        result
+    }
+}
+
+impl Session {
+    /// Create a new GPT session.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_id` - The model ID, after calling `model_load`.
+    /// * `options` - Context parameters, or `None` for the defaults.
+    ///   The parameters affecting the KV cache layout (the batch size,
+    ///   the cache types and flash attention) shall match the state file's.
+    /// * `initial_prompt` - Initial prompt to start the session.
+    /// * `state_file_path` - Path to the session state file to load from or save to.
+    /// * `progress_callback` - Progress callback on either session loading or decoding.
+    ///   Return `true` to continue, or `false` to cancel with [`Error::Cancelled`].
+    ///   The session is not created on error.
+    ///
+    /// The model can not be unloaded while the session exists,
+    /// see [`crate::ModelRegistry::create`].
+    ///
+    // TODO: Return rich information about the session (session_loaded, session_dump_size, context_length).
+    pub fn create(
+        model_id: &str,
+        options: Option<SessionOptions>,
+        initial_prompt: Option<&str>,
+        state_file_path: Option<&str>,
+        progress_callback: Option<impl FnMut(f32) -> bool>,
+    ) -> Result<Self, Error> {
+        let session_id = create(
+            model_id,
+            options,
+            initial_prompt,
+            state_file_path,
+            progress_callback,
+        )?;
+
+        Ok(Self::from_id(session_id, model_id))
+    }
+}
+
+/// Create a new GPT session, see [`Session::create`].
+/// The session must be destroyed with [`super::destroy`].
 ///
 /// # Returns
 /// New GPT session ID.
 ///
-// TODO: Return rich information about the session (session_loaded, session_dump_size, context_length).
 pub fn create(
     model_id: &str,
-    context_size: Option<u32>,
-    batch_size: Option<u32>,
+    options: Option<SessionOptions>,
     initial_prompt: Option<&str>,
     state_file_path: Option<&str>,
     mut progress_callback: Option<impl FnMut(f32) -> bool>,
 ) -> Result<u32, Error> {
-    let model_id = CString::new(model_id).unwrap();
-    let initial_prompt = initial_prompt.map(|p| CString::new(p).unwrap());
-    let state_file_path = state_file_path.map(|p| CString::new(p).unwrap());
+    ModelRegistry::global().create(
+        model_id,
+        options,
+        initial_prompt,
+        state_file_path,
+        progress_callback.as_mut().map(|cb| cb as ProgressCallback),
+    )
+}
+
+/// Create a new GPT session bypassing the model registry.
+pub(crate) fn create_native(
+    model_id: &str,
+    options: Option<SessionOptions>,
+    initial_prompt: Option<&str>,
+    state_file_path: Option<&str>,
+    mut progress_callback: Option<impl FnMut(f32) -> bool>,
+) -> Result<u32, Error> {
+    let options = options.unwrap_or_default();
+    let model_id_c = error::to_cstring(model_id, "model_id")?;
+    let initial_prompt = initial_prompt
+        .map(|p| error::to_cstring(p, "initial_prompt"))
+        .transpose()?;
+    let state_file_path = state_file_path
+        .map(|p| error::to_cstring(p, "state_file_path"))
+        .transpose()?;
+    let context_shift = options
+        .context_shift
+        .map(ffi::SimularityGptContextShift::from);
+    let native_options = ffi::SimularityGptSessionOptions::from(options);
 
     let user_data = if let Some(cb) = progress_callback.as_mut() {
         // See https://stackoverflow.com/a/32270215/3645337.
@@ -49,15 +301,19 @@ pub fn create(
 
     let result = unsafe {
         ffi::simularity_gpt_create(
-            model_id.as_ptr(),
-            context_size.unwrap_or(0),
-            batch_size.unwrap_or(0),
+            model_id_c.as_ptr(),
+            options.context_size.unwrap_or(0),
+            options.batch_size.unwrap_or(0),
+            &native_options,
             initial_prompt
                 .as_ref()
                 .map_or(std::ptr::null(), |p| p.as_ptr()),
             state_file_path
                 .as_ref()
                 .map_or(std::ptr::null(), |p| p.as_ptr()),
+            context_shift
+                .as_ref()
+                .map_or(std::ptr::null(), |c| c as *const _),
             if progress_callback.is_some() {
                 Some(ffi::progress_callback_wrapper)
             } else {
@@ -75,9 +331,15 @@ pub fn create(
     match result {
         -1 => Err(Error::ModelNotFound),
         -2 => Err(Error::SessionLimitReached),
-        -3 => Err(Error::ContextCreationFailed),
-        -4 => Err(Error::DecodeFailed),
+        -3 => Err(Error::ContextCreationFailed(error::last_error())),
+        -4 => Err(Error::ContextOverflow(error::last_error())),
+        -5 => Err(Error::Cancelled),
+        -6 => Err(Error::DecodeFailed(error::last_error())),
+        -7 | -8 => Err(Error::InvalidInput {
+            message: error::last_error().unwrap_or_default(),
+            source: None,
+        }),
         x if x > 0 => Ok(result as u32),
-        x => Err(Error::Unknown(x)),
+        x => Err(Error::unknown(x)),
     }
 }
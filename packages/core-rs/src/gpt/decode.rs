@@ -1,35 +1,53 @@
-use std::ffi::CString;
+use super::Session;
+use crate::{backend::ProgressCallback, error, ffi, Backend, Error, ModelRegistry};
 
-use crate::ffi;
-
-#[derive(Debug, Clone)]
-pub enum Error {
-    SessionNotFound,
-    ContextOverflow,
-    Unknown(i32),
+impl Session {
+    /// Decode the GPT session with the given prompt.
+    /// Clears the uncommitted prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The *full* prompt to decode. The function will take care of
+    ///   reusing and/or updating the KV cache. The more the prompt mismatches
+    ///   existing KV cache, the longer it takes to decode.
+    /// * `progress_callback` - Return `true` to continue,
+    ///   or `false` to cancel with [`Error::Cancelled`].
+    ///
+    /// On error, the session keeps the successfully decoded part of the prompt.
+    ///
+    /// # Returns
+    /// New context length. With a [`super::ContextShift`] policy,
+    /// see [`super::SessionInfo::evicted_tokens`] for the evicted tokens.
+    ///
+    pub fn decode(
+        &self,
+        prompt: &str,
+        mut progress_callback: Option<impl FnMut(f32) -> bool>,
+    ) -> Result<u32, Error> {
+        ModelRegistry::global().backend().decode(
+            self.id,
+            prompt,
+            progress_callback.as_mut().map(|cb| cb as ProgressCallback),
+        )
+    }
 }
 
-/// Decode the GPT session with the given prompt.
-/// Clears the uncommitted prompt.
-///
-/// # Arguments
-///
-/// * `session_id` - GPT session ID.
-/// * `prompt` - The *full* prompt to decode. The function will take care of
-///   reusing and/or updating the KV cache. The more the prompt mismatches
-///   existing KV cache, the longer it takes to decode.
-/// * `progress_callback` - Return `true` to continue,
-///   or `false` to cancel (not implemented yet).
-///
-/// # Returns
-/// New context length.
-///
+/// Decode the GPT session by its ID, see [`Session::decode`].
 pub fn decode(
+    session_id: u32,
+    prompt: &str,
+    progress_callback: Option<impl FnMut(f32) -> bool>,
+) -> Result<u32, Error> {
+    Session::borrow(session_id).decode(prompt, progress_callback)
+}
+
+/// Decode the GPT session with the native library.
+pub(crate) fn decode_native(
     session_id: u32,
     prompt: &str,
     mut progress_callback: Option<impl FnMut(f32) -> bool>,
 ) -> Result<u32, Error> {
-    let prompt = CString::new(prompt).unwrap();
+    let prompt = error::to_cstring(prompt, "prompt")?;
 
     let user_data = if let Some(cb) = progress_callback.as_mut() {
         // See https://stackoverflow.com/a/32270215/3645337.
@@ -59,8 +77,9 @@ pub fn decode(
 
     match result {
         -1 => Err(Error::SessionNotFound),
-        -2 => Err(Error::ContextOverflow),
+        -2 => Err(Error::ContextOverflow(error::last_error())),
+        -3 => Err(Error::Cancelled),
         x if x > 0 => Ok(result as u32),
-        x => Err(Error::Unknown(x)),
+        _ => Err(Error::DecodeFailed(error::last_error())),
     }
 }
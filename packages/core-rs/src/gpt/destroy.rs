@@ -1,22 +1,31 @@
-use crate::ffi;
+use std::mem::ManuallyDrop;
 
-#[derive(Debug, Clone)]
-pub enum Error {
-    SessionNotFound,
-    Unknown(i32),
+use super::Session;
+use crate::{ffi, Error, ModelRegistry};
+
+impl Session {
+    /// Destroy the session explicitly, returning an error if it fails.
+    pub fn destroy(self) -> Result<(), Error> {
+        ModelRegistry::global().destroy(self.into_id())
+    }
 }
 
-/// Destroy a GPT session.
+/// Destroy a GPT session, see [`Session::destroy`].
 ///
 /// # Arguments
 /// * `session_id` - GPT session ID.
 ///
 pub fn destroy(session_id: u32) -> Result<(), Error> {
+    ManuallyDrop::into_inner(Session::borrow(session_id)).destroy()
+}
+
+/// Destroy a GPT session with the native library.
+pub(crate) fn destroy_native(session_id: u32) -> Result<(), Error> {
     let result = unsafe { ffi::simularity_gpt_destroy(session_id) };
 
     match result {
         -1 => Err(Error::SessionNotFound),
         x if x >= 0 => Ok(()),
-        x => Err(Error::Unknown(x)),
+        x => Err(Error::unknown(x)),
     }
 }
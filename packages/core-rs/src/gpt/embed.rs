@@ -0,0 +1,155 @@
+use std::ffi::c_char;
+
+use super::Session;
+use crate::{error, ffi, Error, ModelRegistry};
+
+/// Pooling of token embeddings into a single text embedding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Pooling {
+    /// The model's default, or [`Pooling::Mean`] if it has none
+    /// (e.g. a generative model).
+    #[default]
+    Default,
+
+    /// Average of the token embeddings.
+    Mean,
+
+    /// The embedding of the first (CLS) token.
+    Cls,
+
+    /// The embedding of the last token.
+    Last,
+}
+
+impl Pooling {
+    /// `enum simularity_pooling_type`.
+    fn to_native(self) -> i32 {
+        match self {
+            Pooling::Default => -1,
+            Pooling::Mean => 1,
+            Pooling::Cls => 2,
+            Pooling::Last => 3,
+        }
+    }
+}
+
+/// Normalization of an embedding vector.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Normalization {
+    /// Keep the embedding as is.
+    None,
+
+    /// Divide by the sum of absolute values.
+    Taxicab,
+
+    /// Divide by the Euclidean norm, so that the dot product
+    /// equals the cosine similarity.
+    #[default]
+    Euclidean,
+}
+
+impl Normalization {
+    pub(crate) fn apply(self, embedding: &mut [f32]) {
+        let norm = match self {
+            Normalization::None => return,
+            Normalization::Taxicab => embedding.iter().map(|x| x.abs()).sum::<f32>(),
+            Normalization::Euclidean => embedding.iter().map(|x| x * x).sum::<f32>().sqrt(),
+        };
+
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|x| *x /= norm);
+        }
+    }
+}
+
+impl Session {
+    /// Compute the text embeddings using the session's model, see [`embed`].
+    pub fn embed(
+        &self,
+        texts: &[&str],
+        pooling: Pooling,
+        normalization: Normalization,
+    ) -> Result<Vec<Vec<f32>>, Error> {
+        embed(&self.model_id, texts, pooling, normalization)
+    }
+}
+
+/// Compute the embeddings of the texts, one per text.
+/// An embedding-mode context is created for each call.
+///
+/// # Arguments
+///
+/// * `model_id` - The model ID.
+/// * `texts` - The texts to embed, each must fit into the model's
+///   training context size.
+/// * `pooling` - How to pool the token embeddings.
+/// * `normalization` - How to normalize the embeddings.
+///
+pub fn embed(
+    model_id: &str,
+    texts: &[&str],
+    pooling: Pooling,
+    normalization: Normalization,
+) -> Result<Vec<Vec<f32>>, Error> {
+    ModelRegistry::global().embed(model_id, texts, pooling, normalization)
+}
+
+/// Compute the embeddings of the texts with the native library.
+pub(crate) fn embed_native(
+    model_id: &str,
+    texts: &[&str],
+    pooling: Pooling,
+    normalization: Normalization,
+) -> Result<Vec<Vec<f32>>, Error> {
+    let model_id = error::to_cstring(model_id, "model_id")?;
+    let texts = texts
+        .iter()
+        .map(|text| error::to_cstring(text, "texts"))
+        .collect::<Result<Vec<_>, _>>()?;
+    let text_ptrs: Vec<*const c_char> = texts.iter().map(|text| text.as_ptr()).collect();
+
+    if texts.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // The first call returns the embedding size without computing.
+    let mut embeddings = Vec::new();
+
+    loop {
+        let result = unsafe {
+            ffi::simularity_gpt_embed(
+                model_id.as_ptr(),
+                text_ptrs.as_ptr(),
+                text_ptrs.len() as u32,
+                pooling.to_native(),
+                embeddings.as_mut_ptr(),
+                embeddings.len(),
+            )
+        };
+
+        match result {
+            n if n > 0 && n as usize * texts.len() <= embeddings.len() => {
+                let n_embd = n as usize;
+                embeddings.truncate(n_embd * texts.len());
+
+                return Ok(embeddings
+                    .chunks_exact(n_embd)
+                    .map(|chunk| {
+                        let mut embedding = chunk.to_vec();
+                        normalization.apply(&mut embedding);
+                        embedding
+                    })
+                    .collect());
+            }
+            n if n > 0 => embeddings.resize(n as usize * texts.len(), 0.0),
+            -1 => return Err(Error::ModelNotFound),
+            -2 => return Err(Error::ContextOverflow(error::last_error())),
+            -3 => return Err(Error::ContextCreationFailed(error::last_error())),
+            -4 => return Err(Error::DecodeFailed(error::last_error())),
+            -5 => return Err(Error::TokenizationFailed(error::last_error())),
+            _ => return Err(Error::unknown(result)),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use super::Session;
+use crate::{error, ffi, Error, ModelRegistry};
+
+impl Session {
+    /// Fork the session, creating a new session which copies the KV cache
+    /// and the committed prompt, so that generations may diverge
+    /// from the common prefix without re-decoding it.
+    pub fn fork(&self) -> Result<Session, Error> {
+        Ok(Session {
+            id: ModelRegistry::global().fork(self.id)?,
+            model_id: self.model_id.clone(),
+        })
+    }
+}
+
+/// Fork a GPT session, see [`Session::fork`].
+/// The new session must be destroyed with [`super::destroy`].
+///
+/// # Returns
+/// New GPT session ID.
+///
+pub fn fork(session_id: u32) -> Result<u32, Error> {
+    Session::borrow(session_id).fork().map(Session::into_id)
+}
+
+/// Fork a GPT session with the native library.
+pub(crate) fn fork_native(session_id: u32) -> Result<u32, Error> {
+    let result = unsafe { ffi::simularity_gpt_fork(session_id) };
+
+    match result {
+        -1 => Err(Error::SessionNotFound),
+        -2 => Err(Error::SessionLimitReached),
+        -3 => Err(Error::ContextCreationFailed(error::last_error())),
+        -4 => Err(Error::StateError(error::last_error())),
+        x if x > 0 => Ok(x as u32),
+        x => Err(Error::unknown(x)),
+    }
+}
@@ -1,6 +1,14 @@
-use std::ffi::CString;
-
-use crate::ffi;
+use std::{
+    collections::HashMap,
+    ffi::{c_char, CString},
+    time::Duration,
+};
+
+use super::Session;
+use crate::{
+    backend::{InferenceCallback, ProgressCallback},
+    error, ffi, Error, ModelRegistry,
+};
 
 #[derive(Clone, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -34,6 +42,103 @@ pub struct Mirostat {
     pub eta: Option<f32>,
 }
 
+/// Speculative decoding with a draft model, see [`Options::speculative`].
+#[derive(Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Speculative {
+    /// A loaded model with the same vocabulary as the session's,
+    /// usually a much smaller one.
+    pub draft_model_id: String,
+
+    /// Tokens to draft per step, defaults to 5.
+    pub n_draft: Option<u32>,
+}
+
+/// A Lua library a grammar script may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LuaLibrary {
+    /// Except for `dofile`, `loadfile`, `load` and `loadstring`.
+    Base,
+
+    /// Except for `string.dump`.
+    String,
+
+    Table,
+    Math,
+
+    /// The `cjson` global.
+    Cjson,
+}
+
+impl LuaLibrary {
+    /// `enum simularity_lua_library`.
+    fn to_native(self) -> u32 {
+        match self {
+            LuaLibrary::Base => 1 << 0,
+            LuaLibrary::String => 1 << 1,
+            LuaLibrary::Table => 1 << 2,
+            LuaLibrary::Math => 1 << 3,
+            LuaLibrary::Cjson => 1 << 4,
+        }
+    }
+}
+
+/// Limits for a [`Options::lua_grammar`] script, `None` for the defaults.
+/// The instruction and time limits apply to each script call
+/// (the initial run, `start()` and every `on_eos()`).
+#[derive(Default, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LuaLimits {
+    /// Approximate VM instructions per call, `0` for unlimited.
+    /// Defaults to 10M.
+    pub max_instructions: Option<u32>,
+
+    /// Wall-clock time per call, `0` for unlimited. Defaults to 1s.
+    pub timeout_ms: Option<u32>,
+
+    /// Memory of the Lua state in bytes, `0` for unlimited. Defaults to 64 MiB.
+    pub max_memory: Option<usize>,
+
+    /// The libraries to open, defaults to `base`, `string`, `table` and `cjson`.
+    /// `debug`, `io`, `os` and `package` are never available.
+    pub libraries: Option<Vec<LuaLibrary>>,
+}
+
+/// An [`Options::logit_bias`] key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(untagged)]
+pub enum LogitBiasKey {
+    /// A token ID.
+    Token(i32),
+
+    /// A text, the bias applies to each of its tokens.
+    Text(String),
+}
+
+/// Deserialize [`Options::logit_bias`] from either a map with text keys
+/// (as JSON object keys are strings), or a list of `[key, bias]` pairs.
+fn deserialize_logit_bias<'de, D>(
+    deserializer: D,
+) -> Result<Option<HashMap<LogitBiasKey, f32>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum LogitBias {
+        Map(HashMap<LogitBiasKey, f32>),
+        Pairs(Vec<(LogitBiasKey, f32)>),
+    }
+
+    let logit_bias: Option<LogitBias> = serde::Deserialize::deserialize(deserializer)?;
+
+    Ok(logit_bias.map(|logit_bias| match logit_bias {
+        LogitBias::Map(map) => map,
+        LogitBias::Pairs(pairs) => pairs.into_iter().collect(),
+    }))
+}
+
 #[derive(Default, Clone, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Options {
@@ -51,48 +156,244 @@ pub struct Options {
     pub mirostat: Option<Mirostat>,
     pub seed: Option<u32>,
     pub grammar: Option<String>,
+
+    /// Strings to stop the inference at, excluded from the output.
+    /// Matched on the inferred text, however tokenized. Tokens which may
+    /// start a stop sequence are held back from the inference callback
+    /// until decided, so that it is never yielded (the piece of the token
+    /// it starts within is cut).
     pub stop_sequences: Option<Vec<String>>,
+
     pub lua_grammar: Option<String>,
+    pub lua_limits: Option<LuaLimits>,
+
+    /// Added to the logits of the tokens, `f32::NEG_INFINITY` bans a token.
+    #[serde(default, deserialize_with = "deserialize_logit_bias")]
+    pub logit_bias: Option<HashMap<LogitBiasKey, f32>>,
+
+    /// Strings not to infer, even if spanning several tokens. Once inferred,
+    /// the string is rolled back, and its first token is banned at that
+    /// position. Tokens which may start a banned string are held back from
+    /// the inference callback until decided. Not supported by `infer_many`.
+    pub banned_strings: Option<Vec<String>>,
+
+    /// Let a draft model propose tokens for the session model to verify
+    /// at once. The output is the same as without it, only faster if
+    /// the draft model's guesses are accepted often enough.
+    /// The session keeps the draft context for the next inferences,
+    /// so that the draft model can not be unloaded until it is destroyed.
+    /// Not supported by `infer_many`.
+    pub speculative: Option<Speculative>,
 }
 
+/// A candidate token with its log-probability.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenLogprob {
+    /// The token ID.
+    pub token: i32,
+
+    /// The token text.
+    pub piece: String,
+
+    /// Natural logarithm of the token probability.
+    pub logprob: f32,
+}
+
+/// An inferred token, passed to the inference callback.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InferenceToken {
+    /// The token ID.
+    pub token: i32,
+
+    /// The token text. May contain an incomplete UTF-8 sequence,
+    /// replaced with `U+FFFD`.
+    pub piece: String,
+
+    /// Natural logarithm of the token probability,
+    /// `f32::NEG_INFINITY` if unknown.
+    pub logprob: f32,
+
+    /// Up to [`Options::n_probs`] most probable candidates,
+    /// sorted by probability. Empty unless `n_probs` is set.
+    pub top_logprobs: Vec<TokenLogprob>,
+}
+
+/// Why an inference has stopped.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum StopReason {
+    /// `n_eval` tokens were inferred.
+    NEval,
+
+    /// An end-of-generation token was sampled.
+    Eos,
+
+    /// One of [`Options::stop_sequences`] was matched.
+    StopSequence { sequence: String },
+
+    /// The inference callback returned `false`.
+    Callback,
+
+    /// The [`Options::lua_grammar`] script's `on_eos()` returned `nil`.
+    Lua,
+}
+
+impl StopReason {
+    /// Convert the native `simularity_gpt_stop_reason`.
+    pub(super) fn from_native(
+        stop_reason: i32,
+        stop_sequence: i32,
+        options: Option<&Options>,
+    ) -> Self {
+        match stop_reason {
+            1 => StopReason::Eos,
+            2 => StopReason::StopSequence {
+                sequence: options
+                    .and_then(|o| o.stop_sequences.as_ref())
+                    .and_then(|sequences| sequences.get(stop_sequence as usize))
+                    .cloned()
+                    .unwrap_or_default(),
+            },
+            3 => StopReason::Callback,
+            4 => StopReason::Lua,
+            _ => StopReason::NEval,
+        }
+    }
+}
+
+/// The outcome of [`Session::infer`].
 #[derive(Debug, Clone)]
-pub enum Error {
-    SessionNotFound,
-    ContextOverflow,
-    SamplingError,
-    LuaError,
-    Unknown(i32),
+pub struct InferOutcome {
+    /// The inferred text, excluding a matched stop sequence.
+    pub output: String,
+
+    pub stop_reason: StopReason,
+
+    /// New context length.
+    pub context_length: u32,
+
+    /// The decoded prompt length in tokens, i.e. the context length
+    /// before the inference. May be less than the prompt's token length
+    /// if shortened by a [`super::ContextShift`] policy.
+    pub prompt_tokens: u32,
+
+    /// The number of inferred tokens, excluding a matched stop sequence.
+    pub generated_tokens: u32,
+
+    /// The number of tokens evicted by the context shift policy.
+    pub evicted_tokens: u32,
+
+    /// Time spent decoding the prompt. The reused KV cache is not decoded.
+    pub decode_duration: Duration,
+
+    /// Time spent inferring the tokens.
+    pub generation_duration: Duration,
+
+    /// The number of tokens proposed by the [`Options::speculative`]
+    /// draft model.
+    pub drafted_tokens: u32,
+
+    /// The number of drafted tokens accepted by the session model.
+    pub accepted_tokens: u32,
 }
 
-/// Infer the GPT session with the given prompt.
-/// Clears the uncommitted prompt.
-///
-/// # Arguments
-///
-/// * `session_id` - GPT session ID.
-/// * `prompt` - The *whole* prompt to inference from.
-///   The function will take care of reusing and/or updating the KV cache.
-///   The more the prompt mismatches existing KV cache,
-///   the longer it takes to decode.
-/// * `n_eval` - Number of tokens to decode.
-/// * `options` - Inference options.
-/// * `decode_progress_callback` - Decode progress callback.
-///   Return `true` to continue, or `false` to cancel (not implemented yet).
-/// * `inference_callback` - Inference callback.
-///   Return `true` to continue, or `false` to cancel.
-///
-/// # Returns
-/// New context length.
-///
+impl InferOutcome {
+    /// Inferred tokens per second.
+    pub fn tokens_per_second(&self) -> f64 {
+        let secs = self.generation_duration.as_secs_f64();
+
+        if secs > 0.0 {
+            self.generated_tokens as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// The share of the drafted tokens accepted, `None` without drafting.
+    pub fn acceptance_rate(&self) -> Option<f64> {
+        if self.drafted_tokens > 0 {
+            Some(self.accepted_tokens as f64 / self.drafted_tokens as f64)
+        } else {
+            None
+        }
+    }
+}
+
+impl Session {
+    /// Infer the GPT session with the given prompt.
+    /// Clears the uncommitted prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The *whole* prompt to inference from.
+    ///   The function will take care of reusing and/or updating the KV cache.
+    ///   The more the prompt mismatches existing KV cache,
+    ///   the longer it takes to decode.
+    /// * `n_eval` - Number of tokens to decode.
+    /// * `options` - Inference options.
+    /// * `decode_progress_callback` - Decode progress callback.
+    ///   Return `true` to continue, or `false` to cancel with [`Error::Cancelled`].
+    /// * `inference_callback` - Called with each inferred token.
+    ///   Return `true` to continue, or `false` to cancel.
+    ///
+    /// # Returns
+    /// The output, why the inference has stopped, and the timings.
+    ///
+    pub fn infer(
+        &self,
+        prompt: Option<&str>,
+        n_eval: u32,
+        options: Option<Options>,
+        mut decode_progress_callback: Option<impl FnMut(f32) -> bool>,
+        mut inference_callback: Option<impl FnMut(&InferenceToken) -> bool>,
+    ) -> Result<InferOutcome, Error> {
+        ModelRegistry::global().infer(
+            self.id,
+            prompt,
+            n_eval,
+            options,
+            decode_progress_callback
+                .as_mut()
+                .map(|cb| cb as ProgressCallback),
+            inference_callback
+                .as_mut()
+                .map(|cb| cb as InferenceCallback),
+        )
+    }
+}
+
+/// Infer the GPT session by its ID, see [`Session::infer`].
 pub fn infer(
+    session_id: u32,
+    prompt: Option<&str>,
+    n_eval: u32,
+    options: Option<Options>,
+    decode_progress_callback: Option<impl FnMut(f32) -> bool>,
+    inference_callback: Option<impl FnMut(&InferenceToken) -> bool>,
+) -> Result<InferOutcome, Error> {
+    Session::borrow(session_id).infer(
+        prompt,
+        n_eval,
+        options,
+        decode_progress_callback,
+        inference_callback,
+    )
+}
+
+/// Infer the GPT session with the native library.
+pub(crate) fn infer_native(
     session_id: u32,
     prompt: Option<&str>,
     n_eval: u32,
     options: Option<Options>,
     mut decode_progress_callback: Option<impl FnMut(f32) -> bool>,
-    mut inference_callback: Option<impl FnMut(&str) -> bool>,
-) -> Result<u32, Error> {
-    let prompt = prompt.map(|p| CString::new(p).unwrap());
+    mut inference_callback: Option<impl FnMut(&InferenceToken) -> bool>,
+) -> Result<InferOutcome, Error> {
+    let prompt = prompt.map(|p| error::to_cstring(p, "prompt")).transpose()?;
+
+    let native_options = NativeOptions::new(options.clone())?;
 
     let decode_user_data = if let Some(cb) = decode_progress_callback.as_mut() {
         // See https://stackoverflow.com/a/32270215/3645337.
@@ -102,107 +403,207 @@ pub fn infer(
         std::ptr::null_mut()
     };
 
-    let inference_user_data = if let Some(cb) = inference_callback.as_mut() {
-        // Ditto.
-        #[allow(clippy::type_complexity)]
-        let user_data: Box<Box<dyn FnMut(&str) -> bool>> = Box::new(Box::new(cb));
-        Box::into_raw(user_data) as *mut _
-    } else {
-        std::ptr::null_mut()
-    };
-
-    let mut converted_options = convert_options(options.clone());
-    let mut grammar_ptr: Option<*mut i8> = None;
-    let mut sequence_ptrs: Option<Vec<*mut i8>> = None;
-    let mut lua_grammar_ptr: Option<*mut i8> = None;
+    // Collect the raw pieces, as a token may be an incomplete UTF-8 sequence.
+    let mut pieces: Vec<Vec<u8>> = Vec::new();
+    let mut collect = |token: &InferenceToken, piece: &[u8]| -> bool {
+        pieces.push(piece.to_vec());
 
-    if let Some(options) = options.clone() {
-        if let Some(grammar) = options.grammar {
-            let ptr = CString::new(grammar).unwrap().into_raw();
-            grammar_ptr = Some(ptr);
-            converted_options.grammar = ptr;
+        match inference_callback.as_mut() {
+            Some(cb) => cb(token),
+            None => true,
         }
+    };
 
-        if let Some(stop_sequences) = options.stop_sequences {
-            let ptrs = stop_sequences
-                .iter()
-                .map(|s| CString::new(s.as_str()).unwrap().into_raw())
-                .collect::<Vec<_>>();
-
-            // From Vec<*mut i8> to *const *const i8.
-            converted_options.stop_sequences = ptrs.as_ptr() as *const *const i8;
-            converted_options.stop_sequences_len = ptrs.len() as u32;
+    // Ditto.
+    let inference_user_data: Box<Box<ffi::InferCallback>> = Box::new(Box::new(&mut collect));
+    let inference_user_data = Box::into_raw(inference_user_data) as *mut _;
 
-            sequence_ptrs = Some(ptrs);
-        }
+    log::debug!("Native inference options: {:?}", native_options.raw);
 
-        if let Some(lua_grammar) = options.lua_grammar {
-            let ptr = CString::new(lua_grammar).unwrap().into_raw();
-            lua_grammar_ptr = Some(ptr);
-            converted_options.lua_grammar = ptr;
-        }
-    }
-
-    println!("converted_options: {:?}", converted_options);
+    let mut outcome = ffi::SimularityGptInferResult::default();
 
     let result = unsafe {
         ffi::simularity_gpt_infer(
             session_id,
             prompt.as_ref().map_or(std::ptr::null(), |p| p.as_ptr()),
             n_eval,
-            converted_options,
+            native_options.raw,
             if decode_progress_callback.is_some() {
                 Some(ffi::progress_callback_wrapper)
             } else {
                 None
             },
             decode_user_data,
-            if inference_callback.is_some() {
-                Some(ffi::inference_callback_wrapper)
-            } else {
-                None
-            },
+            Some(ffi::inference_callback_wrapper),
             inference_user_data,
+            &mut outcome,
         )
     };
 
-    if let Some(grammar_ptr) = grammar_ptr {
-        // Drop the CString.
-        let _ = unsafe { CString::from_raw(grammar_ptr) };
-    }
-
-    if let Some(sequence_ptrs) = sequence_ptrs {
-        for ptr in sequence_ptrs {
-            // Drop the CString.
-            let _ = unsafe { CString::from_raw(ptr) };
-        }
-    }
-
-    if let Some(lua_grammar_ptr) = lua_grammar_ptr {
-        // Drop the CString.
-        let _ = unsafe { CString::from_raw(lua_grammar_ptr) };
-    }
-
     if (decode_user_data as usize) != 0 {
         // Drop the box.
         let _: Box<Box<dyn FnMut(f32) -> bool>> =
             unsafe { Box::from_raw(decode_user_data as *mut _) };
     }
 
-    if (inference_user_data as usize) != 0 {
-        // Drop the box.
-        #[allow(clippy::type_complexity)]
-        let _: Box<Box<dyn FnMut(&str) -> bool>> =
-            unsafe { Box::from_raw(inference_user_data as *mut _) };
-    }
+    // Ditto.
+    let _: Box<Box<ffi::InferCallback>> = unsafe { Box::from_raw(inference_user_data as *mut _) };
 
     match result {
-        -1 => Err(Error::SessionNotFound),
-        -2 => Err(Error::ContextOverflow),
-        -3 => Err(Error::SamplingError),
-        -8 => Err(Error::LuaError),
-        x if x > 0 => Ok(result as u32),
-        x => Err(Error::Unknown(x)),
+        -1 => return Err(Error::SessionNotFound),
+        -2 => return Err(Error::ContextOverflow(error::last_error())),
+        -3 => return Err(Error::SamplingError(error::last_error())),
+        -4 | -6 => return Err(Error::DecodeFailed(error::last_error())),
+        -5 => return Err(Error::Cancelled),
+        -8 => return Err(Error::lua(error::last_error())),
+        -9 | -11 => {
+            return Err(Error::InvalidInput {
+                message: error::last_error().unwrap_or_default(),
+                source: None,
+            })
+        }
+        -10 => return Err(Error::ModelNotFound),
+        -12 => return Err(Error::ContextCreationFailed(error::last_error())),
+        x if x > 0 => {}
+        x => return Err(Error::unknown(x)),
+    }
+
+    let n_tokens = (outcome.n_tokens as usize).min(pieces.len());
+
+    Ok(InferOutcome {
+        output: String::from_utf8_lossy(&pieces[..n_tokens].concat()).into_owned(),
+        stop_reason: StopReason::from_native(
+            outcome.stop_reason,
+            outcome.stop_sequence,
+            options.as_ref(),
+        ),
+        context_length: result as u32,
+        prompt_tokens: outcome.n_prompt,
+        generated_tokens: outcome.n_tokens,
+        evicted_tokens: outcome.n_evicted,
+        decode_duration: Duration::from_micros(outcome.decode_us.max(0) as u64),
+        generation_duration: Duration::from_micros(outcome.infer_us.max(0) as u64),
+        drafted_tokens: outcome.n_drafted,
+        accepted_tokens: outcome.n_accepted,
+    })
+}
+
+/// Inference options converted for the native library,
+/// owning the C strings the raw options point to.
+pub(super) struct NativeOptions {
+    pub raw: ffi::SimularityGptInferenceOptions,
+    _grammar: Option<CString>,
+    _stop_sequences: Option<(Vec<CString>, Vec<*const c_char>)>,
+    _lua_grammar: Option<CString>,
+    _logit_bias: (Vec<CString>, Vec<ffi::SimularityGptLogitBias>),
+    _banned_strings: Option<(Vec<CString>, Vec<*const c_char>)>,
+    _draft_model_id: Option<CString>,
+}
+
+impl NativeOptions {
+    pub fn new(options: Option<Options>) -> Result<Self, Error> {
+        let mut raw = convert_options(options.clone());
+
+        let grammar = options
+            .as_ref()
+            .and_then(|o| o.grammar.as_deref())
+            .map(|g| error::to_cstring(g, "grammar"))
+            .transpose()?;
+        let stop_sequences = options
+            .as_ref()
+            .and_then(|o| o.stop_sequences.as_ref())
+            .map(|sequences| {
+                sequences
+                    .iter()
+                    .map(|s| error::to_cstring(s, "stop_sequences"))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?
+            .map(|sequences| {
+                let ptrs = sequences.iter().map(|s| s.as_ptr()).collect::<Vec<_>>();
+                (sequences, ptrs)
+            });
+        let lua_grammar = options
+            .as_ref()
+            .and_then(|o| o.lua_grammar.as_deref())
+            .map(|g| error::to_cstring(g, "lua_grammar"))
+            .transpose()?;
+
+        let draft_model_id = options
+            .as_ref()
+            .and_then(|o| o.speculative.as_ref())
+            .map(|s| error::to_cstring(&s.draft_model_id, "draft_model_id"))
+            .transpose()?;
+
+        let mut logit_bias = (Vec::new(), Vec::new());
+        for (key, &bias) in options.iter().flat_map(|o| o.logit_bias.iter().flatten()) {
+            let (token, text) = match key {
+                LogitBiasKey::Token(token) => (*token, std::ptr::null()),
+                LogitBiasKey::Text(text) => {
+                    let text = error::to_cstring(text, "logit_bias")?;
+                    let ptr = text.as_ptr();
+                    logit_bias.0.push(text);
+                    (0, ptr)
+                }
+            };
+
+            logit_bias
+                .1
+                .push(ffi::SimularityGptLogitBias { token, text, bias });
+        }
+
+        let banned_strings = options
+            .as_ref()
+            .and_then(|o| o.banned_strings.as_ref())
+            .map(|strings| {
+                strings
+                    .iter()
+                    .map(|s| error::to_cstring(s, "banned_strings"))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?
+            .map(|strings| {
+                let ptrs = strings.iter().map(|s| s.as_ptr()).collect::<Vec<_>>();
+                (strings, ptrs)
+            });
+
+        // NOTE: The pointers are stable, as the strings are heap-allocated.
+        if let Some(grammar) = &grammar {
+            raw.grammar = grammar.as_ptr();
+        }
+
+        if let Some((_, ptrs)) = &stop_sequences {
+            raw.stop_sequences = ptrs.as_ptr();
+            raw.stop_sequences_len = ptrs.len() as u32;
+        }
+
+        if let Some(lua_grammar) = &lua_grammar {
+            raw.lua_grammar = lua_grammar.as_ptr();
+        }
+
+        if !logit_bias.1.is_empty() {
+            raw.logit_bias = logit_bias.1.as_ptr();
+            raw.logit_bias_len = logit_bias.1.len() as u32;
+        }
+
+        if let Some((_, ptrs)) = &banned_strings {
+            raw.banned_strings = ptrs.as_ptr();
+            raw.banned_strings_len = ptrs.len() as u32;
+        }
+
+        if let Some(draft_model_id) = &draft_model_id {
+            raw.draft_model_id = draft_model_id.as_ptr();
+        }
+
+        Ok(Self {
+            raw,
+            _grammar: grammar,
+            _stop_sequences: stop_sequences,
+            _lua_grammar: lua_grammar,
+            _logit_bias: logit_bias,
+            _banned_strings: banned_strings,
+            _draft_model_id: draft_model_id,
+        })
     }
 }
 
@@ -282,6 +683,30 @@ fn convert_options(options: Option<Options>) -> ffi::SimularityGptInferenceOptio
             }
         }
 
+        if let Some(lua_limits) = options.lua_limits {
+            if let Some(max_instructions) = lua_limits.max_instructions {
+                result.lua_limits.max_instructions = max_instructions;
+            }
+
+            if let Some(timeout_ms) = lua_limits.timeout_ms {
+                result.lua_limits.timeout_ms = timeout_ms;
+            }
+
+            if let Some(max_memory) = lua_limits.max_memory {
+                result.lua_limits.max_memory = max_memory;
+            }
+
+            if let Some(libraries) = lua_limits.libraries {
+                result.lua_limits.libraries = libraries
+                    .iter()
+                    .fold(0, |flags, library| flags | library.to_native());
+            }
+        }
+
+        if let Some(n_draft) = options.speculative.and_then(|s| s.n_draft) {
+            result.n_draft = n_draft;
+        }
+
         if let Some(mirostat) = options.mirostat {
             result.mirostat = match mirostat.version {
                 MirostatVersion::V1 => 1,
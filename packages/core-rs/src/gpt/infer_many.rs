@@ -0,0 +1,166 @@
+use super::{
+    infer::{InferenceToken, NativeOptions, Options, StopReason},
+    Session,
+};
+use crate::{error, ffi, Error};
+
+/// A sequence inferred by [`Session::infer_many`].
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InferredSequence {
+    /// The inferred text, excluding a matched stop sequence.
+    pub text: String,
+
+    /// The inferred tokens, excluding a matched stop sequence.
+    pub tokens: Vec<i32>,
+
+    pub stop_reason: StopReason,
+}
+
+impl Session {
+    /// Infer `n` independent sequences from the same prompt at once.
+    /// The prompt is decoded once, and then all the sequences are sampled
+    /// in a single batch per step, each with its own sampling state.
+    /// Unless random, the seed is incremented per sequence.
+    ///
+    /// The inferred tokens are not committed to the session,
+    /// which keeps the decoded prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The *whole* prompt to inference from, see [`Session::infer`],
+    ///   or `None` to continue from the already decoded prompt.
+    /// * `n_eval` - Maximum number of tokens to infer per sequence.
+    /// * `n` - Number of sequences to infer, up to the session's
+    ///   [`super::SessionOptions::max_sequences`].
+    /// * `options` - Inference options. [`Options::lua_grammar`] is not supported.
+    /// * `decode_progress_callback` - Decode progress callback.
+    ///   Return `true` to continue, or `false` to cancel with [`Error::Cancelled`].
+    /// * `inference_callback` - Called with the sequence index and each of its
+    ///   inferred tokens. Return `true` to continue, or `false` to stop the sequence.
+    ///
+    pub fn infer_many(
+        &self,
+        prompt: Option<&str>,
+        n_eval: u32,
+        n: u32,
+        options: Option<Options>,
+        mut decode_progress_callback: Option<impl FnMut(f32) -> bool>,
+        mut inference_callback: Option<impl FnMut(u32, &InferenceToken) -> bool>,
+    ) -> Result<Vec<InferredSequence>, Error> {
+        let prompt = prompt.map(|p| error::to_cstring(p, "prompt")).transpose()?;
+        let native_options = NativeOptions::new(options.clone())?;
+
+        let decode_user_data = if let Some(cb) = decode_progress_callback.as_mut() {
+            // See https://stackoverflow.com/a/32270215/3645337.
+            let user_data: Box<Box<dyn FnMut(f32) -> bool>> = Box::new(Box::new(cb));
+            Box::into_raw(user_data) as *mut _
+        } else {
+            std::ptr::null_mut()
+        };
+
+        // Collect the raw pieces, as a token may be an incomplete UTF-8 sequence.
+        let mut tokens: Vec<Vec<i32>> = vec![Vec::new(); n as usize];
+        let mut pieces: Vec<Vec<Vec<u8>>> = vec![Vec::new(); n as usize];
+        let mut collect = |seq: u32, token: &InferenceToken, piece: &[u8]| -> bool {
+            tokens[seq as usize].push(token.token);
+            pieces[seq as usize].push(piece.to_vec());
+
+            match inference_callback.as_mut() {
+                Some(cb) => cb(seq, token),
+                None => true,
+            }
+        };
+
+        // Ditto.
+        let inference_user_data: Box<Box<ffi::InferManyCallback>> =
+            Box::new(Box::new(&mut collect));
+        let inference_user_data = Box::into_raw(inference_user_data) as *mut _;
+
+        let mut results = vec![ffi::SimularityGptSequenceResult::default(); n as usize];
+
+        let result = unsafe {
+            ffi::simularity_gpt_infer_many(
+                self.id,
+                prompt.as_ref().map_or(std::ptr::null(), |p| p.as_ptr()),
+                n_eval,
+                n,
+                native_options.raw,
+                if decode_progress_callback.is_some() {
+                    Some(ffi::progress_callback_wrapper)
+                } else {
+                    None
+                },
+                decode_user_data,
+                Some(ffi::inference_many_callback_wrapper),
+                inference_user_data,
+                results.as_mut_ptr(),
+            )
+        };
+
+        if (decode_user_data as usize) != 0 {
+            // Drop the box.
+            let _: Box<Box<dyn FnMut(f32) -> bool>> =
+                unsafe { Box::from_raw(decode_user_data as *mut _) };
+        }
+
+        // Ditto.
+        let _: Box<Box<ffi::InferManyCallback>> =
+            unsafe { Box::from_raw(inference_user_data as *mut _) };
+
+        match result {
+            -1 => return Err(Error::SessionNotFound),
+            -2 => return Err(Error::ContextOverflow(error::last_error())),
+            -3 => return Err(Error::SamplingError(error::last_error())),
+            -4 | -6 => return Err(Error::DecodeFailed(error::last_error())),
+            -5 => return Err(Error::Cancelled),
+            -9 => {
+                return Err(Error::InvalidInput {
+                    message: error::last_error().unwrap_or_default(),
+                    source: None,
+                })
+            }
+            x if x > 0 => {}
+            x => return Err(Error::unknown(x)),
+        }
+
+        Ok(results
+            .into_iter()
+            .zip(tokens.into_iter().zip(pieces))
+            .map(|(result, (mut tokens, pieces))| {
+                let n_tokens = (result.n_tokens as usize).min(pieces.len());
+                tokens.truncate(n_tokens);
+
+                InferredSequence {
+                    text: String::from_utf8_lossy(&pieces[..n_tokens].concat()).into_owned(),
+                    tokens,
+                    stop_reason: StopReason::from_native(
+                        result.stop_reason,
+                        result.stop_sequence,
+                        options.as_ref(),
+                    ),
+                }
+            })
+            .collect())
+    }
+}
+
+/// Infer multiple sequences by the session ID, see [`Session::infer_many`].
+pub fn infer_many(
+    session_id: u32,
+    prompt: Option<&str>,
+    n_eval: u32,
+    n: u32,
+    options: Option<Options>,
+    decode_progress_callback: Option<impl FnMut(f32) -> bool>,
+    inference_callback: Option<impl FnMut(u32, &InferenceToken) -> bool>,
+) -> Result<Vec<InferredSequence>, Error> {
+    Session::borrow(session_id).infer_many(
+        prompt,
+        n_eval,
+        n,
+        options,
+        decode_progress_callback,
+        inference_callback,
+    )
+}
@@ -0,0 +1,124 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use tokio::sync::mpsc;
+
+use super::{
+    infer::{InferOutcome, InferenceToken, Options},
+    Session,
+};
+use crate::Error;
+
+/// An event yielded by [`InferStream`].
+#[derive(Debug)]
+pub enum InferenceEvent {
+    /// Prompt decoding progress, from 0 to 1.
+    DecodeProgress(f32),
+
+    /// A newly inferred token.
+    Token(InferenceToken),
+
+    /// Inference has finished. Always the last event.
+    Done(Result<InferOutcome, Error>),
+}
+
+/// A stream of inference events, see [`Session::infer_stream`].
+/// Dropping the stream cancels the inference.
+pub struct InferStream {
+    receiver: mpsc::UnboundedReceiver<InferenceEvent>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl InferStream {
+    /// Receive the next event, or `None` when the stream is exhausted.
+    pub async fn recv(&mut self) -> Option<InferenceEvent> {
+        self.receiver.recv().await
+    }
+
+    /// Stop the prompt decoding or the inference as soon as possible.
+    /// The stream would still yield the final [`InferenceEvent::Done`],
+    /// with [`Error::Cancelled`] if cancelled during decoding.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+impl futures_core::Stream for InferStream {
+    type Item = InferenceEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for InferStream {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+impl Session {
+    /// Infer the session on a dedicated worker thread,
+    /// streaming the inferred tokens. See [`Session::infer`] for the arguments.
+    ///
+    /// NOTE: The session must outlive the stream, otherwise
+    /// it is destroyed upon the inference completion.
+    pub fn infer_stream(
+        &self,
+        prompt: Option<String>,
+        n_eval: u32,
+        options: Option<Options>,
+    ) -> InferStream {
+        infer_stream(self.id, prompt, n_eval, options)
+    }
+}
+
+/// Infer the GPT session by its ID, see [`Session::infer_stream`].
+pub fn infer_stream(
+    session_id: u32,
+    prompt: Option<String>,
+    n_eval: u32,
+    options: Option<Options>,
+) -> InferStream {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let cancelled_clone = cancelled.clone();
+
+    std::thread::spawn(move || {
+        let decode_sender = sender.clone();
+        let decode_cancelled = cancelled_clone.clone();
+        let decode_progress_callback = move |progress: f32| -> bool {
+            decode_sender
+                .send(InferenceEvent::DecodeProgress(progress))
+                .is_ok()
+                && !decode_cancelled.load(Ordering::Relaxed)
+        };
+
+        let inference_callback = |token: &InferenceToken| -> bool {
+            // A send error means that the stream has been dropped.
+            sender.send(InferenceEvent::Token(token.clone())).is_ok()
+                && !cancelled_clone.load(Ordering::Relaxed)
+        };
+
+        let result = Session::borrow(session_id).infer(
+            prompt.as_deref(),
+            n_eval,
+            options,
+            Some(decode_progress_callback),
+            Some(inference_callback),
+        );
+
+        let _ = sender.send(InferenceEvent::Done(result));
+    });
+
+    InferStream {
+        receiver,
+        cancelled,
+    }
+}
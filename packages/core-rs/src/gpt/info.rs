@@ -0,0 +1,126 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::Session;
+use crate::{ffi, Error};
+
+/// Information about a GPT session.
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    /// The session ID.
+    pub id: u32,
+
+    /// The ID of the model the session was created with.
+    pub model_id: String,
+
+    /// The context size in tokens.
+    pub context_size: u32,
+
+    /// The batch size in tokens.
+    pub batch_size: u32,
+
+    /// The number of committed (i.e. KV-cached) tokens.
+    pub context_length: u32,
+
+    /// When the session expires, `None` if sessions have no TTL.
+    pub expired_at: Option<SystemTime>,
+
+    /// When the session was last used.
+    pub last_used_at: SystemTime,
+
+    /// The number of tokens evicted by the context shift policy
+    /// during the last decode or inference call.
+    pub evicted_tokens: u32,
+}
+
+impl SessionInfo {
+    /// Whether the session has expired, yet not removed.
+    pub fn is_expired(&self) -> bool {
+        self.expired_at
+            .is_some_and(|expired_at| expired_at < SystemTime::now())
+    }
+}
+
+fn from_unix_ms(ms: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms.max(0) as u64)
+}
+
+impl Session {
+    /// Get information about the session.
+    /// Blocks while the session is in use, e.g. by an inference.
+    /// Does not prolong the session, unlike [`Session::touch`].
+    pub fn info(&self) -> Result<SessionInfo, Error> {
+        let mut info = ffi::SimularityGptSessionInfo::default();
+        let mut model_id = Vec::new();
+
+        loop {
+            let result = unsafe {
+                ffi::simularity_gpt_info(
+                    self.id,
+                    &mut info,
+                    model_id.as_mut_ptr() as *mut _,
+                    model_id.len(),
+                )
+            };
+
+            match result {
+                n if n > 0 && n as usize <= model_id.len() => {
+                    // Exclude the NUL terminator.
+                    model_id.truncate(n as usize - 1);
+                    break;
+                }
+                n if n > 0 => model_id.resize(n as usize, 0),
+                -1 => return Err(Error::SessionNotFound),
+                _ => return Err(Error::unknown(result)),
+            }
+        }
+
+        Ok(SessionInfo {
+            id: self.id,
+            model_id: String::from_utf8_lossy(&model_id).into_owned(),
+            context_size: info.n_ctx,
+            batch_size: info.n_batch,
+            context_length: info.n_prompt,
+            expired_at: (info.expired_at > 0).then(|| from_unix_ms(info.expired_at)),
+            last_used_at: from_unix_ms(info.last_used_at),
+            evicted_tokens: info.n_evicted,
+        })
+    }
+}
+
+/// Get information about a GPT session, see [`Session::info`].
+pub fn info(session_id: u32) -> Result<SessionInfo, Error> {
+    Session::borrow(session_id).info()
+}
+
+/// List the live GPT sessions, including the expired ones
+/// which have not been removed yet (see [`SessionInfo::is_expired`]).
+pub fn list() -> Result<Vec<SessionInfo>, Error> {
+    let mut ids = Vec::new();
+
+    loop {
+        let result = unsafe { ffi::simularity_gpt_list(ids.as_mut_ptr(), ids.len()) };
+
+        match result {
+            n if n >= 0 && n as usize <= ids.len() => {
+                ids.truncate(n as usize);
+                break;
+            }
+            // Sessions may be created in between the calls, hence the loop.
+            n if n >= 0 => ids.resize(n as usize, 0),
+            _ => return Err(Error::unknown(result)),
+        }
+    }
+
+    let mut sessions = Vec::with_capacity(ids.len());
+    for id in ids {
+        match info(id) {
+            Ok(info) => sessions.push(info),
+            // The session may be destroyed in between the calls.
+            Err(Error::SessionNotFound) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    sessions.sort_by_key(|session| session.id);
+    Ok(sessions)
+}
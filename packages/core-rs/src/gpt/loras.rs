@@ -0,0 +1,54 @@
+use std::ffi::c_char;
+
+use super::Session;
+use crate::{error, ffi, Error};
+
+impl Session {
+    /// Set the LoRA adapters applied to the session, replacing the current
+    /// ones without reloading the base model. Changing the adapters clears
+    /// the session KV cache, so that the next prompt is decoded anew.
+    ///
+    /// # Arguments
+    ///
+    /// * `loras` - The adapter IDs (see [`crate::lora_load`]) with their
+    ///   scales, `1.0` for the full effect. Empty to remove all.
+    ///
+    /// Returns the number of tokens cleared from the KV cache,
+    /// zero if the adapters are the same.
+    pub fn set_loras(&self, loras: &[(&str, f32)]) -> Result<u32, Error> {
+        let lora_ids = loras
+            .iter()
+            .map(|(id, _)| error::to_cstring(id, "lora_id"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let lora_id_ptrs: Vec<*const c_char> = lora_ids.iter().map(|id| id.as_ptr()).collect();
+        let scales: Vec<f32> = loras.iter().map(|(_, scale)| *scale).collect();
+
+        let result = unsafe {
+            ffi::simularity_gpt_set_loras(
+                self.id,
+                lora_id_ptrs.len() as u32,
+                lora_id_ptrs.as_ptr(),
+                scales.as_ptr(),
+            )
+        };
+
+        match result {
+            n if n >= 0 => Ok(n as u32),
+            -1 => Err(Error::SessionNotFound),
+            -2 => Err(Error::LoraNotFound),
+            -3 => Err(Error::LoraLoadFailed(error::last_error())),
+            -4 => Err(Error::ModelNotFound),
+            _ => Err(Error::unknown(result)),
+        }
+    }
+}
+
+/// Set the LoRA adapters of a GPT session, see [`Session::set_loras`].
+///
+/// # Arguments
+/// * `session_id` - GPT session ID.
+/// * `loras` - The adapter IDs with their scales.
+///
+pub fn set_loras(session_id: u32, loras: &[(&str, f32)]) -> Result<u32, Error> {
+    Session::borrow(session_id).set_loras(loras)
+}
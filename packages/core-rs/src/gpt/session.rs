@@ -0,0 +1,64 @@
+use std::mem::ManuallyDrop;
+
+use crate::{Backend, ModelRegistry};
+
+/// A GPT session. The session is destroyed when dropped.
+///
+/// Operations are implemented in the corresponding modules,
+/// e.g. [`Session::decode`] in [`super::decode`].
+#[derive(Debug)]
+pub struct Session {
+    pub(super) id: u32,
+    pub(super) model_id: String,
+}
+
+impl Session {
+    /// Take ownership of an existing session, e.g. one created with [`super::create`].
+    /// The session would be destroyed when the returned value is dropped.
+    pub fn from_id(id: u32, model_id: &str) -> Self {
+        Self {
+            id,
+            model_id: model_id.to_string(),
+        }
+    }
+
+    /// Wrap a session by its ID without taking the ownership,
+    /// used by the free functions. The model ID is unknown.
+    pub(super) fn borrow(id: u32) -> ManuallyDrop<Self> {
+        ManuallyDrop::new(Self {
+            id,
+            model_id: String::new(),
+        })
+    }
+
+    /// The session ID.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The ID of the model the session was created with.
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    /// Check if the session exists and is not expired.
+    /// If the session exists, prolong its expiration time.
+    pub fn touch(&self) -> bool {
+        ModelRegistry::global().backend().touch(self.id)
+    }
+
+    /// Release the ownership of the session without destroying it,
+    /// returning its ID. The session must then be destroyed
+    /// with [`super::destroy`].
+    pub fn into_id(self) -> u32 {
+        let mut this = ManuallyDrop::new(self);
+        drop(std::mem::take(&mut this.model_id));
+        this.id
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = ModelRegistry::global().destroy(self.id);
+    }
+}
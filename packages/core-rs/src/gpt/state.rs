@@ -0,0 +1,95 @@
+use super::Session;
+use crate::{error, ffi, Error};
+
+impl Session {
+    /// Save the session state (KV cache and the committed prompt tokens)
+    /// to a file, so that it may be restored later with [`Session::load_state`].
+    pub fn save_state(&self, path: &str) -> Result<(), Error> {
+        let path = error::to_cstring(path, "path")?;
+        let result = unsafe { ffi::simularity_gpt_state_save_file(self.id, path.as_ptr()) };
+
+        match result {
+            0 => Ok(()),
+            -1 => Err(Error::SessionNotFound),
+            -2 => Err(Error::StateError(error::last_error())),
+            _ => Err(Error::unknown(result)),
+        }
+    }
+
+    /// Load the session state from a file saved with [`Session::save_state`].
+    /// The state must have been saved with the same model
+    /// and the same session parameters (e.g. context size).
+    ///
+    /// On error, the session may be cleared.
+    ///
+    /// # Returns
+    /// New context length.
+    ///
+    pub fn load_state(&self, path: &str) -> Result<u32, Error> {
+        let path = error::to_existing_path(path, "path")?;
+        let result = unsafe { ffi::simularity_gpt_state_load_file(self.id, path.as_ptr()) };
+
+        match result {
+            x if x >= 0 => Ok(x as u32),
+            -1 => Err(Error::SessionNotFound),
+            -2 => Err(Error::StateError(error::last_error())),
+            _ => Err(Error::unknown(result)),
+        }
+    }
+
+    /// Save the session state into memory, see [`Session::save_state`].
+    pub fn save_state_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut state = Vec::new();
+
+        loop {
+            let result =
+                unsafe { ffi::simularity_gpt_state_save(self.id, state.as_mut_ptr(), state.len()) };
+
+            match result {
+                n if n >= 0 && n as usize <= state.len() => {
+                    state.truncate(n as usize);
+                    return Ok(state);
+                }
+                // The state may grow in between the calls, hence the loop.
+                n if n >= 0 => state.resize(n as usize, 0),
+                -1 => return Err(Error::SessionNotFound),
+                -2 => return Err(Error::StateError(error::last_error())),
+                _ => return Err(Error::unknown(result as i32)),
+            }
+        }
+    }
+
+    /// Load the session state saved with [`Session::save_state_bytes`],
+    /// see [`Session::load_state`].
+    pub fn load_state_bytes(&self, state: &[u8]) -> Result<u32, Error> {
+        let result =
+            unsafe { ffi::simularity_gpt_state_load(self.id, state.as_ptr(), state.len()) };
+
+        match result {
+            x if x >= 0 => Ok(x as u32),
+            -1 => Err(Error::SessionNotFound),
+            -2 => Err(Error::StateError(error::last_error())),
+            _ => Err(Error::unknown(result)),
+        }
+    }
+}
+
+/// Save a session state to a file, see [`Session::save_state`].
+pub fn save_state(session_id: u32, path: &str) -> Result<(), Error> {
+    Session::borrow(session_id).save_state(path)
+}
+
+/// Load a session state from a file, see [`Session::load_state`].
+pub fn load_state(session_id: u32, path: &str) -> Result<u32, Error> {
+    Session::borrow(session_id).load_state(path)
+}
+
+/// Save a session state into memory, see [`Session::save_state_bytes`].
+pub fn save_state_bytes(session_id: u32) -> Result<Vec<u8>, Error> {
+    Session::borrow(session_id).save_state_bytes()
+}
+
+/// Load a session state from memory, see [`Session::load_state_bytes`].
+pub fn load_state_bytes(session_id: u32, state: &[u8]) -> Result<u32, Error> {
+    Session::borrow(session_id).load_state_bytes(state)
+}
@@ -1,26 +1,31 @@
-use std::ffi::CString;
+use super::Session;
+use crate::{error, ffi, Error, ModelRegistry};
 
-use crate::ffi;
-
-#[derive(Debug, Clone)]
-pub enum Error {
-    ModelNotFound,
-    Unknown(i32),
+impl Session {
+    /// Get the length of the prompt in tokens, using the session's model.
+    pub fn token_length(&self, prompt: &str) -> Result<u32, Error> {
+        token_length(&self.model_id, prompt)
+    }
 }
 
 /**
  * Get the length of the prompt in tokens.
  */
 pub fn token_length(model_id: &str, prompt: &str) -> Result<u32, Error> {
-    let model_id = CString::new(model_id).unwrap();
-    let prompt = CString::new(prompt).unwrap();
+    ModelRegistry::global().token_length(model_id, prompt)
+}
+
+/// Get the length of the prompt in tokens with the native library.
+pub(crate) fn token_length_native(model_id: &str, prompt: &str) -> Result<u32, Error> {
+    let model_id = error::to_cstring(model_id, "model_id")?;
+    let prompt = error::to_cstring(prompt, "prompt")?;
 
     let token_length =
         unsafe { ffi::simularity_gpt_token_length(model_id.as_ptr(), prompt.as_ptr()) };
     if token_length < 0 {
         match token_length {
             -1 => Err(Error::ModelNotFound),
-            _ => Err(Error::Unknown(token_length)),
+            _ => Err(Error::unknown(token_length)),
         }
     } else {
         Ok(token_length as u32)
@@ -0,0 +1,167 @@
+use std::ffi::c_char;
+
+use super::Session;
+use crate::{error, ffi, Error, ModelRegistry};
+
+impl Session {
+    /// Tokenize the text using the session's model, see [`tokenize`].
+    pub fn tokenize(
+        &self,
+        text: &str,
+        add_special: bool,
+        parse_special: bool,
+    ) -> Result<Vec<i32>, Error> {
+        tokenize(&self.model_id, text, add_special, parse_special)
+    }
+
+    /// Convert tokens back to text using the session's model, see [`detokenize`].
+    pub fn detokenize(&self, tokens: &[i32]) -> Result<String, Error> {
+        detokenize(&self.model_id, tokens)
+    }
+
+    /// Convert a token to its text piece using the session's model,
+    /// see [`token_to_piece`].
+    pub fn token_to_piece(&self, token: i32) -> Result<String, Error> {
+        token_to_piece(&self.model_id, token)
+    }
+}
+
+/// Tokenize the text, returning the token IDs.
+///
+/// # Arguments
+///
+/// * `model_id` - The model ID.
+/// * `text` - The text to tokenize.
+/// * `add_special` - Whether to add special tokens (e.g. BOS)
+///   if the model is configured to do so.
+/// * `parse_special` - Whether to parse special tokens in the text
+///   (e.g. `<|im_start|>`), otherwise they are tokenized as plain text.
+///
+pub fn tokenize(
+    model_id: &str,
+    text: &str,
+    add_special: bool,
+    parse_special: bool,
+) -> Result<Vec<i32>, Error> {
+    ModelRegistry::global().tokenize(model_id, text, add_special, parse_special)
+}
+
+/// Tokenize the text with the native library.
+pub(crate) fn tokenize_native(
+    model_id: &str,
+    text: &str,
+    add_special: bool,
+    parse_special: bool,
+) -> Result<Vec<i32>, Error> {
+    let model_id = error::to_cstring(model_id, "model_id")?;
+    let text = error::to_cstring(text, "text")?;
+
+    // A token is at least one byte long, plus the special tokens.
+    let mut tokens = vec![0; text.as_bytes().len() + 2];
+
+    loop {
+        let result = unsafe {
+            ffi::simularity_gpt_tokenize(
+                model_id.as_ptr(),
+                text.as_ptr(),
+                add_special,
+                parse_special,
+                tokens.as_mut_ptr(),
+                tokens.len() as u32,
+            )
+        };
+
+        match result {
+            n if n >= 0 && n as usize <= tokens.len() => {
+                tokens.truncate(n as usize);
+                return Ok(tokens);
+            }
+            n if n >= 0 => tokens.resize(n as usize, 0),
+            -1 => return Err(Error::ModelNotFound),
+            -2 => return Err(Error::TokenizationFailed(error::last_error())),
+            _ => return Err(Error::unknown(result)),
+        }
+    }
+}
+
+/// Convert tokens back to text. Special tokens are rendered as text.
+/// Invalid UTF-8 sequences are replaced with `U+FFFD`.
+pub fn detokenize(model_id: &str, tokens: &[i32]) -> Result<String, Error> {
+    ModelRegistry::global().detokenize(model_id, tokens)
+}
+
+/// Convert tokens back to text with the native library.
+pub(crate) fn detokenize_native(model_id: &str, tokens: &[i32]) -> Result<String, Error> {
+    let model_id = error::to_cstring(model_id, "model_id")?;
+
+    // Most tokens are a few bytes long.
+    let mut text = vec![0u8; tokens.len() * 8];
+
+    loop {
+        let result = unsafe {
+            ffi::simularity_gpt_detokenize(
+                model_id.as_ptr(),
+                tokens.as_ptr(),
+                tokens.len() as u32,
+                text.as_mut_ptr() as *mut c_char,
+                text.len() as u32,
+            )
+        };
+
+        match result {
+            n if n >= 0 && n as usize <= text.len() => {
+                text.truncate(n as usize);
+                return Ok(String::from_utf8_lossy(&text).into_owned());
+            }
+            n if n >= 0 => text.resize(n as usize, 0),
+            -1 => return Err(Error::ModelNotFound),
+            -2 => return Err(Error::TokenizationFailed(error::last_error())),
+            -3 => return Err(invalid_token()),
+            _ => return Err(Error::unknown(result)),
+        }
+    }
+}
+
+/// Convert a single token to its text piece. Special tokens are rendered
+/// as text. NOTE: A piece may be an incomplete UTF-8 sequence,
+/// which is replaced with `U+FFFD`; use [`detokenize`] for whole sequences.
+pub fn token_to_piece(model_id: &str, token: i32) -> Result<String, Error> {
+    ModelRegistry::global().token_to_piece(model_id, token)
+}
+
+/// Convert a single token to its text piece with the native library.
+pub(crate) fn token_to_piece_native(model_id: &str, token: i32) -> Result<String, Error> {
+    let model_id = error::to_cstring(model_id, "model_id")?;
+
+    let mut piece = vec![0u8; 32];
+
+    loop {
+        let result = unsafe {
+            ffi::simularity_gpt_token_to_piece(
+                model_id.as_ptr(),
+                token,
+                piece.as_mut_ptr() as *mut c_char,
+                piece.len() as u32,
+            )
+        };
+
+        match result {
+            n if n >= 0 && n as usize <= piece.len() => {
+                piece.truncate(n as usize);
+                return Ok(String::from_utf8_lossy(&piece).into_owned());
+            }
+            n if n >= 0 => piece.resize(n as usize, 0),
+            -1 => return Err(Error::ModelNotFound),
+            -2 => return Err(Error::TokenizationFailed(error::last_error())),
+            -3 => return Err(invalid_token()),
+            _ => return Err(Error::unknown(result)),
+        }
+    }
+}
+
+fn invalid_token() -> Error {
+    Error::InvalidInput {
+        message: error::last_error().unwrap_or_else(|| "Invalid token".to_string()),
+        source: None,
+    }
+}
@@ -0,0 +1,237 @@
+//! GBNF grammar validation. Parses a grammar with the native llama.cpp
+//! grammar parser without loading a model, so that an invalid grammar
+//! is reported before any inference is attempted.
+//!
+//! See <https://github.com/ggerganov/llama.cpp/blob/master/grammars/README.md>.
+
+use std::{collections::HashSet, ffi::CString};
+
+use crate::{error, ffi};
+
+/// The rule a grammar starts with.
+pub const ROOT_RULE: &str = "root";
+
+/// A successfully parsed grammar.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrammarInfo {
+    /// The defined rules, in the order of definition.
+    pub rules: Vec<RuleInfo>,
+
+    /// The name of the rule the grammar starts with.
+    pub root: String,
+}
+
+/// A grammar rule definition.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleInfo {
+    pub name: String,
+
+    /// The 1-based line the rule is defined at.
+    pub line: u32,
+}
+
+/// A grammar parsing or validation error.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrammarError {
+    pub message: String,
+
+    /// The 1-based line of the error,
+    /// `None` if the error concerns the whole grammar.
+    pub line: Option<u32>,
+
+    /// The 1-based column (in characters) of the error.
+    pub column: Option<u32>,
+
+    /// The name of the rule the error occurred in, if any.
+    pub rule: Option<String>,
+}
+
+impl std::fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " at {}:{}", line, column)?;
+        }
+
+        if let Some(rule) = &self.rule {
+            write!(f, " (in rule `{}`)", rule)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+impl From<GrammarError> for crate::Error {
+    fn from(err: GrammarError) -> Self {
+        crate::Error::InvalidInput {
+            message: format!("Invalid grammar: {}", err),
+            source: Some(Box::new(err)),
+        }
+    }
+}
+
+/// Validate a GBNF grammar without a model, exactly as the
+/// [`crate::gpt::infer::Options::grammar`] option would be:
+/// checks the syntax, that the `root` rule is defined,
+/// that every referenced rule is defined, and that llama.cpp
+/// initializes the grammar.
+pub fn validate(gbnf: &str) -> Result<GrammarInfo, GrammarError> {
+    let definitions = definitions(gbnf);
+
+    let gbnf_c = CString::new(gbnf).map_err(|err| GrammarError {
+        message: "The grammar contains a NUL byte".to_string(),
+        ..position_error(gbnf, err.nul_position(), &definitions)
+    })?;
+
+    let mut error_offset = -1i64;
+    let result = unsafe { ffi::simularity_grammar_validate(gbnf_c.as_ptr(), &mut error_offset) };
+
+    match result {
+        0 => {}
+        -1 => {
+            let message = error::last_error().unwrap_or_else(|| "Invalid grammar".to_string());
+
+            return Err(match usize::try_from(error_offset) {
+                Ok(offset) => GrammarError {
+                    message,
+                    ..position_error(gbnf, offset, &definitions)
+                },
+                Err(_) => GrammarError {
+                    message,
+                    line: None,
+                    column: None,
+                    rule: None,
+                },
+            });
+        }
+        x => {
+            return Err(GrammarError {
+                message: crate::Error::unknown(x).to_string(),
+                line: None,
+                column: None,
+                rule: None,
+            })
+        }
+    }
+
+    // NOTE: A rule may be redefined, the last definition wins.
+    let mut seen = HashSet::new();
+    let mut rules: Vec<RuleInfo> = definitions
+        .iter()
+        .rev()
+        .filter(|(name, _)| seen.insert(*name))
+        .map(|&(name, offset)| RuleInfo {
+            name: name.to_string(),
+            line: line_column(gbnf, offset).0,
+        })
+        .collect();
+    rules.reverse();
+
+    Ok(GrammarInfo {
+        rules,
+        root: ROOT_RULE.to_string(),
+    })
+}
+
+/// The rule definitions (names with their byte offsets), in the source order.
+/// A rule is defined at a line start, as in `name ::= ...`.
+fn definitions(src: &str) -> Vec<(&str, usize)> {
+    let mut definitions = Vec::new();
+    let mut line_start = 0;
+
+    for line in src.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let name_len = trimmed.find(|c| !is_word_char(c)).unwrap_or(trimmed.len());
+
+        if name_len > 0 && trimmed[name_len..].trim_start().starts_with("::=") {
+            let offset = line_start + line.len() - trimmed.len();
+            definitions.push((&trimmed[..name_len], offset));
+        }
+
+        line_start += line.len();
+    }
+
+    definitions
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-'
+}
+
+/// An error at a byte offset, in the rule defined last before it.
+fn position_error(src: &str, mut offset: usize, definitions: &[(&str, usize)]) -> GrammarError {
+    offset = offset.min(src.len());
+    while !src.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    let (line, column) = line_column(src, offset);
+
+    GrammarError {
+        message: String::new(),
+        line: Some(line),
+        column: Some(column),
+        rule: definitions
+            .iter()
+            .take_while(|(_, rule_offset)| *rule_offset <= offset)
+            .last()
+            .map(|(name, _)| name.to_string()),
+    }
+}
+
+/// 1-based line and column (in characters) of a byte offset.
+fn line_column(src: &str, offset: usize) -> (u32, u32) {
+    let before = &src[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    (line as u32, column as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAMMAR: &str = "# A list.\n\
+        root ::= item (\",\" item)*\n\
+        \n\
+        item ::= [a-z]+ | (\n  \"ä\" item\n)\n  item ::= \"x\"\n";
+
+    #[test]
+    fn finds_definitions() {
+        assert_eq!(
+            definitions(GRAMMAR),
+            [
+                ("root", GRAMMAR.find("root").unwrap()),
+                ("item", GRAMMAR.find("item ::= [").unwrap()),
+                ("item", GRAMMAR.find("item ::= \"").unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn locates_errors() {
+        let definitions = definitions(GRAMMAR);
+        let offset = GRAMMAR.find('ä').unwrap();
+
+        for offset in [offset, offset + 1] {
+            let err = position_error(GRAMMAR, offset, &definitions);
+            assert_eq!((err.line, err.column), (Some(5), Some(4)));
+            assert_eq!(err.rule.as_deref(), Some("item"));
+        }
+
+        let err = position_error(GRAMMAR, 2, &definitions);
+        assert_eq!((err.line, err.column), (Some(1), Some(3)));
+        assert_eq!(err.rule, None);
+
+        let err = position_error(GRAMMAR, 1000, &definitions);
+        assert_eq!((err.line, err.column), (Some(8), Some(1)));
+        assert_eq!(err.rule.as_deref(), Some("item"));
+    }
+}
@@ -0,0 +1,141 @@
+use std::ffi::{c_char, c_int, c_void, CStr};
+
+use crate::{error, ffi, Error};
+
+/// Library initialization options, a builder for [`crate::init`]:
+/// `InitOptions::new().forward_logs(true).init()`.
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+    gpt_sessions_ttl: Option<u32>,
+    gpt_sessions_max: Option<u32>,
+    gpt_sessions_cache_dir: Option<String>,
+    log_level: Option<log::LevelFilter>,
+    forward_logs: bool,
+    n_threads: Option<u32>,
+}
+
+impl InitOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The time-to-live for GPT sessions in seconds, unlimited by default.
+    pub fn gpt_sessions_ttl(mut self, seconds: u32) -> Self {
+        self.gpt_sessions_ttl = Some(seconds);
+        self
+    }
+
+    /// The maximum number of GPT sessions, unlimited by default.
+    pub fn gpt_sessions_max(mut self, max: u32) -> Self {
+        self.gpt_sessions_max = Some(max);
+        self
+    }
+
+    /// A directory to resolve relative session state file paths against,
+    /// see [`crate::gpt::Session::create`]. Created if missing.
+    pub fn gpt_sessions_cache_dir(mut self, path: impl Into<String>) -> Self {
+        self.gpt_sessions_cache_dir = Some(path.into());
+        self
+    }
+
+    /// The minimum level of the native messages, `Debug` by default,
+    /// or [`log::max_level`] if the logs are forwarded.
+    pub fn log_level(mut self, level: log::LevelFilter) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// Forward the native messages to the [`log`] crate instead of printing
+    /// them to stdout and stderr, with the `simularity` target for the library
+    /// messages, and `llama.cpp` for the llama.cpp ones. A `tracing`
+    /// subscriber receives them with `tracing-log`.
+    pub fn forward_logs(mut self, forward: bool) -> Self {
+        self.forward_logs = forward;
+        self
+    }
+
+    /// The number of threads for the models loaded without
+    /// [`crate::ModelLoadOptions::n_threads`], the library default otherwise.
+    pub fn n_threads(mut self, n_threads: u32) -> Self {
+        self.n_threads = Some(n_threads);
+        self
+    }
+
+    /// Initialize the library. May be called again to change the options.
+    pub fn init(self) -> Result<(), Error> {
+        let cache_dir = self
+            .gpt_sessions_cache_dir
+            .as_deref()
+            .map(|path| error::to_cstring(path, "gpt_sessions_cache_dir"))
+            .transpose()?;
+
+        let log_level = self.log_level.unwrap_or(if self.forward_logs {
+            log::max_level()
+        } else {
+            log::LevelFilter::Debug
+        });
+
+        if cfg!(feature = "mock") {
+            // NOTE: The mock backend needs no initialization.
+            return Ok(());
+        }
+
+        let mut options = unsafe { ffi::simularity_init_options_default() };
+        options.gpt_sessions_ttl = self.gpt_sessions_ttl.unwrap_or(0);
+        options.gpt_sessions_max = self.gpt_sessions_max.unwrap_or(0);
+        options.log_level = to_native_level(log_level);
+        options.n_threads = self.n_threads.unwrap_or(0);
+
+        if let Some(cache_dir) = &cache_dir {
+            options.gpt_sessions_cache_dir = cache_dir.as_ptr();
+        }
+
+        if self.forward_logs {
+            options.log_callback = Some(log_callback);
+        }
+
+        let result = unsafe { ffi::simularity_init_with_options(&options) };
+
+        match result {
+            0 => Ok(()),
+            -1 => Err(Error::InvalidInput {
+                message: error::last_error().unwrap_or_default(),
+                source: None,
+            }),
+            _ => Err(Error::unknown(result)),
+        }
+    }
+}
+
+/// See `enum simularity_log_level`.
+fn to_native_level(level: log::LevelFilter) -> c_int {
+    match level {
+        log::LevelFilter::Trace => 0,
+        log::LevelFilter::Debug => 1,
+        log::LevelFilter::Info => 2,
+        log::LevelFilter::Warn => 3,
+        log::LevelFilter::Error => 4,
+        log::LevelFilter::Off => 6,
+    }
+}
+
+fn from_native_level(level: c_int) -> log::Level {
+    match level {
+        0 => log::Level::Trace,
+        1 => log::Level::Debug,
+        2 => log::Level::Info,
+        3 => log::Level::Warn,
+        _ => log::Level::Error,
+    }
+}
+
+extern "C" fn log_callback(
+    level: c_int,
+    target: *const c_char,
+    message: *const c_char,
+    _user_data: *mut c_void,
+) {
+    let target = unsafe { CStr::from_ptr(target) }.to_string_lossy();
+    let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    log::log!(target: &target, from_native_level(level), "{}", message);
+}
@@ -1,113 +1,110 @@
-use std::ffi::{c_void, CString};
-
+pub mod backend;
+pub mod chat_template;
+mod error;
 mod ffi;
+pub mod gguf;
 pub mod gpt;
+pub mod grammar;
+mod init;
+mod lora;
+mod model;
+mod registry;
+
+use backend::ProgressCallback;
+pub use backend::{Backend, DefaultBackend, FfiBackend};
+pub use error::Error;
+pub use init::InitOptions;
+pub use model::{LoadedModel, Model, ModelLoadOptions};
+pub use registry::ModelRegistry;
 
+/// Initialize the library, see [`InitOptions`] for more options.
+/// The native messages are printed to stdout and stderr.
+/// A no-op with the `mock` feature.
 pub fn init(gpt_sessions_ttl: Option<u32>, gpt_sessions_max: Option<u32>) {
-    unsafe { ffi::simularity_init(gpt_sessions_ttl.unwrap_or(0), gpt_sessions_max.unwrap_or(0)) };
-}
+    if cfg!(feature = "mock") {
+        return;
+    }
 
-#[derive(Debug)]
-pub enum ModelLoadError {
-    LoadFailed,
-    Unknown(i32),
+    unsafe { ffi::simularity_init(gpt_sessions_ttl.unwrap_or(0), gpt_sessions_max.unwrap_or(0)) };
 }
 
-/// Load a model from a file.
+/// Load a model from a file, see [`ModelRegistry::load`].
 /// If the model already loaded, it will return the model info.
+/// Unlike [`Model::load`], the model stays loaded
+/// until [`model_unload`] is called.
 ///
 /// # Arguments
 ///
 /// * `model_path` - Path to the model file.
 /// * `model_id` - Unique identifier for the model.
+/// * `options` - Loading options, `None` for the defaults.
 /// * `progress_callback` - Rust function that will be called with the progress.
 ///   Return `true` to continue loading, `false` to cancel.
+///   It must not load a model, as the loads are serialized.
 ///
 pub fn model_load(
     model_path: &str,
     model_id: &str,
+    options: Option<ModelLoadOptions>,
     mut progress_callback: Option<impl FnMut(f32) -> bool>,
-) -> Result<ffi::SimularityModelInfo, ModelLoadError> {
-    let user_data = if let Some(cb) = progress_callback.as_mut() {
-        let mut user_data: &mut dyn FnMut(f32) -> bool = cb;
-        &mut user_data as *mut _ as *mut c_void
-    } else {
-        std::ptr::null_mut()
-    };
-
-    let model_path = CString::new(model_path).unwrap();
-    let model_id = CString::new(model_id).unwrap();
-    let mut model_info = ffi::SimularityModelInfo {
-        n_params: 0,
-        size: 0,
-        n_ctx_train: 0,
-    };
-
-    let result = unsafe {
-        ffi::simularity_model_load(
-            model_path.as_ptr(),
-            model_id.as_ptr(),
-            if progress_callback.is_some() {
-                Some(ffi::progress_callback_wrapper)
-            } else {
-                None
-            },
-            user_data,
-            &mut model_info,
-        )
-    };
-
-    match result {
-        0 => Ok(model_info),
-        -1 => Ok(model_info), // Model already loaded, return the info.
-        -2 => Err(ModelLoadError::LoadFailed),
-        _ => Err(ModelLoadError::Unknown(result)),
-    }
-}
-
-#[derive(Debug)]
-pub enum ModelHashError {
-    Unknown(i32),
+) -> Result<ffi::SimularityModelInfo, Error> {
+    ModelRegistry::global().load(
+        model_path,
+        model_id,
+        options,
+        progress_callback.as_mut().map(|cb| cb as ProgressCallback),
+    )
 }
 
-/// Get the hash of a model by its ID.
-pub fn model_get_hash_by_id(model_id: &str) -> Result<u64, ModelHashError> {
-    let model_id = CString::new(model_id).unwrap();
-    let result = unsafe { ffi::simularity_model_get_hash_by_id(model_id.as_ptr()) };
-    match result {
-        0 => Err(ModelHashError::Unknown(result as i32)),
-        _ => Ok(result),
-    }
+/// Get the hash of a model by its ID, see [`ModelRegistry::hash`].
+pub fn model_get_hash_by_id(model_id: &str) -> Result<u64, Error> {
+    ModelRegistry::global().hash(model_id)
 }
 
 /// Get the hash of a model by its path.
-pub fn model_get_hash_by_path(model_path: &str) -> Result<u64, ModelHashError> {
-    let model_path = CString::new(model_path).unwrap();
+pub fn model_get_hash_by_path(model_path: &str) -> Result<u64, Error> {
+    let model_path = error::to_existing_path(model_path, "model_path")?;
     let result = unsafe { ffi::simularity_model_get_hash_by_path(model_path.as_ptr()) };
     match result {
-        0 => Err(ModelHashError::Unknown(result as i32)),
+        0 | u64::MAX => Err(Error::ModelHashFailed(error::last_error())),
         _ => Ok(result),
     }
 }
 
-#[derive(Debug)]
-pub enum ModelUnloadError {
-    ModelNotFound,
-    Unknown(i32),
+/// Get information about a loaded model.
+pub fn model_get_info(model_id: &str) -> Result<ffi::SimularityModelInfo, Error> {
+    ModelRegistry::global().info(model_id)
 }
 
-/// Unload a model.
+/// List the loaded models along with the sessions using them.
+pub fn models_list() -> Result<Vec<LoadedModel>, Error> {
+    ModelRegistry::global().list()
+}
+
+/// Unload a model, unless used by live sessions,
+/// see [`ModelRegistry::unload`].
 ///
 /// # Arguments
 /// * `model_id` The model id, loaded with `model_load`.
-pub fn model_unload(model_id: &str) -> Result<(), ModelUnloadError> {
-    let model_id = CString::new(model_id).unwrap();
+pub fn model_unload(model_id: &str) -> Result<(), Error> {
+    ModelRegistry::global().unload(model_id)
+}
 
-    let result = unsafe { ffi::simularity_model_unload(model_id.as_ptr()) };
+/// Load a LoRA adapter from a file, to be applied to sessions
+/// with [`gpt::Session::set_loras`]. The adapter is initialized
+/// for a base model upon its first use. Loading an already loaded
+/// adapter ID is a no-op.
+///
+/// # Arguments
+///
+/// * `lora_path` - Path to the adapter GGUF file.
+/// * `lora_id` - Unique identifier for the adapter.
+///
+pub fn lora_load(lora_path: &str, lora_id: &str) -> Result<(), Error> {
+    lora::load(lora_path, lora_id)
+}
 
-    match result {
-        0 => Ok(()),
-        -1 => Err(ModelUnloadError::ModelNotFound),
-        _ => Err(ModelUnloadError::Unknown(result)),
-    }
+/// Unload a LoRA adapter. The sessions using it keep it until changed.
+pub fn lora_unload(lora_id: &str) -> Result<(), Error> {
+    lora::unload(lora_id)
 }
@@ -0,0 +1,27 @@
+use crate::{error, ffi, Error};
+
+pub(crate) fn load(lora_path: &str, lora_id: &str) -> Result<(), Error> {
+    let lora_path = error::to_existing_path(lora_path, "lora_path")?;
+    let lora_id = error::to_cstring(lora_id, "lora_id")?;
+
+    let result = unsafe { ffi::simularity_lora_load(lora_path.as_ptr(), lora_id.as_ptr()) };
+
+    match result {
+        // NOTE: -1 means the adapter is already loaded.
+        0 | -1 => Ok(()),
+        -2 => Err(Error::LoraLoadFailed(error::last_error())),
+        _ => Err(Error::unknown(result)),
+    }
+}
+
+pub(crate) fn unload(lora_id: &str) -> Result<(), Error> {
+    let lora_id = error::to_cstring(lora_id, "lora_id")?;
+
+    let result = unsafe { ffi::simularity_lora_unload(lora_id.as_ptr()) };
+
+    match result {
+        0 => Ok(()),
+        -1 => Err(Error::LoraNotFound),
+        _ => Err(Error::unknown(result)),
+    }
+}
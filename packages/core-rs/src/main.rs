@@ -4,13 +4,18 @@ pub fn main() {
     //
     // This is a simple test to ensure that
     // the crate can be compiled and linked.
+    // With the `mock` feature, there is nothing to link.
     //
 
+    if cfg!(feature = "mock") {
+        return;
+    }
+
     simularity_core::init(None, None);
-    simularity_core::model_load("", "", None::<fn(_) -> bool>);
+    simularity_core::model_load("", "", None, None::<fn(_) -> bool>);
     simularity_core::model_get_hash_by_id("");
     simularity_core::gpt::token_length("", "");
-    simularity_core::gpt::create("", None, None, None, None, None::<fn(_) -> bool>);
+    simularity_core::gpt::create("", None, None, None, None::<fn(_) -> bool>);
     simularity_core::gpt::touch(42);
     simularity_core::gpt::decode(42, "", None::<fn(_) -> bool>);
     simularity_core::gpt::infer(
@@ -19,6 +24,6 @@ pub fn main() {
         42,
         None,
         None::<fn(_) -> bool>,
-        None::<fn(&str) -> bool>,
+        None::<fn(&simularity_core::gpt::infer::InferenceToken) -> bool>,
     );
 }
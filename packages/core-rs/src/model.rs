@@ -0,0 +1,429 @@
+use std::{ffi::c_void, mem::ManuallyDrop};
+
+use crate::{backend::ProgressCallback, error, ffi, gpt, Error, ModelRegistry};
+
+/// Model loading options.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ModelLoadOptions {
+    /// Map the model file into memory instead of reading it,
+    /// so that the pages are loaded on demand and shared. `true` by default.
+    pub use_mmap: bool,
+
+    /// Lock the model in RAM, so that it is never swapped out.
+    pub use_mlock: bool,
+
+    /// Only load the vocabulary, e.g. for tokenization.
+    /// Such a model can not be used to create sessions.
+    pub vocab_only: bool,
+
+    /// Validate the tensor data while loading, failing with
+    /// [`Error::ModelLoadFailed`] on invalid values. Slows the loading down.
+    pub check_tensors: bool,
+
+    /// The default number of threads for the sessions created with the model,
+    /// `None` for the library default.
+    pub n_threads: Option<u32>,
+}
+
+impl Default for ModelLoadOptions {
+    fn default() -> Self {
+        Self {
+            use_mmap: true,
+            use_mlock: false,
+            vocab_only: false,
+            check_tensors: false,
+            n_threads: None,
+        }
+    }
+}
+
+impl From<ModelLoadOptions> for ffi::SimularityModelLoadOptions {
+    fn from(options: ModelLoadOptions) -> Self {
+        Self {
+            use_mmap: options.use_mmap,
+            use_mlock: options.use_mlock,
+            vocab_only: options.vocab_only,
+            check_tensors: options.check_tensors,
+            n_threads: options.n_threads.unwrap_or(0),
+        }
+    }
+}
+
+/// A handle to a loaded model. The model is unloaded when the last handle
+/// is dropped, or once no longer used by live sessions if it still is
+/// (see [`ModelRegistry::unload`]).
+///
+/// NOTE: Loading a model with an already existing ID returns another handle
+/// to the existing model. The model can not be unloaded while referenced
+/// by any handle.
+#[derive(Debug)]
+pub struct Model {
+    id: String,
+    info: ffi::SimularityModelInfo,
+}
+
+impl Model {
+    /// Load a model from a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_path` - Path to the model file.
+    /// * `model_id` - Unique identifier for the model.
+    /// * `options` - Loading options, `None` for the defaults.
+    ///   Ignored if the model is already loaded.
+    /// * `progress_callback` - Rust function that will be called with the progress.
+    ///   Return `true` to continue loading, `false` to cancel
+    ///   with [`Error::Cancelled`]. It must not load a model,
+    ///   as the loads are serialized.
+    ///
+    pub fn load(
+        model_path: &str,
+        model_id: &str,
+        options: Option<ModelLoadOptions>,
+        mut progress_callback: Option<impl FnMut(f32) -> bool>,
+    ) -> Result<Self, Error> {
+        let info = ModelRegistry::global().load_handle(
+            model_path,
+            model_id,
+            options,
+            progress_callback.as_mut().map(|cb| cb as ProgressCallback),
+        )?;
+
+        Ok(Self {
+            id: model_id.to_string(),
+            info,
+        })
+    }
+
+    /// The model ID, as passed to [`Model::load`].
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Information about the model.
+    pub fn info(&self) -> &ffi::SimularityModelInfo {
+        &self.info
+    }
+
+    /// Get the hash of the model (memoized).
+    pub fn hash(&self) -> Result<u64, Error> {
+        ModelRegistry::global().hash(&self.id)
+    }
+
+    /// Get the length of the prompt in tokens.
+    pub fn token_length(&self, prompt: &str) -> Result<u32, Error> {
+        gpt::token_length(&self.id, prompt)
+    }
+
+    /// Tokenize the text, see [`gpt::tokenize`].
+    pub fn tokenize(
+        &self,
+        text: &str,
+        add_special: bool,
+        parse_special: bool,
+    ) -> Result<Vec<i32>, Error> {
+        gpt::tokenize(&self.id, text, add_special, parse_special)
+    }
+
+    /// Convert tokens back to text, see [`gpt::detokenize`].
+    pub fn detokenize(&self, tokens: &[i32]) -> Result<String, Error> {
+        gpt::detokenize(&self.id, tokens)
+    }
+
+    /// Convert a token to its text piece, see [`gpt::token_to_piece`].
+    pub fn token_to_piece(&self, token: i32) -> Result<String, Error> {
+        gpt::token_to_piece(&self.id, token)
+    }
+
+    /// Compute the text embeddings, see [`gpt::embed`].
+    pub fn embed(
+        &self,
+        texts: &[&str],
+        pooling: gpt::embed::Pooling,
+        normalization: gpt::embed::Normalization,
+    ) -> Result<Vec<Vec<f32>>, Error> {
+        gpt::embed(&self.id, texts, pooling, normalization)
+    }
+
+    /// Create a new GPT session with this model, see [`gpt::create`].
+    pub fn create_session(
+        &self,
+        options: Option<gpt::SessionOptions>,
+        initial_prompt: Option<&str>,
+        state_file_path: Option<&str>,
+        progress_callback: Option<impl FnMut(f32) -> bool>,
+    ) -> Result<gpt::Session, Error> {
+        let session_id = gpt::create(
+            &self.id,
+            options,
+            initial_prompt,
+            state_file_path,
+            progress_callback,
+        )?;
+
+        Ok(gpt::Session::from_id(session_id, &self.id))
+    }
+
+    /// Release the handle and unload the model explicitly, returning
+    /// an error if it fails, e.g. [`Error::ModelInUse`] if used by sessions
+    /// or other handles, in which case the model stays loaded.
+    pub fn unload(self) -> Result<(), Error> {
+        let id = ManuallyDrop::new(self).take_id();
+        ModelRegistry::global().release_handle(&id, true)
+    }
+
+    /// Release the handle without unloading the model, returning its ID.
+    /// The model must then be unloaded with [`crate::model_unload`].
+    pub fn into_id(self) -> String {
+        let id = ManuallyDrop::new(self).take_id();
+        let _ = ModelRegistry::global().release_handle(&id, false);
+        id
+    }
+
+    fn take_id(&mut self) -> String {
+        std::mem::take(&mut self.id)
+    }
+}
+
+impl Drop for Model {
+    fn drop(&mut self) {
+        ModelRegistry::global().drop_handle(&self.id);
+    }
+}
+
+/// A loaded model, see [`crate::models_list`].
+#[derive(Debug, Clone)]
+pub struct LoadedModel {
+    /// The model ID.
+    pub id: String,
+
+    /// Information about the model. `size` is its memory footprint in bytes.
+    pub info: ffi::SimularityModelInfo,
+
+    /// IDs of the live sessions using the model.
+    pub session_ids: Vec<u32>,
+}
+
+pub(crate) fn get_info(model_id: &str) -> Result<ffi::SimularityModelInfo, Error> {
+    let model_id = error::to_cstring(model_id, "model_id")?;
+    let mut info = ffi::SimularityModelInfo {
+        n_params: 0,
+        size: 0,
+        n_ctx_train: 0,
+    };
+
+    let result = unsafe { ffi::simularity_model_get_info(model_id.as_ptr(), &mut info) };
+
+    match result {
+        0 => Ok(info),
+        -1 => Err(Error::ModelNotFound),
+        _ => Err(Error::unknown(result)),
+    }
+}
+
+pub(crate) fn list() -> Result<Vec<LoadedModel>, Error> {
+    let mut buffer: Vec<u8> = Vec::new();
+
+    loop {
+        let result =
+            unsafe { ffi::simularity_model_list(buffer.as_mut_ptr() as *mut _, buffer.len()) };
+
+        match result {
+            n if n >= 0 && n as usize <= buffer.len() => {
+                buffer.truncate(n as usize);
+                break;
+            }
+            // Models may be loaded in between the calls, hence the loop.
+            n if n >= 0 => buffer.resize(n as usize, 0),
+            _ => return Err(Error::unknown(result)),
+        }
+    }
+
+    let sessions = gpt::list()?;
+    let mut models = Vec::new();
+
+    for id in buffer.split(|&b| b == 0).filter(|id| !id.is_empty()) {
+        let id = String::from_utf8_lossy(id).into_owned();
+
+        let info = match get_info(&id) {
+            Ok(info) => info,
+            // The model may be unloaded in between the calls.
+            Err(Error::ModelNotFound) => continue,
+            Err(err) => return Err(err),
+        };
+
+        let session_ids = sessions
+            .iter()
+            .filter(|session| session.model_id == id)
+            .map(|session| session.id)
+            .collect();
+
+        models.push(LoadedModel {
+            id,
+            info,
+            session_ids,
+        });
+    }
+
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(models)
+}
+
+pub(crate) fn hash_by_id(model_id: &str) -> Result<u64, Error> {
+    let model_id = error::to_cstring(model_id, "model_id")?;
+    let result = unsafe { ffi::simularity_model_get_hash_by_id(model_id.as_ptr()) };
+    match result {
+        0 => Err(Error::ModelHashFailed(error::last_error())),
+        u64::MAX => Err(Error::ModelNotFound),
+        _ => Ok(result),
+    }
+}
+
+/// IDs of the live sessions using the model,
+/// including the expired ones which have not been removed yet.
+pub(crate) fn sessions(model_id: &str) -> Result<Vec<u32>, Error> {
+    Ok(gpt::list()?
+        .into_iter()
+        .filter(|session| session.model_id == model_id)
+        .map(|session| session.id)
+        .collect())
+}
+
+pub(crate) fn unload(model_id: &str) -> Result<(), Error> {
+    let model_id = error::to_cstring(model_id, "model_id")?;
+
+    let result = unsafe { ffi::simularity_model_unload(model_id.as_ptr()) };
+
+    match result {
+        0 => Ok(()),
+        -1 => Err(Error::ModelNotFound),
+        _ => Err(Error::unknown(result)),
+    }
+}
+
+pub(crate) fn load(
+    model_path: &str,
+    model_id: &str,
+    options: Option<ModelLoadOptions>,
+    mut progress_callback: Option<impl FnMut(f32) -> bool>,
+) -> Result<ffi::SimularityModelInfo, Error> {
+    let model_path_c = error::to_existing_path(model_path, "model_path")?;
+    let model_id_c = error::to_cstring(model_id, "model_id")?;
+
+    let user_data = if let Some(cb) = progress_callback.as_mut() {
+        // See https://stackoverflow.com/a/32270215/3645337.
+        let user_data: Box<Box<dyn FnMut(f32) -> bool>> = Box::new(Box::new(cb));
+        Box::into_raw(user_data) as *mut c_void
+    } else {
+        std::ptr::null_mut()
+    };
+    let mut info = ffi::SimularityModelInfo {
+        n_params: 0,
+        size: 0,
+        n_ctx_train: 0,
+    };
+    let options = options.map(ffi::SimularityModelLoadOptions::from);
+
+    let result = unsafe {
+        ffi::simularity_model_load(
+            model_path_c.as_ptr(),
+            model_id_c.as_ptr(),
+            options.as_ref().map_or(std::ptr::null(), |o| o as *const _),
+            if progress_callback.is_some() {
+                Some(ffi::progress_callback_wrapper)
+            } else {
+                None
+            },
+            user_data,
+            &mut info,
+        )
+    };
+
+    if !user_data.is_null() {
+        // Drop the box.
+        let _: Box<Box<dyn FnMut(f32) -> bool>> = unsafe { Box::from_raw(user_data as *mut _) };
+    }
+
+    match result {
+        // NOTE: -1 means the model is already loaded, the info is still set.
+        0 | -1 => Ok(info),
+        -2 => Err(Error::ModelLoadFailed(error::last_error())),
+        -3 => Err(Error::Cancelled),
+        _ => Err(Error::unknown(result)),
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+
+    fn load(model_id: &str) -> Model {
+        Model::load("", model_id, None, None::<fn(_) -> bool>).unwrap()
+    }
+
+    #[test]
+    fn last_handle_unloads() {
+        let model = load("model-handles");
+        let duplicate = load("model-handles");
+
+        assert!(matches!(
+            crate::model_unload("model-handles"),
+            Err(Error::ModelInUse { .. })
+        ));
+
+        drop(model);
+        assert!(crate::model_get_info("model-handles").is_ok());
+
+        drop(duplicate);
+        assert!(matches!(
+            crate::model_get_info("model-handles"),
+            Err(Error::ModelNotFound)
+        ));
+    }
+
+    #[test]
+    fn handle_outlives_sessions() {
+        let model = load("model-sessions");
+        let session = model
+            .create_session(None, None, None, None::<fn(_) -> bool>)
+            .unwrap();
+
+        assert!(matches!(
+            load("model-sessions").unload(),
+            Err(Error::ModelInUse { session_ids }) if session_ids == [session.id()]
+        ));
+
+        drop(session);
+        model.unload().unwrap();
+        assert!(matches!(
+            crate::model_get_info("model-sessions"),
+            Err(Error::ModelNotFound)
+        ));
+    }
+
+    #[test]
+    fn dropped_handle_unloads_after_the_sessions() {
+        let model = load("model-dropped");
+        let session = model
+            .create_session(None, None, None, None::<fn(_) -> bool>)
+            .unwrap();
+
+        drop(model);
+        assert!(crate::model_get_info("model-dropped").is_ok());
+
+        drop(session);
+        assert!(matches!(
+            crate::model_get_info("model-dropped"),
+            Err(Error::ModelNotFound)
+        ));
+    }
+
+    #[test]
+    fn into_id_keeps_the_model() {
+        let model_id = load("model-into-id").into_id();
+        assert!(crate::model_get_info(&model_id).is_ok());
+
+        crate::model_unload(&model_id).unwrap();
+    }
+}
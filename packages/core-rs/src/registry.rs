@@ -0,0 +1,687 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, MutexGuard, OnceLock},
+};
+
+use crate::{
+    backend::{Backend, DefaultBackend, InferenceCallback, ProgressCallback},
+    ffi,
+    gpt::{
+        self,
+        embed::{Normalization, Pooling},
+        InferOutcome, SessionOptions,
+    },
+    Error, LoadedModel, ModelLoadOptions,
+};
+
+/// Serializes the model loads and unloads, and reference-counts the models
+/// used by sessions and by the calls in progress, so that a model is never
+/// unloaded while in use.
+///
+/// The free functions (e.g. [`crate::model_load`] and [`crate::gpt::create`])
+/// and [`crate::gpt::Session`] use the [`ModelRegistry::global`] one.
+/// A registry is `Send + Sync`, so that it can be shared between threads,
+/// e.g. in an `Arc`.
+pub struct ModelRegistry<B: Backend = DefaultBackend> {
+    backend: B,
+
+    /// Locked for the whole model operation, but not while a model is used.
+    state: Mutex<State>,
+
+    /// Serializes the loads, not blocking the other model operations.
+    loading: Mutex<()>,
+}
+
+#[derive(Default)]
+struct State {
+    /// { model_id => the number of its sessions, handles and the calls using it }.
+    references: HashMap<String, u32>,
+
+    /// { session_id => model_id } of the sessions created with the registry.
+    sessions: HashMap<u32, String>,
+
+    /// { session_id => draft_model_id } of the sessions keeping a draft model
+    /// context, see [`gpt::infer::Options::speculative`].
+    drafts: HashMap<u32, String>,
+
+    /// IDs of the models to unload once no longer used, as their last
+    /// [`crate::Model`] handle has been dropped while they were in use.
+    pending_unloads: HashSet<String>,
+}
+
+impl State {
+    fn acquire(&mut self, model_id: &str) {
+        *self.references.entry(model_id.to_string()).or_default() += 1;
+    }
+
+    fn release(&mut self, model_id: &str) {
+        if let Some(count) = self.references.get_mut(model_id) {
+            *count -= 1;
+
+            if *count == 0 {
+                self.references.remove(model_id);
+            }
+        }
+    }
+
+    fn add_session(&mut self, session_id: u32, model_id: &str) {
+        self.acquire(model_id);
+        self.sessions.insert(session_id, model_id.to_string());
+    }
+
+    fn remove_session(&mut self, session_id: u32) {
+        if let Some(model_id) = self.sessions.remove(&session_id) {
+            self.release(&model_id);
+        }
+
+        if let Some(draft_model_id) = self.drafts.remove(&session_id) {
+            self.release(&draft_model_id);
+        }
+    }
+
+    fn set_draft(&mut self, session_id: u32, draft_model_id: &str) {
+        if self.drafts.get(&session_id).map(String::as_str) == Some(draft_model_id) {
+            return;
+        }
+
+        self.acquire(draft_model_id);
+
+        if let Some(previous) = self.drafts.insert(session_id, draft_model_id.to_string()) {
+            self.release(&previous);
+        }
+    }
+}
+
+impl ModelRegistry {
+    /// The registry of the [`DefaultBackend`], used by the free functions
+    /// and [`crate::gpt::Session`].
+    pub fn global() -> &'static Self {
+        static GLOBAL: OnceLock<ModelRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(Self::default)
+    }
+}
+
+impl<B: Backend> ModelRegistry<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            state: Mutex::new(State::default()),
+            loading: Mutex::new(()),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Load a model, see [`crate::model_load`].
+    /// Blocks the other loads until loaded, so that the progress callback
+    /// must not load a model itself; the other model operations may be called.
+    pub fn load(
+        &self,
+        model_path: &str,
+        model_id: &str,
+        options: Option<ModelLoadOptions>,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<ffi::SimularityModelInfo, Error> {
+        let _loading = self.loading();
+        log::debug!("Loading model {}", model_id);
+
+        let info = self
+            .backend
+            .model_load(model_path, model_id, options, progress_callback)?;
+
+        // Loaded explicitly, to be unloaded explicitly.
+        self.lock().pending_unloads.remove(model_id);
+        Ok(info)
+    }
+
+    /// Load a model referenced by a [`crate::Model`] handle,
+    /// released with [`ModelRegistry::release_handle`].
+    /// Blocks the other loads until loaded, see [`ModelRegistry::load`].
+    pub(crate) fn load_handle(
+        &self,
+        model_path: &str,
+        model_id: &str,
+        options: Option<ModelLoadOptions>,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<ffi::SimularityModelInfo, Error> {
+        let _loading = self.loading();
+        log::debug!("Loading model {}", model_id);
+
+        // Referenced beforehand, so that it is not unloaded right after.
+        self.lock().acquire(model_id);
+
+        let result = self
+            .backend
+            .model_load(model_path, model_id, options, progress_callback);
+
+        if result.is_err() {
+            self.lock().release(model_id);
+        }
+
+        result
+    }
+
+    /// Release a model referenced by a [`crate::Model`] handle,
+    /// and unload it if `unload` is set, see [`ModelRegistry::unload`].
+    /// Otherwise, the caller takes over unloading the model.
+    pub(crate) fn release_handle(&self, model_id: &str, unload: bool) -> Result<(), Error> {
+        let mut state = self.lock();
+        state.release(model_id);
+
+        if unload {
+            self.unload_locked(&mut state, model_id)
+        } else {
+            state.pending_unloads.remove(model_id);
+            Ok(())
+        }
+    }
+
+    /// Release a model referenced by a dropped [`crate::Model`] handle,
+    /// and unload it, or once no longer used if it is in use.
+    pub(crate) fn drop_handle(&self, model_id: &str) {
+        let mut state = self.lock();
+        state.release(model_id);
+
+        match self.unload_locked(&mut state, model_id) {
+            Ok(()) | Err(Error::ModelNotFound) => {}
+            Err(Error::ModelInUse { .. }) => {
+                log::debug!("Unloading model {} once no longer used", model_id);
+                state.pending_unloads.insert(model_id.to_string());
+            }
+            Err(err) => log::warn!("Failed to unload model {}: {}", model_id, err),
+        }
+    }
+
+    /// Get the hash of a model by its ID, see [`crate::model_get_hash_by_id`].
+    pub fn hash(&self, model_id: &str) -> Result<u64, Error> {
+        let _reference = self.acquire(model_id);
+        self.backend.model_hash(model_id)
+    }
+
+    /// Get information about a loaded model, see [`crate::model_get_info`].
+    pub fn info(&self, model_id: &str) -> Result<ffi::SimularityModelInfo, Error> {
+        let _lock = self.lock();
+        self.backend.model_info(model_id)
+    }
+
+    /// List the loaded models, see [`crate::models_list`].
+    pub fn list(&self) -> Result<Vec<LoadedModel>, Error> {
+        let _lock = self.lock();
+        self.backend.model_list()
+    }
+
+    /// The number of the live sessions using the model, plus its handles
+    /// and the calls using it in progress (e.g. creating a session).
+    pub fn ref_count(&self, model_id: &str) -> Result<usize, Error> {
+        let mut state = self.lock();
+        let (references, _) = self.users(&mut state, model_id)?;
+        Ok(references)
+    }
+
+    /// Unload a model, failing with [`Error::ModelInUse`]
+    /// if used by live sessions (including the expired ones not removed yet,
+    /// and the ones keeping it as a draft model),
+    /// by [`crate::Model`] handles, or by calls in progress.
+    pub fn unload(&self, model_id: &str) -> Result<(), Error> {
+        self.unload_locked(&mut self.lock(), model_id)
+    }
+
+    fn unload_locked(&self, state: &mut State, model_id: &str) -> Result<(), Error> {
+        let (references, session_ids) = self.users(state, model_id)?;
+
+        if references > 0 {
+            log::debug!(
+                "Refusing to unload model {} used by sessions {:?}",
+                model_id,
+                session_ids
+            );
+
+            return Err(Error::ModelInUse { session_ids });
+        }
+
+        self.backend.model_unload(model_id)?;
+        state.pending_unloads.remove(model_id);
+        Ok(())
+    }
+
+    /// Unload the models pending unload which are no longer used,
+    /// see [`ModelRegistry::drop_handle`].
+    fn unload_pending(&self, state: &mut State) {
+        let unused: Vec<String> = state
+            .pending_unloads
+            .iter()
+            .filter(|id| !state.references.contains_key(*id))
+            .cloned()
+            .collect();
+
+        for model_id in unused {
+            match self.unload_locked(state, &model_id) {
+                // Still used by the sessions created bypassing the registry.
+                Err(Error::ModelInUse { .. }) => {}
+                Ok(()) => log::debug!("Unloaded model {} no longer used", model_id),
+                Err(err) => {
+                    log::warn!("Failed to unload model {}: {}", model_id, err);
+                    state.pending_unloads.remove(&model_id);
+                }
+            }
+        }
+    }
+
+    /// Create a new GPT session, see [`crate::gpt::create`].
+    /// The model is referenced by the session until it is destroyed with
+    /// [`ModelRegistry::destroy`]; the other model operations are not blocked.
+    pub fn create(
+        &self,
+        model_id: &str,
+        options: Option<SessionOptions>,
+        initial_prompt: Option<&str>,
+        state_file_path: Option<&str>,
+        progress_callback: Option<ProgressCallback>,
+    ) -> Result<u32, Error> {
+        let _reference = self.acquire(model_id);
+
+        let session_id = self.backend.create(
+            model_id,
+            options,
+            initial_prompt,
+            state_file_path,
+            progress_callback,
+        )?;
+
+        self.lock().add_session(session_id, model_id);
+        Ok(session_id)
+    }
+
+    /// Infer a GPT session, see [`crate::gpt::infer`]. A draft model is
+    /// referenced by the session from then on, as it keeps the draft context.
+    pub fn infer(
+        &self,
+        session_id: u32,
+        prompt: Option<&str>,
+        n_eval: u32,
+        options: Option<gpt::infer::Options>,
+        decode_progress_callback: Option<ProgressCallback>,
+        inference_callback: Option<InferenceCallback>,
+    ) -> Result<InferOutcome, Error> {
+        let draft_model_id = options
+            .as_ref()
+            .and_then(|o| o.speculative.as_ref())
+            .map(|s| s.draft_model_id.clone());
+
+        let Some(draft_model_id) = draft_model_id else {
+            return self.backend.infer(
+                session_id,
+                prompt,
+                n_eval,
+                options,
+                decode_progress_callback,
+                inference_callback,
+            );
+        };
+
+        let _reference = self.acquire(&draft_model_id);
+
+        let result = self.backend.infer(
+            session_id,
+            prompt,
+            n_eval,
+            options,
+            decode_progress_callback,
+            inference_callback,
+        );
+
+        // NOTE: The session may keep the draft context even on failure.
+        if !matches!(
+            result,
+            Err(Error::SessionNotFound) | Err(Error::ModelNotFound)
+        ) {
+            self.lock().set_draft(session_id, &draft_model_id);
+        }
+
+        result
+    }
+
+    /// Fork a GPT session, see [`crate::gpt::fork`].
+    /// The new session references the same model.
+    pub fn fork(&self, session_id: u32) -> Result<u32, Error> {
+        let model_id = self.lock().sessions.get(&session_id).cloned();
+
+        let Some(model_id) = model_id else {
+            // NOTE: Not created with the registry, yet still accounted
+            // for by the backend upon unload.
+            return self.backend.fork(session_id);
+        };
+
+        let _reference = self.acquire(&model_id);
+        let fork_id = self.backend.fork(session_id)?;
+
+        self.lock().add_session(fork_id, &model_id);
+        Ok(fork_id)
+    }
+
+    /// Destroy a GPT session, see [`crate::gpt::destroy`],
+    /// releasing its model (unloaded if pending unload).
+    pub fn destroy(&self, session_id: u32) -> Result<(), Error> {
+        let result = self.backend.destroy(session_id);
+
+        if matches!(result, Ok(()) | Err(Error::SessionNotFound)) {
+            let mut state = self.lock();
+            state.remove_session(session_id);
+            self.unload_pending(&mut state);
+        }
+
+        result
+    }
+
+    /// See [`crate::gpt::tokenize`].
+    pub fn tokenize(
+        &self,
+        model_id: &str,
+        text: &str,
+        add_special: bool,
+        parse_special: bool,
+    ) -> Result<Vec<i32>, Error> {
+        let _reference = self.acquire(model_id);
+        self.backend
+            .tokenize(model_id, text, add_special, parse_special)
+    }
+
+    /// See [`crate::gpt::detokenize`].
+    pub fn detokenize(&self, model_id: &str, tokens: &[i32]) -> Result<String, Error> {
+        let _reference = self.acquire(model_id);
+        self.backend.detokenize(model_id, tokens)
+    }
+
+    /// See [`crate::gpt::token_to_piece`].
+    pub fn token_to_piece(&self, model_id: &str, token: i32) -> Result<String, Error> {
+        let _reference = self.acquire(model_id);
+        self.backend.token_to_piece(model_id, token)
+    }
+
+    /// See [`crate::gpt::token_length`].
+    pub fn token_length(&self, model_id: &str, prompt: &str) -> Result<u32, Error> {
+        let _reference = self.acquire(model_id);
+        self.backend.token_length(model_id, prompt)
+    }
+
+    /// See [`crate::gpt::embed`].
+    pub fn embed(
+        &self,
+        model_id: &str,
+        texts: &[&str],
+        pooling: Pooling,
+        normalization: Normalization,
+    ) -> Result<Vec<Vec<f32>>, Error> {
+        let _reference = self.acquire(model_id);
+        self.backend.embed(model_id, texts, pooling, normalization)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // NOTE: The counts stay consistent even if a backend call panicked.
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn loading(&self) -> MutexGuard<'_, ()> {
+        self.loading.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Reference the model until the returned value is dropped.
+    fn acquire<'a>(&'a self, model_id: &'a str) -> ModelReference<'a, B> {
+        self.lock().acquire(model_id);
+
+        ModelReference {
+            registry: self,
+            model_id,
+        }
+    }
+
+    /// The number of references to the model, and the IDs of the sessions
+    /// using it, including as a draft model. The sessions removed by the backend
+    /// (e.g. expired ones) are released, and the ones created bypassing
+    /// the registry counted.
+    fn users(&self, state: &mut State, model_id: &str) -> Result<(usize, Vec<u32>), Error> {
+        let mut drafting: Vec<u32> = state
+            .drafts
+            .iter()
+            .filter(|(_, draft_model_id)| *draft_model_id == model_id)
+            .map(|(&id, _)| id)
+            .collect();
+
+        if !drafting.is_empty() {
+            let live: HashSet<u32> = self
+                .backend
+                .model_list()?
+                .into_iter()
+                .flat_map(|model| model.session_ids)
+                .collect();
+
+            for session_id in drafting.iter().filter(|id| !live.contains(id)) {
+                state.remove_session(*session_id);
+            }
+
+            drafting.retain(|id| live.contains(id));
+        }
+
+        let mut session_ids = self.backend.model_sessions(model_id)?;
+
+        let removed: Vec<u32> = state
+            .sessions
+            .iter()
+            .filter(|(id, model)| *model == model_id && !session_ids.contains(id))
+            .map(|(&id, _)| id)
+            .collect();
+
+        for session_id in removed {
+            state.remove_session(session_id);
+        }
+
+        let untracked = session_ids
+            .iter()
+            .filter(|id| !state.sessions.contains_key(id))
+            .count();
+
+        let references = state.references.get(model_id).copied().unwrap_or(0) as usize;
+
+        session_ids.extend(drafting);
+        session_ids.sort();
+        session_ids.dedup();
+        Ok((references + untracked, session_ids))
+    }
+}
+
+impl Default for ModelRegistry {
+    fn default() -> Self {
+        Self::new(DefaultBackend::default())
+    }
+}
+
+/// Releases a model referenced by a call in progress once dropped.
+struct ModelReference<'a, B: Backend> {
+    registry: &'a ModelRegistry<B>,
+    model_id: &'a str,
+}
+
+impl<B: Backend> Drop for ModelReference<'_, B> {
+    fn drop(&mut self) {
+        let mut state = self.registry.lock();
+        state.release(self.model_id);
+        self.registry.unload_pending(&mut state);
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::backend::MockBackend;
+
+    fn registry() -> ModelRegistry<MockBackend> {
+        let registry = ModelRegistry::new(MockBackend::new());
+        registry.load("", "model", None, None).unwrap();
+        registry
+    }
+
+    fn create(registry: &ModelRegistry<MockBackend>) -> Result<u32, Error> {
+        registry.create("model", None, None, None, None)
+    }
+
+    fn in_use_by(result: Result<(), Error>) -> Vec<u32> {
+        match result {
+            Err(Error::ModelInUse { session_ids }) => session_ids,
+            result => panic!("Expected ModelInUse, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn unload_refused_while_a_session_exists() {
+        let registry = registry();
+        let session_id = create(&registry).unwrap();
+        let fork_id = registry.fork(session_id).unwrap();
+        assert_eq!(registry.ref_count("model").unwrap(), 2);
+
+        assert_eq!(in_use_by(registry.unload("model")), [session_id, fork_id]);
+        registry.destroy(session_id).unwrap();
+        assert_eq!(in_use_by(registry.unload("model")), [fork_id]);
+        registry.destroy(fork_id).unwrap();
+
+        assert_eq!(registry.ref_count("model").unwrap(), 0);
+        registry.unload("model").unwrap();
+        assert!(matches!(create(&registry), Err(Error::ModelNotFound)));
+    }
+
+    #[test]
+    fn unload_refused_while_a_session_drafts() {
+        let registry = registry();
+        registry.load("", "draft", None, None).unwrap();
+        let session_id = create(&registry).unwrap();
+
+        let options = gpt::infer::Options {
+            speculative: Some(gpt::infer::Speculative {
+                draft_model_id: "draft".to_string(),
+                n_draft: None,
+            }),
+            ..Default::default()
+        };
+
+        registry
+            .infer(session_id, Some("Hi"), 4, Some(options), None, None)
+            .unwrap();
+        assert_eq!(in_use_by(registry.unload("draft")), [session_id]);
+
+        registry.destroy(session_id).unwrap();
+        registry.unload("draft").unwrap();
+    }
+
+    #[test]
+    fn unload_accounts_for_the_backend_sessions() {
+        let registry = registry();
+
+        // E.g. an expired session removed by the backend.
+        let session_id = create(&registry).unwrap();
+        registry.backend().destroy(session_id).unwrap();
+        assert_eq!(registry.ref_count("model").unwrap(), 0);
+
+        // A session created bypassing the registry.
+        let session_id = registry
+            .backend()
+            .create("model", None, None, None, None)
+            .unwrap();
+        assert_eq!(in_use_by(registry.unload("model")), [session_id]);
+
+        registry.backend().destroy(session_id).unwrap();
+        registry.unload("model").unwrap();
+    }
+
+    #[test]
+    fn unload_refused_while_creating() {
+        let registry = registry();
+        let mut unload_result = None;
+
+        let session_id = registry
+            .create(
+                "model",
+                None,
+                Some("Hi"),
+                None,
+                Some(&mut |_| {
+                    unload_result = Some(registry.unload("model"));
+                    true
+                }),
+            )
+            .unwrap();
+
+        assert!(in_use_by(unload_result.unwrap()).is_empty());
+        assert_eq!(in_use_by(registry.unload("model")), [session_id]);
+    }
+
+    #[test]
+    fn model_operations_while_loading() {
+        let registry = registry();
+        let session_id = create(&registry).unwrap();
+        let mut destroyed = None;
+
+        registry
+            .load(
+                "",
+                "other",
+                None,
+                Some(&mut |_| {
+                    destroyed = Some(registry.destroy(session_id));
+                    registry.tokenize("model", "Hi", false, false).is_ok()
+                }),
+            )
+            .unwrap();
+
+        destroyed.unwrap().unwrap();
+        registry.unload("model").unwrap();
+        registry.unload("other").unwrap();
+    }
+
+    #[test]
+    fn create_concurrent_with_unload() {
+        let registry = registry();
+        let unloaded = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        match create(&registry) {
+                            Ok(session_id) => {
+                                // The model is loaded as long as the session exists.
+                                assert!(!unloaded.load(Ordering::SeqCst));
+                                registry.backend().model_info("model").unwrap();
+                                registry.tokenize("model", "Hi", false, false).unwrap();
+                                registry.destroy(session_id).unwrap();
+                            }
+                            Err(Error::ModelNotFound) => break,
+                            Err(err) => panic!("Unexpected error {:?}", err),
+                        }
+                    }
+                });
+            }
+
+            scope.spawn(|| loop {
+                match registry.unload("model") {
+                    Ok(()) => {
+                        unloaded.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                    Err(Error::ModelInUse { .. }) => std::thread::yield_now(),
+                    Err(err) => panic!("Unexpected error {:?}", err),
+                }
+            });
+        });
+
+        assert!(registry
+            .backend()
+            .model_sessions("model")
+            .unwrap()
+            .is_empty());
+        assert_eq!(registry.ref_count("model").unwrap(), 0);
+        assert!(unloaded.load(Ordering::SeqCst));
+    }
+}
@@ -1,4 +1,11 @@
-use pyo3::{exceptions::PyValueError, prelude::*, types::PyTuple};
+use std::collections::HashMap;
+
+use pyo3::{
+    exceptions::PyValueError,
+    prelude::*,
+    types::{PyBytes, PyDict, PyList, PyTuple},
+};
+use simularity_core::chat_template::Message;
 
 #[pyclass]
 pub struct ModelInfo {
@@ -10,6 +17,187 @@ pub struct ModelInfo {
     pub n_ctx_train: i64,
 }
 
+#[pyclass]
+#[derive(Clone)]
+pub struct ModelLoadOptions {
+    pub use_mmap: bool,
+    pub use_mlock: bool,
+    pub vocab_only: bool,
+    pub check_tensors: bool,
+    pub n_threads: Option<u32>,
+}
+
+#[pymethods]
+impl ModelLoadOptions {
+    #[new]
+    #[pyo3(signature = (use_mmap=true, use_mlock=false, vocab_only=false, check_tensors=false, n_threads=None))]
+    fn new(
+        use_mmap: bool,
+        use_mlock: bool,
+        vocab_only: bool,
+        check_tensors: bool,
+        n_threads: Option<u32>,
+    ) -> Self {
+        ModelLoadOptions {
+            use_mmap,
+            use_mlock,
+            vocab_only,
+            check_tensors,
+            n_threads,
+        }
+    }
+}
+
+impl From<ModelLoadOptions> for simularity_core::ModelLoadOptions {
+    fn from(options: ModelLoadOptions) -> Self {
+        simularity_core::ModelLoadOptions {
+            use_mmap: options.use_mmap,
+            use_mlock: options.use_mlock,
+            vocab_only: options.vocab_only,
+            check_tensors: options.check_tensors,
+            n_threads: options.n_threads,
+        }
+    }
+}
+
+#[pyclass]
+#[derive(Clone)]
+pub struct SessionOptions {
+    pub ubatch_size: Option<u32>,
+    pub threads: Option<u32>,
+    pub batch_threads: Option<u32>,
+    pub type_k: Option<String>, // "f16", "q8_0", "q4_0".
+    pub type_v: Option<String>,
+    pub flash_attn: Option<bool>,
+    pub rope_scaling: Option<String>, // "none", "linear", "yarn".
+    pub rope_freq_base: Option<f32>,
+    pub rope_freq_scale: Option<f32>,
+    pub yarn_ext_factor: Option<f32>,
+    pub yarn_attn_factor: Option<f32>,
+    pub yarn_beta_fast: Option<f32>,
+    pub yarn_beta_slow: Option<f32>,
+    pub yarn_orig_ctx: Option<u32>,
+    pub max_sequences: Option<u32>,
+}
+
+#[pymethods]
+impl SessionOptions {
+    #[new]
+    #[pyo3(signature = (ubatch_size=None, threads=None, batch_threads=None, type_k=None, type_v=None, flash_attn=None, rope_scaling=None, rope_freq_base=None, rope_freq_scale=None, yarn_ext_factor=None, yarn_attn_factor=None, yarn_beta_fast=None, yarn_beta_slow=None, yarn_orig_ctx=None, max_sequences=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        ubatch_size: Option<u32>,
+        threads: Option<u32>,
+        batch_threads: Option<u32>,
+        type_k: Option<String>,
+        type_v: Option<String>,
+        flash_attn: Option<bool>,
+        rope_scaling: Option<String>,
+        rope_freq_base: Option<f32>,
+        rope_freq_scale: Option<f32>,
+        yarn_ext_factor: Option<f32>,
+        yarn_attn_factor: Option<f32>,
+        yarn_beta_fast: Option<f32>,
+        yarn_beta_slow: Option<f32>,
+        yarn_orig_ctx: Option<u32>,
+        max_sequences: Option<u32>,
+    ) -> PyResult<Self> {
+        for kv_cache_type in type_k.iter().chain(type_v.iter()) {
+            if kv_cache_type_from_str(kv_cache_type).is_none() {
+                return Err(PyErr::new::<PyValueError, _>(format!(
+                    "Unknown KV cache type: {}",
+                    kv_cache_type
+                )));
+            }
+        }
+
+        if let Some(rope_scaling) = &rope_scaling {
+            if rope_scaling_type_from_str(rope_scaling).is_none() {
+                return Err(PyErr::new::<PyValueError, _>(format!(
+                    "Unknown RoPE scaling type: {}",
+                    rope_scaling
+                )));
+            }
+        }
+
+        Ok(SessionOptions {
+            ubatch_size,
+            threads,
+            batch_threads,
+            type_k,
+            type_v,
+            flash_attn,
+            rope_scaling,
+            rope_freq_base,
+            rope_freq_scale,
+            yarn_ext_factor,
+            yarn_attn_factor,
+            yarn_beta_fast,
+            yarn_beta_slow,
+            yarn_orig_ctx,
+            max_sequences,
+        })
+    }
+}
+
+fn kv_cache_type_from_str(name: &str) -> Option<simularity_core::gpt::KvCacheType> {
+    use simularity_core::gpt::KvCacheType;
+
+    match name {
+        "f16" => Some(KvCacheType::F16),
+        "q8_0" => Some(KvCacheType::Q8_0),
+        "q4_0" => Some(KvCacheType::Q4_0),
+        _ => None,
+    }
+}
+
+fn rope_scaling_type_from_str(name: &str) -> Option<simularity_core::gpt::RopeScalingType> {
+    use simularity_core::gpt::RopeScalingType;
+
+    match name {
+        "none" => Some(RopeScalingType::None),
+        "linear" => Some(RopeScalingType::Linear),
+        "yarn" => Some(RopeScalingType::Yarn),
+        _ => None,
+    }
+}
+
+impl From<SessionOptions> for simularity_core::gpt::SessionOptions {
+    fn from(options: SessionOptions) -> Self {
+        let yarn = simularity_core::gpt::Yarn {
+            ext_factor: options.yarn_ext_factor,
+            attn_factor: options.yarn_attn_factor,
+            beta_fast: options.yarn_beta_fast,
+            beta_slow: options.yarn_beta_slow,
+            orig_ctx: options.yarn_orig_ctx,
+        };
+
+        let has_yarn = yarn.ext_factor.is_some()
+            || yarn.attn_factor.is_some()
+            || yarn.beta_fast.is_some()
+            || yarn.beta_slow.is_some()
+            || yarn.orig_ctx.is_some();
+
+        simularity_core::gpt::SessionOptions {
+            ubatch_size: options.ubatch_size,
+            threads: options.threads,
+            batch_threads: options.batch_threads,
+            type_k: options.type_k.as_deref().and_then(kv_cache_type_from_str),
+            type_v: options.type_v.as_deref().and_then(kv_cache_type_from_str),
+            flash_attn: options.flash_attn,
+            rope_scaling: options
+                .rope_scaling
+                .as_deref()
+                .and_then(rope_scaling_type_from_str),
+            rope_freq_base: options.rope_freq_base,
+            rope_freq_scale: options.rope_freq_scale,
+            yarn: has_yarn.then_some(yarn),
+            max_sequences: options.max_sequences,
+            ..Default::default()
+        }
+    }
+}
+
 #[pyclass]
 #[derive(Clone)]
 pub struct Dynatemp {
@@ -74,6 +262,82 @@ impl Mirostat {
     }
 }
 
+#[pyclass]
+#[derive(Clone)]
+pub struct Speculative {
+    pub draft_model_id: String,
+    pub n_draft: Option<u32>,
+}
+
+#[pymethods]
+impl Speculative {
+    #[new]
+    #[pyo3(signature = (draft_model_id, n_draft=None))]
+    fn new(draft_model_id: String, n_draft: Option<u32>) -> Self {
+        Speculative {
+            draft_model_id,
+            n_draft,
+        }
+    }
+}
+
+#[pyclass]
+#[derive(Clone)]
+pub struct LuaLimits {
+    pub max_instructions: Option<u32>,
+    pub timeout_ms: Option<u32>,
+    pub max_memory: Option<usize>,
+    pub libraries: Option<Vec<String>>, // "base", "string", "table", "math", "cjson".
+}
+
+#[pymethods]
+impl LuaLimits {
+    #[new]
+    #[pyo3(signature = (max_instructions=None, timeout_ms=None, max_memory=None, libraries=None))]
+    fn new(
+        max_instructions: Option<u32>,
+        timeout_ms: Option<u32>,
+        max_memory: Option<usize>,
+        libraries: Option<Vec<String>>,
+    ) -> PyResult<Self> {
+        for library in libraries.iter().flatten() {
+            if lua_library(library).is_none() {
+                return Err(PyErr::new::<PyValueError, _>(format!(
+                    "Unknown Lua library: {}",
+                    library
+                )));
+            }
+        }
+
+        Ok(LuaLimits {
+            max_instructions,
+            timeout_ms,
+            max_memory,
+            libraries,
+        })
+    }
+}
+
+fn lua_library(name: &str) -> Option<simularity_core::gpt::infer::LuaLibrary> {
+    use simularity_core::gpt::infer::LuaLibrary;
+
+    match name {
+        "base" => Some(LuaLibrary::Base),
+        "string" => Some(LuaLibrary::String),
+        "table" => Some(LuaLibrary::Table),
+        "math" => Some(LuaLibrary::Math),
+        "cjson" => Some(LuaLibrary::Cjson),
+        _ => None,
+    }
+}
+
+/// A logit bias key: either a token ID, or a text biasing each of its tokens.
+#[derive(FromPyObject, Clone, PartialEq, Eq, Hash)]
+pub enum LogitBiasKey {
+    Token(i32),
+    Text(String),
+}
+
 #[pyclass]
 #[derive(Clone)]
 pub struct InferenceOptions {
@@ -93,13 +357,17 @@ pub struct InferenceOptions {
     pub grammar: Option<String>,
     pub stop_sequences: Option<Vec<String>>,
     pub lua_grammar: Option<String>,
+    pub lua_limits: Option<LuaLimits>,
+    pub logit_bias: Option<HashMap<LogitBiasKey, f32>>,
+    pub banned_strings: Option<Vec<String>>,
+    pub speculative: Option<Speculative>,
 }
 
 #[pymethods]
 impl InferenceOptions {
     #[new]
     #[allow(clippy::too_many_arguments)]
-    #[pyo3(signature = (n_prev=None, n_probs=None, min_keep=None, top_k=None, top_p=None, min_p=None, tfs_z=None, typical_p=None, temp=None, dynatemp=None, penalty=None, mirostat=None, seed=None, grammar=None, stop_sequences=None, lua_grammar=None))]
+    #[pyo3(signature = (n_prev=None, n_probs=None, min_keep=None, top_k=None, top_p=None, min_p=None, tfs_z=None, typical_p=None, temp=None, dynatemp=None, penalty=None, mirostat=None, seed=None, grammar=None, stop_sequences=None, lua_grammar=None, lua_limits=None, logit_bias=None, banned_strings=None, speculative=None))]
     fn new(
         n_prev: Option<i32>,
         n_probs: Option<i32>,
@@ -117,6 +385,10 @@ impl InferenceOptions {
         grammar: Option<String>,
         stop_sequences: Option<Vec<String>>,
         lua_grammar: Option<String>,
+        lua_limits: Option<&LuaLimits>,
+        logit_bias: Option<HashMap<LogitBiasKey, f32>>,
+        banned_strings: Option<Vec<String>>,
+        speculative: Option<&Speculative>,
     ) -> Self {
         InferenceOptions {
             n_prev,
@@ -135,6 +407,137 @@ impl InferenceOptions {
             grammar,
             stop_sequences,
             lua_grammar,
+            lua_limits: lua_limits.cloned(),
+            logit_bias,
+            banned_strings,
+            speculative: speculative.cloned(),
+        }
+    }
+}
+
+#[pyclass]
+#[derive(Clone)]
+pub struct TokenLogprob {
+    #[pyo3(get)]
+    pub token: i32,
+    #[pyo3(get)]
+    pub piece: String,
+    #[pyo3(get)]
+    pub logprob: f32,
+}
+
+#[pyclass]
+#[derive(Clone)]
+pub struct InferenceToken {
+    #[pyo3(get)]
+    pub token: i32,
+    #[pyo3(get)]
+    pub piece: String,
+    #[pyo3(get)]
+    pub logprob: f32,
+    #[pyo3(get)]
+    pub top_logprobs: Vec<TokenLogprob>,
+}
+
+impl From<&simularity_core::gpt::infer::InferenceToken> for InferenceToken {
+    fn from(token: &simularity_core::gpt::infer::InferenceToken) -> Self {
+        InferenceToken {
+            token: token.token,
+            piece: token.piece.clone(),
+            logprob: token.logprob,
+            top_logprobs: token
+                .top_logprobs
+                .iter()
+                .map(|candidate| TokenLogprob {
+                    token: candidate.token,
+                    piece: candidate.piece.clone(),
+                    logprob: candidate.logprob,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&InferenceOptions> for simularity_core::gpt::infer::Options {
+    fn from(options: &InferenceOptions) -> Self {
+        Self {
+            n_prev: options.n_prev,
+            n_probs: options.n_probs,
+            min_keep: options.min_keep,
+            top_k: options.top_k,
+            top_p: options.top_p,
+            min_p: options.min_p,
+            tfs_z: options.tfs_z,
+            typical_p: options.typical_p,
+            temp: options.temp,
+            dynatemp: options
+                .dynatemp
+                .as_ref()
+                .map(|d| simularity_core::gpt::infer::Dynatemp {
+                    range: d.range,
+                    exponent: d.exponent,
+                }),
+            penalty: options
+                .penalty
+                .as_ref()
+                .map(|p| simularity_core::gpt::infer::Penalty {
+                    last_n: p.last_n,
+                    repeat: p.repeat,
+                    freq: p.freq,
+                    present: p.present,
+                    penalize_nl: p.penalize_nl,
+                }),
+            mirostat: options
+                .mirostat
+                .as_ref()
+                .map(|m| simularity_core::gpt::infer::Mirostat {
+                    version: match m.version.as_str() {
+                        "v1" => simularity_core::gpt::infer::MirostatVersion::V1,
+                        "v2" => simularity_core::gpt::infer::MirostatVersion::V2,
+                        _ => panic!("Invalid Mirostat version"),
+                    },
+                    tau: m.tau,
+                    eta: m.eta,
+                }),
+            seed: options.seed,
+            grammar: options.grammar.clone(),
+            stop_sequences: options.stop_sequences.clone(),
+            lua_grammar: options.lua_grammar.clone(),
+            lua_limits: options.lua_limits.as_ref().map(|l| {
+                simularity_core::gpt::infer::LuaLimits {
+                    max_instructions: l.max_instructions,
+                    timeout_ms: l.timeout_ms,
+                    max_memory: l.max_memory,
+                    libraries: l
+                        .libraries
+                        .as_ref()
+                        .map(|names| names.iter().filter_map(|name| lua_library(name)).collect()),
+                }
+            }),
+            logit_bias: options.logit_bias.as_ref().map(|logit_bias| {
+                logit_bias
+                    .iter()
+                    .map(|(key, &bias)| {
+                        let key = match key {
+                            LogitBiasKey::Token(token) => {
+                                simularity_core::gpt::infer::LogitBiasKey::Token(*token)
+                            }
+                            LogitBiasKey::Text(text) => {
+                                simularity_core::gpt::infer::LogitBiasKey::Text(text.clone())
+                            }
+                        };
+
+                        (key, bias)
+                    })
+                    .collect()
+            }),
+            banned_strings: options.banned_strings.clone(),
+            speculative: options.speculative.as_ref().map(|s| {
+                simularity_core::gpt::infer::Speculative {
+                    draft_model_id: s.draft_model_id.clone(),
+                    n_draft: s.n_draft,
+                }
+            }),
         }
     }
 }
@@ -145,20 +548,175 @@ pub struct InferenceResult {
     pub result: String,
     #[pyo3(get)]
     pub context_length: u32,
+    #[pyo3(get)]
+    pub tokens: Vec<InferenceToken>,
+    /// The number of tokens evicted by the context shift policy.
+    #[pyo3(get)]
+    pub evicted_tokens: u32,
+    /// One of `n_eval`, `eos`, `stop_sequence`, `callback` or `lua`.
+    #[pyo3(get)]
+    pub stop_reason: String,
+    /// The matched stop sequence (trimmed from the result), if any.
+    #[pyo3(get)]
+    pub stop_sequence: Option<String>,
+    /// The decoded prompt length in tokens.
+    #[pyo3(get)]
+    pub prompt_tokens: u32,
+    /// The number of inferred tokens, excluding a matched stop sequence.
+    #[pyo3(get)]
+    pub generated_tokens: u32,
+    #[pyo3(get)]
+    pub decode_duration_ms: f64,
+    #[pyo3(get)]
+    pub generation_duration_ms: f64,
+    #[pyo3(get)]
+    pub tokens_per_second: f64,
+    /// The number of tokens proposed by the draft model, if any.
+    #[pyo3(get)]
+    pub drafted_tokens: u32,
+    /// The number of drafted tokens accepted.
+    #[pyo3(get)]
+    pub accepted_tokens: u32,
+    /// The share of drafted tokens accepted, `None` without drafting.
+    #[pyo3(get)]
+    pub acceptance_rate: Option<f64>,
+}
+
+/// Convert a stop reason to its name and the matched stop sequence.
+fn stop_reason(stop_reason: simularity_core::gpt::infer::StopReason) -> (String, Option<String>) {
+    use simularity_core::gpt::infer::StopReason;
+
+    let (name, stop_sequence) = match stop_reason {
+        StopReason::NEval => ("n_eval", None),
+        StopReason::Eos => ("eos", None),
+        StopReason::StopSequence { sequence } => ("stop_sequence", Some(sequence)),
+        StopReason::Callback => ("callback", None),
+        StopReason::Lua => ("lua", None),
+    };
+
+    (name.to_string(), stop_sequence)
+}
+
+#[pyclass]
+pub struct InferredSequence {
+    #[pyo3(get)]
+    pub text: String,
+    #[pyo3(get)]
+    pub tokens: Vec<i32>,
+    /// One of `n_eval`, `eos`, `stop_sequence` or `callback`.
+    #[pyo3(get)]
+    pub stop_reason: String,
+    /// The matched stop sequence, if any.
+    #[pyo3(get)]
+    pub stop_sequence: Option<String>,
+}
+
+impl From<simularity_core::gpt::infer_many::InferredSequence> for InferredSequence {
+    fn from(sequence: simularity_core::gpt::infer_many::InferredSequence) -> Self {
+        let (stop_reason, stop_sequence) = stop_reason(sequence.stop_reason);
+
+        InferredSequence {
+            text: sequence.text,
+            tokens: sequence.tokens,
+            stop_reason,
+            stop_sequence,
+        }
+    }
+}
+
+/// A GGUF metadata value, converted to a Python object.
+struct GgufValue<'a>(&'a simularity_core::gguf::Value);
+
+impl<'py> IntoPyObject<'py> for GgufValue<'_> {
+    type Target = PyAny;
+    type Output = Bound<'py, PyAny>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> Result<Self::Output, Self::Error> {
+        use simularity_core::gguf::Value;
+
+        Ok(match self.0 {
+            Value::U8(v) => (*v).into_pyobject(py)?.into_any(),
+            Value::I8(v) => (*v).into_pyobject(py)?.into_any(),
+            Value::U16(v) => (*v).into_pyobject(py)?.into_any(),
+            Value::I16(v) => (*v).into_pyobject(py)?.into_any(),
+            Value::U32(v) => (*v).into_pyobject(py)?.into_any(),
+            Value::I32(v) => (*v).into_pyobject(py)?.into_any(),
+            Value::U64(v) => (*v).into_pyobject(py)?.into_any(),
+            Value::I64(v) => (*v).into_pyobject(py)?.into_any(),
+            Value::F32(v) => (*v).into_pyobject(py)?.into_any(),
+            Value::F64(v) => (*v).into_pyobject(py)?.into_any(),
+            Value::Bool(v) => (*v).into_pyobject(py)?.to_owned().into_any(),
+            Value::String(v) => v.into_pyobject(py)?.into_any(),
+            Value::Array(v) => PyList::new(py, v.iter().map(GgufValue))?.into_any(),
+        })
+    }
 }
 
 /// Initialize the core server.
+///
+/// # Arguments
+///
+/// * `gpt_sessions_cache_dir` - A directory to resolve relative
+///   `state_file_path`s against.
+/// * `log_level` - The minimum native log level: "trace", "debug" (default),
+///   "info", "warn", "error" or "off".
+/// * `n_threads` - The number of threads for the models loaded without one.
 #[pyfunction]
-#[pyo3(signature = (gpt_sessions_ttl=None, gpt_sessions_max=None))]
-fn init(gpt_sessions_ttl: Option<u32>, gpt_sessions_max: Option<u32>) -> PyResult<()> {
-    simularity_core::init(gpt_sessions_ttl, gpt_sessions_max);
-    Ok(())
+#[pyo3(signature = (gpt_sessions_ttl=None, gpt_sessions_max=None, gpt_sessions_cache_dir=None, log_level=None, n_threads=None))]
+fn init(
+    gpt_sessions_ttl: Option<u32>,
+    gpt_sessions_max: Option<u32>,
+    gpt_sessions_cache_dir: Option<String>,
+    log_level: Option<&str>,
+    n_threads: Option<u32>,
+) -> PyResult<()> {
+    let mut options = simularity_core::InitOptions::new();
+
+    if let Some(ttl) = gpt_sessions_ttl {
+        options = options.gpt_sessions_ttl(ttl);
+    }
+
+    if let Some(max) = gpt_sessions_max {
+        options = options.gpt_sessions_max(max);
+    }
+
+    if let Some(cache_dir) = gpt_sessions_cache_dir {
+        options = options.gpt_sessions_cache_dir(cache_dir);
+    }
+
+    if let Some(log_level) = log_level {
+        let level = log_level.parse::<log::LevelFilter>().map_err(|_| {
+            PyErr::new::<PyValueError, _>(format!("Unknown log level: {}", log_level))
+        })?;
+
+        options = options.log_level(level);
+    }
+
+    if let Some(n_threads) = n_threads {
+        options = options.n_threads(n_threads);
+    }
+
+    options
+        .init()
+        .map_err(|err| PyErr::new::<PyValueError, _>(err.to_string()))
 }
 
 /// Load a model from a file.
+/// The options are ignored if the model is already loaded.
 #[pyfunction]
-fn model_load(model_path: &str, model_id: &str) -> PyResult<ModelInfo> {
-    let result = simularity_core::model_load(model_path, model_id, None::<fn(_) -> bool>);
+#[pyo3(signature = (model_path, model_id, options=None))]
+fn model_load(
+    model_path: &str,
+    model_id: &str,
+    options: Option<ModelLoadOptions>,
+) -> PyResult<ModelInfo> {
+    let result = simularity_core::model_load(
+        model_path,
+        model_id,
+        options.map(Into::into),
+        None::<fn(_) -> bool>,
+    );
 
     if let Ok(r) = result {
         Ok(ModelInfo {
@@ -167,7 +725,106 @@ fn model_load(model_path: &str, model_id: &str) -> PyResult<ModelInfo> {
             n_ctx_train: r.n_ctx_train,
         })
     } else {
-        Err(PyErr::new::<PyValueError, _>(format!("{:?}", result.err())))
+        Err(PyErr::new::<PyValueError, _>(result.unwrap_err().to_string()))
+    }
+}
+
+/// Load a LoRA adapter from a file, to be applied with `gpt_set_loras`.
+#[pyfunction]
+fn lora_load(lora_path: &str, lora_id: &str) -> PyResult<()> {
+    simularity_core::lora_load(lora_path, lora_id)
+        .map_err(|err| PyErr::new::<PyValueError, _>(err.to_string()))
+}
+
+/// Unload a LoRA adapter. The sessions using it keep it until changed.
+#[pyfunction]
+fn lora_unload(lora_id: &str) -> PyResult<()> {
+    simularity_core::lora_unload(lora_id)
+        .map_err(|err| PyErr::new::<PyValueError, _>(err.to_string()))
+}
+
+/// Inspect a GGUF model file metadata without loading the model.
+/// Returns a dict, omitting long arrays (e.g. the vocabulary) from `metadata`.
+#[pyfunction]
+fn gguf_inspect(py: Python, model_path: &str) -> PyResult<Py<PyDict>> {
+    let result = simularity_core::gguf::inspect(model_path);
+
+    let summary = match result {
+        Ok(gguf) => gguf.summary(),
+        Err(err) => return Err(PyErr::new::<PyValueError, _>(err.to_string())),
+    };
+
+    let special_tokens = PyDict::new(py);
+    for (name, token) in [
+        ("bos", &summary.special_tokens.bos),
+        ("eos", &summary.special_tokens.eos),
+        ("eot", &summary.special_tokens.eot),
+        ("unk", &summary.special_tokens.unk),
+        ("sep", &summary.special_tokens.sep),
+        ("pad", &summary.special_tokens.pad),
+    ] {
+        let token = token.as_ref().map(|token| (token.id, token.text.clone()));
+        special_tokens.set_item(name, token)?;
+    }
+
+    let metadata = PyDict::new(py);
+    for (key, value) in &summary.metadata {
+        metadata.set_item(key, GgufValue(value))?;
+    }
+
+    let dict = PyDict::new(py);
+    dict.set_item("version", summary.version)?;
+    dict.set_item("tensor_count", summary.tensor_count)?;
+    dict.set_item("n_params", summary.n_params)?;
+    dict.set_item("architecture", summary.architecture)?;
+    dict.set_item("name", summary.name)?;
+    dict.set_item("context_length", summary.context_length)?;
+    dict.set_item("embedding_length", summary.embedding_length)?;
+    dict.set_item("block_count", summary.block_count)?;
+    dict.set_item("file_type", summary.file_type)?;
+    dict.set_item("file_type_name", summary.file_type_name)?;
+    dict.set_item("tokenizer_model", summary.tokenizer_model)?;
+    dict.set_item("vocab_size", summary.vocab_size)?;
+    dict.set_item("special_tokens", special_tokens)?;
+    dict.set_item("chat_template", summary.chat_template)?;
+    dict.set_item("metadata", metadata)?;
+
+    Ok(dict.unbind())
+}
+
+/// Render chat messages (dicts with `role` and `content`) using the model's
+/// chat template, falling back to a built-in template (e.g. `chatml`).
+/// Returns a `(prompt, stop_sequences)` tuple.
+#[pyfunction]
+#[pyo3(signature = (messages, model_path=None, fallback=None, add_generation_prompt=true))]
+fn chat_template_render(
+    messages: Vec<HashMap<String, String>>,
+    model_path: Option<&str>,
+    fallback: Option<&str>,
+    add_generation_prompt: bool,
+) -> PyResult<(String, Vec<String>)> {
+    let messages = messages
+        .into_iter()
+        .map(
+            |mut message| match (message.remove("role"), message.remove("content")) {
+                (Some(role), Some(content)) => Ok(Message { role, content }),
+                _ => Err(PyErr::new::<PyValueError, _>(
+                    "A message must have `role` and `content`",
+                )),
+            },
+        )
+        .collect::<PyResult<Vec<_>>>()?;
+
+    let result = simularity_core::chat_template::render(
+        model_path,
+        fallback,
+        &messages,
+        add_generation_prompt,
+    );
+
+    match result {
+        Ok(rendered) => Ok((rendered.prompt, rendered.stop_sequences)),
+        Err(err) => Err(PyErr::new::<PyValueError, _>(err.to_string())),
     }
 }
 
@@ -175,9 +832,14 @@ fn model_load(model_path: &str, model_id: &str) -> PyResult<ModelInfo> {
 ///
 /// # Arguments
 ///
+/// * `context_shift_keep` - If set, enables the context shift policy,
+///   always keeping this number of first tokens upon an overflow.
+/// * `context_shift_discard` - The number of tokens to evict at once.
+/// * `options` - Other context parameters, see `SessionOptions`.
 /// * `progress_callback` - Python function that will be called with the progress (float), expects a bool return value.
 #[pyfunction]
-#[pyo3(signature = (model_id, context_size=None, batch_size=None, initial_prompt=None, state_file_path=None, progress_callback=None))]
+#[pyo3(signature = (model_id, context_size=None, batch_size=None, initial_prompt=None, state_file_path=None, context_shift_keep=None, context_shift_discard=None, options=None, progress_callback=None))]
+#[allow(clippy::too_many_arguments)]
 fn gpt_create(
     py: Python,
     model_id: &str,
@@ -185,12 +847,24 @@ fn gpt_create(
     batch_size: Option<u32>,
     initial_prompt: Option<&str>,
     state_file_path: Option<&str>,
+    context_shift_keep: Option<u32>,
+    context_shift_discard: Option<u32>,
+    options: Option<SessionOptions>,
     progress_callback: Option<PyObject>,
 ) -> PyResult<u32> {
-    let result = simularity_core::gpt::create(
-        model_id,
+    let options = simularity_core::gpt::SessionOptions {
         context_size,
         batch_size,
+        context_shift: context_shift_keep.map(|n_keep| simularity_core::gpt::ContextShift {
+            n_keep,
+            n_discard: context_shift_discard,
+        }),
+        ..options.map(Into::into).unwrap_or_default()
+    };
+
+    let result = simularity_core::gpt::create(
+        model_id,
+        Some(options),
         initial_prompt,
         state_file_path,
         progress_callback.map(|cb| {
@@ -206,7 +880,7 @@ fn gpt_create(
     if let Ok(r) = result {
         Ok(r)
     } else {
-        Err(PyErr::new::<PyValueError, _>(format!("{:?}", result.err())))
+        Err(PyErr::new::<PyValueError, _>(result.unwrap_err().to_string()))
     }
 }
 
@@ -217,6 +891,57 @@ fn gpt_touch(session_id: u32) -> PyResult<bool> {
     Ok(simularity_core::gpt::touch(session_id))
 }
 
+/// Fork a session, copying its KV cache and committed prompt
+/// into a new session. Returns the new session ID.
+#[pyfunction]
+fn gpt_fork(session_id: u32) -> PyResult<u32> {
+    simularity_core::gpt::fork(session_id)
+        .map_err(|err| PyErr::new::<PyValueError, _>(err.to_string()))
+}
+
+/// Set the session LoRA adapters as `(lora_id, scale)` pairs, replacing
+/// the current ones. Returns the number of tokens cleared from the KV cache.
+#[pyfunction]
+fn gpt_set_loras(session_id: u32, loras: Vec<(String, f32)>) -> PyResult<u32> {
+    let loras: Vec<(&str, f32)> = loras
+        .iter()
+        .map(|(lora_id, scale)| (lora_id.as_str(), *scale))
+        .collect();
+
+    simularity_core::gpt::set_loras(session_id, &loras)
+        .map_err(|err| PyErr::new::<PyValueError, _>(err.to_string()))
+}
+
+/// Save the session state (KV cache and committed prompt) to a file.
+#[pyfunction]
+fn gpt_save_state(session_id: u32, path: &str) -> PyResult<()> {
+    simularity_core::gpt::save_state(session_id, path)
+        .map_err(|err| PyErr::new::<PyValueError, _>(err.to_string()))
+}
+
+/// Load the session state from a file, returning the new context length.
+#[pyfunction]
+fn gpt_load_state(session_id: u32, path: &str) -> PyResult<u32> {
+    simularity_core::gpt::load_state(session_id, path)
+        .map_err(|err| PyErr::new::<PyValueError, _>(err.to_string()))
+}
+
+/// Save the session state into bytes.
+#[pyfunction]
+fn gpt_save_state_bytes(py: Python, session_id: u32) -> PyResult<Py<PyBytes>> {
+    match simularity_core::gpt::save_state_bytes(session_id) {
+        Ok(state) => Ok(PyBytes::new(py, &state).unbind()),
+        Err(err) => Err(PyErr::new::<PyValueError, _>(err.to_string())),
+    }
+}
+
+/// Load the session state from bytes, returning the new context length.
+#[pyfunction]
+fn gpt_load_state_bytes(session_id: u32, state: &[u8]) -> PyResult<u32> {
+    simularity_core::gpt::load_state_bytes(session_id, state)
+        .map_err(|err| PyErr::new::<PyValueError, _>(err.to_string()))
+}
+
 /// Get the length of the prompt in tokens.
 #[pyfunction]
 fn gpt_token_length(model_id: &str, prompt: &str) -> PyResult<u32> {
@@ -225,10 +950,98 @@ fn gpt_token_length(model_id: &str, prompt: &str) -> PyResult<u32> {
     if let Ok(r) = result {
         Ok(r)
     } else {
-        Err(PyErr::new::<PyValueError, _>(format!("{:?}", result.err())))
+        Err(PyErr::new::<PyValueError, _>(result.unwrap_err().to_string()))
+    }
+}
+
+/// Tokenize the text, returning the token IDs.
+#[pyfunction]
+#[pyo3(signature = (model_id, text, add_special=false, parse_special=false))]
+fn gpt_tokenize(
+    model_id: &str,
+    text: &str,
+    add_special: bool,
+    parse_special: bool,
+) -> PyResult<Vec<i32>> {
+    let result = simularity_core::gpt::tokenize(model_id, text, add_special, parse_special);
+
+    if let Ok(r) = result {
+        Ok(r)
+    } else {
+        Err(PyErr::new::<PyValueError, _>(result.unwrap_err().to_string()))
     }
 }
 
+/// Convert tokens back to text.
+#[pyfunction]
+fn gpt_detokenize(model_id: &str, tokens: Vec<i32>) -> PyResult<String> {
+    let result = simularity_core::gpt::detokenize(model_id, &tokens);
+
+    if let Ok(r) = result {
+        Ok(r)
+    } else {
+        Err(PyErr::new::<PyValueError, _>(result.unwrap_err().to_string()))
+    }
+}
+
+/// Convert a single token to its text piece.
+#[pyfunction]
+fn gpt_token_to_piece(model_id: &str, token: i32) -> PyResult<String> {
+    let result = simularity_core::gpt::token_to_piece(model_id, token);
+
+    if let Ok(r) = result {
+        Ok(r)
+    } else {
+        Err(PyErr::new::<PyValueError, _>(result.unwrap_err().to_string()))
+    }
+}
+
+/// Compute the text embeddings using a model.
+///
+/// # Arguments
+///
+/// * `pooling` - One of `default`, `mean`, `cls` or `last`.
+/// * `normalization` - One of `none`, `taxicab` or `euclidean`.
+#[pyfunction]
+#[pyo3(signature = (model_id, texts, pooling="default", normalization="euclidean"))]
+fn gpt_embed(
+    model_id: &str,
+    texts: Vec<String>,
+    pooling: &str,
+    normalization: &str,
+) -> PyResult<Vec<Vec<f32>>> {
+    use simularity_core::gpt::embed::{Normalization, Pooling};
+
+    let pooling = match pooling {
+        "default" => Pooling::Default,
+        "mean" => Pooling::Mean,
+        "cls" => Pooling::Cls,
+        "last" => Pooling::Last,
+        _ => {
+            return Err(PyErr::new::<PyValueError, _>(format!(
+                "Invalid pooling: {}",
+                pooling
+            )))
+        }
+    };
+
+    let normalization = match normalization {
+        "none" => Normalization::None,
+        "taxicab" => Normalization::Taxicab,
+        "euclidean" => Normalization::Euclidean,
+        _ => {
+            return Err(PyErr::new::<PyValueError, _>(format!(
+                "Invalid normalization: {}",
+                normalization
+            )))
+        }
+    };
+
+    let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+    simularity_core::gpt::embed(model_id, &texts, pooling, normalization)
+        .map_err(|err| PyErr::new::<PyValueError, _>(err.to_string()))
+}
+
 /// # Arguments
 ///
 /// * `inference_callback` - Python function that will be called with the inference result (str), expects a bool return value.
@@ -242,13 +1055,13 @@ fn gpt_infer(
     options: Option<&InferenceOptions>,
     inference_callback: Option<PyObject>,
 ) -> PyResult<InferenceResult> {
-    let mut resulting_string = String::new();
+    let mut tokens = Vec::new();
 
-    let inference_callback = |s: &str| {
-        resulting_string.push_str(s);
+    let inference_callback = |token: &simularity_core::gpt::infer::InferenceToken| {
+        tokens.push(InferenceToken::from(token));
 
         if let Some(cb) = &inference_callback {
-            cb.call1(py, PyTuple::new(py, vec![s]).unwrap())
+            cb.call1(py, PyTuple::new(py, vec![&token.piece]).unwrap())
                 .unwrap()
                 .is_truthy(py)
                 .unwrap()
@@ -257,64 +1070,79 @@ fn gpt_infer(
         }
     };
 
-    let result =
-        simularity_core::gpt::infer(
-            session_id,
-            Some(prompt),
-            n_eval,
-            options.map(|options| simularity_core::gpt::infer::Options {
-                n_prev: options.n_prev,
-                n_probs: options.n_probs,
-                min_keep: options.min_keep,
-                top_k: options.top_k,
-                top_p: options.top_p,
-                min_p: options.min_p,
-                tfs_z: options.tfs_z,
-                typical_p: options.typical_p,
-                temp: options.temp,
-                dynatemp: options.dynatemp.as_ref().map(|d| {
-                    simularity_core::gpt::infer::Dynatemp {
-                        range: d.range,
-                        exponent: d.exponent,
-                    }
-                }),
-                penalty: options
-                    .penalty
-                    .as_ref()
-                    .map(|p| simularity_core::gpt::infer::Penalty {
-                        last_n: p.last_n,
-                        repeat: p.repeat,
-                        freq: p.freq,
-                        present: p.present,
-                        penalize_nl: p.penalize_nl,
-                    }),
-                mirostat: options.mirostat.as_ref().map(|m| {
-                    simularity_core::gpt::infer::Mirostat {
-                        version: match m.version.as_str() {
-                            "v1" => simularity_core::gpt::infer::MirostatVersion::V1,
-                            "v2" => simularity_core::gpt::infer::MirostatVersion::V2,
-                            _ => panic!("Invalid Mirostat version"),
-                        },
-                        tau: m.tau,
-                        eta: m.eta,
-                    }
-                }),
-                seed: options.seed,
-                grammar: options.grammar.clone(),
-                stop_sequences: options.stop_sequences.clone(),
-                lua_grammar: options.lua_grammar.clone(),
-            }),
-            None::<fn(_) -> bool>,
-            Some(inference_callback),
-        );
+    let result = simularity_core::gpt::infer(
+        session_id,
+        Some(prompt),
+        n_eval,
+        options.map(simularity_core::gpt::infer::Options::from),
+        None::<fn(_) -> bool>,
+        Some(inference_callback),
+    );
+
+    if let Ok(outcome) = result {
+        let tokens_per_second = outcome.tokens_per_second();
+        let acceptance_rate = outcome.acceptance_rate();
+        let (stop_reason, stop_sequence) = stop_reason(outcome.stop_reason);
 
-    if let Ok(context_length) = result {
         Ok(InferenceResult {
-            result: resulting_string,
-            context_length,
+            result: outcome.output,
+            context_length: outcome.context_length,
+            tokens,
+            evicted_tokens: outcome.evicted_tokens,
+            stop_reason,
+            stop_sequence,
+            prompt_tokens: outcome.prompt_tokens,
+            generated_tokens: outcome.generated_tokens,
+            decode_duration_ms: outcome.decode_duration.as_secs_f64() * 1000.0,
+            generation_duration_ms: outcome.generation_duration.as_secs_f64() * 1000.0,
+            tokens_per_second,
+            drafted_tokens: outcome.drafted_tokens,
+            accepted_tokens: outcome.accepted_tokens,
+            acceptance_rate,
         })
     } else {
-        Err(PyErr::new::<PyValueError, _>(format!("{:?}", result.err())))
+        Err(PyErr::new::<PyValueError, _>(result.unwrap_err().to_string()))
+    }
+}
+
+/// Infer `n` independent sequences from the same prompt at once.
+/// The inferred tokens are not committed to the session.
+///
+/// # Arguments
+///
+/// * `inference_callback` - Python function that will be called with the sequence index
+///   and the inferred piece (str), expects a bool return value.
+#[pyfunction]
+#[pyo3(signature = (session_id, n_eval, n, prompt=None, options=None, inference_callback=None))]
+fn gpt_infer_many(
+    py: Python,
+    session_id: u32,
+    n_eval: u32,
+    n: u32,
+    prompt: Option<&str>,
+    options: Option<&InferenceOptions>,
+    inference_callback: Option<PyObject>,
+) -> PyResult<Vec<InferredSequence>> {
+    let result = simularity_core::gpt::infer_many(
+        session_id,
+        prompt,
+        n_eval,
+        n,
+        options.map(simularity_core::gpt::infer::Options::from),
+        None::<fn(_) -> bool>,
+        inference_callback.map(|cb| {
+            move |seq: u32, token: &simularity_core::gpt::infer::InferenceToken| {
+                cb.call1(py, (seq, &token.piece))
+                    .unwrap()
+                    .is_truthy(py)
+                    .unwrap()
+            }
+        }),
+    );
+
+    match result {
+        Ok(sequences) => Ok(sequences.into_iter().map(InferredSequence::from).collect()),
+        Err(err) => Err(PyErr::new::<PyValueError, _>(err.to_string())),
     }
 }
 
@@ -324,14 +1152,37 @@ fn gpt_infer(
 #[pymodule]
 fn simularity_core_server(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(init, m)?)?;
+    m.add_class::<ModelLoadOptions>()?;
     m.add_function(wrap_pyfunction!(model_load, m)?)?;
+    m.add_function(wrap_pyfunction!(lora_load, m)?)?;
+    m.add_function(wrap_pyfunction!(lora_unload, m)?)?;
+    m.add_function(wrap_pyfunction!(gguf_inspect, m)?)?;
+    m.add_function(wrap_pyfunction!(chat_template_render, m)?)?;
+    m.add_class::<SessionOptions>()?;
     m.add_function(wrap_pyfunction!(gpt_create, m)?)?;
     m.add_function(wrap_pyfunction!(gpt_touch, m)?)?;
+    m.add_function(wrap_pyfunction!(gpt_fork, m)?)?;
+    m.add_function(wrap_pyfunction!(gpt_set_loras, m)?)?;
+    m.add_function(wrap_pyfunction!(gpt_save_state, m)?)?;
+    m.add_function(wrap_pyfunction!(gpt_load_state, m)?)?;
+    m.add_function(wrap_pyfunction!(gpt_save_state_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(gpt_load_state_bytes, m)?)?;
     m.add_function(wrap_pyfunction!(gpt_token_length, m)?)?;
+    m.add_function(wrap_pyfunction!(gpt_tokenize, m)?)?;
+    m.add_function(wrap_pyfunction!(gpt_detokenize, m)?)?;
+    m.add_function(wrap_pyfunction!(gpt_token_to_piece, m)?)?;
+    m.add_function(wrap_pyfunction!(gpt_embed, m)?)?;
     m.add_class::<Dynatemp>()?;
     m.add_class::<Penalty>()?;
     m.add_class::<Mirostat>()?;
+    m.add_class::<Speculative>()?;
+    m.add_class::<LuaLimits>()?;
     m.add_class::<InferenceOptions>()?;
+    m.add_class::<TokenLogprob>()?;
+    m.add_class::<InferenceToken>()?;
+    m.add_class::<InferenceResult>()?;
     m.add_function(wrap_pyfunction!(gpt_infer, m)?)?;
+    m.add_class::<InferredSequence>()?;
+    m.add_function(wrap_pyfunction!(gpt_infer_many, m)?)?;
     Ok(())
 }
@@ -1,3 +1,6 @@
+pub mod chat_template;
+pub mod gguf;
 pub mod gpt;
+pub mod grammar;
 pub mod sqlite;
 pub mod utils;
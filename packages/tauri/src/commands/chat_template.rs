@@ -0,0 +1,32 @@
+use simularity_core::chat_template::{self, Message, Rendered};
+
+/// Render chat messages using the model's chat template,
+/// falling back to a built-in template (e.g. `chatml`).
+/// Returns the prompt and the stop sequences.
+#[tauri::command]
+pub async fn chat_template_render(
+    model_path: Option<String>,
+    fallback: Option<String>,
+    messages: Vec<Message>,
+    add_generation_prompt: Option<bool>,
+) -> Result<Rendered, tauri::ipc::InvokeError> {
+    println!(
+        "chat_template_render(model_path: {:?}, fallback: {:?}, messages: {})",
+        model_path,
+        fallback,
+        messages.len()
+    );
+
+    // Reading the GGUF metadata may take a while.
+    tauri::async_runtime::spawn_blocking(move || {
+        chat_template::render(
+            model_path.as_deref(),
+            fallback.as_deref(),
+            &messages,
+            add_generation_prompt.unwrap_or(true),
+        )
+    })
+    .await
+    .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?
+    .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))
+}
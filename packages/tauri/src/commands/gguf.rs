@@ -0,0 +1,14 @@
+/// Inspect a GGUF model file metadata without loading the model.
+#[tauri::command]
+pub async fn gguf_inspect(
+    model_path: String,
+) -> Result<simularity_core::gguf::Summary, tauri::ipc::InvokeError> {
+    println!("gguf_inspect(model_path: {})", model_path);
+
+    // Reading the vocabulary may take a while.
+    tauri::async_runtime::spawn_blocking(move || simularity_core::gguf::inspect(&model_path))
+        .await
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?
+        .map(|gguf| gguf.summary())
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))
+}
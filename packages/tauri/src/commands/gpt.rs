@@ -1,7 +1,13 @@
 pub mod create;
 pub mod decode;
 pub mod destroy;
+pub mod embed;
 pub mod find;
+pub mod fork;
 pub mod infer;
+pub mod infer_many;
 pub mod load_model;
+pub mod loras;
 pub mod model_hash;
+pub mod state;
+pub mod tokenize;
@@ -1,6 +1,9 @@
 use sha2::{Digest, Sha256};
-use std::time::Instant;
-use tauri::{Emitter, Manager};
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tauri::{Emitter, Listener, Manager};
 
 #[derive(serde::Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -26,15 +29,18 @@ pub struct Response {
     context_length: usize,
 }
 
+const ABORT_SIGNAL: &str = "app://gpt/abort-create";
+
 #[allow(clippy::too_many_arguments)]
 #[tauri::command]
 /// Create a new GPT instance.
+/// Emit `app://gpt/abort-create` with the model ID to abort.
 ///
 /// # Arguments
 ///
 /// * `model_id` - The model ID, obtained from `gpt_load_model`.
-/// * `context_size` - Context size, or `None` for default by model.
-/// * `batch_size` - Batch size, or `None` for default.
+/// * `options` - Context parameters (context size, batch size,
+///    KV cache types, RoPE scaling, context shift policy, etc.).
 /// * `initial_prompt` - If set, would try to load the session
 ///    from cache, otherwise decode from scratch.
 /// * `progress_event_name` - If set, would emit progress events.
@@ -42,8 +48,7 @@ pub struct Response {
 ///
 pub async fn gpt_create(
     model_id: &str,
-    context_size: Option<u32>,
-    batch_size: Option<u32>,
+    options: Option<simularity_core::gpt::SessionOptions>,
     initial_prompt: Option<&str>,
     progress_event_name: Option<&str>,
     cache_dir: Option<&str>,
@@ -52,10 +57,9 @@ pub async fn gpt_create(
     state: tauri::State<'_, crate::AppState>,
 ) -> Result<Response, tauri::ipc::InvokeError> {
     println!(
-        "gpt_create(model_id: {}, context_size: {}, batch_size: {}, initial_prompt: {}, progress_event_name: {}, dump_session: {:?})",
+        "gpt_create(model_id: {}, options: {:?}, initial_prompt: {}, progress_event_name: {}, dump_session: {:?})",
         model_id,
-        context_size.unwrap_or(0),
-        batch_size.unwrap_or(0),
+        options,
         if initial_prompt.is_some() {
             "Some"
         } else {
@@ -64,15 +68,27 @@ pub async fn gpt_create(
         progress_event_name.unwrap_or("None"), cache_dir
     );
 
+    let options = options.unwrap_or_default();
+
     let state_file_path = if let Some(prompt) = initial_prompt.as_ref() {
         if cache_dir.is_some() {
-            let model_hash = simularity_core::model_get_hash_by_id(model_id).unwrap();
+            let model_hash = simularity_core::model_get_hash_by_id(model_id)
+                .map_err(|e| tauri::ipc::InvokeError::from(e.to_string()))?;
             let model_hash = format!("{:x}", model_hash);
 
             let mut hasher = Sha256::new();
             hasher.update(model_hash.as_bytes());
             hasher.update(prompt.as_bytes());
-            hasher.update(batch_size.unwrap_or(0).to_be_bytes());
+            hasher.update(options.batch_size.unwrap_or(0).to_be_bytes());
+
+            // The KV cache layout depends on these as well.
+            hasher.update(
+                format!(
+                    "{:?}{:?}{:?}",
+                    options.type_k, options.type_v, options.flash_attn
+                )
+                .as_bytes(),
+            );
             let state_hash = format!("{:x}", hasher.finalize());
 
             let state_file_path = app
@@ -102,69 +118,58 @@ pub async fn gpt_create(
         None
     };
 
-    let progress_callback = if let Some(event_name) = progress_event_name.as_ref() {
-        let throttle = std::time::Duration::from_millis(500);
-        let mut last_emit: Option<Instant> = None;
-
-        Some(move |progress: f32| -> bool {
+    let aborted = Arc::new(Mutex::new(false));
+    let aborted_clone = aborted.clone();
+    // NOTE: A string is wrapped in quotes in an event payload.
+    let model_id_str = format!("\"{}\"", model_id);
+    let abort_listener = window.listen(ABORT_SIGNAL, move |event| {
+        if model_id_str.eq(event.payload()) {
+            println!("Aborting session creation");
+            *aborted_clone.lock().unwrap() = true;
+        }
+    });
+
+    // NOTE: The callback is always set to be able to abort the creation.
+    let throttle = std::time::Duration::from_millis(500);
+    let mut last_emit: Option<Instant> = None;
+    let window_clone = window.clone();
+    let progress_callback = move |progress: f32| -> bool {
+        if let Some(event_name) = progress_event_name {
             // Throttle the event emission.
-            if let Some(last_emit) = last_emit {
-                if last_emit.elapsed() < throttle {
-                    return true;
-                }
-            }
+            if !last_emit.is_some_and(|last_emit| last_emit.elapsed() < throttle) {
+                let payload = ProgressEventPayload { progress };
 
-            let payload = ProgressEventPayload { progress };
-
-            window.emit(event_name, payload).unwrap();
-            last_emit = Some(std::time::Instant::now());
+                window_clone.emit(event_name, payload).unwrap();
+                last_emit = Some(std::time::Instant::now());
+            }
+        }
 
-            true
-        })
-    } else {
-        None
+        !*aborted.lock().unwrap()
     };
 
-    let create_result = simularity_core::gpt::create(
+    let create_result = simularity_core::gpt::Session::create(
         model_id,
-        context_size,
-        batch_size,
+        Some(options),
         initial_prompt,
         state_file_path.as_deref(),
-        progress_callback,
+        Some(progress_callback),
     );
 
+    window.unlisten(abort_listener);
+
     if let Err(err) = create_result {
-        match err {
-            simularity_core::gpt::create::Error::ModelNotFound => {
-                return Err(tauri::ipc::InvokeError::from("Model not found"));
-            }
-            simularity_core::gpt::create::Error::SessionLimitReached => {
-                return Err(tauri::ipc::InvokeError::from("Session limit reached"));
-            }
-            simularity_core::gpt::create::Error::ContextCreationFailed => {
-                return Err(tauri::ipc::InvokeError::from("Context creation failed"));
-            }
-            simularity_core::gpt::create::Error::DecodeFailed => {
-                return Err(tauri::ipc::InvokeError::from("Decode failed"));
-            }
-            simularity_core::gpt::create::Error::Unknown(code) => {
-                return Err(tauri::ipc::InvokeError::from(format!(
-                    "Unknown error code {}",
-                    code
-                )));
-            }
-        }
+        return Err(tauri::ipc::InvokeError::from(err.to_string()));
     }
 
     // TODO: Return rich information about the session.
-    let session_id = create_result.unwrap() as u32;
+    let session = create_result.unwrap();
+    let session_id = session.id();
     let session_loaded: Option<bool> = None;
     let session_dump_size: Option<usize> = None;
     let context_length = 0;
 
     let mut hash_map_lock = state.gpt_sessions.lock().await;
-    hash_map_lock.insert(session_id, model_id.to_string());
+    hash_map_lock.insert(session_id, Arc::new(session));
 
     Ok(Response {
         session_id: session_id.to_string(),
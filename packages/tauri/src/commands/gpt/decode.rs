@@ -1,6 +1,11 @@
-use std::time::Instant;
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
-use tauri::Emitter;
+use tauri::{Emitter, Listener};
+
+use crate::AppState;
 
 #[derive(serde::Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -16,15 +21,22 @@ pub struct Response {
 
     /// New context length.
     context_length: u32,
+
+    /// The number of prompt tokens evicted by the context shift policy.
+    evicted_tokens: u32,
 }
 
+const ABORT_SIGNAL: &str = "app://gpt/abort-decode";
+
 #[tauri::command]
 /// Decode prompt, updating the KV cache.
+/// Emit `app://gpt/abort-decode` with the session ID to abort.
 pub async fn gpt_decode(
     session_id: &str,
     prompt: &str,
     callback_event_name: Option<&str>,
     window: tauri::Window,
+    state: tauri::State<'_, AppState>,
 ) -> Result<Response, tauri::ipc::InvokeError> {
     println!(
         "gpt_decode(session_id: {}, callback_event_name: {})",
@@ -36,51 +48,57 @@ pub async fn gpt_decode(
         tauri::ipc::InvokeError::from(format!("Invalid session ID: {}", session_id))
     })?;
 
-    let progress_callback = if let Some(event_name) = callback_event_name.as_ref() {
-        let throttle = std::time::Duration::from_millis(500);
-        let mut last_emit: Option<Instant> = None;
+    let session = state
+        .gpt_sessions
+        .lock()
+        .await
+        .get(&session_id)
+        .cloned()
+        .ok_or_else(|| tauri::ipc::InvokeError::from("Session not found"))?;
 
-        Some(move |progress: f32| -> bool {
-            // Throttle the event emission.
-            if let Some(last_emit) = last_emit {
-                if last_emit.elapsed() < throttle {
-                    return true;
-                }
-            }
+    let aborted = Arc::new(Mutex::new(false));
+    let aborted_clone = aborted.clone();
+    // NOTE: A string is wrapped in quotes in an event payload.
+    let session_id_str = format!("\"{}\"", session_id);
+    let abort_listener = window.listen(ABORT_SIGNAL, move |event| {
+        if session_id_str.eq(event.payload()) {
+            println!("Aborting decoding");
+            *aborted_clone.lock().unwrap() = true;
+        }
+    });
 
-            let payload = ProgressEventPayload { progress };
+    // NOTE: The callback is always set to be able to abort the decoding.
+    let throttle = std::time::Duration::from_millis(500);
+    let mut last_emit: Option<Instant> = None;
+    let window_clone = window.clone();
+    let progress_callback = move |progress: f32| -> bool {
+        if let Some(event_name) = callback_event_name {
+            // Throttle the event emission.
+            if !last_emit.is_some_and(|last_emit| last_emit.elapsed() < throttle) {
+                let payload = ProgressEventPayload { progress };
 
-            window.emit(event_name, payload).unwrap();
-            last_emit = Some(std::time::Instant::now());
+                window_clone.emit(event_name, payload).unwrap();
+                last_emit = Some(std::time::Instant::now());
+            }
+        }
 
-            true
-        })
-    } else {
-        None
+        !*aborted.lock().unwrap()
     };
 
     let start = Instant::now();
-    let decode_result = simularity_core::gpt::decode(session_id, prompt, progress_callback);
+    let decode_result = session.decode(prompt, Some(progress_callback));
+    window.unlisten(abort_listener);
 
     if let Err(err) = decode_result {
-        match err {
-            simularity_core::gpt::decode::Error::SessionNotFound => {
-                return Err(tauri::ipc::InvokeError::from("Session not found"));
-            }
-            simularity_core::gpt::decode::Error::ContextOverflow => {
-                return Err(tauri::ipc::InvokeError::from("Context overflow"));
-            }
-            simularity_core::gpt::decode::Error::Unknown(code) => {
-                return Err(tauri::ipc::InvokeError::from(format!(
-                    "Unknown error code {}",
-                    code
-                )));
-            }
-        }
+        return Err(tauri::ipc::InvokeError::from(err.to_string()));
     }
 
+    let duration = start.elapsed().as_millis() as u32;
+    let evicted_tokens = session.info().map_or(0, |info| info.evicted_tokens);
+
     Ok(Response {
-        duration: start.elapsed().as_millis() as u32,
+        duration,
         context_length: decode_result.unwrap(),
+        evicted_tokens,
     })
 }
@@ -0,0 +1,42 @@
+use simularity_core::gpt::embed::{Normalization, Pooling};
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    /// One embedding per text.
+    embeddings: Vec<Vec<f32>>,
+}
+
+#[tauri::command]
+/// Compute the text embeddings using a model with the given ID.
+pub async fn gpt_embed(
+    model_id: String,
+    texts: Vec<String>,
+    pooling: Option<Pooling>,
+    normalization: Option<Normalization>,
+) -> Result<Response, tauri::ipc::InvokeError> {
+    println!(
+        "gpt_embed(model_id: {}, texts: {}, pooling: {:?}, normalization: {:?})",
+        model_id,
+        texts.len(),
+        pooling,
+        normalization
+    );
+
+    // Embedding may take a while.
+    let embeddings = tauri::async_runtime::spawn_blocking(move || {
+        let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+
+        simularity_core::gpt::embed(
+            &model_id,
+            &texts,
+            pooling.unwrap_or_default(),
+            normalization.unwrap_or_default(),
+        )
+    })
+    .await
+    .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?
+    .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?;
+
+    Ok(Response { embeddings })
+}
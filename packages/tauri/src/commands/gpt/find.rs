@@ -8,6 +8,7 @@ pub struct Response {
 
 #[tauri::command]
 /// Return whether a GPT instance with the given ID exists.
+/// A destroyed or expired instance is forgotten.
 pub async fn gpt_find(
     session_id: &str,
     state: tauri::State<'_, AppState>,
@@ -18,10 +19,25 @@ pub async fn gpt_find(
         tauri::ipc::InvokeError::from(format!("Invalid session ID: {}", session_id))
     })?;
 
-    let hash_map_lock = state.gpt_sessions.lock().await;
-    let model_id = hash_map_lock.get(&session_id);
+    if !state.gpt_sessions.lock().await.contains_key(&session_id) {
+        return Ok(None);
+    }
 
-    Ok(model_id.map(|model_id| Response {
-        model_id: model_id.clone(),
-    }))
+    // The native session may be gone, e.g. removed upon TTL expiry.
+    // NOTE: Getting the info blocks while the session is in use,
+    // hence the sessions lock is not held meanwhile.
+    let info = tauri::async_runtime::spawn_blocking(move || simularity_core::gpt::info(session_id))
+        .await
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?;
+
+    match info {
+        Ok(info) if !info.is_expired() => Ok(Some(Response {
+            model_id: info.model_id,
+        })),
+        Ok(_) | Err(simularity_core::Error::SessionNotFound) => {
+            state.gpt_sessions.lock().await.remove(&session_id);
+            Ok(None)
+        }
+        Err(err) => Err(tauri::ipc::InvokeError::from(err.to_string())),
+    }
 }
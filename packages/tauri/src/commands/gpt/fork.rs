@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use crate::AppState;
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    /// The new session ID.
+    session_id: String,
+}
+
+#[tauri::command]
+/// Fork a GPT instance, copying its KV cache and committed prompt
+/// into a new instance, so that generations may branch cheaply.
+pub async fn gpt_fork(
+    session_id: &str,
+    state: tauri::State<'_, AppState>,
+) -> Result<Response, tauri::ipc::InvokeError> {
+    println!("gpt_fork(session_id: {})", session_id);
+
+    let session_id = session_id.parse::<u32>().map_err(|_| {
+        tauri::ipc::InvokeError::from(format!("Invalid session ID: {}", session_id))
+    })?;
+
+    let parent = state
+        .gpt_sessions
+        .lock()
+        .await
+        .get(&session_id)
+        .cloned()
+        .ok_or_else(|| tauri::ipc::InvokeError::from("Session not found"))?;
+
+    // Copying the state may take a while.
+    let child = tauri::async_runtime::spawn_blocking(move || parent.fork())
+        .await
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?;
+
+    let child_id = child.id();
+    state
+        .gpt_sessions
+        .lock()
+        .await
+        .insert(child_id, Arc::new(child));
+
+    Ok(Response {
+        session_id: child_id.to_string(),
+    })
+}
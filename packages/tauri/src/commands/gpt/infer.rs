@@ -3,6 +3,7 @@ use std::{
     time::Instant,
 };
 
+use simularity_core::gpt::InferenceEvent;
 use tauri::{Emitter, Listener};
 
 use crate::AppState;
@@ -16,15 +17,51 @@ pub struct DecodeProgressEventPayload {
 #[derive(serde::Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct InferenceEventPayload {
+    /// The token text.
     pub content: String,
+
+    /// The token ID.
+    pub token: i32,
+
+    /// Natural logarithm of the token probability.
+    pub logprob: f32,
+
+    /// Top `nProbs` candidates, if requested in the options.
+    pub top_logprobs: Vec<simularity_core::gpt::infer::TokenLogprob>,
 }
 
 #[derive(serde::Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Response {
+    /// The inferred text, excluding a matched stop sequence.
     pub result: String,
+
     pub input_context_length: u32,
     pub output_context_length: u32,
+
+    /// The number of tokens evicted by the context shift policy.
+    pub evicted_tokens: u32,
+
+    pub stop_reason: simularity_core::gpt::infer::StopReason,
+
+    /// The decoded prompt length in tokens.
+    pub prompt_tokens: u32,
+
+    /// The number of inferred tokens, excluding a matched stop sequence.
+    pub generated_tokens: u32,
+
+    pub decode_duration_ms: f64,
+    pub generation_duration_ms: f64,
+    pub tokens_per_second: f64,
+
+    /// The number of tokens proposed by the draft model, if any.
+    pub drafted_tokens: u32,
+
+    /// The number of drafted tokens accepted.
+    pub accepted_tokens: u32,
+
+    /// The share of drafted tokens accepted, `null` without drafting.
+    pub acceptance_rate: Option<f64>,
 }
 
 const ABORT_SIGNAL: &str = "app://gpt/abort-inference";
@@ -53,15 +90,14 @@ pub async fn gpt_infer(
         tauri::ipc::InvokeError::from(format!("Invalid session ID: {}", session_id))
     })?;
 
-    let hash_map_lock = state.gpt_sessions.lock().await;
-    let model_id = hash_map_lock.get(&session_id);
-    if model_id.is_none() {
-        return Err(tauri::ipc::InvokeError::from("Session not found"));
-    }
-    let model_id = model_id.unwrap();
+    let session = state
+        .gpt_sessions
+        .lock()
+        .await
+        .get(&session_id)
+        .cloned()
+        .ok_or_else(|| tauri::ipc::InvokeError::from("Session not found"))?;
 
-    // OPTIMIZE: Use a more efficient way to abort the inference,
-    // as we're always in the same thread?
     let aborted = Arc::new(Mutex::new(false));
     let aborted_clone = aborted.clone();
     // NOTE: A string is wrapped in quotes in an event payload.
@@ -75,102 +111,90 @@ pub async fn gpt_infer(
         }
     });
 
-    let window_clone = window.clone();
-    let mut resulting_string = String::new();
-    let inference_callback = Some(|content: &str| -> bool {
-        resulting_string.push_str(content);
-
-        if let Some(event_name) = inference_callback_event_name.as_ref() {
-            window_clone
-                .emit(
-                    event_name,
-                    InferenceEventPayload {
-                        content: content.to_string(),
-                    },
-                )
-                .unwrap();
-        }
-
-        !*aborted.lock().unwrap()
-    });
-
-    let decode_progress_callback = if let Some(event_name) = decode_callback_event_name.as_ref() {
-        let throttle = std::time::Duration::from_millis(500);
-        let mut last_emit: Option<Instant> = None;
-
-        Some(move |progress| -> bool {
-            // Throttle the event emission.
-            if let Some(last_emit) = last_emit {
-                if last_emit.elapsed() < throttle {
-                    return true;
-                }
-            }
-
-            let payload = DecodeProgressEventPayload { progress };
-
-            window.emit(event_name, payload).unwrap();
-            last_emit = Some(std::time::Instant::now());
-
-            true
-        })
-    } else {
-        None
-    };
-
     let input_token_length = if let Some(prompt) = prompt {
-        simularity_core::gpt::token_length(model_id, prompt)
+        session.token_length(prompt)
     } else {
         Ok(0)
     };
 
     if let Err(error) = input_token_length {
-        match error {
-            simularity_core::gpt::token_length::Error::ModelNotFound => {
-                return Err(tauri::ipc::InvokeError::from("Model not found"))
-            }
-            simularity_core::gpt::token_length::Error::Unknown(code) => {
-                return Err(tauri::ipc::InvokeError::from(format!(
-                    "Unknown error code {}",
-                    code
-                )))
-            }
-        }
+        return Err(tauri::ipc::InvokeError::from(error.to_string()));
     }
 
     let input_token_length = input_token_length.unwrap();
 
-    let inference_result = simularity_core::gpt::infer(
-        session_id,
-        prompt,
-        n_eval,
-        options,
-        decode_progress_callback,
-        inference_callback,
-    );
+    // The inference runs on a dedicated worker thread,
+    // so the async runtime is not blocked.
+    let mut stream = session.infer_stream(prompt.map(str::to_string), n_eval, options);
+
+    let throttle = std::time::Duration::from_millis(500);
+    let mut last_decode_emit: Option<Instant> = None;
+
+    while let Some(event) = stream.recv().await {
+        match event {
+            InferenceEvent::DecodeProgress(progress) => {
+                let Some(event_name) = decode_callback_event_name else {
+                    continue;
+                };
+
+                // Throttle the event emission.
+                if let Some(last_emit) = last_decode_emit {
+                    if last_emit.elapsed() < throttle {
+                        continue;
+                    }
+                }
 
-    if let Ok(new_context_length) = inference_result {
-        Ok(Response {
-            result: resulting_string,
-            input_context_length: input_token_length,
-            output_context_length: new_context_length - input_token_length,
-        })
-    } else {
-        match inference_result.unwrap_err() {
-            simularity_core::gpt::infer::Error::SessionNotFound => {
-                Err(tauri::ipc::InvokeError::from("Session not found"))
-            }
-            simularity_core::gpt::infer::Error::ContextOverflow => {
-                Err(tauri::ipc::InvokeError::from("Context overflow"))
+                let payload = DecodeProgressEventPayload { progress };
+
+                window.emit(event_name, payload).unwrap();
+                last_decode_emit = Some(std::time::Instant::now());
             }
-            simularity_core::gpt::infer::Error::SamplingError => {
-                Err(tauri::ipc::InvokeError::from("Sampling error"))
+
+            InferenceEvent::Token(token) => {
+                if let Some(event_name) = inference_callback_event_name {
+                    let payload = InferenceEventPayload {
+                        content: token.piece,
+                        token: token.token,
+                        logprob: token.logprob,
+                        top_logprobs: token.top_logprobs,
+                    };
+
+                    window.emit(event_name, payload).unwrap();
+                }
+
+                if *aborted.lock().unwrap() {
+                    stream.cancel();
+                }
             }
-            simularity_core::gpt::infer::Error::LuaError => {
-                Err(tauri::ipc::InvokeError::from("Lua error"))
+
+            InferenceEvent::Done(result) => {
+                return match result {
+                    Ok(outcome) => Ok(Response {
+                        input_context_length: input_token_length,
+                        // NOTE: The context may have been shifted, hence the post-shift
+                        // prompt length, and the saturation.
+                        output_context_length: outcome
+                            .context_length
+                            .saturating_sub(outcome.prompt_tokens),
+                        evicted_tokens: outcome.evicted_tokens,
+                        prompt_tokens: outcome.prompt_tokens,
+                        generated_tokens: outcome.generated_tokens,
+                        decode_duration_ms: outcome.decode_duration.as_secs_f64() * 1000.0,
+                        generation_duration_ms: outcome.generation_duration.as_secs_f64() * 1000.0,
+                        tokens_per_second: outcome.tokens_per_second(),
+                        drafted_tokens: outcome.drafted_tokens,
+                        accepted_tokens: outcome.accepted_tokens,
+                        acceptance_rate: outcome.acceptance_rate(),
+                        stop_reason: outcome.stop_reason,
+                        result: outcome.output,
+                    }),
+                    Err(error) => Err(tauri::ipc::InvokeError::from(error.to_string())),
+                };
             }
-            simularity_core::gpt::infer::Error::Unknown(code) => Err(
-                tauri::ipc::InvokeError::from(format!("Unknown error code {}", code)),
-            ),
         }
     }
+
+    Err(tauri::ipc::InvokeError::from(
+        "Inference stream ended unexpectedly",
+    ))
 }
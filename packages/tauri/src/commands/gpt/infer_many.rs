@@ -0,0 +1,84 @@
+use tauri::Emitter;
+
+use crate::AppState;
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct InferenceEventPayload {
+    /// The sequence index.
+    pub seq: u32,
+
+    /// The token text.
+    pub content: String,
+
+    /// The token ID.
+    pub token: i32,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub sequences: Vec<simularity_core::gpt::infer_many::InferredSequence>,
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+/// Infer `n` independent sequences from the same prompt in a single batch.
+/// The inferred tokens are not committed to the session.
+pub async fn gpt_infer_many(
+    session_id: &str,
+    prompt: Option<String>,
+    n_eval: u32,
+    n: u32,
+    options: Option<simularity_core::gpt::infer::Options>,
+    inference_callback_event_name: Option<String>,
+    window: tauri::Window,
+    state: tauri::State<'_, AppState>,
+) -> Result<Response, tauri::ipc::InvokeError> {
+    println!(
+        "gpt_infer_many(gpt_id: {}, prompt: {}, n_eval: {}, n: {})",
+        session_id,
+        if prompt.is_some() { "Some" } else { "None" },
+        n_eval,
+        n
+    );
+
+    let session_id = session_id.parse::<u32>().map_err(|_| {
+        tauri::ipc::InvokeError::from(format!("Invalid session ID: {}", session_id))
+    })?;
+
+    let session = state
+        .gpt_sessions
+        .lock()
+        .await
+        .get(&session_id)
+        .cloned()
+        .ok_or_else(|| tauri::ipc::InvokeError::from("Session not found"))?;
+
+    let sequences = tauri::async_runtime::spawn_blocking(move || {
+        session.infer_many(
+            prompt.as_deref(),
+            n_eval,
+            n,
+            options,
+            None::<fn(f32) -> bool>,
+            inference_callback_event_name.map(|event_name| {
+                move |seq: u32, token: &simularity_core::gpt::infer::InferenceToken| {
+                    let payload = InferenceEventPayload {
+                        seq,
+                        content: token.piece.clone(),
+                        token: token.token,
+                    };
+
+                    window.emit(&event_name, payload).unwrap();
+                    true
+                }
+            }),
+        )
+    })
+    .await
+    .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?
+    .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?;
+
+    Ok(Response { sequences })
+}
@@ -1,4 +1,15 @@
 use sha2::{Digest, Sha256};
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tauri::{Emitter, Listener};
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressEventPayload {
+    pub progress: f32,
+}
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -9,28 +20,66 @@ pub struct Response {
     n_ctx_train: i64,
 }
 
+const ABORT_SIGNAL: &str = "app://gpt/abort-load-model";
+
 #[tauri::command]
 /// Load a model at path, returning the path hash as the model ID.
-/// Can be called multiple times with the same model path.
-pub async fn gpt_load_model(model_path: String) -> Result<Response, tauri::ipc::InvokeError> {
-    println!("gpt_load_model(model_path: {})", model_path);
+/// Can be called multiple times with the same model path,
+/// in which case the options are ignored.
+/// Emit `app://gpt/abort-load-model` with the model path to abort.
+pub async fn gpt_load_model(
+    model_path: String,
+    options: Option<simularity_core::ModelLoadOptions>,
+    progress_event_name: Option<&str>,
+    window: tauri::Window,
+) -> Result<Response, tauri::ipc::InvokeError> {
+    println!(
+        "gpt_load_model(model_path: {}, options: {:?}, progress_event_name: {})",
+        model_path,
+        options,
+        progress_event_name.unwrap_or("None")
+    );
 
     let mut hasher = Sha256::new();
     hasher.update(model_path.as_bytes());
     let model_id = format!("{:x}", hasher.finalize());
 
+    let aborted = Arc::new(Mutex::new(false));
+    let aborted_clone = aborted.clone();
+    // NOTE: A string is wrapped in quotes in an event payload.
+    let model_path_str = serde_json::to_string(&model_path).unwrap();
+    let abort_listener = window.listen(ABORT_SIGNAL, move |event| {
+        if model_path_str.eq(event.payload()) {
+            println!("Aborting model loading");
+            *aborted_clone.lock().unwrap() = true;
+        }
+    });
+
+    // NOTE: The callback is always set to be able to abort the loading.
+    let throttle = std::time::Duration::from_millis(500);
+    let mut last_emit: Option<Instant> = None;
+    let window_clone = window.clone();
+    let progress_callback = move |progress: f32| -> bool {
+        if let Some(event_name) = progress_event_name {
+            // Throttle the event emission.
+            if !last_emit.is_some_and(|last_emit| last_emit.elapsed() < throttle) {
+                let payload = ProgressEventPayload { progress };
+
+                window_clone.emit(event_name, payload).unwrap();
+                last_emit = Some(std::time::Instant::now());
+            }
+        }
+
+        !*aborted.lock().unwrap()
+    };
+
     let model_load_result =
-        simularity_core::model_load(&model_path, &model_id, None::<fn(_) -> bool>);
+        simularity_core::model_load(&model_path, &model_id, options, Some(progress_callback));
+
+    window.unlisten(abort_listener);
 
     match model_load_result {
-        Err(err) => match err {
-            simularity_core::ModelLoadError::LoadFailed => {
-                Err(tauri::ipc::InvokeError::from("Model load failed"))
-            }
-            simularity_core::ModelLoadError::Unknown(code) => Err(tauri::ipc::InvokeError::from(
-                format!("Model load failed with unhandled code {}", code),
-            )),
-        },
+        Err(err) => Err(tauri::ipc::InvokeError::from(err.to_string())),
         Ok(ok) => Ok(Response {
             model_id,
             n_params: ok.n_params,
@@ -0,0 +1,83 @@
+use sha2::{Digest, Sha256};
+
+use crate::AppState;
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadResponse {
+    lora_id: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetResponse {
+    /// The number of tokens cleared from the KV cache.
+    cleared_tokens: u32,
+}
+
+#[tauri::command]
+/// Load a LoRA adapter at path, returning the path hash as the adapter ID.
+/// Can be called multiple times with the same adapter path.
+pub async fn gpt_load_lora(lora_path: String) -> Result<LoadResponse, tauri::ipc::InvokeError> {
+    println!("gpt_load_lora(lora_path: {})", lora_path);
+
+    let mut hasher = Sha256::new();
+    hasher.update(lora_path.as_bytes());
+    let lora_id = format!("{:x}", hasher.finalize());
+
+    simularity_core::lora_load(&lora_path, &lora_id)
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?;
+
+    Ok(LoadResponse { lora_id })
+}
+
+#[tauri::command]
+/// Unload a LoRA adapter by ID.
+/// The sessions using the adapter keep it until their adapters are set.
+pub async fn gpt_unload_lora(lora_id: &str) -> Result<(), tauri::ipc::InvokeError> {
+    println!("gpt_unload_lora(lora_id: {})", lora_id);
+
+    simularity_core::lora_unload(lora_id)
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))
+}
+
+#[tauri::command]
+/// Set the LoRA adapters of a GPT instance as `(lora_id, scale)` pairs,
+/// replacing the current ones. Clears the KV cache if the adapters change.
+pub async fn gpt_set_loras(
+    session_id: &str,
+    loras: Vec<(String, f32)>,
+    state: tauri::State<'_, AppState>,
+) -> Result<SetResponse, tauri::ipc::InvokeError> {
+    println!(
+        "gpt_set_loras(session_id: {}, loras: {:?})",
+        session_id, loras
+    );
+
+    let session_id = session_id.parse::<u32>().map_err(|_| {
+        tauri::ipc::InvokeError::from(format!("Invalid session ID: {}", session_id))
+    })?;
+
+    let session = state
+        .gpt_sessions
+        .lock()
+        .await
+        .get(&session_id)
+        .cloned()
+        .ok_or_else(|| tauri::ipc::InvokeError::from("Session not found"))?;
+
+    // Waits for an ongoing inference, if any.
+    let cleared_tokens = tauri::async_runtime::spawn_blocking(move || {
+        let loras: Vec<(&str, f32)> = loras
+            .iter()
+            .map(|(lora_id, scale)| (lora_id.as_str(), *scale))
+            .collect();
+
+        session.set_loras(&loras)
+    })
+    .await
+    .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?
+    .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?;
+
+    Ok(SetResponse { cleared_tokens })
+}
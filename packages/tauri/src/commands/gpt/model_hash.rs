@@ -12,11 +12,7 @@ pub async fn gpt_model_hash_by_id(model_id: String) -> Result<Response, tauri::i
     let result = simularity_core::model_get_hash_by_id(&model_id);
 
     match result {
-        Err(err) => match err {
-            simularity_core::ModelHashError::Unknown(code) => Err(tauri::ipc::InvokeError::from(
-                format!("Model hashing failed with unhandled code {}", code),
-            )),
-        },
+        Err(err) => Err(tauri::ipc::InvokeError::from(err.to_string())),
         Ok(ok) => Ok(Response {
             // Format the hash as a hex string.
             xx64_hash: format!("{:x}", ok),
@@ -34,11 +30,7 @@ pub async fn gpt_model_hash_by_path(
     let result = simularity_core::model_get_hash_by_path(&model_path);
 
     match result {
-        Err(err) => match err {
-            simularity_core::ModelHashError::Unknown(code) => Err(tauri::ipc::InvokeError::from(
-                format!("Model hashing failed with unhandled code {}", code),
-            )),
-        },
+        Err(err) => Err(tauri::ipc::InvokeError::from(err.to_string())),
         Ok(ok) => Ok(Response {
             // Format the hash as a hex string.
             xx64_hash: format!("{:x}", ok),
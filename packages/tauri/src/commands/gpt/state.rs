@@ -0,0 +1,61 @@
+use crate::AppState;
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadStateResponse {
+    /// New context length.
+    context_length: u32,
+}
+
+async fn get_session(
+    session_id: &str,
+    state: &tauri::State<'_, AppState>,
+) -> Result<std::sync::Arc<simularity_core::gpt::Session>, tauri::ipc::InvokeError> {
+    let session_id = session_id.parse::<u32>().map_err(|_| {
+        tauri::ipc::InvokeError::from(format!("Invalid session ID: {}", session_id))
+    })?;
+
+    state
+        .gpt_sessions
+        .lock()
+        .await
+        .get(&session_id)
+        .cloned()
+        .ok_or_else(|| tauri::ipc::InvokeError::from("Session not found"))
+}
+
+#[tauri::command]
+/// Save the session state (KV cache and committed prompt) to a file.
+pub async fn gpt_save_state(
+    session_id: &str,
+    path: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), tauri::ipc::InvokeError> {
+    println!("gpt_save_state(session_id: {}, path: {})", session_id, path);
+
+    let session = get_session(session_id, &state).await?;
+
+    tauri::async_runtime::spawn_blocking(move || session.save_state(&path))
+        .await
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))
+}
+
+#[tauri::command]
+/// Restore the session state from a file saved with `gpt_save_state`.
+/// The session must have been created with the same model and context size.
+pub async fn gpt_load_state(
+    session_id: &str,
+    path: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<LoadStateResponse, tauri::ipc::InvokeError> {
+    println!("gpt_load_state(session_id: {}, path: {})", session_id, path);
+
+    let session = get_session(session_id, &state).await?;
+
+    tauri::async_runtime::spawn_blocking(move || session.load_state(&path))
+        .await
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))?
+        .map(|context_length| LoadStateResponse { context_length })
+        .map_err(|err| tauri::ipc::InvokeError::from(err.to_string()))
+}
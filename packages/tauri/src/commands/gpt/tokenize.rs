@@ -0,0 +1,73 @@
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenizeResponse {
+    tokens: Vec<i32>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DetokenizeResponse {
+    text: String,
+}
+
+#[tauri::command]
+/// Tokenize the text using a model with the given ID.
+pub async fn gpt_tokenize(
+    model_id: String,
+    text: String,
+    add_special: Option<bool>,
+    parse_special: Option<bool>,
+) -> Result<TokenizeResponse, tauri::ipc::InvokeError> {
+    println!("gpt_tokenize(model_id: {})", model_id);
+
+    let result = simularity_core::gpt::tokenize(
+        &model_id,
+        &text,
+        add_special.unwrap_or(false),
+        parse_special.unwrap_or(false),
+    );
+
+    match result {
+        Err(err) => Err(tauri::ipc::InvokeError::from(err.to_string())),
+        Ok(tokens) => Ok(TokenizeResponse { tokens }),
+    }
+}
+
+#[tauri::command]
+/// Convert tokens back to text using a model with the given ID.
+pub async fn gpt_detokenize(
+    model_id: String,
+    tokens: Vec<i32>,
+) -> Result<DetokenizeResponse, tauri::ipc::InvokeError> {
+    println!(
+        "gpt_detokenize(model_id: {}, tokens: {})",
+        model_id,
+        tokens.len()
+    );
+
+    let result = simularity_core::gpt::detokenize(&model_id, &tokens);
+
+    match result {
+        Err(err) => Err(tauri::ipc::InvokeError::from(err.to_string())),
+        Ok(text) => Ok(DetokenizeResponse { text }),
+    }
+}
+
+#[tauri::command]
+/// Convert a single token to its text piece using a model with the given ID.
+pub async fn gpt_token_to_piece(
+    model_id: String,
+    token: i32,
+) -> Result<DetokenizeResponse, tauri::ipc::InvokeError> {
+    println!(
+        "gpt_token_to_piece(model_id: {}, token: {})",
+        model_id, token
+    );
+
+    let result = simularity_core::gpt::token_to_piece(&model_id, token);
+
+    match result {
+        Err(err) => Err(tauri::ipc::InvokeError::from(err.to_string())),
+        Ok(text) => Ok(DetokenizeResponse { text }),
+    }
+}
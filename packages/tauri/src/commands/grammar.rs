@@ -0,0 +1,30 @@
+use simularity_core::grammar::{self, GrammarError, GrammarInfo};
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    /// Set if the grammar is valid.
+    pub info: Option<GrammarInfo>,
+
+    /// Set if the grammar is invalid.
+    pub error: Option<GrammarError>,
+}
+
+/// Validate a GBNF grammar without loading a model.
+/// An invalid grammar is not a command error, the error
+/// (with its position) is returned instead, e.g. for an editor.
+#[tauri::command]
+pub async fn grammar_validate(gbnf: String) -> Result<Response, tauri::ipc::InvokeError> {
+    println!("grammar_validate(gbnf: {} bytes)", gbnf.len());
+
+    Ok(match grammar::validate(&gbnf) {
+        Ok(info) => Response {
+            info: Some(info),
+            error: None,
+        },
+        Err(error) => Response {
+            info: None,
+            error: Some(error),
+        },
+    })
+}
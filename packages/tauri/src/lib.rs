@@ -9,8 +9,8 @@ mod commands;
 mod sqlite;
 
 struct AppState {
-    /// { id => model_id }.
-    pub gpt_sessions: Mutex<HashMap<u32, String>>,
+    /// { id => session }. A session is destroyed once removed and no longer in use.
+    pub gpt_sessions: Mutex<HashMap<u32, Arc<simularity_core::gpt::Session>>>,
 
     /// { uri => connection }. A connection will be held until it is closed.
     pub sqlite_connections: Mutex<HashMap<String, Arc<Mutex<rusqlite::Connection>>>>,
@@ -52,6 +52,11 @@ pub fn run() {
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_store::Builder::default().build())
         .plugin(tauri_plugin_persisted_scope::init())
+        .plugin(
+            tauri_plugin_log::Builder::new()
+                .level(log::LevelFilter::Debug)
+                .build(),
+        )
         .setup(move |app| {
             app.manage(AppState::new());
 
@@ -62,18 +67,36 @@ pub fn run() {
                 .expect("This should never be None");
             create_dir_all(&path)?;
 
-            simularity_core::init(None, None);
+            // Forward the native logs to the log plugin.
+            simularity_core::InitOptions::new()
+                .forward_logs(true)
+                .init()?;
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
+            commands::chat_template::chat_template_render,
+            commands::gguf::gguf_inspect,
+            commands::grammar::grammar_validate,
             commands::gpt::load_model::gpt_load_model,
+            commands::gpt::loras::gpt_load_lora,
+            commands::gpt::loras::gpt_unload_lora,
+            commands::gpt::loras::gpt_set_loras,
             commands::gpt::model_hash::gpt_model_hash_by_id,
             commands::gpt::model_hash::gpt_model_hash_by_path,
             commands::gpt::find::gpt_find,
+            commands::gpt::fork::gpt_fork,
             commands::gpt::create::gpt_create,
             commands::gpt::decode::gpt_decode,
             commands::gpt::infer::gpt_infer,
+            commands::gpt::infer_many::gpt_infer_many,
             commands::gpt::destroy::gpt_destroy,
+            commands::gpt::embed::gpt_embed,
+            commands::gpt::state::gpt_save_state,
+            commands::gpt::state::gpt_load_state,
+            commands::gpt::tokenize::gpt_tokenize,
+            commands::gpt::tokenize::gpt_detokenize,
+            commands::gpt::tokenize::gpt_token_to_piece,
             commands::sqlite::sqlite_open,
             commands::sqlite::sqlite_execute,
             commands::sqlite::sqlite_execute_batch,